@@ -0,0 +1,47 @@
+use clap::Parser;
+use libbit::daemon::{Daemon, DaemonOpts, DEFAULT_DAEMON_PORT};
+use libbit::error::BitResult;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+#[derive(Parser, Debug)]
+struct Opts {
+    /// the address to listen on
+    #[arg(long, default_value = "0.0.0.0")]
+    listen: String,
+    #[arg(long, default_value_t = DEFAULT_DAEMON_PORT)]
+    port: u16,
+    /// requested paths are relative to this directory
+    #[arg(long)]
+    base_path: Option<PathBuf>,
+    /// serve all repositories, not just those containing `git-daemon-export-ok`
+    #[arg(long)]
+    export_all: bool,
+    /// seconds to wait for a client to send its request after connecting
+    #[arg(long)]
+    init_timeout: Option<u64>,
+    /// seconds a connection may be idle once the request has been received
+    #[arg(long)]
+    timeout: Option<u64>,
+    /// refuse connections beyond this many concurrent connections (0 for no limit)
+    #[arg(long, default_value_t = 32)]
+    max_connections: usize,
+    /// only serve repositories within these directories
+    directories: Vec<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> BitResult<()> {
+    let opts = Opts::parse();
+    let daemon = Daemon::new(DaemonOpts {
+        base_path: opts.base_path,
+        export_all: opts.export_all,
+        whitelist: opts.directories,
+        init_timeout: opts.init_timeout.map(Duration::from_secs),
+        timeout: opts.timeout.map(Duration::from_secs),
+        max_connections: (opts.max_connections > 0).then_some(opts.max_connections),
+    })?;
+    let listener = TcpListener::bind((opts.listen.as_str(), opts.port)).await?;
+    daemon.serve(listener).await
+}
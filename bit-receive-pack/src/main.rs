@@ -0,0 +1,18 @@
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::receive_pack::ReceivePack;
+use libbit::repo::BitRepo;
+use std::path::PathBuf;
+use tokio::io::BufReader;
+
+#[derive(Parser, Debug)]
+struct Opts {
+    path: PathBuf,
+}
+
+fn main() -> BitResult<()> {
+    let opts = Opts::parse();
+    BitRepo::find(opts.path, |repo| {
+        ReceivePack::new(repo, BufReader::new(tokio::io::stdin()), tokio::io::stdout()).run()
+    })
+}
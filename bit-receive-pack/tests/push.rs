@@ -0,0 +1,200 @@
+use libbit::error::BitResult;
+use libbit::obj::Oid;
+use libbit::refs::{BitRefDbBackend, SymbolicRef};
+use libbit::remote::{PushOpts, PushStatus, PushSummary, Refspec};
+use libbit::repo::BitRepo;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tempfile::TempDir;
+
+const BIT_RECEIVE_PACK: &str = env!("CARGO_BIN_EXE_bit-receive-pack");
+
+/// Copy one of libbit's test repositories to push from
+fn local_repo(name: &str) -> BitResult<TempDir> {
+    let tmpdir = tempfile::tempdir()?;
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../libbit/tests/repos").join(name);
+    let options = fs_extra::dir::CopyOptions { content_only: true, ..Default::default() };
+    fs_extra::dir::copy(path, &tmpdir, &options)?;
+    set_identity(tmpdir.path())?;
+    Ok(tmpdir)
+}
+
+/// creates an empty repository to push to, optionally accepting pushes to its checked out branch
+fn remote_repo(deny_current_branch: bool) -> BitResult<TempDir> {
+    let remote = tempfile::tempdir()?;
+    BitRepo::init(remote.path())?;
+    set_identity(remote.path())?;
+    if !deny_current_branch {
+        BitRepo::find(remote.path(), |repo| {
+            repo.with_raw_local_config(|config| {
+                config.set("receive", "denyCurrentBranch", "ignore")
+            })
+        })?;
+    }
+    Ok(remote)
+}
+
+/// the reflog needs an identity and we can't rely on one being configured globally
+fn set_identity(path: &Path) -> BitResult<()> {
+    BitRepo::find(path, |repo| {
+        repo.with_raw_local_config(|config| {
+            config.set("user", "name", "bit")?;
+            config.set("user", "email", "bit@example.com")
+        })
+    })
+}
+
+/// adds `remote` to `local` as a remote called `name` that is pushed to using the given
+/// receive-pack program (defaulting to `git-receive-pack`)
+fn add_remote(
+    local: &Path,
+    name: &str,
+    remote: &Path,
+    receive_pack: Option<&str>,
+) -> BitResult<()> {
+    BitRepo::find(local, |repo| {
+        repo.add_remote(name, remote.to_str().unwrap())?;
+        if let Some(receive_pack) = receive_pack {
+            repo.with_raw_local_config(|config| {
+                config.set_subsection("remote", name, "receivepack", receive_pack)
+            })?;
+        }
+        Ok(())
+    })
+}
+
+fn push(local: &Path, name: &str, refspecs: &[&str]) -> BitResult<PushSummary> {
+    push_with_opts(local, name, refspecs, PushOpts::default())
+}
+
+fn push_with_opts(
+    local: &Path,
+    name: &str,
+    refspecs: &[&str],
+    opts: PushOpts,
+) -> BitResult<PushSummary> {
+    BitRepo::find(local, |repo| {
+        let refspecs =
+            refspecs.iter().copied().map(Refspec::parse_push).collect::<BitResult<Vec<_>>>()?;
+        repo.push_blocking(name, &refspecs, opts)
+    })
+}
+
+/// reads the refs of a repository directly (i.e. not through the transport)
+fn read_refs(path: &Path) -> BitResult<BTreeMap<SymbolicRef, Option<Oid>>> {
+    BitRepo::find(path, |repo| {
+        repo.ls_refs()?.into_iter().map(|sym| Ok((sym, repo.try_fully_resolve_ref(sym)?))).collect()
+    })
+}
+
+fn read_ref(path: &Path, name: &str) -> BitResult<Option<Oid>> {
+    BitRepo::find(path, |repo| repo.try_fully_resolve_ref(SymbolicRef::intern(name)))
+}
+
+#[test]
+fn test_push_matches_git_receive_pack() -> BitResult<()> {
+    let local = local_repo("ribble")?;
+    let refspecs = ["master", "new-branch"];
+
+    let expected = remote_repo(false)?;
+    add_remote(local.path(), "expected", expected.path(), None)?;
+    assert!(push(local.path(), "expected", &refspecs)?.is_ok());
+
+    let actual = remote_repo(false)?;
+    add_remote(local.path(), "actual", actual.path(), Some(BIT_RECEIVE_PACK))?;
+    let summary = push(local.path(), "actual", &refspecs)?;
+    assert!(summary.is_ok());
+    assert!(summary.updates.iter().all(|update| update.status == PushStatus::Ok));
+    assert_eq!(read_refs(actual.path())?, read_refs(expected.path())?);
+
+    // cloning requires every object to have made it across
+    let clone = tempfile::tempdir()?;
+    BitRepo::clone_blocking(clone.path(), actual.path().to_str().unwrap())?;
+    let expected_tree = BitRepo::find(local.path(), |repo| repo.head_tree())?;
+    BitRepo::find(clone.path(), |repo| {
+        assert_eq!(repo.head_tree()?, expected_tree);
+        Ok(())
+    })?;
+
+    // pushing again should have nothing to do
+    let summary = push(local.path(), "actual", &refspecs)?;
+    assert!(summary.updates.iter().all(|update| update.status == PushStatus::UpToDate));
+    Ok(())
+}
+
+#[test]
+fn test_push_writes_reflog() -> BitResult<()> {
+    let local = local_repo("ribble")?;
+    let remote = remote_repo(false)?;
+    add_remote(local.path(), "origin2", remote.path(), Some(BIT_RECEIVE_PACK))?;
+    push(local.path(), "origin2", &["master"])?;
+
+    let master = read_ref(local.path(), "refs/heads/master")?.unwrap();
+    BitRepo::find(remote.path(), |repo| {
+        let reflog = repo.refdb()?.read_reflog(SymbolicRef::MASTER)?;
+        assert_eq!(reflog.len(), 1);
+        assert_eq!(reflog[0].old_oid, Oid::UNKNOWN);
+        assert_eq!(reflog[0].new_oid, master);
+        assert_eq!(reflog[0].message, "update by push");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_push_new_branch_then_delete() -> BitResult<()> {
+    let local = local_repo("ribble")?;
+    let remote = remote_repo(true)?;
+    add_remote(local.path(), "origin2", remote.path(), Some(BIT_RECEIVE_PACK))?;
+
+    let master = read_ref(local.path(), "refs/heads/master")?;
+    assert!(push(local.path(), "origin2", &["master:feature"])?.is_ok());
+    assert_eq!(read_ref(remote.path(), "refs/heads/feature")?, master);
+
+    assert!(push(local.path(), "origin2", &[":feature"])?.is_ok());
+    assert_eq!(read_ref(remote.path(), "refs/heads/feature")?, None);
+    Ok(())
+}
+
+#[test]
+fn test_push_to_checked_out_branch_is_refused() -> BitResult<()> {
+    let local = local_repo("ribble")?;
+    let remote = remote_repo(true)?;
+    add_remote(local.path(), "origin2", remote.path(), Some(BIT_RECEIVE_PACK))?;
+
+    // without `--atomic`, the other ref is still updated
+    let summary = push(local.path(), "origin2", &["master", "new-branch"])?;
+    assert!(!summary.is_ok());
+    assert_eq!(
+        summary.updates[0].status,
+        PushStatus::RemoteRejected("branch is currently checked out".to_owned())
+    );
+    assert_eq!(summary.updates[1].status, PushStatus::Ok);
+    assert_eq!(read_ref(remote.path(), "refs/heads/master")?, None);
+    assert_eq!(
+        read_ref(remote.path(), "refs/heads/new-branch")?,
+        read_ref(local.path(), "refs/heads/new-branch")?
+    );
+    Ok(())
+}
+
+#[test]
+fn test_atomic_push_to_checked_out_branch_is_refused() -> BitResult<()> {
+    let local = local_repo("ribble")?;
+    let remote = remote_repo(true)?;
+    add_remote(local.path(), "origin2", remote.path(), Some(BIT_RECEIVE_PACK))?;
+
+    // the other ref is rejected too as the push is applied atomically
+    let opts = PushOpts { atomic: true, ..Default::default() };
+    let summary = push_with_opts(local.path(), "origin2", &["master", "new-branch"], opts)?;
+    assert!(!summary.is_ok());
+    assert_eq!(
+        summary.updates[0].status,
+        PushStatus::RemoteRejected("branch is currently checked out".to_owned())
+    );
+    assert_eq!(
+        summary.updates[1].status,
+        PushStatus::RemoteRejected("atomic push failure".to_owned())
+    );
+    assert!(read_refs(remote.path())?.values().all(Option::is_none));
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use libbit::error::BitResult;
+use libbit::obj::Oid;
+use libbit::pathspec::Pathspec;
+use libbit::refs::{BitRef, SymbolicRef};
+use libbit::remote::{FetchOpts, FetchStatus, DEFAULT_REMOTE};
+use libbit::repo::BitRepo;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tempfile::TempDir;
+
+const BIT_UPLOAD_PACK: &str = env!("CARGO_BIN_EXE_bit-upload-pack");
+
+/// Copy one of libbit's test repositories so it can be served by either upload-pack.
+/// The test repositories store their git directory as `.bit` which `git-upload-pack` doesn't understand.
+fn remote_repo(name: &str) -> BitResult<TempDir> {
+    let tmpdir = tempfile::tempdir()?;
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../libbit/tests/repos").join(name);
+    let options = fs_extra::dir::CopyOptions { content_only: true, ..Default::default() };
+    fs_extra::dir::copy(path, &tmpdir, &options)?;
+    std::fs::rename(tmpdir.path().join(".bit"), tmpdir.path().join(".git"))?;
+    Ok(tmpdir)
+}
+
+/// clones `remote` using the given upload-pack program (defaulting to `git-upload-pack`)
+fn clone_with(remote: &Path, upload_pack: Option<&str>) -> BitResult<TempDir> {
+    let local = tempfile::tempdir()?;
+    BitRepo::init_load(local.path(), |repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.to_str().unwrap())?;
+        if let Some(upload_pack) = upload_pack {
+            repo.with_raw_local_config(|config| {
+                config.set_subsection("remote", DEFAULT_REMOTE, "uploadpack", upload_pack)
+            })?;
+        }
+        repo.clone_origin_blocking(&FetchOpts::default())
+    })?;
+    Ok(local)
+}
+
+fn summarize(repo: &BitRepo) -> BitResult<(BitRef, Oid, BTreeMap<SymbolicRef, Option<Oid>>)> {
+    let refs = repo
+        .ls_refs()?
+        .into_iter()
+        .map(|sym| Ok((sym, repo.try_fully_resolve_ref(sym)?)))
+        .collect::<BitResult<_>>()?;
+    Ok((repo.read_head()?, repo.head_tree()?, refs))
+}
+
+#[test]
+fn test_clone_matches_git_upload_pack() -> BitResult<()> {
+    let remote = remote_repo("ribble")?;
+    let expected = clone_with(remote.path(), None)?;
+    let actual = clone_with(remote.path(), Some(BIT_UPLOAD_PACK))?;
+
+    let expected = BitRepo::find(expected.path(), |repo| summarize(&repo))?;
+    BitRepo::find(actual.path(), |repo| {
+        assert_eq!(summarize(&repo)?, expected);
+        assert!(repo.status(Pathspec::MATCH_ALL)?.is_empty());
+
+        // nothing has changed so there should be nothing to fetch
+        let summary = repo.fetch_blocking(DEFAULT_REMOTE)?;
+        assert_eq!(summary.status, FetchStatus::UpToDate);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_clone_empty_repo() -> BitResult<()> {
+    let remote = tempfile::tempdir()?;
+    BitRepo::init(remote.path())?;
+    clone_with(remote.path(), Some(BIT_UPLOAD_PACK))?;
+    Ok(())
+}
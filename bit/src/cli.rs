@@ -1,25 +1,38 @@
 mod cli_add;
 mod cli_bit_diff;
 mod cli_branch;
+mod cli_check_ignore;
 mod cli_checkout;
 mod cli_cherrypick;
 mod cli_clone;
 mod cli_commit;
+mod cli_commit_graph;
 mod cli_commit_tree;
 mod cli_config;
 mod cli_fetch;
+mod cli_fsck;
+mod cli_gc;
 mod cli_index_pack;
 mod cli_init;
 mod cli_log;
 mod cli_ls_files;
 mod cli_merge;
 mod cli_merge_base;
+mod cli_multi_pack_index;
+mod cli_pack_refs;
+mod cli_prune;
+mod cli_prune_packed;
+mod cli_push;
+mod cli_rebase;
 mod cli_reflog;
 mod cli_remote;
+mod cli_repack;
 mod cli_reset;
 mod cli_revlist;
+mod cli_stash;
 mod cli_status;
 mod cli_switch;
+mod cli_tag;
 mod cli_update_index;
 
 // notes
@@ -33,19 +46,30 @@ use clap::Parser;
 use cli_add::BitAddCliOpts;
 use cli_bit_diff::BitDiffCliOpts;
 use cli_branch::*;
+use cli_check_ignore::BitCheckIgnoreCliOpts;
 use cli_checkout::BitCheckoutCliOpts;
 use cli_commit::BitCommitCliOpts;
+use cli_commit_graph::BitCommitGraphCliOpts;
 use cli_commit_tree::BitCommitTreeCliOpts;
 use cli_config::BitConfigCliOpts;
 use cli_log::BitLogCliOpts;
 use cli_ls_files::BitLsFilesCliOpts;
 use cli_merge::BitMergeCliOpts;
 use cli_merge_base::BitMergeBaseCliOpts;
+use cli_multi_pack_index::BitMultiPackIndexCliOpts;
+use cli_pack_refs::BitPackRefsCliOpts;
+use cli_prune::BitPruneCliOpts;
+use cli_prune_packed::BitPrunePackedCliOpts;
+use cli_push::BitPushCliOpts;
+use cli_rebase::BitRebaseCliOpts;
 use cli_reflog::BitReflogCliOpts;
+use cli_repack::BitRepackCliOpts;
 use cli_reset::BitResetCliOpts;
 use cli_revlist::BitRevlistCliOpts;
+use cli_stash::BitStashCliOpts;
 use cli_status::BitStatusCliOpts;
 use cli_switch::BitSwitchCliOpts;
+use cli_tag::BitTagCliOpts;
 use cli_update_index::BitUpdateIndexCliOpts;
 use libbit::cmd::*;
 use libbit::error::BitResult;
@@ -58,6 +82,8 @@ use std::path::PathBuf;
 use self::cli_cherrypick::BitCherryPickCliOpts;
 use self::cli_clone::BitCloneCliOpts;
 use self::cli_fetch::BitFetchCliOpts;
+use self::cli_fsck::BitFsckCliOpts;
+use self::cli_gc::BitGcCliOpts;
 use self::cli_index_pack::BitIndexPackCliOpts;
 use self::cli_init::BitInitCliOpts;
 use self::cli_remote::BitRemoteCliOpts;
@@ -95,25 +121,38 @@ pub fn run<T: Into<OsString> + Clone>(args: impl IntoIterator<Item = T>) -> BitR
             },
         BitSubCmd::Branch(opts) => opts.exec(repo),
         BitSubCmd::CatFile(opts) => repo.bit_cat_file(opts.into()),
+        BitSubCmd::CheckIgnore(opts) => opts.exec(repo),
         BitSubCmd::Checkout(opts) => opts.exec(repo),
         BitSubCmd::CherryPick(opts) => opts.exec(repo),
         BitSubCmd::Config(opts) => opts.execute(repo),
         BitSubCmd::CommitTree(opts) =>
             repo.bit_commit_tree(opts.tree, opts.parents.into_iter().collect(), opts.message),
         BitSubCmd::Commit(opts) => opts.exec(repo),
+        BitSubCmd::CommitGraph(opts) => opts.exec(repo),
         BitSubCmd::Diff(opts) => opts.exec(repo),
         BitSubCmd::Fetch(opts) => opts.exec(repo),
+        BitSubCmd::Fsck(opts) => opts.exec(repo),
+        BitSubCmd::Gc(opts) => opts.exec(repo),
         BitSubCmd::HashObject(opts) => repo.bit_hash_object(opts.into()),
         BitSubCmd::Log(opts) => opts.exec(repo),
         BitSubCmd::LsFiles(opts) => repo.bit_ls_files(opts.into()),
         BitSubCmd::Merge(opts) => opts.exec(repo),
         BitSubCmd::MergeBase(opts) => opts.exec(repo),
+        BitSubCmd::MultiPackIndex(opts) => opts.exec(repo),
+        BitSubCmd::PackRefs(opts) => opts.exec(repo),
+        BitSubCmd::Prune(opts) => opts.exec(repo),
+        BitSubCmd::PrunePacked(opts) => opts.exec(repo),
+        BitSubCmd::Push(opts) => opts.exec(repo),
+        BitSubCmd::Rebase(opts) => opts.exec(repo),
         BitSubCmd::Reflog(opts) => opts.exec(repo),
         BitSubCmd::Remote(opts) => opts.exec(repo),
+        BitSubCmd::Repack(opts) => opts.exec(repo),
         BitSubCmd::Reset(opts) => opts.exec(repo),
         BitSubCmd::RevList(opts) => opts.exec(repo),
+        BitSubCmd::Stash(opts) => opts.exec(repo),
         BitSubCmd::Status(opts) => opts.exec(repo),
         BitSubCmd::Switch(opts) => opts.exec(repo),
+        BitSubCmd::Tag(opts) => opts.exec(repo),
         BitSubCmd::UpdateIndex(opts) => {
             dbg!(opts);
             todo!()
@@ -136,14 +175,18 @@ pub enum BitSubCmd {
     Add(BitAddCliOpts),
     Branch(BitBranchCliOpts),
     CatFile(BitCatFileCliOpts),
+    CheckIgnore(BitCheckIgnoreCliOpts),
     Checkout(BitCheckoutCliOpts),
     CherryPick(BitCherryPickCliOpts),
     Clone(BitCloneCliOpts),
     CommitTree(BitCommitTreeCliOpts),
     Config(BitConfigCliOpts),
     Commit(BitCommitCliOpts),
+    CommitGraph(BitCommitGraphCliOpts),
     Diff(BitDiffCliOpts),
     Fetch(BitFetchCliOpts),
+    Fsck(BitFsckCliOpts),
+    Gc(BitGcCliOpts),
     HashObject(BitHashObjectCliOpts),
     IndexPack(BitIndexPackCliOpts),
     Init(BitInitCliOpts),
@@ -151,12 +194,21 @@ pub enum BitSubCmd {
     LsFiles(BitLsFilesCliOpts),
     Merge(BitMergeCliOpts),
     MergeBase(BitMergeBaseCliOpts),
+    MultiPackIndex(BitMultiPackIndexCliOpts),
+    PackRefs(BitPackRefsCliOpts),
+    Prune(BitPruneCliOpts),
+    PrunePacked(BitPrunePackedCliOpts),
+    Push(BitPushCliOpts),
+    Rebase(BitRebaseCliOpts),
     Reflog(BitReflogCliOpts),
     Remote(BitRemoteCliOpts),
+    Repack(BitRepackCliOpts),
     Reset(BitResetCliOpts),
     RevList(BitRevlistCliOpts),
+    Stash(BitStashCliOpts),
     Status(BitStatusCliOpts),
     Switch(BitSwitchCliOpts),
+    Tag(BitTagCliOpts),
     UpdateIndex(BitUpdateIndexCliOpts),
     WriteTree,
 }
@@ -1,5 +1,6 @@
 use super::Cmd;
 use clap::Parser;
+use libbit::diff::RenameOpts;
 use libbit::error::BitResult;
 use libbit::pathspec::Pathspec;
 use libbit::repo::BitRepo;
@@ -7,12 +8,36 @@ use libbit::rev::Revspec;
 use libbit::xdiff::DiffFormatExt;
 use std::process::{Command, Stdio};
 
+fn parse_similarity(s: &str) -> Result<u8, String> {
+    libbit::diff::parse_similarity(s).map_err(|err| err.to_string())
+}
+
 #[derive(Parser, Debug, PartialEq)]
 pub struct BitDiffCliOpts {
     #[arg(long = "stat")]
     stat: bool,
     #[arg(long = "staged")]
     staged: bool,
+    /// detect renames, optionally with a similarity threshold (e.g. `-M50%`)
+    #[arg(
+        short = 'M',
+        long = "find-renames",
+        num_args = 0..=1,
+        default_missing_value = "50%",
+        value_parser = parse_similarity
+    )]
+    find_renames: Option<u8>,
+    /// detect copies as well as renames, optionally with a similarity threshold
+    #[arg(
+        short = 'C',
+        long = "find-copies",
+        num_args = 0..=1,
+        default_missing_value = "50%",
+        value_parser = parse_similarity
+    )]
+    find_copies: Option<u8>,
+    #[arg(long = "no-renames", conflicts_with_all(&["find_renames", "find_copies"]))]
+    no_renames: bool,
     #[arg(num_args=..=2)]
     revs: Vec<Revspec>,
     // pathspec: Option<Pathspec>,
@@ -22,7 +47,8 @@ impl Cmd for BitDiffCliOpts {
     fn exec(self, repo: BitRepo) -> BitResult<()> {
         // let pathspec = self.pathspec.unwrap_or(Pathspec::MATCH_ALL);
         let pathspec = Pathspec::MATCH_ALL;
-        let diff = match &self.revs[..] {
+        let rename_opts = self.rename_opts(&repo);
+        let mut diff = match &self.revs[..] {
             [] =>
                 if self.staged {
                     repo.diff_head_index(pathspec)?
@@ -49,6 +75,10 @@ impl Cmd for BitDiffCliOpts {
             _ => unreachable!(),
         };
 
+        if let Some(rename_opts) = rename_opts {
+            diff.detect_renames(&repo, rename_opts)?;
+        }
+
         if self.stat {
             diff.print_diffstat(&repo)?;
         } else {
@@ -60,6 +90,19 @@ impl Cmd for BitDiffCliOpts {
     }
 }
 
+impl BitDiffCliOpts {
+    fn rename_opts(&self, repo: &BitRepo) -> Option<RenameOpts> {
+        if self.no_renames {
+            return None;
+        }
+        match (self.find_renames, self.find_copies) {
+            (_, Some(threshold)) => Some(RenameOpts { threshold, find_copies: true }),
+            (Some(threshold), None) => Some(RenameOpts::with_threshold(threshold)),
+            (None, None) => repo.config().renames().then(RenameOpts::default),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,4 +128,23 @@ mod tests {
         assert!(!opts.staged);
         assert_eq!(opts.revs.len(), 2);
     }
+
+    #[test]
+    fn test_cli_parse_bit_diff_find_renames() {
+        let opts = BitDiffCliOpts::parse_from(["--", "-M"]);
+        assert_eq!(opts.find_renames, Some(50));
+
+        let opts = BitDiffCliOpts::parse_from(["--", "-M75%", "--stat"]);
+        assert_eq!(opts.find_renames, Some(75));
+        assert!(opts.stat);
+
+        let opts = BitDiffCliOpts::parse_from(["--", "-C9"]);
+        assert_eq!(opts.find_copies, Some(90));
+
+        let opts = BitDiffCliOpts::parse_from(["--", "--find-renames=05"]);
+        assert_eq!(opts.find_renames, Some(5));
+
+        assert!(BitDiffCliOpts::try_parse_from(["--", "-M150%"]).is_err());
+        assert!(BitDiffCliOpts::try_parse_from(["--", "-M", "--no-renames"]).is_err());
+    }
 }
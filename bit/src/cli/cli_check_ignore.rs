@@ -0,0 +1,45 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::path::BitPath;
+use libbit::repo::BitRepo;
+use std::path::PathBuf;
+
+// bit check-ignore [-v] [-n] <pathname>...
+#[derive(Parser, Debug)]
+pub struct BitCheckIgnoreCliOpts {
+    /// show the pattern that matched each path
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+    /// also show paths that don't match any pattern (only useful with `--verbose`)
+    #[arg(short = 'n', long = "non-matching", requires = "verbose")]
+    non_matching: bool,
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+}
+
+impl Cmd for BitCheckIgnoreCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        let paths = self.paths.iter().map(BitPath::intern).collect::<Vec<_>>();
+        for (path, m) in repo.check_ignore(&paths)? {
+            match m {
+                Some(m) if self.verbose => {
+                    let source = m.source.as_ref().map(|source| source.display().to_string());
+                    let line = m.line_number.map(|line| line.to_string());
+                    println!(
+                        "{}:{}:{}\t{}",
+                        source.unwrap_or_default(),
+                        line.unwrap_or_default(),
+                        m.pattern,
+                        path
+                    );
+                }
+                Some(m) if m.is_ignore() => println!("{path}"),
+                Some(..) => {}
+                None if self.non_matching => println!("::\t{path}"),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use super::Cmd;
+use crate::progress::stderr_progress;
 use clap::Parser;
 use libbit::checkout::{CheckoutOpts, CheckoutStrategy};
 use libbit::error::BitResult;
@@ -15,7 +16,7 @@ pub struct BitCheckoutCliOpts {
 
 impl Cmd for BitCheckoutCliOpts {
     fn exec(self, repo: BitRepo) -> BitResult<()> {
-        let mut opts = CheckoutOpts::default();
+        let mut opts = CheckoutOpts { progress: stderr_progress(), ..Default::default() };
         if self.force {
             opts.strategy = CheckoutStrategy::Force;
         }
@@ -1,7 +1,10 @@
+use crate::progress::stderr_progress;
 use clap::Parser;
 use git_url_parse::GitUrl;
 use libbit::error::BitResult;
+use libbit::remote::FetchOpts;
 use libbit::repo::BitRepo;
+use libbit::shallow::parse_shallow_since;
 use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
@@ -10,6 +13,20 @@ pub struct BitCloneCliOpts {
     url: String,
     /// The directory to clone into. If the directory exists it must be empty
     directory: Option<PathBuf>,
+    /// Create a shallow clone with the history truncated to this many commits
+    #[arg(long = "depth")]
+    depth: Option<u32>,
+    /// Create a shallow clone with the history after this date (seconds since the epoch or e.g.
+    /// `2.weeks.ago`)
+    #[arg(long = "shallow-since")]
+    shallow_since: Option<String>,
+    /// Create a shallow clone excluding the history reachable from this remote branch or tag
+    #[arg(long = "shallow-exclude")]
+    shallow_exclude: Vec<String>,
+    /// Create a partial clone omitting the objects that don't pass this filter (`blob:none`,
+    /// `blob:limit=<n>[kmg]` or `tree:<depth>`), which are fetched later when they are needed
+    #[arg(long = "filter")]
+    filter: Option<String>,
 }
 
 impl BitCloneCliOpts {
@@ -17,8 +34,16 @@ impl BitCloneCliOpts {
         let url = GitUrl::parse(&self.url)?;
         let directory =
             base_path.join(self.directory.as_deref().unwrap_or_else(|| Path::new(&url.name)));
+        let opts = FetchOpts {
+            depth: self.depth,
+            shallow_since: self.shallow_since.as_deref().map(parse_shallow_since).transpose()?,
+            shallow_exclude: self.shallow_exclude,
+            filter: self.filter.as_deref().map(str::parse).transpose()?,
+            progress: stderr_progress(),
+            ..Default::default()
+        };
 
         eprintln!("cloning into `{}`", directory.display());
-        BitRepo::clone_blocking(directory, &self.url)
+        BitRepo::clone_with_opts_blocking(directory, &self.url, opts)
     }
 }
@@ -0,0 +1,32 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::repo::BitRepo;
+
+// bit commit-graph (write | verify)
+#[derive(Parser, Debug)]
+pub struct BitCommitGraphCliOpts {
+    #[command(subcommand)]
+    subcmd: BitCommitGraphSubcommand,
+}
+
+#[derive(Parser, Debug)]
+pub enum BitCommitGraphSubcommand {
+    /// write a commit-graph containing every commit reachable from any ref
+    Write,
+    /// check the commit-graph is consistent with the commits it contains
+    Verify,
+}
+
+impl Cmd for BitCommitGraphCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        match self.subcmd {
+            BitCommitGraphSubcommand::Write => {
+                let graph = repo.write_commit_graph()?;
+                println!("wrote commit-graph with {} commits", graph.commits.len());
+            }
+            BitCommitGraphSubcommand::Verify => repo.verify_commit_graph()?,
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,29 @@
 use super::Cmd;
+use crate::progress::stderr_progress;
 use clap::Parser;
 use libbit::error::BitResult;
+use libbit::remote::FetchOpts;
 use libbit::repo::BitRepo;
+use libbit::shallow::parse_shallow_since;
 
 #[derive(Parser, Debug)]
 pub struct BitFetchCliOpts {
     remote: Option<String>,
+    /// Limit the history of each remote branch to this many commits (counted from the tip)
+    #[arg(long = "depth")]
+    depth: Option<u32>,
+    /// Deepen the history of a shallow repository by this many commits
+    #[arg(long = "deepen")]
+    deepen: Option<u32>,
+    /// Only fetch history after this date (seconds since the epoch or e.g. `2.weeks.ago`)
+    #[arg(long = "shallow-since")]
+    shallow_since: Option<String>,
+    /// Exclude the history reachable from this remote branch or tag
+    #[arg(long = "shallow-exclude")]
+    shallow_exclude: Vec<String>,
+    /// Fetch all the missing history of a shallow repository
+    #[arg(long = "unshallow")]
+    unshallow: bool,
 }
 
 impl Cmd for BitFetchCliOpts {
@@ -17,14 +35,23 @@ impl Cmd for BitFetchCliOpts {
 impl BitFetchCliOpts {
     #[tokio::main]
     async fn exec_async(self, repo: BitRepo) -> BitResult<()> {
+        let opts = FetchOpts {
+            depth: self.depth,
+            deepen: self.deepen,
+            shallow_since: self.shallow_since.as_deref().map(parse_shallow_since).transpose()?,
+            shallow_exclude: self.shallow_exclude,
+            unshallow: self.unshallow,
+            progress: stderr_progress(),
+            ..Default::default()
+        };
         match self.remote {
             Some(remote) => {
-                repo.fetch(&remote).await?;
+                repo.fetch_with_opts(&remote, &opts).await?;
             }
             None => {
                 // TODO run these using join concurrently
                 for remote in repo.ls_remotes() {
-                    repo.fetch(remote.name).await?;
+                    repo.fetch_with_opts(remote.name, &opts).await?;
                 }
             }
         };
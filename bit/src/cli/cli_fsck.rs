@@ -0,0 +1,49 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::repo::BitRepo;
+
+// bit fsck [--unreachable] [--no-dangling]
+#[derive(Parser, Debug)]
+pub struct BitFsckCliOpts {
+    /// print all unreachable objects (rather than just the dangling ones)
+    #[arg(long = "unreachable")]
+    unreachable: bool,
+    /// don't print dangling objects
+    #[arg(long = "no-dangling")]
+    no_dangling: bool,
+}
+
+impl Cmd for BitFsckCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        let report = repo.fsck()?;
+        for problem in &report.problems {
+            println!("{}", problem);
+        }
+        for missing in &report.missing {
+            println!("{}", missing);
+        }
+        if self.unreachable {
+            for (obj_type, oid) in &report.unreachable {
+                println!("unreachable {} {}", obj_type, oid);
+            }
+        } else if !self.no_dangling {
+            for (obj_type, oid) in &report.dangling {
+                println!("dangling {} {}", obj_type, oid);
+            }
+        }
+        ensure!(report.is_ok(), "repository is corrupt");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fsck_cli() {
+        let opts = BitFsckCliOpts::try_parse_from(["--", "--unreachable"]).unwrap();
+        assert!(opts.unreachable && !opts.no_dangling);
+    }
+}
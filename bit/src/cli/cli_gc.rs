@@ -0,0 +1,36 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::gc::GcOpts;
+use libbit::repo::BitRepo;
+
+// bit gc [--auto] [--prune <time>]
+#[derive(Parser, Debug)]
+pub struct BitGcCliOpts {
+    /// only run if there are enough loose objects or packs (see `gc.auto` and `gc.autoPackLimit`)
+    #[arg(long = "auto")]
+    auto: bool,
+    /// prune unreachable loose objects older than this (defaults to `gc.pruneExpire`)
+    #[arg(long = "prune")]
+    prune: Option<String>,
+}
+
+impl Cmd for BitGcCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        let prune_expire = self.prune.as_deref().map(str::parse).transpose()?;
+        repo.gc(GcOpts { auto: self.auto, prune_expire })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gc_cli() {
+        let opts = BitGcCliOpts::try_parse_from(["--", "--auto", "--prune", "now"]).unwrap();
+        assert!(opts.auto);
+        assert_eq!(opts.prune.as_deref(), Some("now"));
+    }
+}
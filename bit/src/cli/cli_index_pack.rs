@@ -1,3 +1,4 @@
+use crate::progress::stderr_progress;
 use clap::Parser;
 use libbit::error::BitResult;
 use libbit::pack::{IndexPackOpts, PackIndexer};
@@ -18,7 +19,8 @@ pub struct BitIndexPackCliOpts {
 impl BitIndexPackCliOpts {
     pub fn exec(self) -> BitResult<()> {
         let Self { path, index_file, verbose } = self;
-        let opts = IndexPackOpts { index_file_path: index_file, verbose };
+        let progress = stderr_progress();
+        let opts = IndexPackOpts { index_file_path: index_file, verbose, progress };
         PackIndexer::write_pack_index(path, opts)?;
         Ok(())
     }
@@ -0,0 +1,32 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::repo::BitRepo;
+
+// bit multi-pack-index (write | verify)
+#[derive(Parser, Debug)]
+pub struct BitMultiPackIndexCliOpts {
+    #[command(subcommand)]
+    subcmd: BitMultiPackIndexSubcommand,
+}
+
+#[derive(Parser, Debug)]
+pub enum BitMultiPackIndexSubcommand {
+    /// write a multi-pack-index covering all the packs in the repository
+    Write,
+    /// check the multi-pack-index is consistent with the packs it covers
+    Verify,
+}
+
+impl Cmd for BitMultiPackIndexCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        match self.subcmd {
+            BitMultiPackIndexSubcommand::Write => {
+                let midx = repo.write_multi_pack_index()?;
+                println!("indexed {} objects in {} packs", midx.oids.len(), midx.pack_names.len());
+            }
+            BitMultiPackIndexSubcommand::Verify => repo.verify_multi_pack_index()?,
+        }
+        Ok(())
+    }
+}
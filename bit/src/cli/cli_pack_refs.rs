@@ -0,0 +1,21 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::repo::BitRepo;
+
+// bit pack-refs [--all] [--no-prune]
+#[derive(Parser, Debug)]
+pub struct BitPackRefsCliOpts {
+    /// pack all refs (by default only tags and refs that are already packed are packed)
+    #[arg(long = "all")]
+    all: bool,
+    /// don't remove the loose refs after packing them
+    #[arg(long = "no-prune")]
+    no_prune: bool,
+}
+
+impl Cmd for BitPackRefsCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        repo.refdb()?.pack_refs(self.all, !self.no_prune)
+    }
+}
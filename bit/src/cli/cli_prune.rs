@@ -0,0 +1,21 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::repo::BitRepo;
+
+// bit prune [--expire <time>]
+#[derive(Parser, Debug)]
+pub struct BitPruneCliOpts {
+    /// only prune unreachable loose objects older than this (e.g. `2.weeks.ago` or `never`)
+    #[arg(long = "expire", default_value = "now")]
+    expire: String,
+}
+
+impl Cmd for BitPruneCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        for oid in repo.prune(self.expire.parse()?)? {
+            println!("{}", oid);
+        }
+        Ok(())
+    }
+}
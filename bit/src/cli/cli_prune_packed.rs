@@ -0,0 +1,16 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::repo::BitRepo;
+
+// bit prune-packed
+#[derive(Parser, Debug)]
+pub struct BitPrunePackedCliOpts {}
+
+impl Cmd for BitPrunePackedCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        let pruned = repo.prune_packed()?;
+        println!("removed {} loose objects", pruned);
+        Ok(())
+    }
+}
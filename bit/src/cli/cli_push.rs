@@ -0,0 +1,100 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::refs::SymbolicRefKind;
+use libbit::remote::{PushOpts, PushStatus, PushUpdate, Refspec, DEFAULT_REMOTE};
+use libbit::repo::BitRepo;
+
+fn parse_push_refspec(s: &str) -> Result<Refspec, String> {
+    Refspec::parse_push(s).map_err(|err| err.to_string())
+}
+
+// bit push [-f] [--force-with-lease] [--atomic] [<remote> [<refspec>...]]
+#[derive(Parser, Debug)]
+pub struct BitPushCliOpts {
+    /// allow updates that are not fast-forwards
+    #[arg(short = 'f', long = "force")]
+    force: bool,
+    /// allow updates that are not fast-forwards only if the remote ref is where our remote
+    /// tracking branch says it is
+    #[arg(long = "force-with-lease")]
+    force_with_lease: bool,
+    /// either update every ref on the remote or none of them
+    #[arg(long = "atomic")]
+    atomic: bool,
+    remote: Option<String>,
+    /// `[+]<src>[:<dst>]`, an empty `<src>` deletes `<dst>` from the remote
+    /// (defaults to the current branch)
+    #[arg(value_parser = parse_push_refspec)]
+    refspecs: Vec<Refspec>,
+}
+
+impl Cmd for BitPushCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        self.exec_async(repo)
+    }
+}
+
+impl BitPushCliOpts {
+    #[tokio::main]
+    async fn exec_async(self, repo: BitRepo) -> BitResult<()> {
+        let remote = repo.get_remote(self.remote.as_deref().unwrap_or(DEFAULT_REMOTE))?;
+        let opts = PushOpts {
+            force: self.force,
+            force_with_lease: self.force_with_lease,
+            atomic: self.atomic,
+        };
+        let summary = repo.push_remote(&remote, &self.refspecs, opts).await?;
+
+        if summary.updates.iter().all(|update| update.status == PushStatus::UpToDate) {
+            eprintln!("Everything up-to-date");
+            return Ok(());
+        }
+
+        eprintln!("To {}", remote.url);
+        for update in &summary.updates {
+            if update.status != PushStatus::UpToDate {
+                eprintln!("{}", fmt_update(update));
+            }
+        }
+
+        if !summary.is_ok() {
+            bail!("failed to push some refs to `{}`", remote.url)
+        }
+        Ok(())
+    }
+}
+
+// formatted similarly to git
+fn fmt_update(update: &PushUpdate) -> String {
+    let refs = match update.src {
+        Some(src) => format!("{} -> {}", src.short(), update.dst.short()),
+        None => update.dst.short().to_owned(),
+    };
+    match &update.status {
+        PushStatus::Ok if update.new.is_unknown() => format!(" - {:<17} {}", "[deleted]", refs),
+        PushStatus::Ok if update.old.is_unknown() => {
+            let kind = match update.dst.kind() {
+                SymbolicRefKind::Tag => "[new tag]",
+                _ => "[new branch]",
+            };
+            format!(" * {:<17} {}", kind, refs)
+        }
+        PushStatus::Ok if update.forced => {
+            let range = format!("{}...{}", update.old.short(), update.new.short());
+            format!(" + {:<17} {} (forced update)", range, refs)
+        }
+        PushStatus::Ok => {
+            let range = format!("{}..{}", update.old.short(), update.new.short());
+            format!("   {:<17} {}", range, refs)
+        }
+        PushStatus::UpToDate => format!(" = {:<17} {}", "[up to date]", refs),
+        PushStatus::RejectedNonFastForward =>
+            format!(" ! {:<17} {} (non-fast-forward)", "[rejected]", refs),
+        PushStatus::RejectedStale => format!(" ! {:<17} {} (stale info)", "[rejected]", refs),
+        PushStatus::RemoteRejected(reason) =>
+            format!(" ! {:<17} {} ({})", "[remote rejected]", refs, reason),
+        PushStatus::AtomicPushFailed =>
+            format!(" ! {:<17} {} (atomic push failed)", "[rejected]", refs),
+    }
+}
@@ -0,0 +1,66 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::rebase::{RebaseOpts, RebaseResults};
+use libbit::repo::BitRepo;
+use libbit::rev::Revspec;
+
+// bit rebase [-i] [--onto <newbase>] <upstream>
+// bit rebase (--continue | --skip | --abort)
+#[derive(Parser, Debug)]
+pub struct BitRebaseCliOpts {
+    #[arg(required_unless_present_any(&["cont", "skip", "abort"]))]
+    upstream: Option<Revspec>,
+    /// replay the commits onto `newbase` instead of `upstream`
+    #[arg(long = "onto")]
+    onto: Option<Revspec>,
+    /// edit the list of commits to be rebased before starting
+    #[arg(short = 'i', long = "interactive")]
+    interactive: bool,
+    #[arg(long = "continue", conflicts_with_all(&["skip", "abort", "upstream"]))]
+    cont: bool,
+    #[arg(long = "skip", conflicts_with_all(&["abort", "upstream"]))]
+    skip: bool,
+    #[arg(long = "abort", conflicts_with("upstream"))]
+    abort: bool,
+}
+
+impl Cmd for BitRebaseCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        let results = if self.cont {
+            repo.rebase_continue()?
+        } else if self.skip {
+            repo.rebase_skip()?
+        } else if self.abort {
+            return repo.rebase_abort();
+        } else {
+            let upstream = repo.fully_resolve_rev(self.upstream.as_ref().unwrap())?;
+            let onto = match &self.onto {
+                Some(onto) => Some(repo.fully_resolve_rev(onto)?),
+                None => None,
+            };
+            repo.rebase(upstream, RebaseOpts { onto, interactive: self.interactive })?
+        };
+
+        match results {
+            RebaseResults::UpToDate => println!("Current branch is up to date."),
+            RebaseResults::Done => println!("Successfully rebased and updated HEAD."),
+            RebaseResults::Conflicts { commit, conflicts } => {
+                for conflict in conflicts {
+                    println!("CONFLICT {}", conflict);
+                }
+                println!("Could not apply {}", commit.short());
+                println!("Resolve all conflicts manually, mark them as resolved with `bit add`,");
+                println!("then run `bit rebase --continue`.");
+                println!("You can instead skip this commit with `bit rebase --skip`.");
+                println!("To abort and get back to the original branch, run `bit rebase --abort`.");
+            }
+            RebaseResults::Edit { commit } => {
+                println!("Stopped at {}", commit.short());
+                println!("You can amend the commit now by staging the changes,");
+                println!("then run `bit rebase --continue` once you are satisfied.");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::gc::RepackOpts;
+use libbit::repo::BitRepo;
+
+// bit repack [-a|-A] [-d] [-b]
+#[derive(Parser, Debug)]
+pub struct BitRepackCliOpts {
+    /// pack everything reachable into a single pack (by default only loose objects are packed)
+    #[arg(short = 'a')]
+    all: bool,
+    /// like `-a`, but with `-d` the unreachable objects in the deleted packs are written out as
+    /// loose objects (so `prune` decides when they go)
+    #[arg(short = 'A')]
+    all_unpack_unreachable: bool,
+    /// delete the packs and loose objects made redundant by the new pack
+    #[arg(short = 'd')]
+    delete: bool,
+    /// write a reachability bitmap index for the new pack (only with `-a`)
+    #[arg(short = 'b', long = "write-bitmap-index")]
+    bitmaps: bool,
+}
+
+impl Cmd for BitRepackCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        let all = self.all || self.all_unpack_unreachable;
+        let bitmaps = all && (self.bitmaps || repo.config().write_bitmaps());
+        let opts = RepackOpts {
+            all,
+            delete: self.delete,
+            bitmaps,
+            unpack_unreachable: self.all_unpack_unreachable,
+        };
+        match repo.repack(opts)? {
+            Some(index) => println!("pack-{}: {} objects", index.pack_hash, index.oids.len()),
+            None => println!("nothing new to pack"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_repack_cli() {
+        let opts = BitRepackCliOpts::try_parse_from(["--", "-a", "-d"]).unwrap();
+        assert!(opts.all && opts.delete && !opts.bitmaps);
+        let opts = BitRepackCliOpts::try_parse_from(["--", "-A", "-d"]).unwrap();
+        assert!(opts.all_unpack_unreachable && opts.delete && !opts.all);
+    }
+}
@@ -8,12 +8,41 @@ use libbit::rev::Revspec;
 
 #[derive(Parser, Debug)]
 pub struct BitRevlistCliOpts {
+    /// Print the number of commits (or objects with `--objects`) instead of listing them
+    #[arg(long)]
+    count: bool,
+    /// List every object reachable from the revisions (not just commits)
+    #[arg(long)]
+    objects: bool,
     #[arg(required = true)]
     revisions: Vec<Revspec>,
 }
 
 impl Cmd for BitRevlistCliOpts {
     fn exec(self, repo: BitRepo) -> BitResult<()> {
+        if self.count || self.objects {
+            let oids = self
+                .revisions
+                .iter()
+                .map(|rev| repo.fully_resolve_rev(rev))
+                .collect::<BitResult<Vec<_>>>()?;
+            // these use the reachability bitmaps if there are any
+            if self.count && self.objects {
+                println!("{}", repo.count_reachable_objects(&oids, &[])?);
+            } else if self.count {
+                println!("{}", repo.count_reachable_commits(&oids, &[])?);
+            } else {
+                for (oid, path) in repo.reachable_objects(&oids, &[])? {
+                    if path.is_empty() {
+                        println!("{}", oid);
+                    } else {
+                        println!("{} {}", oid, path);
+                    }
+                }
+            }
+            return Ok(());
+        }
+
         let revisions = self.revisions.iter().collect::<Vec<_>>();
         let revwalk = repo.revwalk(&revisions)?;
         revwalk.for_each(|commit| {
@@ -37,4 +66,12 @@ mod tests {
         let opts = BitRevlistCliOpts::try_parse_from(["--", "HEAD", "master", "branch"]).unwrap();
         assert_eq!(opts.revisions.len(), 3);
     }
+
+    #[test]
+    fn test_parse_revlist_count_objects() {
+        let opts =
+            BitRevlistCliOpts::try_parse_from(["--", "--count", "--objects", "HEAD"]).unwrap();
+        assert!(opts.count && opts.objects);
+        assert_eq!(opts.revisions.len(), 1);
+    }
 }
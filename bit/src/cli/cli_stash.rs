@@ -0,0 +1,126 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::merge::MergeResults;
+use libbit::repo::BitRepo;
+use libbit::stash::StashPushOpts;
+use libbit::xdiff::DiffFormatExt;
+use std::process::{Command, Stdio};
+
+/// accepts either `stash@{<n>}` or just `<n>`
+fn parse_stash(s: &str) -> Result<usize, String> {
+    let n = s.strip_prefix("stash@{").and_then(|s| s.strip_suffix('}')).unwrap_or(s);
+    n.parse().map_err(|_| format!("`{}` is not a valid stash reference", s))
+}
+
+// bit stash [push [-m <message>] [-u]]
+// bit stash (pop | apply | drop | show [-p]) [<stash>]
+// bit stash list
+#[derive(Parser, Debug)]
+pub struct BitStashCliOpts {
+    #[command(subcommand)]
+    subcmd: Option<BitStashSubcommand>,
+}
+
+#[derive(Parser, Debug)]
+pub enum BitStashSubcommand {
+    Push(BitStashPushOpts),
+    Pop(BitStashTargetOpts),
+    Apply(BitStashTargetOpts),
+    List,
+    Drop(BitStashTargetOpts),
+    Show(BitStashShowOpts),
+}
+
+#[derive(Parser, Default, Debug)]
+pub struct BitStashPushOpts {
+    #[arg(short = 'm', long = "message")]
+    message: Option<String>,
+    /// also stash untracked files
+    #[arg(short = 'u', long = "include-untracked")]
+    include_untracked: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct BitStashTargetOpts {
+    #[arg(default_value = "0", value_parser = parse_stash)]
+    stash: usize,
+}
+
+#[derive(Parser, Debug)]
+pub struct BitStashShowOpts {
+    /// show the changes as a patch rather than a diffstat
+    #[arg(short = 'p', long = "patch")]
+    patch: bool,
+    #[arg(default_value = "0", value_parser = parse_stash)]
+    stash: usize,
+}
+
+impl Cmd for BitStashCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        match self.subcmd {
+            Some(subcmd) => match subcmd {
+                BitStashSubcommand::Push(opts) => opts.exec(repo),
+                BitStashSubcommand::Pop(opts) => {
+                    print_apply_results(repo.stash_pop(opts.stash)?);
+                    Ok(())
+                }
+                BitStashSubcommand::Apply(opts) => {
+                    print_apply_results(repo.stash_apply(opts.stash)?);
+                    Ok(())
+                }
+                BitStashSubcommand::List => {
+                    for entry in repo.stash_list()? {
+                        println!("stash@{{{}}}: {}", entry.index, entry.message);
+                    }
+                    Ok(())
+                }
+                BitStashSubcommand::Drop(opts) => {
+                    let oid = repo.stash_drop(opts.stash)?;
+                    println!("Dropped stash@{{{}}} ({})", opts.stash, oid);
+                    Ok(())
+                }
+                BitStashSubcommand::Show(opts) => opts.exec(repo),
+            },
+            None => BitStashPushOpts::default().exec(repo),
+        }
+    }
+}
+
+impl Cmd for BitStashPushOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        let opts =
+            StashPushOpts { message: self.message, include_untracked: self.include_untracked };
+        match repo.stash_push(opts)? {
+            Some(_) => {
+                let message = &repo.stash_list()?[0].message;
+                println!("Saved working directory and index state {}", message)
+            }
+            None => println!("No local changes to save"),
+        }
+        Ok(())
+    }
+}
+
+impl Cmd for BitStashShowOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        let diff = repo.stash_show(self.stash)?;
+        if self.patch {
+            let mut pager = Command::new(repo.config().pager()).stdin(Stdio::piped()).spawn()?;
+            diff.format_diff_into(&repo, pager.stdin.as_mut().unwrap())?;
+            pager.wait()?;
+        } else {
+            diff.print_diffstat(&repo)?;
+        }
+        Ok(())
+    }
+}
+
+fn print_apply_results(results: MergeResults) {
+    if let MergeResults::Conflicts(conflicts) = results {
+        for conflict in conflicts {
+            println!("CONFLICT {}", conflict);
+        }
+        println!("The stash entry is kept in case you need it again.");
+    }
+}
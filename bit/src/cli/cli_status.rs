@@ -7,12 +7,16 @@ use libbit::repo::BitRepo;
 #[derive(Parser, Debug)]
 pub struct BitStatusCliOpts {
     pathspec: Option<Pathspec>,
+    /// show ignored files
+    #[arg(long = "ignored")]
+    ignored: bool,
 }
 
 impl Cmd for BitStatusCliOpts {
     fn exec(self, repo: BitRepo) -> BitResult<()> {
         let pathspec = self.pathspec.unwrap_or(Pathspec::MATCH_ALL);
-        let status = repo.status(pathspec)?;
+        let status =
+            if self.ignored { repo.status_with_ignored(pathspec)? } else { repo.status(pathspec)? };
         Ok(println!("{status}"))
     }
 }
@@ -0,0 +1,66 @@
+use super::Cmd;
+use clap::Parser;
+use libbit::error::BitResult;
+use libbit::repo::BitRepo;
+use libbit::rev::Revspec;
+
+// bit tag [-l]
+// bit tag [-f] [-a] [-m <msg>] <tagname> [<commit>]
+// bit tag -d <tagname>
+#[derive(Parser, Debug)]
+pub struct BitTagCliOpts {
+    /// list tags
+    #[arg(short = 'l', long = "list", conflicts_with_all(&["delete", "annotate", "message"]))]
+    list: bool,
+    /// delete the tag
+    #[arg(short = 'd', long = "delete", conflicts_with_all(&["annotate", "message", "force"]))]
+    delete: bool,
+    /// create an annotated tag object
+    #[arg(short = 'a', long = "annotate")]
+    annotate: bool,
+    /// the tag message (implies `--annotate`)
+    #[arg(short = 'm', long = "message")]
+    message: Option<String>,
+    /// replace the tag if it already exists
+    #[arg(short = 'f', long = "force")]
+    force: bool,
+    #[arg(required_if_eq("delete", "true"))]
+    name: Option<String>,
+    #[arg(default_value = "HEAD")]
+    revision: Revspec,
+}
+
+impl Cmd for BitTagCliOpts {
+    fn exec(self, repo: BitRepo) -> BitResult<()> {
+        let name = match self.name {
+            Some(name) if !self.list => name,
+            _ => {
+                for tag in repo.bit_list_tags()? {
+                    println!("{}", tag.short());
+                }
+                return Ok(());
+            }
+        };
+
+        if self.delete {
+            let oid = repo.bit_delete_tag(&name)?;
+            println!("Deleted tag '{}' (was {})", name, oid.short());
+            return Ok(());
+        }
+
+        let message = match self.message {
+            // tag messages are expected to be newline terminated
+            Some(mut message) => {
+                if !message.ends_with('\n') {
+                    message.push('\n');
+                }
+                Some(message)
+            }
+            None if self.annotate => bail!("annotated tags require a message (use `-m <msg>`)"),
+            None => None,
+        };
+
+        repo.bit_create_tag(&name, &self.revision, message, self.force)?;
+        Ok(())
+    }
+}
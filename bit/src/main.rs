@@ -1,6 +1,7 @@
 #![feature(iter_intersperse)]
 
 mod cli;
+mod progress;
 mod util;
 
 #[cfg(test)]
@@ -14,12 +15,17 @@ extern crate pretty_assertions;
 #[macro_use]
 extern crate anyhow;
 
+use libbit::error::BitResultExt;
+
 pub fn main() -> ! {
     env_logger::builder().parse_env("BIT_LOG").init();
     if let Err(err) = cli::run(std::env::args_os()) {
         eprintln!("{err}");
-        let backtrace = err.backtrace();
-        println!("{backtrace}");
+        // the remote has already said what went wrong, our backtrace would only be noise
+        if !err.is_remote_err() {
+            let backtrace = err.backtrace();
+            println!("{backtrace}");
+        }
         std::process::exit(1)
     } else {
         std::process::exit(0)
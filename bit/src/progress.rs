@@ -0,0 +1,83 @@
+use libbit::progress::{Progress, ProgressPhase, ProgressSink, ProgressUpdate};
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// redrawing on every update would be slower than whatever is being reported on
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Renders progress on stderr like git does, e.g. `Receiving objects: 1.20 MiB | 512.00 KiB/s`
+#[derive(Default)]
+struct TerminalProgress {
+    last_drawn: Mutex<Option<Instant>>,
+}
+
+/// Reports progress on stderr unless it isn't a terminal (e.g. it's being redirected to a file)
+pub fn stderr_progress() -> ProgressSink {
+    if std::io::stderr().is_terminal() {
+        ProgressSink::new(TerminalProgress::default())
+    } else {
+        ProgressSink::default()
+    }
+}
+
+impl Progress for TerminalProgress {
+    fn update(&self, update: &ProgressUpdate) {
+        let mut last_drawn = self.last_drawn.lock().unwrap();
+        if last_drawn.map_or(false, |last_drawn| last_drawn.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        *last_drawn = Some(Instant::now());
+        // `\x1b[K` clears whatever is left of the previous line
+        eprint!("\r{}\x1b[K", render(update));
+    }
+
+    fn finish(&self, update: &ProgressUpdate) {
+        // there's nothing worth reporting if nothing was done
+        if update.done == 0 && update.bytes == 0 {
+            return;
+        }
+        *self.last_drawn.lock().unwrap() = None;
+        eprintln!("\r{}, done.\x1b[K", render(update));
+    }
+
+    fn remote_message(&self, message: &str) {
+        // lines that end with `\r` are overwritten by the next one
+        for line in message.split_inclusive(['\r', '\n']) {
+            eprint!("remote: {}", line);
+        }
+    }
+}
+
+fn render(update: &ProgressUpdate) -> String {
+    let mut rendered = format!("{}: ", update.phase);
+    match (update.percentage(), update.total) {
+        (Some(percentage), Some(total)) =>
+            rendered.push_str(&format!("{:3}% ({}/{})", percentage, update.done, total)),
+        // the objects being received are only counted when the pack is indexed
+        _ if update.phase == ProgressPhase::Receiving => {}
+        _ => rendered.push_str(&update.done.to_string()),
+    }
+    if update.bytes > 0 {
+        if update.phase != ProgressPhase::Receiving {
+            rendered.push_str(", ");
+        }
+        let throughput = human_bytes(update.throughput());
+        rendered.push_str(&format!("{} | {}/s", human_bytes(update.bytes as f64), throughput));
+    }
+    rendered
+}
+
+fn human_bytes(mut bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024.0 {
+        return format!("{} bytes", bytes as u64);
+    }
+    let mut unit = 0;
+    bytes /= 1024.0;
+    while bytes >= 1024.0 && unit + 1 < UNITS.len() {
+        bytes /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", bytes, UNITS[unit])
+}
@@ -1,8 +1,17 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use fallible_iterator::FallibleIterator;
 use libbit::index::BitIndexInner;
+use libbit::pack::PackIndex;
+use libbit::pathspec::Pathspec;
 use libbit::repo::BitRepo;
+use libbit::rev::Revspec;
 use libbit::serialize::Deserialize;
+use rayon::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+
+// the HEAD commit of the history contained in `tests/files/pack.pack` (~500 commits)
+const PACKED_HEAD: &str = "1806658f16f76480a3f40461db577a02d1e01591";
 
 pub fn bench_index_tree_iter(c: &mut Criterion) {
     let bytes = include_bytes!("../tests/files/lg2index") as &[u8];
@@ -29,5 +38,73 @@ pub fn bench_write_tree(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, bench_write_tree, bench_index_tree_iter);
+/// Creates a repository where every object lives in a single pack, with `HEAD` checked out
+fn packed_repo() -> tempfile::TempDir {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path();
+    BitRepo::init(path).unwrap();
+    let pack_dir = path.join(".git/objects/pack");
+    std::fs::create_dir_all(&pack_dir).unwrap();
+    std::fs::copy(test_files_dir!("pack.pack"), pack_dir.join("pack.pack")).unwrap();
+    std::fs::copy(test_files_dir!("pack.idx"), pack_dir.join("pack.idx")).unwrap();
+    std::fs::write(path.join(".git/refs/heads/master"), format!("{}\n", PACKED_HEAD)).unwrap();
+    BitRepo::find(path, |repo| repo.force_checkout_tree(repo.fully_resolve_head()?)).unwrap();
+    tmp
+}
+
+fn packed_oids() -> Vec<libbit::obj::Oid> {
+    let bytes = include_bytes!("../tests/files/pack.idx") as &[u8];
+    PackIndex::deserialize(BufReader::new(bytes)).unwrap().oids
+}
+
+// each iteration opens the repository again so the object cache starts off cold
+fn in_repo<R>(path: &Path, f: impl FnOnce(BitRepo) -> libbit::error::BitResult<R>) -> R {
+    BitRepo::find(path, f).unwrap()
+}
+
+pub fn bench_packed_log(c: &mut Criterion) {
+    let tmp = packed_repo();
+    let head = "HEAD".parse::<Revspec>().unwrap();
+    c.bench_function("packed_log", |b| {
+        b.iter(|| in_repo(tmp.path(), |repo| repo.revwalk(&[&head])?.count()))
+    });
+}
+
+pub fn bench_packed_status(c: &mut Criterion) {
+    let tmp = packed_repo();
+    c.bench_function("packed_status", |b| {
+        b.iter(|| in_repo(tmp.path(), |repo| repo.status(Pathspec::MATCH_ALL)))
+    });
+}
+
+pub fn bench_packed_read_all_objects(c: &mut Criterion) {
+    let tmp = packed_repo();
+    let oids = packed_oids();
+    let mut group = c.benchmark_group("packed_read_all_objects");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+            in_repo(tmp.path(), |repo| {
+                oids.iter().try_for_each(|&oid| repo.read_obj(oid).map(drop))
+            })
+        })
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| {
+            in_repo(tmp.path(), |repo| {
+                oids.par_iter().try_for_each(|&oid| repo.read_obj(oid).map(drop))
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_write_tree,
+    bench_index_tree_iter,
+    bench_packed_log,
+    bench_packed_status,
+    bench_packed_read_all_objects
+);
 criterion_main!(benches);
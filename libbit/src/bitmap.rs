@@ -0,0 +1,625 @@
+mod ewah;
+
+use crate::chunk_format::verify_checksum;
+use crate::commit_graph::CommitInfoReader;
+use crate::error::BitResult;
+use crate::hash::{OID_SIZE, SHA1Hash};
+use crate::io::{HashWriter, ReadExt, WriteExt};
+use crate::obj::{BitObjType, Oid};
+use crate::pack::{PACK_EXT, PACK_IDX_EXT, PackIndexReader};
+use crate::path::BitPath;
+use crate::repo::BitRepo;
+use crate::serialize::Serialize;
+use filebuffer::FileBuffer;
+use indexmap::IndexMap;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::io::{BufWriter, Read, Write};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+// The format is documented at https://git-scm.com/docs/bitmap-format
+// A `.bitmap` file sits alongside a pack and stores, for some of the commits in the pack, the set
+// of objects reachable from that commit as a bitmap where the `i`th bit represents the `i`th object
+// of the pack (in the order the objects appear in the pack, not the order of the index).
+// Every object reachable from a bitmapped commit must be in the pack.
+// Enumerating objects then only requires walking history until a bitmapped commit is found.
+
+pub const BITMAP_EXT: &str = "bitmap";
+const BITMAP_SIGNATURE: &[u8; 4] = b"BITM";
+const BITMAP_VERSION: u16 = 1;
+/// the pack is closed under reachability (the only kind of bitmap git writes or reads)
+const BITMAP_OPT_FULL_DAG: u16 = 0x1;
+/// the file ends with the name hash of every object (in index order) so packs built from the
+/// bitmaps can still sort objects by name when looking for deltas
+const BITMAP_OPT_HASH_CACHE: u16 = 0x4;
+/// roughly one in this many commits gets a bitmap (along with the tip of every ref)
+const BITMAP_COMMIT_INTERVAL: usize = 100;
+
+/// The order of the type bitmaps in the file
+const BITMAP_TYPES: [BitObjType; 4] =
+    [BitObjType::Commit, BitObjType::Tree, BitObjType::Blob, BitObjType::Tag];
+
+/// An uncompressed set of bits (see `ewah` for the compressed form that is stored on disk)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bitmap {
+    /// there are never any trailing zero words
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn from_words(mut words: Vec<u64>) -> Self {
+        while words.last() == Some(&0) {
+            words.pop();
+        }
+        Self { words }
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.words.get(i / 64).map_or(false, |word| word & 1 << (i % 64) != 0)
+    }
+
+    pub fn set(&mut self, i: usize) {
+        if i / 64 >= self.words.len() {
+            self.words.resize(i / 64 + 1, 0);
+        }
+        self.words[i / 64] |= 1 << (i % 64);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// the index of the highest set bit
+    pub fn last(&self) -> Option<usize> {
+        let word = self.words.last()?;
+        Some(self.words.len() * 64 - 1 - word.leading_zeros() as usize)
+    }
+
+    pub fn union_with(&mut self, other: &Bitmap) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    pub fn symmetric_difference_with(&mut self, other: &Bitmap) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word ^= other;
+        }
+        *self = Self::from_words(std::mem::take(&mut self.words));
+    }
+
+    pub fn intersection(&self, other: &Bitmap) -> Bitmap {
+        Self::from_words(self.words.iter().zip(&other.words).map(|(a, b)| a & b).collect())
+    }
+
+    pub fn difference(&self, other: &Bitmap) -> Bitmap {
+        let words = self.words.iter().enumerate();
+        Self::from_words(words.map(|(i, word)| word & !other.words.get(i).unwrap_or(&0)).collect())
+    }
+
+    /// the indices of the set bits in increasing order
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &word)| {
+            (0..64).filter(move |bit| word & 1 << bit != 0).map(move |bit| i * 64 + bit)
+        })
+    }
+}
+
+/// The reachability bitmaps of a pack
+#[derive(Debug, PartialEq)]
+pub struct PackBitmapIndex {
+    pack_hash: SHA1Hash,
+    /// the objects of the pack in pack order (i.e. sorted by offset)
+    objects: Vec<Oid>,
+    /// the position of each object in `objects` (which is the bit that represents it)
+    positions: FxHashMap<Oid, usize>,
+    /// the objects of each type (in the order of `BITMAP_TYPES`)
+    types: [Bitmap; 4],
+    /// the objects reachable from each bitmapped commit
+    bitmaps: FxHashMap<Oid, Bitmap>,
+    /// the hash of the path each object was packed with (empty if the file has no hash cache)
+    name_hashes: FxHashMap<Oid, u32>,
+}
+
+impl PackBitmapIndex {
+    /// An empty bitmap index for the pack with index `index`
+    fn new<B: Deref<Target = [u8]>>(index: &PackIndexReader<B>) -> BitResult<Self> {
+        let mut objects = (0..index.len())
+            .map(|i| Ok((index.offset_at(i)?, index.read_oid_at(i)?)))
+            .collect::<BitResult<Vec<_>>>()?;
+        objects.sort_by_key(|&(offset, _)| offset);
+        let objects = objects.into_iter().map(|(_, oid)| oid).collect::<Vec<_>>();
+        let positions = objects.iter().enumerate().map(|(i, &oid)| (oid, i)).collect();
+        Ok(Self {
+            pack_hash: index.pack_hash()?,
+            objects,
+            positions,
+            types: Default::default(),
+            bitmaps: Default::default(),
+            name_hashes: Default::default(),
+        })
+    }
+
+    /// Reads the bitmap index (`bytes`) of the pack with index `index`
+    pub fn read<B: Deref<Target = [u8]>>(
+        index: &PackIndexReader<B>,
+        bytes: &[u8],
+    ) -> BitResult<Self> {
+        verify_checksum("bitmap index", bytes)?;
+        let mut reader = &bytes[..bytes.len() - OID_SIZE];
+
+        let mut signature = [0; 4];
+        reader.read_exact(&mut signature)?;
+        ensure!(&signature == BITMAP_SIGNATURE, "invalid bitmap index signature");
+        let version = reader.read_u16()?;
+        ensure!(version == BITMAP_VERSION, "unsupported bitmap index version `{}`", version);
+        let flags = reader.read_u16()?;
+        ensure!(flags & BITMAP_OPT_FULL_DAG != 0, "bitmap index is not for a full pack");
+        let entryc = reader.read_u32()?;
+        let pack_hash = reader.read_oid()?;
+        ensure!(pack_hash == index.pack_hash()?, "bitmap index does not belong to the pack");
+
+        let mut this = Self::new(index)?;
+        let mut types = <[Bitmap; 4]>::default();
+        for bitmap in &mut types {
+            *bitmap = this.read_bitmap(&mut reader)?;
+        }
+        this.types = types;
+
+        // each entry is optionally xor'ed with the bitmap of an earlier entry
+        let mut entries = Vec::<(Oid, Bitmap)>::new();
+        for _ in 0..entryc {
+            let position = reader.read_u32()? as u64;
+            let xor_offset = reader.read_u8()? as usize;
+            let _flags = reader.read_u8()?;
+            let mut bitmap = this.read_bitmap(&mut reader)?;
+
+            ensure!(position < index.len(), "bitmap index entry is out of bounds");
+            let oid = index.read_oid_at(position)?;
+            if xor_offset > 0 {
+                ensure!(xor_offset <= entries.len(), "bitmap index has an invalid xor offset");
+                bitmap.symmetric_difference_with(&entries[entries.len() - xor_offset].1);
+            }
+            entries.push((oid, bitmap));
+        }
+        this.bitmaps = entries.into_iter().collect();
+
+        if flags & BITMAP_OPT_HASH_CACHE != 0 {
+            for i in 0..index.len() {
+                this.name_hashes.insert(index.read_oid_at(i)?, reader.read_u32()?);
+            }
+        }
+        // the optional lookup table is not used
+        Ok(this)
+    }
+
+    fn read_bitmap(&self, reader: &mut impl Read) -> BitResult<Bitmap> {
+        let bitmap = Bitmap::read_ewah(reader)?;
+        ensure!(
+            bitmap.last().map_or(true, |last| last < self.objects.len()),
+            "bitmap index has a bitmap with more bits than there are objects"
+        );
+        Ok(bitmap)
+    }
+
+    /// Builds bitmaps for the pack with index `index` (whose objects must be readable from `repo`).
+    /// `name_hashes` are the hashes of the paths the objects were packed with (missing ones are 0).
+    pub fn build<B: Deref<Target = [u8]>>(
+        repo: &BitRepo,
+        index: &PackIndexReader<B>,
+        name_hashes: &FxHashMap<Oid, u32>,
+    ) -> BitResult<Self> {
+        let mut this = Self::new(index)?;
+        this.name_hashes = this
+            .objects
+            .iter()
+            .map(|oid| (*oid, name_hashes.get(oid).copied().unwrap_or(0)))
+            .collect();
+        let mut commits = vec![];
+        for (i, &oid) in this.objects.iter().enumerate() {
+            let obj_type = repo.read_obj_header(oid)?.obj_type;
+            this.types[type_index(obj_type)].set(i);
+            if obj_type == BitObjType::Commit {
+                commits.push(oid);
+            }
+        }
+
+        let reader = CommitInfoReader::new(repo.clone())?;
+        for oid in Self::select_commits(repo, &reader, commits)? {
+            let mut walk = BitmapWalk::new(repo, &reader, &this, None);
+            walk.add_commit(oid)?;
+            // only commits whose history is entirely within the pack can have a bitmap
+            if walk.found.extra.is_empty() {
+                let bitmap = walk.found.bitmap;
+                this.bitmaps.insert(oid, bitmap);
+            }
+        }
+        Ok(this)
+    }
+
+    /// Chooses the commits to build bitmaps for (oldest first, so the bitmaps of the newer commits
+    /// can be built from the older ones).
+    fn select_commits(
+        repo: &BitRepo,
+        reader: &CommitInfoReader,
+        commits: Vec<Oid>,
+    ) -> BitResult<Vec<Oid>> {
+        let mut tips = FxHashSet::default();
+        let mut pending = vec![];
+        pending.extend(repo.try_fully_resolve_ref(repo.read_head()?)?);
+        for r in repo.ls_refs()? {
+            pending.extend(repo.try_fully_resolve_ref(r)?);
+        }
+        for mut oid in pending {
+            while repo.read_obj_header(oid)?.obj_type == BitObjType::Tag {
+                oid = repo.read_obj(oid)?.try_into_tag()?.object;
+            }
+            tips.insert(oid);
+        }
+
+        let mut infos =
+            commits.into_iter().map(|oid| reader.read(oid)).collect::<BitResult<Vec<_>>>()?;
+        infos.sort_by_key(|info| (info.generation, info.commit_time));
+        let n = infos.len();
+        Ok(infos
+            .into_iter()
+            .enumerate()
+            .filter(|(i, info)| {
+                tips.contains(&info.oid) || (n - 1 - i) % BITMAP_COMMIT_INTERVAL == 0
+            })
+            .map(|(_, info)| info.oid)
+            .collect())
+    }
+
+    pub fn pack_hash(&self) -> SHA1Hash {
+        self.pack_hash
+    }
+
+    /// the number of commits that have a bitmap
+    pub fn len(&self) -> usize {
+        self.bitmaps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bitmaps.is_empty()
+    }
+
+    pub fn bitmap(&self, oid: Oid) -> Option<&Bitmap> {
+        self.bitmaps.get(&oid)
+    }
+
+    /// the hash of the path `oid` was packed with (if the pack has a name-hash cache)
+    pub fn name_hash(&self, oid: Oid) -> Option<u32> {
+        self.name_hashes.get(&oid).copied()
+    }
+
+    fn position(&self, oid: Oid) -> Option<usize> {
+        self.positions.get(&oid).copied()
+    }
+}
+
+fn type_index(obj_type: BitObjType) -> usize {
+    BITMAP_TYPES.iter().position(|&ty| ty == obj_type).unwrap()
+}
+
+impl Serialize for PackBitmapIndex {
+    fn serialize(&self, writer: &mut dyn Write) -> BitResult<()> {
+        let mut writer = BufWriter::new(HashWriter::new_sha1(writer));
+        writer.write_all(BITMAP_SIGNATURE)?;
+        writer.write_u16(BITMAP_VERSION)?;
+        let mut flags = BITMAP_OPT_FULL_DAG;
+        if !self.name_hashes.is_empty() {
+            flags |= BITMAP_OPT_HASH_CACHE;
+        }
+        writer.write_u16(flags)?;
+        writer.write_u32(self.bitmaps.len().try_into()?)?;
+        writer.write_oid(self.pack_hash)?;
+        for bitmap in &self.types {
+            bitmap.write_ewah(&mut writer)?;
+        }
+
+        // entries refer to commits by their position in the pack index (i.e. in sorted order)
+        let mut sorted = self.objects.clone();
+        sorted.sort();
+        let mut entries = self
+            .bitmaps
+            .iter()
+            .map(|(oid, bitmap)| (sorted.binary_search(oid).unwrap(), bitmap))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|&(position, _)| position);
+        for (position, bitmap) in entries {
+            writer.write_u32(position.try_into()?)?;
+            // the bitmaps are never xor'ed with each other and there are no flags
+            writer.write_u8(0)?;
+            writer.write_u8(0)?;
+            bitmap.write_ewah(&mut writer)?;
+        }
+
+        if !self.name_hashes.is_empty() {
+            for oid in &sorted {
+                writer.write_u32(self.name_hashes.get(oid).copied().unwrap_or(0))?;
+            }
+        }
+
+        match writer.into_inner() {
+            Ok(writer) => writer.write_hash()?,
+            Err(..) => bail!("hash writer flush failed while writing bitmap index"),
+        };
+        Ok(())
+    }
+}
+
+/// A set of objects: those in the bitmapped pack are represented by a bitmap and any others
+/// (e.g. loose objects written since the pack) are kept separately in the order they were found.
+#[derive(Debug, Default)]
+struct ObjectSet {
+    bitmap: Bitmap,
+    extra: IndexMap<Oid, BitObjType>,
+}
+
+/// Finds all the objects reachable from some tips, using the bitmap of any commit it comes across
+/// rather than walking its history.
+struct BitmapWalk<'a> {
+    repo: &'a BitRepo,
+    commits: &'a CommitInfoReader,
+    index: &'a PackBitmapIndex,
+    /// objects known to be excluded, the walk stops at these as everything reachable from them is
+    /// excluded too
+    haves: Option<&'a ObjectSet>,
+    found: ObjectSet,
+}
+
+impl<'a> BitmapWalk<'a> {
+    fn new(
+        repo: &'a BitRepo,
+        commits: &'a CommitInfoReader,
+        index: &'a PackBitmapIndex,
+        haves: Option<&'a ObjectSet>,
+    ) -> Self {
+        Self { repo, commits, index, haves, found: Default::default() }
+    }
+
+    fn contains(&self, oid: Oid) -> bool {
+        let position = self.index.position(oid);
+        let contains = |set: &ObjectSet| match position {
+            Some(i) => set.bitmap.get(i),
+            None => set.extra.contains_key(&oid),
+        };
+        contains(&self.found) || self.haves.map_or(false, contains)
+    }
+
+    /// returns false if the object has already been found (or is excluded)
+    fn insert(&mut self, oid: Oid, obj_type: BitObjType) -> bool {
+        if self.contains(oid) {
+            return false;
+        }
+        match self.index.position(oid) {
+            Some(i) => self.found.bitmap.set(i),
+            None => {
+                self.found.extra.insert(oid, obj_type);
+            }
+        }
+        true
+    }
+
+    fn add_tip(&mut self, mut oid: Oid) -> BitResult<()> {
+        loop {
+            match self.repo.read_obj_header(oid)?.obj_type {
+                BitObjType::Commit => return self.add_commit(oid),
+                BitObjType::Tree => return self.add_tree(oid),
+                BitObjType::Blob => {
+                    self.insert(oid, BitObjType::Blob);
+                    return Ok(());
+                }
+                BitObjType::Tag => {
+                    if !self.insert(oid, BitObjType::Tag) {
+                        return Ok(());
+                    }
+                    oid = self.repo.read_obj(oid)?.try_into_tag()?.object;
+                }
+            }
+        }
+    }
+
+    fn add_commit(&mut self, oid: Oid) -> BitResult<()> {
+        let mut pending = vec![oid];
+        let mut trees = vec![];
+        while let Some(oid) = pending.pop() {
+            if self.contains(oid) {
+                continue;
+            }
+
+            if let Some(bitmap) = self.index.bitmap(oid) {
+                self.found.bitmap.union_with(bitmap);
+                continue;
+            }
+
+            self.insert(oid, BitObjType::Commit);
+            let info = self.commits.read(oid)?;
+            trees.push(info.tree);
+            pending.extend(info.parents.iter().copied());
+        }
+
+        for tree in trees {
+            self.add_tree(tree)?;
+        }
+        Ok(())
+    }
+
+    fn add_tree(&mut self, oid: Oid) -> BitResult<()> {
+        let mut pending = vec![oid];
+        while let Some(tree) = pending.pop() {
+            // everything in a tree that has already been found has been found too
+            if !self.insert(tree, BitObjType::Tree) {
+                continue;
+            }
+
+            for entry in &self.repo.read_obj_tree(tree)?.entries {
+                if entry.mode.is_tree() {
+                    pending.push(entry.oid);
+                } else if entry.mode.is_gitlink() {
+                    // submodule commits live in another repository
+                    continue;
+                } else {
+                    self.insert(entry.oid, BitObjType::Blob);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The objects reachable from some set of objects but not from another as computed with bitmaps
+pub struct BitmapReachableObjects {
+    index: Arc<PackBitmapIndex>,
+    objects: ObjectSet,
+}
+
+impl BitmapReachableObjects {
+    pub fn len(&self) -> usize {
+        self.objects.bitmap.count_ones() + self.objects.extra.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn count(&self, obj_type: BitObjType) -> usize {
+        let extra = self.objects.extra.values().filter(|&&ty| ty == obj_type).count();
+        self.objects.bitmap.intersection(&self.index.types[type_index(obj_type)]).count_ones()
+            + extra
+    }
+
+    /// The objects in the same order as [`BitRepo::reachable_objects`] (tags and commits before
+    /// trees and blobs). The paths of the objects are unknown, but [`PackBuilder`] sorts them using
+    /// the name hashes stored in the bitmap index instead.
+    ///
+    /// [`PackBuilder`]: crate::pack::PackBuilder
+    pub fn into_objects(self) -> Vec<(Oid, BitPath)> {
+        let mut objects = Vec::with_capacity(self.len());
+        for obj_type in [BitObjType::Tag, BitObjType::Commit, BitObjType::Tree, BitObjType::Blob] {
+            let of_type = self.objects.bitmap.intersection(&self.index.types[type_index(obj_type)]);
+            objects.extend(of_type.iter().map(|i| (self.index.objects[i], BitPath::EMPTY)));
+            objects.extend(
+                self.objects
+                    .extra
+                    .iter()
+                    .filter(|(_, &ty)| ty == obj_type)
+                    .map(|(&oid, _)| (oid, BitPath::EMPTY)),
+            );
+        }
+        objects
+    }
+}
+
+impl BitRepo {
+    /// Loads the bitmap index of a pack if `pack.useBitmaps` is enabled.
+    /// git only ever keeps bitmaps for one pack at a time, so the first one found is used.
+    pub(crate) fn load_pack_bitmap_index(&self) -> Option<Arc<PackBitmapIndex>> {
+        if !self.config().use_bitmaps() {
+            return None;
+        }
+
+        // the bitmaps are just an optimization so we can do without them if they're broken
+        match self.find_pack_bitmap_index() {
+            Ok(index) => index.map(Arc::new),
+            Err(err) => {
+                warn!("ignoring pack bitmap index: {}", err);
+                None
+            }
+        }
+    }
+
+    fn find_pack_bitmap_index(&self) -> BitResult<Option<PackBitmapIndex>> {
+        let pack_dir = self.pack_objects_dir();
+        if !pack_dir.try_exists()? {
+            return Ok(None);
+        }
+
+        let mut paths = std::fs::read_dir(pack_dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<BitResult<Vec<_>>>()?;
+        paths.sort();
+        for path in paths {
+            if path.extension() != Some(BITMAP_EXT.as_ref()) {
+                continue;
+            }
+
+            let idx_path = path.with_extension(PACK_IDX_EXT);
+            if !path.with_extension(PACK_EXT).exists() || !idx_path.exists() {
+                continue;
+            }
+            let index = PackIndexReader::new(FileBuffer::open(&idx_path)?)?;
+            return Ok(Some(PackBitmapIndex::read(&index, &FileBuffer::open(&path)?)?));
+        }
+        Ok(None)
+    }
+
+    /// Writes a bitmap index alongside the pack at `pack_path`.
+    /// Only commits whose history is entirely within the pack get a bitmap.
+    /// `name_hashes` are stored in the bitmap index for packs built from it later on.
+    pub fn write_pack_bitmap_index(
+        &self,
+        pack_path: impl AsRef<Path>,
+        name_hashes: &FxHashMap<Oid, u32>,
+    ) -> BitResult<PackBitmapIndex> {
+        let pack_path = pack_path.as_ref();
+        let index =
+            PackIndexReader::new(FileBuffer::open(pack_path.with_extension(PACK_IDX_EXT))?)?;
+        let bitmaps = PackBitmapIndex::build(self, &index, name_hashes)?;
+        let mut file = tempfile::NamedTempFile::new_in(pack_path.parent().unwrap())?;
+        bitmaps.serialize(&mut file)?;
+        file.persist(pack_path.with_extension(BITMAP_EXT))?;
+        self.refresh_pack_bitmap_index();
+        Ok(bitmaps)
+    }
+
+    /// The objects reachable from `include` but not from `exclude` computed using the reachability
+    /// bitmaps (or `None` if there are none).
+    /// Unlike a plain walk, everything reachable from `exclude` is excluded.
+    pub fn bitmap_reachable_objects(
+        &self,
+        include: &[Oid],
+        exclude: &[Oid],
+    ) -> BitResult<Option<BitmapReachableObjects>> {
+        let index = match self.pack_bitmap_index() {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let commits = CommitInfoReader::new(self.clone())?;
+
+        let mut walk = BitmapWalk::new(self, &commits, &index, None);
+        for &oid in exclude {
+            walk.add_tip(oid)?;
+        }
+        let haves = walk.found;
+
+        let mut walk = BitmapWalk::new(self, &commits, &index, Some(&haves));
+        for &oid in include {
+            walk.add_tip(oid)?;
+        }
+        let mut wants = walk.found;
+
+        // the bitmaps of commits may include some objects that are also reachable from `exclude`
+        wants.bitmap = wants.bitmap.difference(&haves.bitmap);
+        Ok(Some(BitmapReachableObjects { index, objects: wants }))
+    }
+}
+
+#[cfg(test)]
+mod tests;
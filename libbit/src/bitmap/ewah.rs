@@ -0,0 +1,95 @@
+use super::Bitmap;
+use crate::error::BitResult;
+use crate::io::{ReadExt, WriteExt};
+use std::io::{Read, Write};
+
+// EWAH (enhanced word-aligned hybrid) is the run-length encoding git uses for its bitmaps.
+// The bits are split into 64-bit words which are stored as a sequence of markers, each followed
+// by some number of literal words. A marker describes a run of words that are either all zeroes
+// or all ones: the lowest bit is the value of the run, the next 32 bits are the length of the run
+// and the top 31 bits are the number of literal words that follow the marker.
+// The serialized form is the number of bits, the number of (compressed) words, the words
+// themselves and finally the position of the last marker.
+
+const RUNNING_LEN_BITS: u32 = 32;
+const MAX_RUNNING_LEN: u64 = (1 << RUNNING_LEN_BITS) - 1;
+const MAX_LITERAL_WORDS: u64 = (1 << 31) - 1;
+
+fn marker(run_bit: bool, running_len: u64, literal_words: u64) -> u64 {
+    run_bit as u64 | running_len << 1 | literal_words << (1 + RUNNING_LEN_BITS)
+}
+
+impl Bitmap {
+    pub fn write_ewah(&self, writer: &mut dyn Write) -> BitResult<()> {
+        let (compressed, last_marker) = self.compress();
+        writer.write_u32((self.words.len() * 64).try_into()?)?;
+        writer.write_u32(compressed.len().try_into()?)?;
+        for word in compressed {
+            writer.write_u64(word)?;
+        }
+        writer.write_u32(last_marker.try_into()?)?;
+        Ok(())
+    }
+
+    /// returns the compressed words and the index of the last marker
+    fn compress(&self) -> (Vec<u64>, usize) {
+        let words = &self.words;
+        let mut compressed = vec![];
+        let mut i = 0;
+        loop {
+            let run_bit = words.get(i) == Some(&!0);
+            let clean = if run_bit { !0 } else { 0 };
+            let mut running_len = 0;
+            while i < words.len() && words[i] == clean && running_len < MAX_RUNNING_LEN {
+                running_len += 1;
+                i += 1;
+            }
+
+            let start = i;
+            while i < words.len()
+                && words[i] != 0
+                && words[i] != !0
+                && ((i - start) as u64) < MAX_LITERAL_WORDS
+            {
+                i += 1;
+            }
+
+            let last_marker = compressed.len();
+            compressed.push(marker(run_bit, running_len, (i - start) as u64));
+            compressed.extend_from_slice(&words[start..i]);
+            if i == words.len() {
+                break (compressed, last_marker);
+            }
+        }
+    }
+
+    pub fn read_ewah(reader: &mut impl Read) -> BitResult<Self> {
+        let bit_len = reader.read_u32()? as usize;
+        let max_words = (bit_len + 63) / 64;
+        let n = reader.read_u32()? as usize;
+
+        let mut words = Vec::with_capacity(max_words);
+        let mut i = 0;
+        while i < n {
+            let marker = reader.read_u64()?;
+            i += 1;
+            let run_bit = marker & 1 != 0;
+            let running_len = ((marker >> 1) & MAX_RUNNING_LEN) as usize;
+            let literal_words = (marker >> (1 + RUNNING_LEN_BITS)) as usize;
+            ensure!(
+                i + literal_words <= n && words.len() + running_len + literal_words <= max_words,
+                "ewah bitmap is corrupt"
+            );
+
+            let clean = if run_bit { !0 } else { 0 };
+            words.resize(words.len() + running_len, clean);
+            for _ in 0..literal_words {
+                words.push(reader.read_u64()?);
+            }
+            i += literal_words;
+        }
+        // the position of the last marker is only required to append to the compressed bitmap
+        let _last_marker = reader.read_u32()?;
+        Ok(Self::from_words(words))
+    }
+}
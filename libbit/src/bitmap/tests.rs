@@ -0,0 +1,273 @@
+use super::*;
+use crate::graph::DagBuilder;
+use crate::pack::{name_hash, PackBuilder, PackIndex};
+use crate::test_utils::CommitGraphBuilder;
+
+fn ewah_roundtrip(bitmap: &Bitmap) -> BitResult<Bitmap> {
+    let mut bytes = vec![];
+    bitmap.write_ewah(&mut bytes)?;
+    Bitmap::read_ewah(&mut &bytes[..])
+}
+
+fn bitmap(bits: impl IntoIterator<Item = usize>) -> Bitmap {
+    let mut bitmap = Bitmap::new();
+    for i in bits {
+        bitmap.set(i);
+    }
+    bitmap
+}
+
+/// Packs everything reachable from HEAD (along with bitmaps) into the repository
+fn pack_with_bitmaps(repo: &BitRepo) -> BitResult<PackIndex> {
+    let head = repo.fully_resolve_head()?;
+    let objects = repo.reachable_objects(&[head], &[])?;
+    let pack_dir = repo.pack_objects_dir();
+    std::fs::create_dir_all(pack_dir)?;
+    let index = PackBuilder::new(repo.clone(), objects).bitmaps(true).write_to_dir(pack_dir)?;
+    repo.refresh_odb()?;
+    Ok(index)
+}
+
+fn index_reader(index: &PackIndex) -> BitResult<PackIndexReader<Vec<u8>>> {
+    let mut bytes = vec![];
+    index.serialize(&mut bytes)?;
+    PackIndexReader::new(bytes)
+}
+
+fn sorted(objects: Vec<(Oid, BitPath)>) -> Vec<Oid> {
+    let mut oids = objects.into_iter().map(|(oid, _)| oid).collect::<Vec<_>>();
+    oids.sort();
+    oids
+}
+
+#[test]
+fn test_bitmap_operations() {
+    let a = bitmap([0, 3, 64, 200]);
+    let b = bitmap([3, 65, 200]);
+    assert!(a.get(64) && !a.get(65) && !a.get(10_000));
+    assert_eq!(a.iter().collect::<Vec<_>>(), [0, 3, 64, 200]);
+    assert_eq!(a.count_ones(), 4);
+    assert_eq!(a.last(), Some(200));
+    assert_eq!(a.intersection(&b), bitmap([3, 200]));
+    assert_eq!(a.difference(&b), bitmap([0, 64]));
+    // trailing zero words are dropped so equal sets are equal bitmaps
+    assert_eq!(bitmap([200]).difference(&bitmap([200])), Bitmap::new());
+
+    let mut union = a.clone();
+    union.union_with(&b);
+    assert_eq!(union, bitmap([0, 3, 64, 65, 200]));
+    let mut xor = a;
+    xor.symmetric_difference_with(&b);
+    assert_eq!(xor, bitmap([0, 64, 65]));
+}
+
+#[test]
+fn test_ewah_encoding() -> BitResult<()> {
+    // a run of one clean word of zeroes, a run of two clean words of ones followed by a literal
+    let bitmap = bitmap((64..192).chain([194]));
+    let mut bytes = vec![];
+    bitmap.write_ewah(&mut bytes)?;
+
+    let mut expected = vec![];
+    expected.write_u32(256)?;
+    expected.write_u32(3)?;
+    expected.write_u64(1 << 1)?;
+    expected.write_u64(1 | 2 << 1 | 1 << 33)?;
+    expected.write_u64(1 << 2)?;
+    expected.write_u32(1)?;
+    assert_eq!(bytes, expected);
+    Ok(())
+}
+
+#[test]
+fn test_ewah_roundtrip() -> BitResult<()> {
+    for bitmap in [
+        Bitmap::new(),
+        bitmap([0]),
+        bitmap([63, 64, 1000, 1001, 5000]),
+        bitmap(0..10_000),
+        bitmap((0..10_000).filter(|i| i % 3 == 0).chain(20_000..20_100)),
+    ] {
+        assert_eq!(ewah_roundtrip(&bitmap)?, bitmap);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_read_corrupt_ewah() -> BitResult<()> {
+    // a run that is longer than the number of bits
+    let mut bytes = vec![];
+    bytes.write_u32(64)?;
+    bytes.write_u32(1)?;
+    bytes.write_u64(1 | 2 << 1)?;
+    bytes.write_u32(0)?;
+    assert!(Bitmap::read_ewah(&mut &bytes[..]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_serde_pack_bitmap_index() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let name_hashes = repo
+            .reachable_objects(&[head], &[])?
+            .into_iter()
+            .map(|(oid, path)| (oid, name_hash(path)))
+            .collect::<FxHashMap<_, _>>();
+        let index = index_reader(&pack_with_bitmaps(&repo)?)?;
+        let bitmaps = PackBitmapIndex::build(&repo, &index, &name_hashes)?;
+        assert!(!bitmaps.is_empty());
+
+        let mut bytes = vec![];
+        bitmaps.serialize(&mut bytes)?;
+        assert_eq!(PackBitmapIndex::read(&index, &bytes)?, bitmaps);
+
+        // without the name-hash cache
+        let bitmaps = PackBitmapIndex::build(&repo, &index, &Default::default())?;
+        let mut bytes = vec![];
+        bitmaps.serialize(&mut bytes)?;
+        assert_eq!(PackBitmapIndex::read(&index, &bytes)?, bitmaps);
+
+        let n = bytes.len();
+        bytes[n / 2] ^= 0xff;
+        assert!(PackBitmapIndex::read(&index, &bytes).is_err());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_bitmap_reachable_objects_matches_walk() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let expected = sorted(repo.reachable_objects(&[head], &[])?);
+        let index = pack_with_bitmaps(&repo)?;
+
+        let bitmaps = repo.pack_bitmap_index().expect("bitmaps were written");
+        assert_eq!(bitmaps.pack_hash(), index.pack_hash);
+        assert!(bitmaps.bitmap(head).is_some());
+
+        let objects = repo.bitmap_reachable_objects(&[head], &[])?.unwrap();
+        assert_eq!(objects.len(), expected.len());
+        assert_eq!(objects.count(BitObjType::Commit), 2);
+        assert_eq!(sorted(objects.into_objects()), expected);
+        assert_eq!(sorted(repo.reachable_objects(&[head], &[])?), expected);
+        assert_eq!(repo.count_reachable_objects(&[head], &[])?, expected.len());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_bitmap_index_keeps_name_hashes() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let objects = repo.reachable_objects(&[head], &[])?;
+        pack_with_bitmaps(&repo)?;
+
+        let bitmaps = repo.pack_bitmap_index().expect("bitmaps were written");
+        for (oid, path) in objects {
+            assert_eq!(bitmaps.name_hash(oid), Some(name_hash(path)));
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn test_bitmap_reachable_objects_with_exclusions() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        // a - b - d
+        //   \   /
+        //     c
+        let mut dag = DagBuilder::default();
+        let [a, b, c, d] = dag.mk_nodes();
+        dag.add_parents([(b, a), (c, a), (d, b), (d, c)]);
+        let commits = CommitGraphBuilder::new(repo.clone()).apply(&dag)?;
+        bit_branch!(repo: "d" @ rev!(commits[&d]));
+
+        let cases = [
+            (vec![commits[&d]], vec![commits[&b]]),
+            (vec![commits[&d]], vec![commits[&b], commits[&c]]),
+            (vec![commits[&b], commits[&c]], vec![commits[&a]]),
+            (vec![commits[&d]], vec![commits[&d]]),
+        ];
+        // everything reachable from `include` but not from `exclude` (found by walking everything)
+        let mut expected = vec![];
+        for (include, exclude) in &cases {
+            let excluded = sorted(repo.reachable_objects(exclude, &[])?);
+            let all = sorted(repo.reachable_objects(include, &[])?);
+            expected.push(
+                all.into_iter()
+                    .filter(|oid| excluded.binary_search(oid).is_err())
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        let pack_dir = repo.pack_objects_dir();
+        std::fs::create_dir_all(pack_dir)?;
+        let objects = repo.reachable_objects(&[commits[&d]], &[])?;
+        PackBuilder::new(repo.clone(), objects).bitmaps(true).write_to_dir(pack_dir)?;
+        repo.refresh_odb()?;
+
+        for ((include, exclude), expected) in cases.iter().zip(expected) {
+            let objects = repo.bitmap_reachable_objects(include, exclude)?.unwrap();
+            assert_eq!(sorted(objects.into_objects()), expected);
+        }
+        assert_eq!(repo.count_reachable_commits(&[commits[&d]], &[commits[&b]])?, 2);
+        assert_eq!(repo.count_reachable_commits(&[commits[&d]], &[commits[&d]])?, 0);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_bitmap_reachable_objects_includes_objects_outside_the_pack() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let old_head = repo.fully_resolve_head()?;
+        pack_with_bitmaps(&repo)?;
+
+        touch!(repo: "newfile" < "new content");
+        bit_commit_all!(repo);
+        let head = repo.fully_resolve_head()?;
+        assert!(repo.pack_bitmap_index().unwrap().bitmap(head).is_none());
+
+        let objects = repo.bitmap_reachable_objects(&[head], &[old_head])?.unwrap();
+        assert_eq!(objects.count(BitObjType::Commit), 1);
+        let oids = sorted(objects.into_objects());
+        let commit = repo.read_obj_commit(head)?;
+        assert!(oids.contains(&head));
+        assert!(oids.contains(&commit.tree));
+        assert!(!oids.contains(&old_head));
+        assert_eq!(repo.count_reachable_commits(&[head], &[])?, 3);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_corrupt_bitmap_index_is_ignored() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let expected = sorted(repo.reachable_objects(&[head], &[])?);
+        let index = pack_with_bitmaps(&repo)?;
+
+        let path = repo.pack_objects_dir().join(format!("pack-{}.{}", index.pack_hash, BITMAP_EXT));
+        let mut bytes = std::fs::read(path)?;
+        bytes[20] ^= 0xff;
+        std::fs::remove_file(path)?;
+        std::fs::write(path, bytes)?;
+        repo.refresh_pack_bitmap_index();
+
+        assert!(repo.pack_bitmap_index().is_none());
+        assert_eq!(sorted(repo.reachable_objects(&[head], &[])?), expected);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_bitmaps_can_be_disabled() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        pack_with_bitmaps(&repo)?;
+        assert!(repo.pack_bitmap_index().is_some());
+        repo.with_raw_local_config(|config| config.set("pack", "useBitmaps", false))?;
+        repo.refresh_pack_bitmap_index();
+        assert!(repo.pack_bitmap_index().is_none());
+        Ok(())
+    })
+}
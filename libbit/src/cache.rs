@@ -1,25 +1,278 @@
-use crate::error::BitResult;
-use crate::obj::{BitObjKind, Oid};
-use rustc_hash::FxHashMap;
+use crate::obj::{BitObjKind, BitObjType, BitObject, Oid};
+use parking_lot::{Mutex, MutexGuard};
+use rustc_hash::{FxHashMap, FxHasher};
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::ops::Add;
 
-#[derive(Default)]
+/// The default value of `core.objectCacheLimit`
+pub const DEFAULT_OBJ_CACHE_LIMIT: u64 = 128 * 1024 * 1024;
+
+/// A rough estimate of the memory used by a parsed object on top of its serialized size
+const OBJ_OVERHEAD: u64 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// number of cached values
+    pub entries: usize,
+    /// the total (estimated) size of the cached values in bytes
+    pub size: u64,
+    pub limit: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            accesses => self.hits as f64 / accesses as f64,
+        }
+    }
+}
+
+impl Add for CacheStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            entries: self.entries + other.entries,
+            size: self.size + other.size,
+            limit: self.limit + other.limit,
+        }
+    }
+}
+
+/// Caches with a smaller budget than this are not sharded as each shard would be too small to be
+/// of much use (and the results of a single shard are easier to reason about)
+const MIN_SHARD_LIMIT: u64 = 4 * 1024 * 1024;
+const MAX_SHARDS: u64 = 16;
+
+/// A cache split into independently locked shards by key so that threads reading different
+/// objects rarely contend on the same lock
+#[derive(Debug)]
+pub(crate) struct Sharded<T> {
+    shards: Box<[Mutex<T>]>,
+}
+
+impl<T> Sharded<T> {
+    /// Split a budget of `limit` bytes evenly between the shards created by `new_shard`
+    pub fn new(limit: u64, new_shard: impl Fn(u64) -> T) -> Self {
+        let n = (limit / MIN_SHARD_LIMIT).clamp(1, MAX_SHARDS);
+        Self { shards: (0..n).map(|_| Mutex::new(new_shard(limit / n))).collect() }
+    }
+
+    /// Lock the shard that `key` belongs to
+    pub fn shard(&self, key: impl Hash) -> MutexGuard<'_, T> {
+        let mut hasher = FxHasher::default();
+        key.hash(&mut hasher);
+        self.shards[hasher.finish() as usize % self.shards.len()].lock()
+    }
+
+    /// Combine some value of each shard (locking one shard at a time)
+    pub fn fold<A>(&self, init: A, f: impl Fn(A, &T) -> A) -> A {
+        self.shards.iter().fold(init, |acc, shard| f(acc, &shard.lock()))
+    }
+}
+
+/// A least recently used cache bounded by the total (estimated) size of its values in bytes
+#[derive(Debug)]
+pub(crate) struct LruCache<K, V> {
+    limit: u64,
+    entries: FxHashMap<K, LruEntry<V>>,
+    /// the keys ordered by when they were last used (least recent first)
+    lru: BTreeMap<u64, K>,
+    /// incremented on every access
+    tick: u64,
+    size: u64,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Debug)]
+struct LruEntry<V> {
+    value: V,
+    size: u64,
+    last_used: u64,
+}
+
+impl<K: Copy + Eq + Hash, V: Clone> LruCache<K, V> {
+    /// Create a cache that holds at most `limit` bytes (a limit of `0` disables it)
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            entries: Default::default(),
+            lru: Default::default(),
+            tick: 0,
+            size: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the cached value for `key` (and marks it as the most recently used)
+    pub fn get(&mut self, key: K) -> Option<V> {
+        let value = self.lookup(key);
+        if value.is_none() {
+            self.misses += 1;
+        }
+        value
+    }
+
+    /// As `get`, but only hits are recorded. This is for when a value may be looked for in
+    /// multiple caches and it's up to the caller to record the miss.
+    fn lookup(&mut self, key: K) -> Option<V> {
+        let entry = self.entries.get_mut(&key)?;
+        self.tick += 1;
+        self.lru.remove(&entry.last_used);
+        self.lru.insert(self.tick, key);
+        entry.last_used = self.tick;
+        self.hits += 1;
+        Some(entry.value.clone())
+    }
+
+    /// Cache `value` (which takes up `size` bytes), evicting the least recently used values to stay
+    /// within the limit. Values that are larger than the limit by themselves are not cached.
+    /// If `key` is already cached then the existing value is kept and returned instead.
+    pub fn insert(&mut self, key: K, value: V, size: u64) -> V {
+        if let Some(entry) = self.entries.get(&key) {
+            return entry.value.clone();
+        }
+
+        if size > self.limit {
+            return value;
+        }
+
+        while self.size + size > self.limit {
+            let (_, evicted) = self.lru.pop_first().expect("cache is over its limit while empty");
+            self.size -= self.entries.remove(&evicted).unwrap().size;
+        }
+
+        self.tick += 1;
+        self.lru.insert(self.tick, key);
+        self.entries.insert(key, LruEntry { value: value.clone(), size, last_used: self.tick });
+        self.size += size;
+        value
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+            size: self.size,
+            limit: self.limit,
+        }
+    }
+}
+
+/// Cache of parsed objects.
+/// Each object type has its own budget as commits and trees are read over and over again when
+/// walking history, but blobs are usually only read once.
+#[derive(Debug)]
 pub struct BitObjCache {
-    // consider using LRU but is very unclear what size to use as most implementations require a fixed
-    objects: FxHashMap<Oid, BitObjKind>,
+    shards: Sharded<BitObjCacheShard>,
+}
+
+#[derive(Debug)]
+struct BitObjCacheShard {
+    commits: LruCache<Oid, BitObjKind>,
+    trees: LruCache<Oid, BitObjKind>,
+    blobs: LruCache<Oid, BitObjKind>,
+    tags: LruCache<Oid, BitObjKind>,
+    misses: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BitObjCacheStats {
+    pub commits: CacheStats,
+    pub trees: CacheStats,
+    pub blobs: CacheStats,
+    pub tags: CacheStats,
+    /// The type of an object that is not cached is not known when it is looked up, so misses
+    /// are only counted here (and not by the stats of each type)
+    pub misses: u64,
+}
+
+impl BitObjCacheStats {
+    /// the stats of all the object types combined
+    pub fn total(&self) -> CacheStats {
+        let total = self.commits + self.trees + self.blobs + self.tags;
+        CacheStats { misses: self.misses, ..total }
+    }
+}
+
+impl Default for BitObjCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_OBJ_CACHE_LIMIT)
+    }
 }
 
 impl BitObjCache {
-    pub(crate) fn get_or_insert_with(
-        &mut self,
-        oid: Oid,
-        f: impl FnOnce() -> BitResult<BitObjKind>,
-    ) -> BitResult<BitObjKind> {
-        if let Some(obj) = self.objects.get(&oid) {
-            Ok(obj.clone())
-        } else {
-            let obj = f()?;
-            self.objects.insert(oid, obj.clone());
-            Ok(obj)
+    /// Create a cache that holds roughly `limit` bytes of objects in total
+    pub fn new(limit: u64) -> Self {
+        Self { shards: Sharded::new(limit, BitObjCacheShard::new) }
+    }
+
+    pub(crate) fn get(&self, oid: Oid) -> Option<BitObjKind> {
+        self.shards.shard(oid).get(oid)
+    }
+
+    /// Insert `obj` (which was not found by `get`) into the cache, returning the cached object.
+    /// If another thread raced us and cached the object first then that object is returned instead.
+    pub(crate) fn insert(&self, oid: Oid, obj: BitObjKind) -> BitObjKind {
+        self.shards.shard(oid).insert(oid, obj)
+    }
+
+    pub fn stats(&self) -> BitObjCacheStats {
+        self.shards.fold(BitObjCacheStats::default(), |acc, shard| BitObjCacheStats {
+            commits: acc.commits + shard.commits.stats(),
+            trees: acc.trees + shard.trees.stats(),
+            blobs: acc.blobs + shard.blobs.stats(),
+            tags: acc.tags + shard.tags.stats(),
+            misses: acc.misses + shard.misses,
+        })
+    }
+}
+
+impl BitObjCacheShard {
+    fn new(limit: u64) -> Self {
+        Self {
+            commits: LruCache::new(limit / 8 * 3),
+            trees: LruCache::new(limit / 2),
+            blobs: LruCache::new(limit / 16),
+            tags: LruCache::new(limit / 16),
+            misses: 0,
         }
     }
+
+    fn cache_mut(&mut self, obj_type: BitObjType) -> &mut LruCache<Oid, BitObjKind> {
+        match obj_type {
+            BitObjType::Commit => &mut self.commits,
+            BitObjType::Tree => &mut self.trees,
+            BitObjType::Blob => &mut self.blobs,
+            BitObjType::Tag => &mut self.tags,
+        }
+    }
+
+    fn get(&mut self, oid: Oid) -> Option<BitObjKind> {
+        // we don't know the type of the object upfront so look in the hotter caches first
+        let obj = [BitObjType::Commit, BitObjType::Tree, BitObjType::Blob, BitObjType::Tag]
+            .into_iter()
+            .find_map(|obj_type| self.cache_mut(obj_type).lookup(oid));
+        if obj.is_none() {
+            self.misses += 1;
+        }
+        obj
+    }
+
+    fn insert(&mut self, oid: Oid, obj: BitObjKind) -> BitObjKind {
+        let size = obj.obj_cached().size() + OBJ_OVERHEAD;
+        self.cache_mut(obj.obj_ty()).insert(oid, obj, size)
+    }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,105 @@
+use super::*;
+use crate::error::BitResult;
+use crate::obj::MutableBlob;
+use crate::repo::BitRepo;
+
+fn write_blob(repo: &BitRepo, len: usize, byte: u8) -> BitResult<Oid> {
+    repo.write_obj(&MutableBlob::new(vec![byte; len]))
+}
+
+#[test]
+fn test_lru_cache_evicts_least_recently_used() {
+    let mut cache = LruCache::<u64, u64>::new(10);
+    cache.insert(0, 0, 4);
+    cache.insert(1, 1, 4);
+    // using `0` makes `1` the least recently used
+    assert_eq!(cache.get(0), Some(0));
+    cache.insert(2, 2, 4);
+    assert_eq!(cache.get(1), None);
+    assert_eq!(cache.get(0), Some(0));
+    assert_eq!(cache.get(2), Some(2));
+    // too big to ever fit
+    assert_eq!(cache.insert(3, 3, 11), 3);
+    assert_eq!(cache.get(3), None);
+    // the existing value is kept
+    assert_eq!(cache.insert(2, 42, 4), 2);
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (3, 2));
+    assert_eq!((stats.entries, stats.size, stats.limit), (2, 8, 10));
+}
+
+#[test]
+fn test_sharded_cache() {
+    let limit = 64 * 1024 * 1024;
+    let sharded = Sharded::new(limit, LruCache::<u64, u64>::new);
+    assert_eq!(sharded.shards.len(), MAX_SHARDS as usize);
+    for key in 0..100 {
+        assert_eq!(sharded.shard(key).get(key), None);
+        sharded.shard(key).insert(key, key, 1);
+    }
+    for key in 0..100 {
+        assert_eq!(sharded.shard(key).get(key), Some(key));
+    }
+
+    let stats = sharded.fold(CacheStats::default(), |acc, shard| acc + shard.stats());
+    assert_eq!((stats.hits, stats.misses), (100, 100));
+    assert_eq!((stats.entries, stats.size, stats.limit), (100, 100, limit));
+    // small caches are not worth sharding
+    assert_eq!(Sharded::new(MIN_SHARD_LIMIT, LruCache::<u64, u64>::new).shards.len(), 1);
+}
+
+#[test]
+fn test_obj_cache_blob_budget() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        // the blob budget is a sixteenth of the total
+        let cache = BitObjCache::new(16 * (2 * (100 + OBJ_OVERHEAD)));
+        let oids = (0..3).map(|i| write_blob(&repo, 100, i)).collect::<BitResult<Vec<_>>>()?;
+        for &oid in &oids {
+            assert!(cache.get(oid).is_none());
+            cache.insert(oid, repo.read_obj(oid)?);
+        }
+
+        // only the two most recent blobs fit
+        assert!(cache.get(oids[0]).is_none());
+        assert!(cache.get(oids[1]).is_some());
+        assert!(cache.get(oids[2]).is_some());
+
+        // misses are counted when looking up an object, even if it's never inserted
+        let stats = cache.stats();
+        assert_eq!(stats.misses, 4);
+        assert_eq!(stats.blobs.hits, 2);
+        assert_eq!(stats.blobs.entries, 2);
+        assert_eq!(stats.commits, CacheStats { limit: stats.commits.limit, ..Default::default() });
+        assert_eq!((stats.total().hits, stats.total().misses), (2, 4));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_repo_obj_cache_stats() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let before = repo.obj_cache_stats().total();
+        repo.read_obj_commit(head)?;
+        repo.read_obj_commit(head)?;
+        let after = repo.obj_cache_stats().total();
+        // the first read may or may not have been cached already
+        assert_eq!(after.hits + after.misses, before.hits + before.misses + 2);
+        assert!(after.hits > before.hits);
+        assert!(after.hit_rate() > 0.0);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_obj_cache_disabled() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let cache = BitObjCache::new(0);
+        let oid = write_blob(&repo, 10, 0)?;
+        cache.insert(oid, repo.read_obj(oid)?);
+        assert!(cache.get(oid).is_none());
+        assert_eq!(cache.stats().total().entries, 0);
+        Ok(())
+    })
+}
@@ -5,6 +5,7 @@ use crate::iter::{BitEntry, BitEntryIterator, BitTreeIterator};
 use crate::obj::{FileMode, TreeEntry, Treeish};
 use crate::path::BitPath;
 use crate::pathspec::Pathspec;
+use crate::progress::{ProgressPhase, ProgressSink};
 use crate::refs::BitRef;
 use crate::repo::BitRepo;
 use crate::rev::Revspec;
@@ -20,11 +21,13 @@ use std::os::unix::prelude::{OsStrExt, PermissionsExt};
 #[derive(Debug, Default)]
 pub struct CheckoutOpts {
     pub strategy: CheckoutStrategy,
+    /// where to report the progress of writing files to the worktree
+    pub progress: ProgressSink,
 }
 
 impl CheckoutOpts {
     pub fn forced() -> Self {
-        Self { strategy: CheckoutStrategy::Force }
+        Self { strategy: CheckoutStrategy::Force, ..Default::default() }
     }
 
     fn is_forced(&self) -> bool {
@@ -134,8 +137,9 @@ impl BitRepo {
     ) -> BitResult<()> {
         let baseline = self.head_tree_iter()?;
         let worktree = self.index()?.worktree_tree_iter()?;
+        let progress = opts.progress.clone();
         let migration = self.generate_migration(baseline, target, worktree, opts)?;
-        self.apply_migration(&migration)
+        self.apply_migration(&migration, &progress)
     }
 
     fn generate_migration(
@@ -148,7 +152,7 @@ impl BitRepo {
         CheckoutCtxt::new(self.clone(), opts).generate(baseline, target, worktree)
     }
 
-    fn apply_migration(&self, migration: &Migration) -> BitResult<()> {
+    fn apply_migration(&self, migration: &Migration, progress: &ProgressSink) -> BitResult<()> {
         let mut index = self.index_mut()?;
         migration.rmrfs.iter().try_for_each(|rmrf| {
             let path = self.to_absolute_path(rmrf.path);
@@ -186,6 +190,11 @@ impl BitRepo {
             })?;
         }
 
+        // fetch whatever a partial clone is missing in one go rather than a file at a time
+        let blobs = migration.creates.iter().filter(|create| create.mode.is_blob());
+        self.prefetch_objects(blobs.map(|create| create.oid))?;
+        let total = migration.creates.len() as u64;
+        let mut checking_out = progress.begin(ProgressPhase::CheckingOut, Some(total));
         for create in &migration.creates {
             let path = self.to_absolute_path(create.path);
             let bytes = create.read_to_bytes(self)?;
@@ -214,7 +223,9 @@ impl BitRepo {
             let mut index_entry = BitIndexEntry::from_absolute_path(self, &path)?;
             index_entry.oid = create.oid;
             index.add_entry(index_entry)?;
+            checking_out.inc();
         }
+        checking_out.finish();
         Ok(())
     }
 }
@@ -353,7 +364,8 @@ impl CheckoutCtxt {
         worktree: &mut impl BitTreeIterator,
         worktree_entry: BitIndexEntry,
     ) -> BitResult<()> {
-        // TODO consider .gitignore rules
+        // ignored files never reach here as they are filtered out by the worktree iterator,
+        // so (as in git) they are considered expendable and may be overwritten
         match worktree_entry.mode() {
             FileMode::REG | FileMode::EXEC | FileMode::LINK => {
                 worktree.next()?;
@@ -1,4 +1,5 @@
 use crate::error::BitResult;
+use crate::merge::MergeResults;
 use crate::obj::Oid;
 use crate::peel::Peel;
 use crate::refs::BitRef;
@@ -26,4 +27,17 @@ impl BitRepo {
         // handle merge result
         Ok(())
     }
+
+    /// Apply the changes introduced by the commit `oid` (relative to its parent) to the index and
+    /// worktree without committing them
+    pub(crate) fn cherrypick_uncommitted(&self, oid: Oid) -> BitResult<MergeResults> {
+        let commit = self.read_obj_commit(oid)?;
+        ensure!(commit.parents.len() < 2, "TODO cherrypick merge commit");
+        let base_tree = match commit.parents.get(0) {
+            Some(&parent) => self.read_obj_commit(parent)?.tree_oid(),
+            None => Oid::UNKNOWN,
+        };
+        let desc = format!("{}... {}", oid.short(), commit.message.subject);
+        self.merge_uncommitted(base_tree, oid, &desc)
+    }
 }
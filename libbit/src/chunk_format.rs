@@ -0,0 +1,155 @@
+use crate::error::BitResult;
+use crate::hash::{self, OID_SIZE};
+use crate::io::WriteExt;
+use crate::obj::Oid;
+use crate::pack::{search_oid_table, FANOUT_ENTRYC, FANOUT_SIZE};
+use std::convert::TryInto;
+use std::io::Write;
+use std::ops::Range;
+
+// Helpers for the "chunk format" shared by the multi-pack-index and commit-graph files.
+// The format is documented at https://git-scm.com/docs/gitformat-chunk
+// A file consists of a header, a table of contents (the chunk lookup), the chunks themselves and
+// finally a checksum of everything before it.
+
+pub(crate) const CHUNK_LOOKUP_ENTRY_SIZE: usize = 12;
+
+pub(crate) const CHUNK_OID_FANOUT: u32 = u32::from_be_bytes(*b"OIDF");
+pub(crate) const CHUNK_OID_LOOKUP: u32 = u32::from_be_bytes(*b"OIDL");
+
+pub(crate) fn read_u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn read_u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Checks the trailing checksum of a chunk file
+pub(crate) fn verify_checksum(file: &str, bytes: &[u8]) -> BitResult<()> {
+    ensure!(bytes.len() >= OID_SIZE, "{} is truncated", file);
+    let end = bytes.len() - OID_SIZE;
+    let checksum = Oid::new(bytes[end..].try_into().unwrap());
+    ensure!(hash::hash_bytes(&bytes[..end]) == checksum, "{} checksum mismatch", file);
+    Ok(())
+}
+
+/// Writes the chunk lookup for chunks of the given `(id, size)` that are written in order directly
+/// after the lookup itself.
+/// The lookup is terminated by an entry with id 0 that points to the end of the last chunk.
+pub(crate) fn write_chunk_lookup(
+    writer: &mut dyn Write,
+    header_size: usize,
+    chunks: &[(u32, usize)],
+) -> BitResult<()> {
+    let mut offset = header_size + (chunks.len() + 1) * CHUNK_LOOKUP_ENTRY_SIZE;
+    for &(id, size) in chunks {
+        writer.write_u32(id)?;
+        writer.write_u64(offset as u64)?;
+        offset += size;
+    }
+    writer.write_u32(0)?;
+    writer.write_u64(offset as u64)?;
+    Ok(())
+}
+
+/// The location of each chunk in a file
+pub(crate) struct ChunkLookup {
+    /// the kind of file (used for error messages)
+    file: &'static str,
+    chunks: Vec<(u32, Range<usize>)>,
+}
+
+impl ChunkLookup {
+    /// Parse the lookup of `chunkc` chunks that starts directly after the header.
+    /// Checks every chunk is within the bounds of `bytes` (excluding the trailing checksum).
+    pub fn parse(
+        file: &'static str,
+        bytes: &[u8],
+        header_size: usize,
+        chunkc: usize,
+    ) -> BitResult<Self> {
+        // the end of the last chunk is the start of the trailing checksum
+        ensure!(bytes.len() >= header_size + OID_SIZE, "{} is truncated", file);
+        let end = bytes.len() - OID_SIZE;
+        ensure!(
+            header_size + (chunkc + 1) * CHUNK_LOOKUP_ENTRY_SIZE <= end,
+            "{} is truncated",
+            file
+        );
+
+        let entries = (0..=chunkc)
+            .map(|i| {
+                let entry = header_size + i * CHUNK_LOOKUP_ENTRY_SIZE;
+                (read_u32_at(bytes, entry), read_u64_at(bytes, entry + 4) as usize)
+            })
+            .collect::<Vec<_>>();
+        let mut chunks = vec![];
+        for window in entries.windows(2) {
+            let ((id, start), (_, next)) = (window[0], window[1]);
+            ensure!(start <= next && next <= end, "{} has an invalid chunk offset", file);
+            chunks.push((id, start..next));
+        }
+        Ok(Self { file, chunks })
+    }
+
+    pub fn get(&self, id: u32) -> Option<Range<usize>> {
+        self.chunks.iter().find(|(chunk_id, _)| *chunk_id == id).map(|(_, range)| range.clone())
+    }
+
+    pub fn required(&self, id: u32) -> BitResult<Range<usize>> {
+        self.get(id).ok_or_else(|| {
+            anyhow!(
+                "{} is missing the required `{}` chunk",
+                self.file,
+                String::from_utf8_lossy(&id.to_be_bytes())
+            )
+        })
+    }
+
+    /// Reads the oid fanout table (which has the same format as the one in pack indexes)
+    pub fn read_fanout(&self, bytes: &[u8]) -> BitResult<[u32; FANOUT_ENTRYC]> {
+        let chunk = self.required(CHUNK_OID_FANOUT)?;
+        ensure!(chunk.len() == FANOUT_SIZE as usize, "{} fanout is invalid", self.file);
+        let mut fanout = [0; FANOUT_ENTRYC];
+        for (i, count) in fanout.iter_mut().enumerate() {
+            *count = read_u32_at(bytes, chunk.start + i * 4);
+        }
+        ensure!(fanout.is_sorted(), "{} fanout is invalid", self.file);
+        Ok(fanout)
+    }
+
+    /// Reads the location of the sorted table of `n` oids
+    pub fn read_oid_lookup(&self, n: usize) -> BitResult<OidLookup> {
+        let chunk = self.required(CHUNK_OID_LOOKUP)?;
+        ensure!(chunk.len() == n * OID_SIZE, "{} oid lookup is invalid", self.file);
+        Ok(OidLookup { start: chunk.start })
+    }
+}
+
+/// The sorted table of oids in a chunk file (which is searched using the fanout table)
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OidLookup {
+    start: usize,
+}
+
+impl OidLookup {
+    pub fn oid_at(self, bytes: &[u8], index: usize) -> Oid {
+        let start = self.start + index * OID_SIZE;
+        Oid::new(bytes[start..start + OID_SIZE].try_into().unwrap())
+    }
+
+    /// returns the index of `oid` if it's found, otherwise the index where it would be inserted
+    pub fn find_oid_index(
+        self,
+        bytes: &[u8],
+        fanout: &[u32; FANOUT_ENTRYC],
+        oid: Oid,
+    ) -> Result<usize, usize> {
+        let prefix = oid[0] as usize;
+        let low = if prefix == 0 { 0 } else { fanout[prefix - 1] } as usize;
+        let high = fanout[prefix] as usize;
+        let oids = &bytes[self.start + low * OID_SIZE..self.start + high * OID_SIZE];
+        search_oid_table(oids, oid).map(|idx| low + idx).map_err(|idx| low + idx)
+    }
+}
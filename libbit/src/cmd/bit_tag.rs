@@ -0,0 +1,45 @@
+use crate::error::BitResult;
+use crate::obj::Oid;
+use crate::refs::{self, BitRefDbBackend, RefUpdateCause, SymbolicRef, SymbolicRefKind};
+use crate::repo::BitRepo;
+use crate::rev::Revspec;
+
+impl BitRepo {
+    /// Create a tag `name` pointing at `target`.
+    /// If a `message` is given, an annotated tag object is written and the tag points at that.
+    /// Otherwise, a lightweight tag pointing directly at `target` is created.
+    pub fn bit_create_tag(
+        &self,
+        name: &str,
+        target: &Revspec,
+        message: Option<String>,
+        force: bool,
+    ) -> BitResult<SymbolicRef> {
+        ensure!(refs::is_valid_name(name), "invalid tag name `{}`", name);
+        let sym = SymbolicRef::new_tag(name);
+        ensure!(force || !self.refdb()?.exists(sym)?, "tag `{}` already exists", name);
+
+        let target = self.fully_resolve_rev(target)?;
+        let oid = match message {
+            Some(message) => self.write_tag(target, name, message)?,
+            None => target,
+        };
+        self.update_ref(sym, oid, RefUpdateCause::NewTag { target })?;
+        Ok(sym)
+    }
+
+    /// Deletes the tag `name` returning the oid it pointed to
+    pub fn bit_delete_tag(&self, name: &str) -> BitResult<Oid> {
+        ensure!(refs::is_valid_name(name), "invalid tag name `{}`", name);
+        let sym = SymbolicRef::new_tag(name);
+        let refdb = self.refdb()?;
+        ensure!(refdb.exists(sym)?, "tag `{}` not found", name);
+        let oid = self.fully_resolve_ref(sym)?;
+        refdb.delete(sym)?;
+        Ok(oid)
+    }
+
+    pub fn bit_list_tags(&self) -> BitResult<Vec<SymbolicRef>> {
+        Ok(self.ls_refs()?.into_iter().filter(|sym| sym.kind() == SymbolicRefKind::Tag).collect())
+    }
+}
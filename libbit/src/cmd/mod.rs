@@ -6,6 +6,7 @@ mod bit_cat_file;
 mod bit_commit_tree;
 mod bit_hash_object;
 mod bit_ls_files;
+mod bit_tag;
 mod bit_update_index;
 mod bit_write_tree;
 
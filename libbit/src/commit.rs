@@ -1,4 +1,4 @@
-use crate::diff::WorkspaceStatus;
+use crate::diff::{DiffOpts, WorkspaceStatus};
 use crate::error::BitResult;
 use crate::obj::{BitObject, Commit, Oid};
 use crate::pathspec::Pathspec;
@@ -61,8 +61,13 @@ impl BitRepo {
 
         self.update_ref(sym, commit_oid, cause)?;
 
+        let opts = DiffOpts { renames: self.default_rename_opts(), ..Default::default() };
         Ok(CommitSummary {
-            status: self.diff_tree_to_tree(parent.unwrap_or(Oid::UNKNOWN), commit.tree)?,
+            status: self.diff_tree_to_tree_with_opts(
+                parent.unwrap_or(Oid::UNKNOWN),
+                commit.tree,
+                opts,
+            )?,
             repo: self.clone(),
             sym,
             commit,
@@ -0,0 +1,507 @@
+use crate::chunk_format::*;
+use crate::error::BitResult;
+use crate::hash::OID_SIZE;
+use crate::io::{HashWriter, WriteExt, WriteExtSized};
+use crate::lockfile::{Lockfile, LockfileFlags};
+use crate::obj::{BitObject, Commit, CommitParents, Oid};
+use crate::pack::{FANOUT_ENTRYC, PackIndex};
+use crate::repo::BitRepo;
+use crate::serialize::{Deserialize, Serialize};
+use filebuffer::FileBuffer;
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::SmallVec;
+use std::fmt::{self, Debug, Formatter};
+use std::io::{BufRead, BufWriter, Write};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+// The format is documented at https://git-scm.com/docs/gitformat-commit-graph
+// The commit-graph stores the parents, commit time and generation number of each commit so
+// history can be walked without parsing every commit object. The generation number of a commit
+// is one more than the maximum generation of its parents (so a root commit has generation 1) and
+// is what allows a walk to stop early, as a commit can only be reached from commits with a
+// strictly larger generation.
+
+pub const COMMIT_GRAPH_FILE_PATH: &str = "info/commit-graph";
+const GRAPH_SIGNATURE: &[u8; 4] = b"CGPH";
+const GRAPH_VERSION: u8 = 1;
+/// the oid version for sha1
+const GRAPH_OID_VERSION: u8 = 1;
+const GRAPH_HEADER_SIZE: usize = 8;
+const COMMIT_DATA_SIZE: usize = OID_SIZE + 16;
+
+const CHUNK_COMMIT_DATA: u32 = u32::from_be_bytes(*b"CDAT");
+const CHUNK_EXTRA_EDGES: u32 = u32::from_be_bytes(*b"EDGE");
+
+/// the parent position used for a commit with fewer parents
+const GRAPH_PARENT_NONE: u32 = 0x70000000;
+/// set in the second parent position of an octopus merge, the rest of the bits are an index into
+/// the extra edges chunk
+const GRAPH_EXTRA_EDGES_NEEDED: u32 = 0x80000000;
+/// set on the final parent of a commit in the extra edges chunk
+const GRAPH_LAST_EDGE: u32 = 0x80000000;
+/// only the low 34 bits of commit times are stored
+const COMMIT_TIME_MASK: u64 = (1 << 34) - 1;
+
+/// The generation of commits that are not in the commit-graph.
+/// This is larger than any real generation number so walks are never pruned at these commits.
+pub const GENERATION_NUMBER_INFINITY: u32 = u32::MAX;
+/// Generation numbers are stored in 30 bits, larger generations are clamped to this.
+pub const GENERATION_NUMBER_MAX: u32 = 0x3FFFFFFF;
+
+/// The information about a commit that is required to walk history.
+/// This is read from the commit-graph where possible to avoid having to parse the commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitInfo {
+    pub oid: Oid,
+    pub tree: Oid,
+    pub parents: CommitParents,
+    /// the committer time (in seconds since the epoch), normalized to what the commit-graph
+    /// can represent
+    pub commit_time: i64,
+    /// [`GENERATION_NUMBER_INFINITY`] if the commit is not in the commit-graph
+    pub generation: u32,
+}
+
+impl CommitInfo {
+    pub fn from_commit(commit: &Commit) -> Self {
+        Self {
+            oid: commit.oid(),
+            tree: commit.tree,
+            parents: commit.parents.clone(),
+            commit_time: normalize_commit_time(commit.committer.time.time.seconds()),
+            generation: GENERATION_NUMBER_INFINITY,
+        }
+    }
+}
+
+/// The commit-graph only has room for unsigned 34-bit commit times, so times read from commit
+/// objects are clamped to that range to be comparable with those read from the commit-graph
+/// (commits with negative timestamps are treated as if they were made at the epoch)
+fn normalize_commit_time(seconds: i64) -> i64 {
+    seconds.clamp(0, COMMIT_TIME_MASK as i64)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitGraph {
+    /// sorted by oid
+    pub commits: Vec<CommitInfo>,
+}
+
+impl CommitGraph {
+    /// Build a commit-graph of `commits` (computing their generation numbers).
+    /// The parents of every commit must also be included.
+    pub fn new(commits: impl IntoIterator<Item = CommitInfo>) -> BitResult<Self> {
+        let mut commits = commits.into_iter().collect::<Vec<_>>();
+        commits.sort_by_key(|commit| commit.oid);
+        commits.dedup_by_key(|commit| commit.oid);
+
+        let positions = commits
+            .iter()
+            .enumerate()
+            .map(|(i, commit)| (commit.oid, i))
+            .collect::<FxHashMap<_, _>>();
+        let mut generations = vec![0; commits.len()];
+        // compute the generations without recursion as the history may be arbitrarily deep
+        for root in 0..commits.len() {
+            let mut stack = vec![root];
+            while let Some(&i) = stack.last() {
+                if generations[i] != 0 {
+                    stack.pop();
+                    continue;
+                }
+
+                let mut generation = 0;
+                let mut pending = false;
+                for parent in &commits[i].parents {
+                    let &j = positions.get(parent).ok_or_else(|| {
+                        anyhow!(
+                            "parent `{}` of commit `{}` is missing from the commit-graph",
+                            parent,
+                            commits[i].oid
+                        )
+                    })?;
+                    match generations[j] {
+                        0 => {
+                            pending = true;
+                            stack.push(j);
+                        }
+                        parent_generation => generation = generation.max(parent_generation),
+                    }
+                }
+
+                if !pending {
+                    generations[i] = (generation + 1).min(GENERATION_NUMBER_MAX);
+                    stack.pop();
+                }
+            }
+        }
+
+        commits.iter_mut().zip(generations).for_each(|(commit, generation)| {
+            commit.generation = generation;
+        });
+        Ok(Self { commits })
+    }
+
+    fn position(&self, oid: Oid) -> BitResult<u32> {
+        match self.commits.binary_search_by_key(&oid, |commit| commit.oid) {
+            Ok(i) => Ok(i as u32),
+            Err(..) => bail!("commit `{}` is missing from the commit-graph", oid),
+        }
+    }
+}
+
+impl Serialize for CommitGraph {
+    fn serialize(&self, writer: &mut dyn Write) -> BitResult<()> {
+        let mut writer = BufWriter::new(HashWriter::new_sha1(writer));
+
+        let mut commit_data = Vec::with_capacity(self.commits.len() * COMMIT_DATA_SIZE);
+        let mut extra_edges = vec![];
+        for commit in &self.commits {
+            let parents = commit
+                .parents
+                .iter()
+                .map(|&parent| self.position(parent))
+                .collect::<BitResult<SmallVec<[u32; 2]>>>()?;
+            let (parent1, parent2) = match parents[..] {
+                [] => (GRAPH_PARENT_NONE, GRAPH_PARENT_NONE),
+                [parent] => (parent, GRAPH_PARENT_NONE),
+                [parent1, parent2] => (parent1, parent2),
+                [parent1, ref rest @ ..] => {
+                    let edge_index = extra_edges.len() as u32;
+                    extra_edges.extend_from_slice(rest);
+                    *extra_edges.last_mut().unwrap() |= GRAPH_LAST_EDGE;
+                    (parent1, GRAPH_EXTRA_EDGES_NEEDED | edge_index)
+                }
+            };
+
+            let time = commit.commit_time as u64 & COMMIT_TIME_MASK;
+            commit_data.write_oid(commit.tree)?;
+            commit_data.write_u32(parent1)?;
+            commit_data.write_u32(parent2)?;
+            commit_data.write_u32(
+                commit.generation.min(GENERATION_NUMBER_MAX) << 2 | (time >> 32) as u32,
+            )?;
+            commit_data.write_u32(time as u32)?;
+        }
+
+        let n = self.commits.len();
+        let mut chunks = vec![
+            (CHUNK_OID_FANOUT, FANOUT_ENTRYC * 4),
+            (CHUNK_OID_LOOKUP, n * OID_SIZE),
+            (CHUNK_COMMIT_DATA, commit_data.len()),
+        ];
+        if !extra_edges.is_empty() {
+            chunks.push((CHUNK_EXTRA_EDGES, extra_edges.len() * 4));
+        }
+
+        writer.write_all(GRAPH_SIGNATURE)?;
+        writer.write_u8(GRAPH_VERSION)?;
+        writer.write_u8(GRAPH_OID_VERSION)?;
+        writer.write_u8(chunks.len() as u8)?;
+        // number of base commit-graph files
+        writer.write_u8(0)?;
+        write_chunk_lookup(&mut writer, GRAPH_HEADER_SIZE, &chunks)?;
+
+        let oids = self.commits.iter().map(|commit| commit.oid).collect::<Vec<_>>();
+        writer.write_iter(PackIndex::build_fanout(&oids))?;
+        writer.write_iter(&oids)?;
+        writer.write_all(&commit_data)?;
+        writer.write_iter(extra_edges)?;
+
+        match writer.into_inner() {
+            Ok(writer) => writer.write_hash()?,
+            Err(..) => bail!("hash writer flush failed while writing commit-graph"),
+        };
+        Ok(())
+    }
+}
+
+impl Deserialize for CommitGraph {
+    fn deserialize(mut reader: impl BufRead) -> BitResult<Self>
+    where
+        Self: Sized,
+    {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let reader = CommitGraphReader::new(bytes)?;
+        reader.verify_checksum()?;
+        let commits = (0..reader.len()).map(|i| reader.commit_at(i)).collect::<BitResult<_>>()?;
+        Ok(Self { commits })
+    }
+}
+
+/// Reads a commit-graph directly from its bytes (usually memory mapped)
+pub struct CommitGraphReader<B> {
+    bytes: B,
+    fanout: [u32; FANOUT_ENTRYC],
+    oid_lookup: OidLookup,
+    commit_data: Range<usize>,
+    extra_edges: Option<Range<usize>>,
+}
+
+impl<B> Debug for CommitGraphReader<B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "CommitGraphReader({} commits)", self.fanout[FANOUT_ENTRYC - 1])
+    }
+}
+
+impl<B: Deref<Target = [u8]>> CommitGraphReader<B> {
+    pub fn new(bytes: B) -> BitResult<Self> {
+        ensure!(bytes.len() >= GRAPH_HEADER_SIZE + OID_SIZE, "commit-graph is truncated");
+        ensure!(&bytes[..4] == GRAPH_SIGNATURE, "invalid commit-graph signature");
+        ensure!(bytes[4] == GRAPH_VERSION, "unsupported commit-graph version `{}`", bytes[4]);
+        ensure!(
+            bytes[5] == GRAPH_OID_VERSION,
+            "unsupported commit-graph oid version `{}`",
+            bytes[5]
+        );
+        ensure!(bytes[7] == 0, "commit-graph chains are not supported");
+        let chunks =
+            ChunkLookup::parse("commit-graph", &bytes, GRAPH_HEADER_SIZE, bytes[6] as usize)?;
+
+        let fanout = chunks.read_fanout(&bytes)?;
+        let n = fanout[FANOUT_ENTRYC - 1] as usize;
+        let oid_lookup = chunks.read_oid_lookup(n)?;
+        let commit_data = chunks.required(CHUNK_COMMIT_DATA)?;
+        ensure!(commit_data.len() == n * COMMIT_DATA_SIZE, "commit-graph commit data is invalid");
+        // any other chunks (such as corrected commit dates or bloom filters) are optional
+        let extra_edges = chunks.get(CHUNK_EXTRA_EDGES);
+
+        Ok(Self { bytes, fanout, oid_lookup, commit_data, extra_edges })
+    }
+
+    /// the number of commits in the commit-graph
+    pub fn len(&self) -> usize {
+        self.fanout[FANOUT_ENTRYC - 1] as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn oid_at(&self, index: usize) -> Oid {
+        self.oid_lookup.oid_at(&self.bytes, index)
+    }
+
+    fn parent_at(&self, position: u32) -> BitResult<Oid> {
+        ensure!(
+            (position as usize) < self.len(),
+            "commit-graph has invalid parent position `{}`",
+            position
+        );
+        Ok(self.oid_at(position as usize))
+    }
+
+    pub fn commit_at(&self, index: usize) -> BitResult<CommitInfo> {
+        let data = self.commit_data.start + index * COMMIT_DATA_SIZE;
+        let tree = Oid::new(self.bytes[data..data + OID_SIZE].try_into().unwrap());
+        let parent1 = read_u32_at(&self.bytes, data + OID_SIZE);
+        let parent2 = read_u32_at(&self.bytes, data + OID_SIZE + 4);
+        let generation_and_time = read_u32_at(&self.bytes, data + OID_SIZE + 8);
+        let time = read_u32_at(&self.bytes, data + OID_SIZE + 12);
+
+        let mut parents = CommitParents::new();
+        if parent1 != GRAPH_PARENT_NONE {
+            parents.push(self.parent_at(parent1)?);
+        }
+        if parent2 & GRAPH_EXTRA_EDGES_NEEDED != 0 {
+            let extra_edges = match &self.extra_edges {
+                Some(extra_edges) => extra_edges,
+                None => bail!("commit-graph is missing the extra edges chunk"),
+            };
+            let mut edge = extra_edges.start + (parent2 & !GRAPH_EXTRA_EDGES_NEEDED) as usize * 4;
+            loop {
+                ensure!(edge < extra_edges.end, "commit-graph has an invalid extra edge");
+                let parent = read_u32_at(&self.bytes, edge);
+                parents.push(self.parent_at(parent & !GRAPH_LAST_EDGE)?);
+                if parent & GRAPH_LAST_EDGE != 0 {
+                    break;
+                }
+                edge += 4;
+            }
+        } else if parent2 != GRAPH_PARENT_NONE {
+            parents.push(self.parent_at(parent2)?);
+        }
+
+        // a generation of zero means the generation was not computed (by older versions of git)
+        let generation = match generation_and_time >> 2 {
+            0 => GENERATION_NUMBER_INFINITY,
+            generation => generation,
+        };
+        let commit_time = ((generation_and_time as i64 & 0x3) << 32) | time as i64;
+        Ok(CommitInfo { oid: self.oid_at(index), tree, parents, commit_time, generation })
+    }
+
+    pub fn find_commit(&self, oid: Oid) -> BitResult<Option<CommitInfo>> {
+        match self.oid_lookup.find_oid_index(&self.bytes, &self.fanout, oid) {
+            Ok(index) => self.commit_at(index).map(Some),
+            Err(..) => Ok(None),
+        }
+    }
+
+    pub fn verify_checksum(&self) -> BitResult<()> {
+        verify_checksum("commit-graph", &self.bytes)
+    }
+}
+
+/// Looks up the parents etc. of commits, using the commit-graph if possible and otherwise
+/// falling back to parsing the commit.
+/// The shallow commits of a shallow repository are read as if they have no parents.
+#[derive(Debug, Clone)]
+pub(crate) struct CommitInfoReader {
+    repo: BitRepo,
+    graph: Option<Arc<CommitGraphReader<FileBuffer>>>,
+    shallow: Arc<FxHashSet<Oid>>,
+}
+
+impl CommitInfoReader {
+    pub fn new(repo: BitRepo) -> BitResult<Self> {
+        let graph = repo.commit_graph();
+        let shallow = repo.shallow_commits()?;
+        Ok(Self { repo, graph, shallow })
+    }
+
+    pub fn read(&self, oid: Oid) -> BitResult<CommitInfo> {
+        if let Some(graph) = &self.graph {
+            if let Some(info) = graph.find_commit(oid)? {
+                return Ok(self.graft(info));
+            }
+        }
+        Ok(self.graft(CommitInfo::from_commit(&self.repo.read_obj_commit(oid)?)))
+    }
+
+    /// The same as [`CommitInfo::from_commit`] but respecting shallow commits, and with the
+    /// generation number from the commit-graph if the commit is in it
+    pub fn from_commit(&self, commit: &Commit) -> CommitInfo {
+        let info = match &self.graph {
+            Some(graph) => graph.find_commit(commit.oid()).ok().flatten(),
+            None => None,
+        };
+        self.graft(info.unwrap_or_else(|| CommitInfo::from_commit(commit)))
+    }
+
+    fn graft(&self, mut info: CommitInfo) -> CommitInfo {
+        if self.shallow.contains(&info.oid) {
+            info.parents.clear();
+        }
+        info
+    }
+}
+
+impl BitRepo {
+    /// Loads the commit-graph file (if there is a valid one and `core.commitGraph` is enabled)
+    pub(crate) fn load_commit_graph(&self) -> Option<Arc<CommitGraphReader<FileBuffer>>> {
+        if !self.config().commit_graph() {
+            return None;
+        }
+
+        // the parents of the shallow commits would be wrong once the repository is deepened
+        if self.is_shallow().unwrap_or(true) {
+            return None;
+        }
+
+        let path = self.commit_graph_path();
+        if !path.exists() {
+            return None;
+        }
+
+        // the commit-graph is just an optimization so we can do without it if it's broken
+        match FileBuffer::open(path).map_err(Into::into).and_then(CommitGraphReader::new) {
+            Ok(graph) => Some(Arc::new(graph)),
+            Err(err) => {
+                warn!("ignoring commit-graph `{}`: {}", path, err);
+                None
+            }
+        }
+    }
+
+    /// Write a commit-graph containing every commit reachable from any ref
+    pub fn write_commit_graph(&self) -> BitResult<CommitGraph> {
+        ensure!(!self.is_shallow()?, "cannot write a commit-graph for a shallow repository");
+        let mut pending = vec![];
+        if let Some(oid) = self.try_fully_resolve_ref(self.read_head()?)? {
+            pending.push(oid);
+        }
+        for r in self.ls_refs()? {
+            pending.extend(self.try_fully_resolve_ref(r)?);
+        }
+
+        // the commits are read through the existing commit-graph (if any) as that is faster
+        let reader = CommitInfoReader::new(self.clone())?;
+        let mut commits = FxHashMap::default();
+        while let Some(oid) = pending.pop() {
+            if commits.contains_key(&oid) {
+                continue;
+            }
+            let info = reader.read(oid)?;
+            pending.extend(info.parents.iter().copied());
+            commits.insert(oid, info);
+        }
+        ensure!(!commits.is_empty(), "no commits to write to the commit-graph");
+
+        let graph = CommitGraph::new(commits.into_values())?;
+        let path = self.commit_graph_path();
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        Lockfile::with_mut(path, LockfileFlags::SET_READONLY, |lockfile| {
+            graph.serialize(lockfile)
+        })?;
+        self.refresh_commit_graph();
+        Ok(graph)
+    }
+
+    /// Check the commit-graph is well formed and agrees with the commits it contains
+    pub fn verify_commit_graph(&self) -> BitResult<()> {
+        let path = self.commit_graph_path();
+        ensure!(path.exists(), "no commit-graph found");
+        let graph = CommitGraphReader::new(FileBuffer::open(path)?)?;
+        graph.verify_checksum()?;
+
+        for index in 0..graph.len() {
+            let info = graph.commit_at(index)?;
+            ensure!(
+                index == 0 || graph.oid_at(index - 1) < info.oid,
+                "commit-graph oid lookup is not sorted at index `{}`",
+                index
+            );
+            ensure!(
+                graph.find_commit(info.oid)?.as_ref() == Some(&info),
+                "failed to find commit `{}` in commit-graph",
+                info.oid
+            );
+
+            let commit = self.read_obj_commit(info.oid)?;
+            let expected = CommitInfo::from_commit(&commit);
+            ensure!(info.tree == expected.tree, "incorrect tree for commit `{}`", info.oid);
+            ensure!(
+                info.parents == expected.parents,
+                "incorrect parents for commit `{}`",
+                info.oid
+            );
+            ensure!(
+                info.commit_time == expected.commit_time,
+                "incorrect commit time for commit `{}`",
+                info.oid
+            );
+
+            let mut expected_generation = 1;
+            for &parent in &info.parents {
+                let parent = graph.find_commit(parent)?.ok_or_else(|| {
+                    anyhow!("parent `{}` is missing from the commit-graph", parent)
+                })?;
+                expected_generation = expected_generation.max(parent.generation.saturating_add(1));
+            }
+            ensure!(
+                info.generation == expected_generation.min(GENERATION_NUMBER_MAX),
+                "incorrect generation for commit `{}` (expected `{}`, found `{}`)",
+                info.oid,
+                expected_generation,
+                info.generation
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,234 @@
+use super::*;
+use crate::graph::DagBuilder;
+use crate::rev::RevWalk;
+use crate::test_utils::CommitGraphBuilder;
+use fallible_iterator::FallibleIterator;
+use std::str::FromStr;
+
+fn info(oid: &str, parents: &[&str], commit_time: i64) -> BitResult<CommitInfo> {
+    Ok(CommitInfo {
+        oid: Oid::from_str(oid)?,
+        tree: Oid::EMPTY_TREE,
+        parents: parents.iter().map(|parent| Oid::from_str(parent)).collect::<BitResult<_>>()?,
+        commit_time,
+        generation: GENERATION_NUMBER_INFINITY,
+    })
+}
+
+const A: &str = "1000000000000000000000000000000000000000";
+const B: &str = "2000000000000000000000000000000000000000";
+const C: &str = "3000000000000000000000000000000000000000";
+const D: &str = "4000000000000000000000000000000000000000";
+const E: &str = "5000000000000000000000000000000000000000";
+
+#[test]
+fn test_build_commit_graph_generations() -> BitResult<()> {
+    // a - b - d - e
+    //   \   /   /
+    //     c ----
+    let graph = CommitGraph::new([
+        info(E, &[D, C], 5)?,
+        info(D, &[B, C], 4)?,
+        info(C, &[A], 3)?,
+        info(B, &[A], 2)?,
+        info(A, &[], 1)?,
+    ])?;
+    let generations = graph.commits.iter().map(|commit| commit.generation).collect::<Vec<_>>();
+    assert_eq!(generations, [1, 2, 2, 3, 4]);
+    Ok(())
+}
+
+#[test]
+fn test_build_commit_graph_with_missing_parent() -> BitResult<()> {
+    let err = CommitGraph::new([info(B, &[A], 0)?]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("parent `{}` of commit `{}` is missing from the commit-graph", A, B)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_serde_commit_graph() -> BitResult<()> {
+    let graph = CommitGraph::new([
+        info(A, &[], 0)?,
+        info(B, &[A], 1 << 33)?,
+        info(C, &[A], 1_600_000_000)?,
+        info(D, &[A, B], 1_600_000_001)?,
+        // an octopus merge needs the extra edges chunk
+        info(E, &[D, B, C, A], 1_600_000_002)?,
+    ])?;
+
+    let mut bytes = vec![];
+    graph.serialize(&mut bytes)?;
+    assert_eq!(CommitGraph::deserialize_unbuffered(&bytes[..])?, graph);
+
+    let reader = CommitGraphReader::new(&bytes[..])?;
+    assert_eq!(reader.find_commit(Oid::from_str(E)?)?.as_ref(), graph.commits.last());
+    assert_eq!(reader.find_commit(Oid::UNKNOWN)?, None);
+    Ok(())
+}
+
+#[test]
+fn test_commit_graph_detects_corruption() -> BitResult<()> {
+    let graph = CommitGraph::new([info(A, &[], 0)?, info(B, &[A], 1)?])?;
+    let mut bytes = vec![];
+    graph.serialize(&mut bytes)?;
+    let n = bytes.len();
+    bytes[n / 2] ^= 0xff;
+    assert!(CommitGraphReader::new(&bytes[..]).and_then(|r| r.verify_checksum()).is_err());
+    assert!(CommitGraphReader::new(&bytes[..GRAPH_HEADER_SIZE]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_write_and_verify_commit_graph() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let mut dag = DagBuilder::default();
+        let [a, b, c, d, e] = dag.mk_nodes();
+        dag.add_parents([(b, a), (c, a), (d, b), (e, d), (e, c), (e, a)]);
+        let commits = CommitGraphBuilder::new(repo.clone()).apply(&dag)?;
+        bit_branch!(repo: "e" @ rev!(commits[&e]));
+
+        assert!(repo.commit_graph().is_none());
+        let graph = repo.write_commit_graph()?;
+        assert_eq!(graph.commits.len(), 5);
+        repo.verify_commit_graph()?;
+
+        let reader = repo.commit_graph().expect("commit-graph was just written");
+        for (node, generation) in [(a, 1), (b, 2), (c, 2), (d, 3), (e, 4)] {
+            let commit = repo.read_obj_commit(commits[&node])?;
+            let info = reader.find_commit(commits[&node])?.unwrap();
+            assert_eq!(info, CommitInfo { generation, ..CommitInfo::from_commit(&commit) });
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn test_write_commit_graph_without_commits() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let err = repo.write_commit_graph().unwrap_err();
+        assert_eq!(err.to_string(), "no commits to write to the commit-graph");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_corrupt_commit_graph_is_ignored() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let path = repo.commit_graph_path();
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, "not a commit-graph")?;
+        repo.refresh_commit_graph();
+        assert!(repo.commit_graph().is_none());
+        assert!(repo.verify_commit_graph().is_err());
+        Ok(())
+    })
+}
+
+/// a - b  - c - i - j
+///     \       /
+///      d  -  e  -  f
+///       \
+///        g - h
+#[test]
+fn test_history_queries_agree_with_commit_graph() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let mut dag = DagBuilder::default();
+        let nodes @ [a, b, c, d, e, f, g, h, i, j] = dag.mk_nodes();
+        dag.add_parents([
+            (j, i),
+            (i, e),
+            (i, c),
+            (c, b),
+            (b, a),
+            (e, d),
+            (f, e),
+            (h, g),
+            (g, d),
+            (d, b),
+        ]);
+        let commits = CommitGraphBuilder::new(repo.clone()).apply(&dag)?;
+        let oids = nodes.iter().map(|node| commits[node]).collect::<Vec<_>>();
+
+        let query = |repo: &BitRepo| -> BitResult<_> {
+            let mut merge_bases = vec![];
+            let mut ancestry = vec![];
+            for &x in &oids {
+                for &y in &oids {
+                    let mut bases =
+                        repo.merge_bases(x, y)?.iter().map(|base| base.oid()).collect::<Vec<_>>();
+                    bases.sort();
+                    merge_bases.push(bases);
+                    ancestry.push(repo.is_descendant_of(x, y)?);
+                }
+            }
+            let revwalk = RevWalk::walk_commits(
+                [commits[&j], commits[&f], commits[&h]]
+                    .into_iter()
+                    .map(|oid| repo.read_obj_commit(oid))
+                    .collect::<BitResult<Vec<_>>>()?,
+            )?;
+            let walked = revwalk.map(|commit| Ok(commit.oid())).collect::<Vec<_>>()?;
+            Ok((merge_bases, ancestry, walked))
+        };
+
+        let without_graph = query(&repo)?;
+        bit_branch!(repo: "j" @ rev!(commits[&j]));
+        bit_branch!(repo: "f" @ rev!(commits[&f]));
+        bit_branch!(repo: "h" @ rev!(commits[&h]));
+        repo.write_commit_graph()?;
+        assert!(repo.commit_graph().is_some());
+        let with_graph = query(&repo)?;
+        assert_eq!(without_graph, with_graph);
+
+        assert!(repo.is_descendant_of(commits[&j], commits[&a])?);
+        assert!(repo.is_descendant_of(commits[&h], commits[&d])?);
+        assert!(!repo.is_descendant_of(commits[&h], commits[&e])?);
+        assert!(!repo.is_descendant_of(commits[&a], commits[&j])?);
+        Ok(())
+    })
+}
+
+/// a - x - b
+///      \
+///       c1 - c2 - c3
+#[test]
+fn test_revwalk_waits_for_uninteresting_commits_with_larger_generations() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let mut dag = DagBuilder::default();
+        let [a, x, b, c1, c2, c3] = dag.mk_nodes();
+        dag.add_parents([(x, a), (b, x), (c1, x), (c2, c1), (c3, c2)]);
+        let commits = CommitGraphBuilder::new(repo.clone()).apply(&dag)?;
+        bit_branch!(repo: "b" @ rev!(commits[&b]));
+        bit_branch!(repo: "c" @ rev!(commits[&c3]));
+        repo.write_commit_graph()?;
+
+        // all the commits have the same timestamp in tests, so without generation numbers `x`
+        // is reached from `b` before the longer excluded path through `c3` gets to it
+        let revwalk = repo
+            .revwalk_builder()
+            .roots_iter([commits[&b]])?
+            .excluding(smallvec![repo.read_obj_commit(commits[&c3])?])
+            .build()?;
+        let walked = revwalk.map(|commit| Ok(commit.oid())).collect::<Vec<_>>()?;
+        assert_eq!(walked, [commits[&b]]);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_commit_time_is_normalized() -> BitResult<()> {
+    // the commit-graph has no room for negative or very large times
+    assert_eq!(normalize_commit_time(-1_000), 0);
+    assert_eq!(normalize_commit_time(1_600_000_000), 1_600_000_000);
+    assert_eq!(normalize_commit_time(i64::MAX), COMMIT_TIME_MASK as i64);
+
+    let graph = CommitGraph::new([info(A, &[], normalize_commit_time(-1_000))?])?;
+    let mut bytes = vec![];
+    graph.serialize(&mut bytes)?;
+    let reader = CommitGraphReader::new(&bytes[..])?;
+    assert_eq!(reader.find_commit(Oid::from_str(A)?)?.unwrap().commit_time, 0);
+    Ok(())
+}
@@ -1,7 +1,12 @@
+use crate::cache::DEFAULT_OBJ_CACHE_LIMIT;
 use crate::error::BitResult;
+use crate::gc::{DEFAULT_GC_AUTO, DEFAULT_GC_AUTO_PACK_LIMIT, DEFAULT_GC_PRUNE_EXPIRE};
 use crate::interner::Intern;
 use crate::merge::ConflictStyle;
+use crate::pack::DEFAULT_DELTA_BASE_CACHE_LIMIT;
+use crate::partial_clone::ObjectFilter;
 use crate::path::BitPath;
+use crate::receive_pack::DenyCurrentBranch;
 use crate::remote::Refspec;
 use crate::repo::BitRepo;
 use git_config::file::{GitConfig, GitConfigError, SectionBody};
@@ -14,6 +19,7 @@ use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs::File;
 use std::io::Write;
+use std::path::PathBuf;
 use std::str::FromStr;
 
 /// Defines a left biased merge operation
@@ -46,7 +52,15 @@ pub struct BitConfig {
 pub struct BitConfigInner {
     pub(crate) core: CoreConfig,
     pub(crate) user: UserConfig,
+    pub(crate) diff: DiffConfig,
     pub(crate) merge: MergeConfig,
+    pub(crate) gc: GcConfig,
+    pub(crate) http: HttpConfig,
+    pub(crate) credential: CredentialConfig,
+    pub(crate) protocol: ProtocolConfig,
+    pub(crate) pack: PackConfig,
+    pub(crate) receive: ReceiveConfig,
+    pub(crate) repack: RepackConfig,
     pub(crate) remote: RemotesConfig,
 }
 
@@ -79,21 +93,29 @@ impl BitConfigInner {
         Ok(Self {
             core: CoreConfig::from_config(config)?,
             user: UserConfig::from_config(config)?,
+            diff: DiffConfig::from_config(config)?,
             merge: MergeConfig::from_config(config)?,
+            gc: GcConfig::from_config(config)?,
+            http: HttpConfig::from_config(config)?,
+            credential: CredentialConfig::from_config(config)?,
+            protocol: ProtocolConfig::from_config(config)?,
+            pack: PackConfig::from_config(config)?,
+            receive: ReceiveConfig::from_config(config)?,
+            repack: RepackConfig::from_config(config)?,
             remote: RemotesConfig::from_config(config)?,
         })
     }
 
     /// Creates a merged configuration from the following sources (in order of increasing precedence):
     /// (NOT system atm /etc/gitconfig)
-    /// - config dir (~/.config/git/config)
+    /// - config dir ($XDG_CONFIG_HOME/git/config or ~/.config/git/config)
     /// - home directory (~/.gitconfig)
     /// - local config
     pub fn init(local_path: BitPath) -> BitResult<Self> {
         // start with the highest precedence config as `merge` is left-biased
         let mut config_paths = vec![local_path];
 
-        if let Some(config_dir) = dirs::config_dir() {
+        if let Some(config_dir) = xdg_config_home() {
             config_paths.push(BitPath::intern(config_dir.join("git/config")));
         }
 
@@ -109,6 +131,15 @@ impl BitConfigInner {
     }
 }
 
+/// The directory git looks in for its user-level `config` and `ignore` files.
+/// Unlike `dirs::config_dir`, this is `~/.config` on every platform unless overridden.
+pub fn xdg_config_home() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => dirs::home_dir().map(|home| home.join(".config")),
+    }
+}
+
 #[derive(Debug, PartialEq, Merge, Clone, Default)]
 pub struct RemotesConfig {
     pub remotes: HashMap<&'static str, RemoteConfig>,
@@ -132,6 +163,15 @@ impl RemotesConfig {
 pub struct RemoteConfig {
     pub url: GitUrl,
     pub fetch: Refspec,
+    /// `remote.<name>.uploadpack`: the program to run on the remote end when fetching
+    pub upload_pack: Option<String>,
+    /// `remote.<name>.receivepack`: the program to run on the remote end when pushing
+    pub receive_pack: Option<String>,
+    /// `remote.<name>.promisor`: whether the remote provides the objects missing from a partial
+    /// clone
+    pub promisor: Option<bool>,
+    /// `remote.<name>.partialclonefilter`: the filter used when fetching from a promisor remote
+    pub partial_clone_filter: Option<ObjectFilter>,
 }
 
 impl RemoteConfig {
@@ -140,10 +180,25 @@ impl RemoteConfig {
         Ok(RemoteConfig {
             url: section.get("url")?.ok_or_else(|| anyhow!("remote is missing `url`"))?,
             fetch: section.get("fetch")?.ok_or_else(|| anyhow!("remote is missing `fetch`"))?,
+            upload_pack: section.get("uploadpack")?,
+            receive_pack: section.get("receivepack")?,
+            promisor: section.get("promisor")?,
+            partial_clone_filter: section.get("partialclonefilter")?,
         })
     }
 }
 
+#[derive(Debug, Merge, Default)]
+pub struct DiffConfig {
+    renames: Option<bool>,
+}
+
+impl DiffConfig {
+    fn from_config(config: &RawConfig<'_>) -> BitResult<Self> {
+        Ok(Self { renames: config.get("diff", "renames")? })
+    }
+}
+
 #[derive(Debug, Merge, Default)]
 pub struct MergeConfig {
     conflict_style: Option<ConflictStyle>,
@@ -155,6 +210,95 @@ impl MergeConfig {
     }
 }
 
+#[derive(Debug, Merge, Default)]
+pub struct GcConfig {
+    gc_auto: Option<i64>,
+    gc_auto_pack_limit: Option<i64>,
+    gc_prune_expire: Option<String>,
+}
+
+impl GcConfig {
+    fn from_config(config: &RawConfig<'_>) -> BitResult<Self> {
+        Ok(Self {
+            gc_auto: config.get("gc", "auto")?,
+            gc_auto_pack_limit: config.get("gc", "autoPackLimit")?,
+            gc_prune_expire: config.get("gc", "pruneExpire")?,
+        })
+    }
+}
+
+#[derive(Debug, Merge, Default)]
+pub struct HttpConfig {
+    http_extra_header: Option<String>,
+}
+
+impl HttpConfig {
+    fn from_config(config: &RawConfig<'_>) -> BitResult<Self> {
+        Ok(Self { http_extra_header: config.get("http", "extraHeader")? })
+    }
+}
+
+#[derive(Debug, Merge, Default)]
+pub struct CredentialConfig {
+    credential_helper: Option<String>,
+}
+
+impl CredentialConfig {
+    fn from_config(config: &RawConfig<'_>) -> BitResult<Self> {
+        Ok(Self { credential_helper: config.get("credential", "helper")? })
+    }
+}
+
+#[derive(Debug, Merge, Default)]
+pub struct ProtocolConfig {
+    protocol_version: Option<i64>,
+}
+
+impl ProtocolConfig {
+    fn from_config(config: &RawConfig<'_>) -> BitResult<Self> {
+        Ok(Self { protocol_version: config.get("protocol", "version")? })
+    }
+}
+
+#[derive(Debug, Merge, Default)]
+pub struct PackConfig {
+    use_bitmaps: Option<bool>,
+}
+
+impl PackConfig {
+    fn from_config(config: &RawConfig<'_>) -> BitResult<Self> {
+        Ok(Self { use_bitmaps: config.get("pack", "useBitmaps")? })
+    }
+}
+
+#[derive(Debug, Merge, Default)]
+pub struct ReceiveConfig {
+    deny_non_fast_forwards: Option<bool>,
+    deny_deletes: Option<bool>,
+    deny_current_branch: Option<DenyCurrentBranch>,
+}
+
+impl ReceiveConfig {
+    fn from_config(config: &RawConfig<'_>) -> BitResult<Self> {
+        Ok(Self {
+            deny_non_fast_forwards: config.get("receive", "denyNonFastForwards")?,
+            deny_deletes: config.get("receive", "denyDeletes")?,
+            deny_current_branch: config.get("receive", "denyCurrentBranch")?,
+        })
+    }
+}
+
+#[derive(Debug, Merge, Default)]
+pub struct RepackConfig {
+    write_bitmaps: Option<bool>,
+}
+
+impl RepackConfig {
+    fn from_config(config: &RawConfig<'_>) -> BitResult<Self> {
+        Ok(Self { write_bitmaps: config.get("repack", "writeBitmaps")? })
+    }
+}
+
 #[derive(Debug, Merge, Default)]
 pub struct UserConfig {
     name: Option<String>,
@@ -173,6 +317,11 @@ pub struct CoreConfig {
     bare: Option<bool>,
     filemode: Option<bool>,
     pager: Option<String>,
+    editor: Option<String>,
+    excludes_file: Option<String>,
+    delta_base_cache_limit: Option<i64>,
+    object_cache_limit: Option<i64>,
+    commit_graph: Option<bool>,
 }
 
 impl CoreConfig {
@@ -194,6 +343,11 @@ impl CoreConfig {
             bare: get!("bare"),
             filemode: get!("filemode"),
             pager: get!("pager"),
+            editor: get!("editor"),
+            excludes_file: get!("excludesFile"),
+            delta_base_cache_limit: get!("deltaBaseCacheLimit"),
+            object_cache_limit: get!("objectCacheLimit"),
+            commit_graph: get!("commitGraph"),
         })
     }
 }
@@ -269,6 +423,12 @@ impl BitConfigValue for Refspec {
     }
 }
 
+impl BitConfigValue for ObjectFilter {
+    fn parse(bytes: &[u8]) -> BitResult<Self> {
+        ObjectFilter::from_str(std::str::from_utf8(bytes)?)
+    }
+}
+
 impl BitConfigValue for ConflictStyle {
     fn parse(bytes: &[u8]) -> BitResult<Self> {
         match bytes {
@@ -279,6 +439,20 @@ impl BitConfigValue for ConflictStyle {
     }
 }
 
+impl BitConfigValue for DenyCurrentBranch {
+    fn parse(bytes: &[u8]) -> BitResult<Self> {
+        match bytes {
+            b"refuse" => Ok(DenyCurrentBranch::Refuse),
+            b"warn" => Ok(DenyCurrentBranch::Warn),
+            b"ignore" => Ok(DenyCurrentBranch::Ignore),
+            _ => match bool::parse(bytes)? {
+                true => Ok(DenyCurrentBranch::Refuse),
+                false => Ok(DenyCurrentBranch::Ignore),
+            },
+        }
+    }
+}
+
 impl<'c> RawConfig<'c> {
     #[cfg(test)]
     pub fn new(source: &'c str) -> Self {
@@ -410,13 +584,36 @@ macro_rules! get {
 
 get!(core.filemode: bool, false);
 get!(core.pager: String, "less".to_owned());
+get!(core.delta_base_cache_limit: i64, DEFAULT_DELTA_BASE_CACHE_LIMIT as i64);
+get!(core.object_cache_limit: i64, DEFAULT_OBJ_CACHE_LIMIT as i64);
+get!(core.commit_graph: bool, true);
+
+get!(diff.renames: bool, true);
 
 get!(merge.conflict_style: ConflictStyle, ConflictStyle::Merge);
 
+get!(gc.gc_auto: i64, DEFAULT_GC_AUTO);
+get!(gc.gc_auto_pack_limit: i64, DEFAULT_GC_AUTO_PACK_LIMIT);
+get!(gc.gc_prune_expire: String, DEFAULT_GC_PRUNE_EXPIRE.to_owned());
+
+get!(protocol.protocol_version: i64, 2);
+
+get!(pack.use_bitmaps: bool, true);
+
+get!(repack.write_bitmaps: bool, false);
+
+get!(receive.deny_non_fast_forwards: bool, false);
+get!(receive.deny_deletes: bool, false);
+get!(receive.deny_current_branch: DenyCurrentBranch, DenyCurrentBranch::Refuse);
+
 get_opt!(core.bare: bool);
 get_opt!(core.repositoryformatversion: i64);
+get_opt!(core.excludes_file: String);
+get_opt!(core.editor: String);
 get_opt!(user.name: String);
 get_opt!(user.email: String);
+get_opt!(http.http_extra_header: String);
+get_opt!(credential.credential_helper: String);
 
 #[cfg(test)]
 mod tests;
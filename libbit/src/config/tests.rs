@@ -15,6 +15,10 @@ fn test_add_remote() -> BitResult<()> {
                 name: "foo",
                 fetch: Refspec::default_fetch_for_remote("foo"),
                 url: GitUrl::parse("bar")?,
+                upload_pack: None,
+                receive_pack: None,
+                promisor: false,
+                partial_clone_filter: None,
             }
         );
         assert!(remotes.next().is_none());
@@ -71,13 +75,38 @@ fn test_config_parse_remotes() -> BitResult<()> {
                 "origin" => RemoteConfig {
                     url: GitUrl::parse("git@github.com:andyyu2004/bit")?,
                     fetch: "+refs/heads/*:refs/remotes/origin/*".parse()?,
+                    upload_pack: None,
+                    receive_pack: None,
+                    promisor: None,
+                    partial_clone_filter: None,
                 },
                 "gitlab" => RemoteConfig {
                     url: GitUrl::parse("git@gitlab.com:andyyu2004/bit")?,
                     fetch: "+refs/heads/*:refs/remotes/origin/*".parse()?,
+                    upload_pack: None,
+                    receive_pack: None,
+                    promisor: None,
+                    partial_clone_filter: None,
                 }
             }
         }
     );
     Ok(())
 }
+
+#[test]
+fn test_config_parse_delta_base_cache_limit() -> BitResult<()> {
+    let raw = RawConfig::new("[core]\n\tdeltaBaseCacheLimit = 16m\n");
+    assert_eq!(CoreConfig::from_config(&raw)?.delta_base_cache_limit, Some(16 << 20));
+
+    let raw = RawConfig::new("[core]\n\tbare = false\n");
+    assert_eq!(CoreConfig::from_config(&raw)?.delta_base_cache_limit, None);
+    Ok(())
+}
+
+#[test]
+fn test_config_parse_object_cache_limit() -> BitResult<()> {
+    let raw = RawConfig::new("[core]\n\tobjectCacheLimit = 1g\n");
+    assert_eq!(CoreConfig::from_config(&raw)?.object_cache_limit, Some(1 << 30));
+    Ok(())
+}
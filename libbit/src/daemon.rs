@@ -0,0 +1,259 @@
+use crate::error::BitResult;
+use crate::protocol::{BitProtocolRead, BitProtocolWrite};
+use crate::repo::BitRepo;
+use crate::upload_pack::UploadPack;
+use parking_lot::{Condvar, Mutex};
+use std::io;
+use std::net::Shutdown;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+
+pub const DEFAULT_DAEMON_PORT: u16 = 9418;
+/// repositories are only served if this file exists in their git directory (unless `export_all`)
+pub const EXPORT_OK_FILE: &str = "git-daemon-export-ok";
+
+#[derive(Debug, Default)]
+pub struct DaemonOpts {
+    /// requested paths are relative to this directory if given
+    pub base_path: Option<PathBuf>,
+    /// serve repositories even if they don't contain the `git-daemon-export-ok` file
+    pub export_all: bool,
+    /// only repositories within these directories are served (all are if empty)
+    pub whitelist: Vec<PathBuf>,
+    /// time allowed between accepting a connection and receiving its request
+    pub init_timeout: Option<Duration>,
+    /// time allowed for a single read or write once the request has been received
+    pub timeout: Option<Duration>,
+    /// connections beyond this many concurrent connections are refused
+    pub max_connections: Option<usize>,
+}
+
+/// A read-only server for the `git://` protocol.
+/// Only `git-upload-pack` is served, `git-receive-pack` is never enabled.
+#[derive(Debug)]
+pub struct Daemon {
+    opts: DaemonOpts,
+}
+
+impl Daemon {
+    pub fn new(mut opts: DaemonOpts) -> BitResult<Self> {
+        // the repositories are compared against the whitelist by their canonical paths
+        for dir in &mut opts.whitelist {
+            *dir = dir.canonicalize()?;
+        }
+        Ok(Self { opts })
+    }
+
+    /// Accept connections forever.
+    /// Each connection is handled on its own blocking thread as entering a repository is tied to
+    /// the current thread.
+    pub async fn serve(self, listener: TcpListener) -> BitResult<()> {
+        let connections =
+            Arc::new(Semaphore::new(self.opts.max_connections.unwrap_or(Semaphore::MAX_PERMITS)));
+        let daemon = Arc::new(self);
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let permit = match Arc::clone(&connections).try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(..) => {
+                    warn!("daemon: too many connections, refusing connection from `{}`", addr);
+                    continue;
+                }
+            };
+            let daemon = Arc::clone(&daemon);
+            let runtime = Handle::current();
+            tokio::task::spawn_blocking(move || {
+                if let Err(err) = daemon.handle_with_timeouts(&runtime, stream) {
+                    warn!("daemon: request from `{}` failed: {}", addr, err);
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    fn handle_with_timeouts(&self, runtime: &Handle, stream: TcpStream) -> BitResult<()> {
+        let watchdog = Arc::new(Watchdog::new(self.opts.init_timeout));
+        let stream = if self.opts.init_timeout.is_some() || self.opts.timeout.is_some() {
+            // the watchdog shuts down its own handle to the socket which wakes up the handler
+            let stream = stream.into_std()?;
+            let socket = stream.try_clone()?;
+            let watchdog = Arc::clone(&watchdog);
+            std::thread::spawn(move || watchdog.watch(socket));
+            let _guard = runtime.enter();
+            TcpStream::from_std(stream)?
+        } else {
+            stream
+        };
+        let res = self.handle(runtime, stream, &watchdog);
+        watchdog.finish();
+        res
+    }
+
+    // the request is the first packet sent by the client and looks like
+    //    git-upload-pack /project.git\0host=myserver.com\0
+    // possibly followed by extra parameters (e.g. `\0version=1\0`) which are ignored
+    fn handle(
+        &self,
+        runtime: &Handle,
+        stream: TcpStream,
+        watchdog: &Arc<Watchdog>,
+    ) -> BitResult<()> {
+        let (reader, writer) = stream.into_split();
+        let mut reader = BufReader::new(Tracked::new(reader, Arc::clone(watchdog)));
+        let mut writer = Tracked::new(writer, Arc::clone(watchdog));
+        let packet = runtime.block_on(reader.recv_packet())?;
+        watchdog.set_timeout(self.opts.timeout);
+        let request = std::str::from_utf8(&packet)?;
+        let command = request.split('\0').next().unwrap_or_default().trim_end();
+        let (service, path) =
+            command.split_once(' ').ok_or_else(|| anyhow!("malformed request `{}`", command))?;
+
+        let repo_path = match service {
+            "git-upload-pack" => self
+                .resolve(path)
+                .ok_or_else(|| format!("access denied or repository not exported: {}", path)),
+            _ => Err(format!("service not enabled: {}", service)),
+        };
+        match repo_path {
+            Ok(repo_path) => BitRepo::open(repo_path, |repo| {
+                runtime.block_on(UploadPack::new(repo, reader, writer).serve())
+            }),
+            Err(err) => {
+                runtime.block_on(writer.write_error_line(&err))?;
+                bail!(err)
+            }
+        }
+    }
+
+    /// The path of the repository to serve for the requested `path` if it may be served at all
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        if Path::new(path).components().any(|component| component == Component::ParentDir) {
+            return None;
+        }
+        let path = match &self.opts.base_path {
+            Some(base_path) => base_path.join(path.trim_start_matches('/')),
+            None => PathBuf::from(path),
+        };
+
+        // the requested path itself must be the repository, parent directories are not searched
+        // as that would allow serving a repository by naming any directory within it
+        let path = path.canonicalize().ok()?;
+        let whitelisted = self.opts.whitelist.is_empty()
+            || self.opts.whitelist.iter().any(|dir| path.starts_with(dir));
+        let bitdir = BitRepo::bitdir_at(&path).ok()??;
+        let exported = self.opts.export_all || bitdir.join(EXPORT_OK_FILE).exists();
+        (whitelisted && exported).then_some(path)
+    }
+}
+
+/// Shuts down a connection once it has been idle for longer than the current timeout
+#[derive(Debug)]
+struct Watchdog {
+    state: Mutex<WatchdogState>,
+    condvar: Condvar,
+}
+
+#[derive(Debug)]
+struct WatchdogState {
+    last_active: Instant,
+    timeout: Option<Duration>,
+    finished: bool,
+}
+
+impl Watchdog {
+    fn new(timeout: Option<Duration>) -> Self {
+        let state = WatchdogState { last_active: Instant::now(), timeout, finished: false };
+        Self { state: Mutex::new(state), condvar: Condvar::new() }
+    }
+
+    fn touch(&self) {
+        self.state.lock().last_active = Instant::now();
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) {
+        let mut state = self.state.lock();
+        state.last_active = Instant::now();
+        state.timeout = timeout;
+        self.condvar.notify_one();
+    }
+
+    fn finish(&self) {
+        self.state.lock().finished = true;
+        self.condvar.notify_one();
+    }
+
+    fn watch(&self, socket: std::net::TcpStream) {
+        let mut state = self.state.lock();
+        while !state.finished {
+            match state.timeout {
+                Some(timeout) => {
+                    let deadline = state.last_active + timeout;
+                    if Instant::now() >= deadline {
+                        let _ = socket.shutdown(Shutdown::Both);
+                        return;
+                    }
+                    self.condvar.wait_until(&mut state, deadline);
+                }
+                None => self.condvar.wait(&mut state),
+            }
+        }
+    }
+}
+
+/// Records any progress made on the wrapped connection half with the watchdog
+struct Tracked<T> {
+    inner: T,
+    watchdog: Arc<Watchdog>,
+}
+
+impl<T> Tracked<T> {
+    fn new(inner: T, watchdog: Arc<Watchdog>) -> Self {
+        Self { inner, watchdog }
+    }
+
+    fn track<R>(&self, poll: Poll<R>) -> Poll<R> {
+        if poll.is_ready() {
+            self.watchdog.touch();
+        }
+        poll
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Tracked<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.track(poll)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Tracked<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.track(poll)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_flush(cx);
+        self.track(poll)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_shutdown(cx);
+        self.track(poll)
+    }
+}
@@ -1,16 +1,18 @@
-// allow for now as this unit is unused
-#![allow(dead_code)]
-
 use crate::error::BitResult;
-use crate::io::{BufReadExt, ReadExt};
-use crate::serialize::{Deserialize, DeserializeSized};
+use crate::io::{BufReadExt, ReadExt, WriteExt};
+use crate::serialize::{Deserialize, DeserializeSized, Serialize};
+use arrayvec::ArrayVec;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::{self, Debug, Formatter};
-use std::io::BufRead;
+use std::io::{BufRead, Write};
 use std::ops::Deref;
 
 const CHUNK_SIZE: usize = 16;
+/// the length of an insert is stored in the 7 bits following the MSB
+const MAX_INSERT_SIZE: usize = 0x7f;
+/// git never generates copies larger than this (although up to 3 bytes of size can be encoded)
+const MAX_COPY_SIZE: usize = 0x10000;
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct Delta {
@@ -20,6 +22,12 @@ pub struct Delta {
 }
 
 impl Delta {
+    /// Computes a delta that transforms `source` into `target`
+    pub fn compute(source: &[u8], target: &[u8]) -> Self {
+        let ops = DeltaIndex::new(source).compress(target).into_iter().map(DeltaOp::from).collect();
+        Self { source_size: source.len() as u64, target_size: target.len() as u64, ops }
+    }
+
     pub fn expand(&self, bytes: impl AsRef<[u8]>) -> BitResult<Vec<u8>> {
         trace!(
             "Delta::expand(bytes: ...) (source_size: {} -> target_size: {})",
@@ -66,6 +74,47 @@ pub enum DeltaOp {
     Insert(Vec<u8>),
 }
 
+impl From<DeltaOpSlice<'_>> for DeltaOp {
+    fn from(op: DeltaOpSlice<'_>) -> Self {
+        match op {
+            DeltaOpSlice::Copy(offset, size) => Self::Copy(offset as u64, size as u64),
+            DeltaOpSlice::Insert(bytes) => Self::Insert(bytes.to_vec()),
+        }
+    }
+}
+
+impl Serialize for DeltaOp {
+    fn serialize(&self, writer: &mut dyn Write) -> BitResult<()> {
+        match self {
+            &DeltaOp::Copy(offset, size) => {
+                debug_assert!(offset <= u32::MAX as u64);
+                debug_assert!(size <= 0xFFFFFF);
+                // the inverse of `read_le_packed`: only the non-zero bytes are written
+                // and the header records which bytes are present
+                let size = if size == 0x10000 { 0 } else { size };
+                let n = offset | size << 32;
+                let mut header = 0x80;
+                let mut bytes = ArrayVec::<u8, 7>::new();
+                for i in 0..7 {
+                    let byte = (n >> (i * 8)) as u8;
+                    if byte != 0 {
+                        header |= 1 << i;
+                        bytes.push(byte);
+                    }
+                }
+                writer.write_u8(header)?;
+                writer.write_all(&bytes)?;
+            }
+            DeltaOp::Insert(bytes) => {
+                debug_assert!(!bytes.is_empty() && bytes.len() <= MAX_INSERT_SIZE);
+                writer.write_u8(bytes.len() as u8)?;
+                writer.write_all(bytes)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Deserialize for DeltaOp {
     fn deserialize(mut reader: impl BufRead) -> BitResult<Self>
     where
@@ -118,6 +167,17 @@ impl DeserializeSized for Delta {
     }
 }
 
+impl Serialize for Delta {
+    fn serialize(&self, writer: &mut dyn Write) -> BitResult<()> {
+        writer.write_size(self.source_size)?;
+        writer.write_size(self.target_size)?;
+        for op in &self.ops {
+            op.serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Default)]
 struct DeltaIndex<'s> {
     source: &'s [u8],
@@ -164,8 +224,8 @@ impl<'s> DeltaIndex<'s> {
 struct DeltaIndexCompressor<'a, 's> {
     delta_index: &'a DeltaIndex<'s>,
     target: &'s [u8],
-    /// insert buffer
-    insert: &'s [u8],
+    /// start of the pending insert (which spans up to `target_idx`)
+    insert_start: usize,
     /// current index into target slice
     target_idx: usize,
     ops: Vec<DeltaOpSlice<'s>>,
@@ -173,56 +233,67 @@ struct DeltaIndexCompressor<'a, 's> {
 
 impl<'a, 's> DeltaIndexCompressor<'a, 's> {
     pub fn new(delta_index: &'a DeltaIndex<'s>, target: &'s [u8]) -> Self {
-        Self { delta_index, target, target_idx: 0, insert: b"", ops: Default::default() }
+        Self { delta_index, target, insert_start: 0, target_idx: 0, ops: Default::default() }
     }
 
-    /// returns the current slice of the target
+    /// returns the chunk of the target starting at the current index
     #[inline]
-    fn slice(&self) -> &'s [u8; 16] {
-        self.source[self.target_idx..self.target_idx + CHUNK_SIZE].try_into().unwrap()
+    fn chunk(&self) -> &'s [u8; CHUNK_SIZE] {
+        self.target[self.target_idx..self.target_idx + CHUNK_SIZE].try_into().unwrap()
     }
 
-    fn expand_left(&mut self, mut source_idx: usize, mut target_idx: usize) -> usize {
-        debug_assert_eq!(self.source[source_idx], self.target[target_idx]);
-        while self.source[target_idx - 1] == self.target[target_idx - 1] {
-            source_idx -= 1;
-            target_idx -= 1;
-            self.insert = &self.insert[..self.insert.len() - 1];
+    /// extends the match of the current target chunk with the source chunk at `source_idx` in both directions
+    /// returns the range of the match in the source and the start of the match in the target
+    fn expand_match(&self, source_idx: usize) -> (usize, usize, usize) {
+        let (source, target) = (self.source, self.target);
+        let (mut start, mut target_start) = (source_idx, self.target_idx);
+        // only bytes that are still part of the pending insert can be absorbed into the copy
+        while start > 0
+            && target_start > self.insert_start
+            && source[start - 1] == target[target_start - 1]
+        {
+            start -= 1;
+            target_start -= 1;
         }
-        source_idx
-    }
 
-    fn expand_right(&mut self, mut source_idx: usize, mut target_idx: usize) -> usize {
-        debug_assert_eq!(self.source[source_idx - 1], self.target[target_idx - 1]);
-        while self.source[source_idx] == self.target[target_idx] {
-            source_idx += 1;
-            target_idx += 1;
+        let (mut end, mut target_end) = (source_idx + CHUNK_SIZE, self.target_idx + CHUNK_SIZE);
+        while end < source.len() && target_end < target.len() && source[end] == target[target_end] {
+            end += 1;
+            target_end += 1;
         }
-        source_idx
+        (start, end, target_start)
     }
 
-    /// returns range into the source slice
-    fn expand_match(&mut self, source_idx: usize, target_idx: usize) -> (usize, usize) {
-        let l = self.expand_left(source_idx, target_idx);
-        let r = self.expand_right(source_idx + CHUNK_SIZE, target_idx + CHUNK_SIZE);
-        (l, r)
+    /// emits the pending insert (if any) splitting it as necessary
+    fn flush_insert(&mut self) {
+        let insert = &self.target[self.insert_start..self.target_idx];
+        self.ops.extend(insert.chunks(MAX_INSERT_SIZE).map(DeltaOpSlice::Insert));
+        self.insert_start = self.target_idx;
     }
 
-    // everything here is wrong (including anything it calls transitively)
     fn compress(mut self) -> Vec<DeltaOpSlice<'s>> {
-        loop {
-            let slice = self.slice();
-            dbg!(unsafe { std::str::from_utf8_unchecked(slice) });
-            if let Some(&source_idx) = self.indices.get(slice) {
-                let (start, end) = self.expand_match(source_idx, self.target_idx);
-                self.ops.push(DeltaOpSlice::Insert(std::mem::take(&mut self.insert)));
-                self.ops.push(DeltaOpSlice::Copy(start, end - start));
-                self.target_idx = end;
-            } else {
-                self.insert = slice;
-                self.target_idx += 1;
+        while self.target_idx + CHUNK_SIZE <= self.target.len() {
+            let source_idx = match self.indices.get(self.chunk()) {
+                Some(&source_idx) => source_idx,
+                None => {
+                    self.target_idx += 1;
+                    continue;
+                }
+            };
+
+            let (start, end, target_start) = self.expand_match(source_idx);
+            self.target_idx = target_start;
+            self.flush_insert();
+            for offset in (start..end).step_by(MAX_COPY_SIZE) {
+                self.ops.push(DeltaOpSlice::Copy(offset, MAX_COPY_SIZE.min(end - offset)));
             }
+            self.target_idx += end - start;
+            self.insert_start = self.target_idx;
         }
+
+        self.target_idx = self.target.len();
+        self.flush_insert();
+        self.ops
     }
 }
 
@@ -46,10 +46,56 @@ fn test_parse_delta_zero_size() -> BitResult<()> {
     Ok(())
 }
 
-// #[test]
-// fn test_delta_compress_simple_outputs_correct_operations() {
-//     let source = b"the quick brown fox jumps over the slow lazy dog";
-//     let target = b"over the slow lazy dog the quick brown fox jumps";
-//     let ops = DeltaIndex::new(source).compress(target);
-//     dbg!(ops);
-// }
+fn compute_then_expand(source: &[u8], target: &[u8]) -> BitResult<Delta> {
+    let delta = Delta::compute(source, target);
+    let mut buf = vec![];
+    delta.serialize(&mut buf)?;
+    let deserialized = Delta::deserialize_sized(&buf[..], buf.len() as u64)?;
+    assert_eq!(deserialized, delta);
+    assert_eq!(delta.expand(source)?, target);
+    Ok(delta)
+}
+
+#[test]
+fn test_delta_compress_simple_outputs_correct_operations() -> BitResult<()> {
+    let source = b"the quick brown fox jumps over the slow lazy dog";
+    let target = b"over the slow lazy dog the quick brown fox jumps";
+    let delta = compute_then_expand(source, target)?;
+    assert_eq!(
+        delta.ops,
+        vec![DeltaOp::Copy(26, 22), DeltaOp::Insert(b" ".to_vec()), DeltaOp::Copy(0, 25)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_delta_compress_without_matches() -> BitResult<()> {
+    let delta = compute_then_expand(b"short", b"entirely different contents")?;
+    assert_eq!(delta.ops, vec![DeltaOp::Insert(b"entirely different contents".to_vec())]);
+    Ok(())
+}
+
+#[test]
+fn test_delta_compress_splits_large_inserts_and_copies() -> BitResult<()> {
+    let source = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let mut target = vec![0xff; 300];
+    target.extend_from_slice(&source);
+    let delta = compute_then_expand(&source, &target)?;
+    assert!(delta.ops.iter().all(|op| match op {
+        DeltaOp::Copy(_, size) => *size as usize <= MAX_COPY_SIZE,
+        DeltaOp::Insert(bytes) => bytes.len() <= MAX_INSERT_SIZE,
+    }));
+    Ok(())
+}
+
+#[quickcheck]
+fn test_delta_compute_then_expand(source: Vec<u8>, edits: Vec<(usize, Vec<u8>)>) -> BitResult<()> {
+    // derive the target from the source so there is actually something in common
+    let mut target = source.clone();
+    for (i, bytes) in edits {
+        let i = if target.is_empty() { 0 } else { i % target.len() };
+        target = [&target[..i], &bytes[..], &target[i..]].concat();
+    }
+    compute_then_expand(&source, &target)?;
+    Ok(())
+}
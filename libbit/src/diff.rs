@@ -1,4 +1,6 @@
+mod rename;
 mod tree_diff;
+pub use rename::*;
 pub use tree_diff::*;
 
 use crate::error::{BitGenericError, BitResult};
@@ -97,6 +99,17 @@ pub trait Differ {
     fn on_created(&mut self, new: BitIndexEntry) -> BitResult<()>;
     fn on_modified(&mut self, old: BitIndexEntry, new: BitIndexEntry) -> BitResult<()>;
     fn on_deleted(&mut self, old: BitIndexEntry) -> BitResult<()>;
+
+    /// Only called if rename detection is enabled, defaults to a deletion and a creation
+    fn on_renamed(&mut self, rename: RenameEntry) -> BitResult<()> {
+        self.on_deleted(rename.old)?;
+        self.on_created(rename.new)
+    }
+
+    /// Only called if copy detection is enabled, defaults to a creation
+    fn on_copied(&mut self, copy: RenameEntry) -> BitResult<()> {
+        self.on_created(copy.new)
+    }
 }
 
 pub struct IndexWorktreeDiffDriver<'d, D, I, J>
@@ -230,7 +243,12 @@ impl BitRepo {
         b: impl BitTreeIterator,
         opts: DiffOpts,
     ) -> BitResult<WorkspaceStatus> {
-        TreeStatusDiffer::default().build_diff(self.clone(), a, b, opts)
+        let renames = opts.renames;
+        let mut status = TreeStatusDiffer::default().build_diff(self.clone(), a, b, opts)?;
+        if let Some(rename_opts) = renames {
+            status.detect_renames(self, rename_opts)?;
+        }
+        Ok(status)
     }
 
     pub fn diff_iterators(
@@ -295,15 +313,36 @@ pub struct WorkspaceStatus {
     pub new: Vec<BitIndexEntry>,
     pub modified: Vec<(BitIndexEntry, BitIndexEntry)>,
     pub deleted: Vec<BitIndexEntry>,
+    /// only populated by [`WorkspaceStatus::detect_renames`]
+    pub renamed: Vec<RenameEntry>,
+    /// only populated by [`WorkspaceStatus::detect_renames`] with copy detection enabled
+    pub copied: Vec<RenameEntry>,
 }
 
 impl WorkspaceStatus {
     pub fn len(&self) -> usize {
-        self.new.len() + self.modified.len() + self.deleted.len()
+        self.new.len()
+            + self.modified.len()
+            + self.deleted.len()
+            + self.renamed.len()
+            + self.copied.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.new.is_empty() && self.deleted.is_empty() && self.modified.is_empty()
+        self.new.is_empty()
+            && self.deleted.is_empty()
+            && self.modified.is_empty()
+            && self.renamed.is_empty()
+            && self.copied.is_empty()
+    }
+
+    /// Every entry on either side of the diff
+    fn entries(&self) -> impl Iterator<Item = &BitIndexEntry> {
+        self.deleted
+            .iter()
+            .chain(self.modified.iter().flat_map(|(old, new)| [old, new]))
+            .chain(self.renamed.iter().chain(&self.copied).flat_map(|r| [&r.old, &r.new]))
+            .chain(&self.new)
     }
 
     pub fn iter_paths(&self) -> impl Iterator<Item = BitPath> + '_ {
@@ -312,6 +351,7 @@ impl WorkspaceStatus {
             .map(BitEntry::path)
             .chain(self.modified.iter().map(|(_, entry)| entry.path()))
             .chain(self.deleted.iter().map(BitEntry::path))
+            .chain(self.renamed.iter().chain(&self.copied).map(|rename| rename.new.path))
     }
 }
 
@@ -369,6 +409,11 @@ where
 
 pub trait Diff {
     fn apply_with<D: Differ>(self, differ: &mut D) -> BitResult<()>;
+
+    /// Fetch the objects the diff will read that are missing from a partial clone
+    fn prefetch_objects(&self, _repo: &BitRepo) -> BitResult<()> {
+        Ok(())
+    }
 }
 
 impl<'a> Diff for &'a WorkspaceStatus {
@@ -379,11 +424,31 @@ impl<'a> Diff for &'a WorkspaceStatus {
         for &(old, new) in self.modified.iter() {
             differ.on_modified(old, new)?;
         }
+        for &rename in self.renamed.iter() {
+            differ.on_renamed(rename)?;
+        }
+        for &copy in self.copied.iter() {
+            differ.on_copied(copy)?;
+        }
         for &new in self.new.iter() {
             differ.on_created(new)?;
         }
         Ok(())
     }
+
+    fn prefetch_objects(&self, repo: &BitRepo) -> BitResult<()> {
+        if !repo.is_partial_clone() {
+            return Ok(());
+        }
+        let mut oids = vec![];
+        for entry in self.entries() {
+            // entries from the worktree may have been hashed without being written
+            if entry.oid.is_known() && entry.read_unstored_from_worktree(repo)?.is_none() {
+                oids.push(entry.oid);
+            }
+        }
+        repo.prefetch_objects(oids)
+    }
 }
 
 impl<I> Diff for EntryDiffIter<I>
@@ -0,0 +1,227 @@
+use super::*;
+use rustc_hash::FxHashMap;
+
+/// Pairs of created and deleted entries are only compared by content if there are fewer than
+/// this many candidates on each side (as every pair has to be compared)
+const RENAME_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenameOpts {
+    /// the minimum similarity (as a percentage) for two entries to be considered a rename or copy
+    pub threshold: u8,
+    /// also look for created entries that are copies of modified or renamed entries
+    pub find_copies: bool,
+}
+
+impl RenameOpts {
+    pub const DEFAULT_THRESHOLD: u8 = 50;
+
+    pub fn with_threshold(threshold: u8) -> Self {
+        Self { threshold, ..Default::default() }
+    }
+}
+
+impl Default for RenameOpts {
+    fn default() -> Self {
+        Self { threshold: Self::DEFAULT_THRESHOLD, find_copies: false }
+    }
+}
+
+/// An entry that was renamed (or copied) from `old` to `new`, possibly with some modifications
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenameEntry {
+    pub old: BitIndexEntry,
+    pub new: BitIndexEntry,
+    /// how similar the contents of `old` and `new` are as a percentage
+    pub similarity: u8,
+}
+
+/// Parses a similarity threshold in the same format as git's `-M<n>`.
+/// `n` is either a percentage (`50%`) or the digits after the decimal point (`5` is `50%`).
+pub fn parse_similarity(s: &str) -> BitResult<u8> {
+    let similarity = match s.strip_suffix('%') {
+        Some(percentage) => percentage.parse().ok().filter(|&percentage: &u8| percentage <= 100),
+        // only the first two digits after the decimal point matter
+        None if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) =>
+            Some(format!("{:0<2}", &s[..s.len().min(2)]).parse().unwrap()),
+        None => None,
+    };
+    similarity.ok_or_else(|| anyhow!("invalid similarity `{}`", s))
+}
+
+/// Estimates how similar two blobs are as a percentage.
+/// Similar to git, this is the number of bytes of `old` that survive into `new`
+/// (in units of whole lines) relative to the size of the larger of the two.
+pub(crate) fn similarity(old: &[u8], new: &[u8]) -> u8 {
+    let max = old.len().max(new.len());
+    if max == 0 {
+        return 100;
+    }
+
+    let mut lines = FxHashMap::<&[u8], usize>::default();
+    for line in old.split_inclusive(|&b| b == b'\n') {
+        *lines.entry(line).or_default() += 1;
+    }
+
+    let mut common = 0;
+    for line in new.split_inclusive(|&b| b == b'\n') {
+        if let Some(count) = lines.get_mut(line).filter(|count| **count > 0) {
+            *count -= 1;
+            common += line.len();
+        }
+    }
+    (common * 100 / max) as u8
+}
+
+struct RenameDetector<'r> {
+    repo: &'r BitRepo,
+    opts: RenameOpts,
+    contents: FxHashMap<(BitPath, Oid), Vec<u8>>,
+}
+
+impl<'r> RenameDetector<'r> {
+    fn is_candidate(entry: &BitIndexEntry) -> bool {
+        // empty files are not worth pairing up as they are all identical
+        entry.mode.is_blob() && !entry.is_unmerged() && entry.oid != Oid::EMPTY_BLOB
+    }
+
+    fn contents(&mut self, entry: BitIndexEntry) -> BitResult<&[u8]> {
+        let key = (entry.path, entry.oid);
+        if !self.contents.contains_key(&key) {
+            let bytes = entry.read_to_bytes(self.repo)?;
+            self.contents.insert(key, bytes);
+        }
+        Ok(&self.contents[&key])
+    }
+
+    /// Compare every pair of `new` and `sources` by content, returning the pairs that are similar
+    /// enough as `(similarity, new_index, source_index)` with the most similar pairs first
+    fn similar_pairs(
+        &mut self,
+        new: &[Option<BitIndexEntry>],
+        sources: &[Option<BitIndexEntry>],
+    ) -> BitResult<Vec<(u8, usize, usize)>> {
+        let new_count = new.iter().flatten().count();
+        let source_count = sources.iter().flatten().count();
+        if new_count > RENAME_LIMIT || source_count > RENAME_LIMIT {
+            return Ok(vec![]);
+        }
+
+        let mut pairs = vec![];
+        let new = new.iter().enumerate().filter_map(|(j, new)| Some((j, (*new)?)));
+        for (j, new) in new {
+            for (i, &source) in sources.iter().enumerate() {
+                let source = match source {
+                    Some(source) => source,
+                    None => continue,
+                };
+                // a symlink can't be renamed to a regular file
+                if new.mode.is_link() != source.mode.is_link() {
+                    continue;
+                }
+
+                let new_len = self.contents(new)?.len();
+                let source_len = self.contents(source)?.len();
+                // the similarity can't be any higher than the ratio of the sizes
+                let (min, max) = (new_len.min(source_len), new_len.max(source_len));
+                if min * 100 < max * self.opts.threshold as usize {
+                    continue;
+                }
+
+                let score = similarity(
+                    &self.contents[&(source.path, source.oid)],
+                    &self.contents[&(new.path, new.oid)],
+                );
+                if score >= self.opts.threshold {
+                    pairs.push((score, j, i));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+        Ok(pairs)
+    }
+
+    fn detect(&mut self, status: &mut WorkspaceStatus) -> BitResult<()> {
+        let mut new = std::mem::take(&mut status.new).into_iter().map(Some).collect::<Vec<_>>();
+        let mut deleted =
+            std::mem::take(&mut status.deleted).into_iter().map(Some).collect::<Vec<_>>();
+
+        // exact renames are cheap to find so look for them first
+        let mut deleted_by_oid = FxHashMap::<Oid, Vec<usize>>::default();
+        for (i, old) in deleted.iter().enumerate().rev() {
+            let old = old.as_ref().unwrap();
+            if Self::is_candidate(old) && old.oid.is_known() {
+                deleted_by_oid.entry(old.oid).or_default().push(i);
+            }
+        }
+        for new_slot in new.iter_mut().filter(|new| Self::is_candidate(new.as_ref().unwrap())) {
+            let oid = new_slot.as_ref().unwrap().oid;
+            if let Some(i) = deleted_by_oid.get_mut(&oid).and_then(Vec::pop) {
+                status.renamed.push(RenameEntry {
+                    old: deleted[i].take().unwrap(),
+                    new: new_slot.take().unwrap(),
+                    similarity: 100,
+                });
+            }
+        }
+
+        let candidates = |entries: &[Option<BitIndexEntry>]| {
+            entries.iter().map(|entry| entry.filter(Self::is_candidate)).collect::<Vec<_>>()
+        };
+
+        // the remaining entries are paired up by content, most similar pairs first
+        for (similarity, j, i) in self.similar_pairs(&candidates(&new), &candidates(&deleted))? {
+            if new[j].is_none() || deleted[i].is_none() {
+                continue;
+            }
+            let (old, new) = (deleted[i].take().unwrap(), new[j].take().unwrap());
+            status.renamed.push(RenameEntry { old, new, similarity });
+        }
+
+        if self.opts.find_copies {
+            // as with git (without `--find-copies-harder`), only files that were changed are
+            // considered as the source of a copy
+            let sources = status
+                .modified
+                .iter()
+                .map(|&(old, _)| old)
+                .chain(status.renamed.iter().map(|rename| rename.old))
+                .filter(Self::is_candidate)
+                .map(Some)
+                .collect::<Vec<_>>();
+
+            for (similarity, j, i) in self.similar_pairs(&candidates(&new), &sources)? {
+                if let Some(new) = new[j].take() {
+                    let old = sources[i].unwrap();
+                    status.copied.push(RenameEntry { old, new, similarity });
+                }
+            }
+        }
+
+        status.new = new.into_iter().flatten().collect();
+        status.deleted = deleted.into_iter().flatten().collect();
+        status.renamed.sort_by(|a, b| a.new.diff_cmp(&b.new));
+        status.copied.sort_by(|a, b| a.new.diff_cmp(&b.new));
+        Ok(())
+    }
+}
+
+impl WorkspaceStatus {
+    /// Pairs up deleted and created entries with similar contents as renames.
+    /// If `opts.find_copies` is set, created entries similar to a changed entry are
+    /// recorded as copies.
+    pub fn detect_renames(&mut self, repo: &BitRepo, opts: RenameOpts) -> BitResult<()> {
+        if self.new.is_empty() || (self.deleted.is_empty() && !opts.find_copies) {
+            return Ok(());
+        }
+        (&*self).prefetch_objects(repo)?;
+        RenameDetector { repo, opts, contents: Default::default() }.detect(self)
+    }
+}
+
+impl BitRepo {
+    /// The rename detection to use by default (as configured by `diff.renames`)
+    pub(crate) fn default_rename_opts(&self) -> Option<RenameOpts> {
+        self.config().renames().then(RenameOpts::default)
+    }
+}
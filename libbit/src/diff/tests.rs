@@ -1,4 +1,4 @@
-use crate::diff::{DiffOptFlags, DiffOpts};
+use crate::diff::{parse_similarity, similarity, DiffOptFlags, DiffOpts, RenameOpts};
 use crate::error::BitResult;
 use crate::obj::FileMode;
 use crate::pathspec::Pathspec;
@@ -194,3 +194,133 @@ fn test_tree_diff_include_unmodified() -> BitResult<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_tree_diff_exact_rename() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let a = tree! {
+            bar
+            foo < "some contents"
+        };
+        let b = tree! {
+            bar
+            baz < "some contents"
+        };
+
+        // renames are only detected if requested
+        let diff = repo.diff_tree_to_tree(a, b)?;
+        assert!(diff.renamed.is_empty());
+        assert_eq!(diff.new.len(), 1);
+        assert_eq!(diff.deleted.len(), 1);
+
+        let diff =
+            repo.diff_tree_to_tree_with_opts(a, b, DiffOpts::with_renames(Default::default()))?;
+        assert!(diff.new.is_empty());
+        assert!(diff.deleted.is_empty());
+        assert_eq!(diff.renamed.len(), 1);
+        assert_eq!(diff.renamed[0].old.path, "foo");
+        assert_eq!(diff.renamed[0].new.path, "baz");
+        assert_eq!(diff.renamed[0].similarity, 100);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_tree_diff_similar_rename_threshold() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let a = tree! {
+            foo < "a\nb\nc\nd\n"
+            unrelated < "x\ny\nz\n"
+        };
+        let b = tree! {
+            bar < "a\nb\nc\ne\n"
+            other < "1\n2\n3\n"
+        };
+
+        let diff =
+            repo.diff_tree_to_tree_with_opts(a, b, DiffOpts::with_renames(Default::default()))?;
+        assert_eq!(diff.renamed.len(), 1);
+        assert_eq!(diff.renamed[0].old.path, "foo");
+        assert_eq!(diff.renamed[0].new.path, "bar");
+        assert_eq!(diff.renamed[0].similarity, 75);
+        assert_eq!(diff.new.len(), 1);
+        assert_eq!(diff.new[0].path, "other");
+        assert_eq!(diff.deleted.len(), 1);
+        assert_eq!(diff.deleted[0].path, "unrelated");
+
+        let opts = DiffOpts::with_renames(RenameOpts::with_threshold(80));
+        let diff = repo.diff_tree_to_tree_with_opts(a, b, opts)?;
+        assert!(diff.renamed.is_empty());
+        assert_eq!(diff.new.len(), 2);
+        assert_eq!(diff.deleted.len(), 2);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_tree_diff_copy_detection() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let a = tree! {
+            foo < "1\n2\n3\n4\n"
+        };
+        let b = tree! {
+            copy < "1\n2\n3\n4\n"
+            foo < "1\n2\n3\n4\n5\n"
+        };
+
+        let diff =
+            repo.diff_tree_to_tree_with_opts(a, b, DiffOpts::with_renames(Default::default()))?;
+        assert!(diff.copied.is_empty());
+        assert_eq!(diff.new.len(), 1);
+
+        let opts = RenameOpts { find_copies: true, ..Default::default() };
+        let diff = repo.diff_tree_to_tree_with_opts(a, b, DiffOpts::with_renames(opts))?;
+        assert!(diff.new.is_empty());
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.copied.len(), 1);
+        assert_eq!(diff.copied[0].old.path, "foo");
+        assert_eq!(diff.copied[0].new.path, "copy");
+        assert_eq!(diff.copied[0].similarity, 100);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_status_detects_staged_renames() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        rm!(repo: "foo");
+        touch!(repo: "bar" < "default foo contents");
+        bit_add_all!(repo);
+
+        let status = bit_status!(repo);
+        assert!(status.staged.new.is_empty());
+        assert!(status.staged.deleted.is_empty());
+        assert_eq!(status.staged.renamed.len(), 1);
+        assert_eq!(status.staged.renamed[0].old.path, "foo");
+        assert_eq!(status.staged.renamed[0].new.path, "bar");
+
+        repo.with_raw_local_config(|config| config.set("diff", "renames", false))?;
+        let status = bit_status!(repo);
+        assert!(status.staged.renamed.is_empty());
+        assert_eq!(status.staged.new.len(), 1);
+        assert_eq!(status.staged.deleted.len(), 1);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_parse_similarity() -> BitResult<()> {
+    assert_eq!(parse_similarity("50%")?, 50);
+    assert_eq!(parse_similarity("100%")?, 100);
+    assert_eq!(parse_similarity("5")?, 50);
+    assert_eq!(parse_similarity("05")?, 5);
+    assert_eq!(parse_similarity("755")?, 75);
+    assert!(parse_similarity("101%").is_err());
+    assert!(parse_similarity("").is_err());
+    assert!(parse_similarity("x").is_err());
+
+    assert_eq!(similarity(b"", b""), 100);
+    assert_eq!(similarity(b"a\nb\n", b"b\na\n"), 100);
+    assert_eq!(similarity(b"a\nb\n", b"c\nd\n"), 0);
+    Ok(())
+}
@@ -9,13 +9,20 @@ use std::fmt::{self, Debug, Formatter};
 #[derive(Debug, Default)]
 pub struct DiffOpts {
     pub flags: DiffOptFlags,
+    /// pair up deleted and created files as renames (only applies to [`WorkspaceStatus`] diffs)
+    pub renames: Option<RenameOpts>,
 }
 
 impl DiffOpts {
-    pub const INCLUDE_UNMODIFIED: Self = Self { flags: DiffOptFlags::INCLUDE_UNMODIFIED };
+    pub const INCLUDE_UNMODIFIED: Self =
+        Self { flags: DiffOptFlags::INCLUDE_UNMODIFIED, renames: None };
 
     pub fn with_flags(flags: DiffOptFlags) -> Self {
-        Self { flags }
+        Self { flags, ..Default::default() }
+    }
+
+    pub fn with_renames(renames: RenameOpts) -> Self {
+        Self { renames: Some(renames), ..Default::default() }
     }
 
     pub fn include_unmodified(&self) -> bool {
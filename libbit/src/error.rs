@@ -23,6 +23,12 @@ pub enum BitError {
     CheckoutConflict(CheckoutConflicts),
     ExpectedCommit(Oid, BitObjType),
     PackBackendWrite,
+    /// the remote sent an `ERR` pkt-line (e.g. it doesn't have the object we asked for)
+    RemoteErr(String),
+    /// the remote sent a message on the error sideband (3) and gave up on sending the pack
+    RemoteSidebandError(String),
+    /// the ref didn't have the expected value when it was locked to be updated
+    StaleRef(SymbolicRef),
 }
 
 pub trait BitErrorExt {
@@ -97,6 +103,8 @@ impl BitErrorExt for BitGenericError {
 pub trait BitResultExt {
     fn is_not_found_err(&self) -> bool;
     fn is_fatal(&self) -> bool;
+    fn is_remote_err(&self) -> bool;
+    fn is_stale_ref_err(&self) -> bool;
 }
 
 macro_rules! error_ext_is_method {
@@ -114,6 +122,10 @@ impl<T> BitResultExt for BitResult<T> {
     error_ext_is_method!(is_not_found_err);
 
     error_ext_is_method!(is_fatal);
+
+    error_ext_is_method!(is_remote_err);
+
+    error_ext_is_method!(is_stale_ref_err);
 }
 
 impl BitResultExt for BitGenericError {
@@ -136,10 +148,22 @@ impl BitResultExt for BitGenericError {
                     | BitError::MergeConflict(..)
                     | BitError::CheckoutConflict(..)
                     | BitError::PackBackendWrite
+                    | BitError::StaleRef(..)
             ),
             None => true,
         }
     }
+
+    fn is_remote_err(&self) -> bool {
+        match self.downcast_ref::<BitError>() {
+            Some(err) => matches!(err, BitError::RemoteErr(..) | BitError::RemoteSidebandError(..)),
+            None => false,
+        }
+    }
+
+    fn is_stale_ref_err(&self) -> bool {
+        matches!(self.downcast_ref::<BitError>(), Some(BitError::StaleRef(..)))
+    }
 }
 
 macro_rules! write_hint {
@@ -172,6 +196,10 @@ impl Display for BitError {
             }
             BitError::ExpectedCommit(oid, obj_type) =>
                 writeln!(f, "`{oid}` is a {obj_type}, expected commit"),
+            BitError::RemoteErr(msg) => write!(f, "remote error: {msg}"),
+            BitError::RemoteSidebandError(msg) =>
+                write!(f, "remote error while sending pack: {msg}"),
+            BitError::StaleRef(sym) => write!(f, "ref `{sym}` changed while it was being updated"),
         }
     }
 }
@@ -0,0 +1,569 @@
+use crate::error::BitResult;
+use crate::hash;
+use crate::obj::{BitObjType, FileMode, Oid};
+use crate::pack::{DeltaBaseCache, Pack, PACK_IDX_EXT};
+use crate::path::BitPath;
+use crate::repo::BitRepo;
+use fallible_iterator::FallibleIterator;
+use filebuffer::FileBuffer;
+use flate2::read::ZlibDecoder;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cmp::Ordering;
+use std::ffi::OsStr;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// The output of `bit fsck` is one problem or object per line in the same format as git
+// e.g. `error in tree <oid>: treeNotSorted: entries are not properly sorted`,
+// `missing blob <oid>` or `dangling commit <oid>`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsckSeverity {
+    Error,
+    Warning,
+}
+
+impl Display for FsckSeverity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckSeverity::Error => write!(f, "error"),
+            FsckSeverity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Where a problem was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckLocation {
+    /// the type is not known if the object couldn't be read
+    Object(Option<BitObjType>, Oid),
+    Pack(PathBuf),
+}
+
+impl Display for FsckLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckLocation::Object(obj_type, oid) => write!(f, "{} {}", ObjTypeName(*obj_type), oid),
+            FsckLocation::Pack(path) => write!(f, "pack {}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckProblemKind {
+    /// the object (or pack) could not be read
+    Corrupt(String),
+    /// the contents of the object hash to something other than its oid
+    HashMismatch(Oid),
+    BadCrc,
+    BadPackChecksum,
+    BadTree,
+    BadFilemode(String),
+    ZeroPaddedFilemode,
+    BadEntryName(String),
+    TreeNotSorted,
+    DuplicateEntries,
+    MissingHeader(&'static str),
+    BadHeader(&'static str),
+}
+
+impl FsckProblemKind {
+    /// A stable identifier for the kind of problem
+    pub fn id(&self) -> &'static str {
+        match self {
+            FsckProblemKind::Corrupt(..) => "corruptObject",
+            FsckProblemKind::HashMismatch(..) => "hashMismatch",
+            FsckProblemKind::BadCrc => "badCrc",
+            FsckProblemKind::BadPackChecksum => "badPackChecksum",
+            FsckProblemKind::BadTree => "badTree",
+            FsckProblemKind::BadFilemode(..) => "badFilemode",
+            FsckProblemKind::ZeroPaddedFilemode => "zeroPaddedFilemode",
+            FsckProblemKind::BadEntryName(..) => "badEntryName",
+            FsckProblemKind::TreeNotSorted => "treeNotSorted",
+            FsckProblemKind::DuplicateEntries => "duplicateEntries",
+            FsckProblemKind::MissingHeader(..) => "missingHeader",
+            FsckProblemKind::BadHeader(..) => "badHeader",
+        }
+    }
+
+    pub fn severity(&self) -> FsckSeverity {
+        match self {
+            // git writes these itself so there are plenty around
+            FsckProblemKind::ZeroPaddedFilemode => FsckSeverity::Warning,
+            _ => FsckSeverity::Error,
+        }
+    }
+}
+
+impl Display for FsckProblemKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.id())?;
+        match self {
+            FsckProblemKind::Corrupt(reason) => write!(f, "{}", reason),
+            FsckProblemKind::HashMismatch(actual) => write!(f, "contents hash to `{}`", actual),
+            FsckProblemKind::BadCrc => write!(f, "crc32 does not match the pack index"),
+            FsckProblemKind::BadPackChecksum => write!(f, "checksum does not match its contents"),
+            FsckProblemKind::BadTree => write!(f, "truncated tree entry"),
+            FsckProblemKind::BadFilemode(mode) => write!(f, "contains bad file mode `{}`", mode),
+            FsckProblemKind::ZeroPaddedFilemode => write!(f, "contains zero-padded file modes"),
+            FsckProblemKind::BadEntryName(name) => write!(f, "contains bad entry name `{}`", name),
+            FsckProblemKind::TreeNotSorted => write!(f, "entries are not properly sorted"),
+            FsckProblemKind::DuplicateEntries => write!(f, "contains duplicate entries"),
+            FsckProblemKind::MissingHeader(header) => write!(f, "missing `{}` header", header),
+            FsckProblemKind::BadHeader(header) => write!(f, "malformed `{}` header", header),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsckProblem {
+    pub location: FsckLocation,
+    pub kind: FsckProblemKind,
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in {}: {}", self.kind.severity(), self.location, self.kind)
+    }
+}
+
+/// An object that is referred to but doesn't exist
+/// (the type is unknown for objects only referred to directly by refs, reflogs and the index)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsckMissing {
+    pub obj_type: Option<BitObjType>,
+    pub oid: Oid,
+}
+
+impl Display for FsckMissing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "missing {} {}", ObjTypeName(self.obj_type), self.oid)
+    }
+}
+
+struct ObjTypeName(Option<BitObjType>);
+
+impl Display for ObjTypeName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(obj_type) => write!(f, "{}", obj_type),
+            None => write!(f, "object"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    pub problems: Vec<FsckProblem>,
+    pub missing: Vec<FsckMissing>,
+    /// unreachable objects that are not referred to by any other unreachable object
+    pub dangling: Vec<(BitObjType, Oid)>,
+    /// all objects that are not reachable from any ref, reflog or the index
+    pub unreachable: Vec<(BitObjType, Oid)>,
+}
+
+impl FsckReport {
+    /// Whether the repository is free of errors (and missing objects)
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty()
+            && self.problems.iter().all(|problem| problem.kind.severity() != FsckSeverity::Error)
+    }
+}
+
+#[derive(Default)]
+struct Fsck {
+    problems: Vec<FsckProblem>,
+    /// every object that could be read
+    objects: FxHashMap<Oid, BitObjType>,
+    /// the objects each commit, tree and tag refers to
+    links: FxHashMap<Oid, Vec<(Oid, BitObjType)>>,
+}
+
+impl BitRepo {
+    /// Verifies the integrity of every object and checks that everything reachable from the refs,
+    /// reflogs and the index exists (`bit fsck`).
+    /// Problems are collected into the report rather than returned as errors.
+    pub fn fsck(&self) -> BitResult<FsckReport> {
+        let mut fsck = Fsck::default();
+
+        let mut loose = self.loose_objects()?;
+        loose.sort();
+        for (oid, path) in loose {
+            fsck.check_loose(oid, &path);
+        }
+
+        for pack in self.pack_paths()? {
+            if let Err(err) = fsck.check_pack(self, &pack) {
+                fsck.report(FsckLocation::Pack(pack), FsckProblemKind::Corrupt(err.to_string()));
+            }
+        }
+
+        if self.multi_pack_index_path().try_exists()? {
+            if let Err(err) = self.verify_multi_pack_index() {
+                let location = FsckLocation::Pack(self.multi_pack_index_path().to_path_buf());
+                fsck.report(location, FsckProblemKind::Corrupt(err.to_string()));
+            }
+        }
+
+        // the parents of shallow commits are expected to be missing
+        for oid in self.shallow_commits()?.iter() {
+            if let Some(links) = fsck.links.get_mut(oid) {
+                links.retain(|&(_, obj_type)| obj_type != BitObjType::Commit);
+            }
+        }
+
+        let roots = self.root_oids()?;
+        let mut report = fsck.check_connectivity(roots);
+        // objects that are referred to by other objects are promised by the remote of a partial
+        // clone (only refs pointing at missing objects are a problem)
+        if self.is_partial_clone() {
+            report.missing.retain(|missing| missing.obj_type.is_none());
+        }
+        Ok(report)
+    }
+}
+
+impl Fsck {
+    fn report(&mut self, location: FsckLocation, kind: FsckProblemKind) {
+        let problem = FsckProblem { location, kind };
+        // each kind of problem is only reported once per object
+        if !self.problems.contains(&problem) {
+            self.problems.push(problem);
+        }
+    }
+
+    fn check_loose(&mut self, oid: Oid, path: &Path) {
+        let mut bytes = vec![];
+        let read = File::open(path).and_then(|file| ZlibDecoder::new(file).read_to_end(&mut bytes));
+        if let Err(err) = read {
+            let kind = FsckProblemKind::Corrupt(format!("failed to decompress: {}", err));
+            return self.report(FsckLocation::Object(None, oid), kind);
+        }
+
+        let (obj_type, contents) = match parse_loose_header(&bytes) {
+            Some(parsed) => parsed,
+            None => {
+                let kind = FsckProblemKind::Corrupt("malformed object header".to_owned());
+                return self.report(FsckLocation::Object(None, oid), kind);
+            }
+        };
+
+        let actual = hash::hash_bytes(&bytes);
+        if actual != oid {
+            let location = FsckLocation::Object(Some(obj_type), oid);
+            return self.report(location, FsckProblemKind::HashMismatch(actual));
+        }
+        self.check_object(oid, obj_type, contents);
+    }
+
+    fn check_pack(&mut self, repo: &BitRepo, path: &Path) -> BitResult<()> {
+        let bytes = FileBuffer::open(path)?;
+        let location = || FsckLocation::Pack(path.to_path_buf());
+        ensure!(bytes.len() >= 32, "packfile is truncated");
+        let (data, checksum) = bytes.split_at(bytes.len() - 20);
+        if &hash::hash_bytes(data)[..] != checksum {
+            self.report(location(), FsckProblemKind::BadPackChecksum);
+        }
+
+        let delta_base_cache = DeltaBaseCache::new(repo.config().delta_base_cache_limit() as u64);
+        let pack = Pack::new(
+            BitPath::intern(path),
+            path.with_extension(PACK_IDX_EXT),
+            Arc::new(delta_base_cache),
+        )?;
+        let mut entries = pack
+            .idx_reader()
+            .oid_iter(0)
+            .map(|oid| pack.obj_crc_offset(oid).map(|(crc, offset)| (offset, crc, oid)))
+            .collect::<Vec<_>>()?;
+        entries.sort();
+
+        for (i, &(offset, crc, oid)) in entries.iter().enumerate() {
+            // the packed data of each object ends where the next begins (or at the trailer)
+            let end = entries.get(i + 1).map_or(data.len() as u64, |&(next, ..)| next);
+            let span = (offset <= end).then(|| data.get(offset as usize..end as usize)).flatten();
+            let span = match span {
+                Some(span) => span,
+                None => {
+                    let kind = FsckProblemKind::Corrupt(format!("bad offset `{}`", offset));
+                    self.report(FsckLocation::Object(None, oid), kind);
+                    continue;
+                }
+            };
+            if crc32fast::hash(span) != crc {
+                self.report(FsckLocation::Object(None, oid), FsckProblemKind::BadCrc);
+            }
+
+            let raw = match pack.read_obj_raw_at(offset) {
+                Ok(raw) => raw,
+                Err(err) => {
+                    let kind = FsckProblemKind::Corrupt(err.to_string());
+                    self.report(FsckLocation::Object(None, oid), kind);
+                    continue;
+                }
+            };
+            let actual = raw.oid();
+            if actual != oid {
+                let location = FsckLocation::Object(Some(raw.obj_type), oid);
+                self.report(location, FsckProblemKind::HashMismatch(actual));
+                continue;
+            }
+            self.check_object(oid, raw.obj_type, &raw.bytes);
+        }
+        Ok(())
+    }
+
+    /// Checks the syntax of an object whose hash has already been verified
+    fn check_object(&mut self, oid: Oid, obj_type: BitObjType, bytes: &[u8]) {
+        // the same object may be both loose and packed
+        if self.objects.insert(oid, obj_type).is_some() {
+            return;
+        }
+
+        let mut problems = vec![];
+        let mut links = vec![];
+        match obj_type {
+            BitObjType::Blob => return,
+            BitObjType::Tree => check_tree(bytes, &mut problems, &mut links),
+            BitObjType::Commit => check_commit(bytes, &mut problems, &mut links),
+            BitObjType::Tag => check_tag(bytes, &mut problems, &mut links),
+        }
+        for kind in problems {
+            self.report(FsckLocation::Object(Some(obj_type), oid), kind);
+        }
+        self.links.insert(oid, links);
+    }
+
+    fn check_connectivity(self, roots: Vec<Oid>) -> FsckReport {
+        let Fsck { problems, objects, links } = self;
+        let mut reachable = FxHashSet::default();
+        let mut missing = FxHashMap::<Oid, Option<BitObjType>>::default();
+        let mut pending = roots.into_iter().map(|oid| (oid, None)).collect::<Vec<_>>();
+        while let Some((oid, obj_type)) = pending.pop() {
+            if !objects.contains_key(&oid) {
+                // the type is known if the missing object is referred to by another object
+                let missing_type = missing.entry(oid).or_default();
+                *missing_type = missing_type.or(obj_type);
+                continue;
+            }
+            if !reachable.insert(oid) {
+                continue;
+            }
+            if let Some(links) = links.get(&oid) {
+                pending.extend(links.iter().map(|&(oid, obj_type)| (oid, Some(obj_type))));
+            }
+        }
+
+        let mut unreachable = objects
+            .iter()
+            .filter(|(oid, _)| !reachable.contains(*oid))
+            .map(|(&oid, &obj_type)| (obj_type, oid))
+            .collect::<Vec<_>>();
+        unreachable.sort_by_key(|&(_, oid)| oid);
+
+        // an unreachable object can only be referred to by other unreachable objects
+        let referenced = unreachable
+            .iter()
+            .filter_map(|(_, oid)| links.get(oid))
+            .flatten()
+            .map(|&(oid, _)| oid)
+            .collect::<FxHashSet<_>>();
+        let dangling = unreachable
+            .iter()
+            .copied()
+            .filter(|(_, oid)| !referenced.contains(oid))
+            .collect::<Vec<_>>();
+
+        let mut missing = missing
+            .into_iter()
+            .map(|(oid, obj_type)| FsckMissing { obj_type, oid })
+            .collect::<Vec<_>>();
+        missing.sort_by_key(|missing| missing.oid);
+        FsckReport { problems, missing, dangling, unreachable }
+    }
+}
+
+/// Parses the `<type> <size>\0` header of a (decompressed) loose object
+fn parse_loose_header(bytes: &[u8]) -> Option<(BitObjType, &[u8])> {
+    let nul = bytes.iter().position(|&b| b == 0)?;
+    let header = std::str::from_utf8(&bytes[..nul]).ok()?;
+    let (obj_type, size) = header.split_once(' ')?;
+    let contents = &bytes[nul + 1..];
+    if size.parse::<usize>().ok()? != contents.len() {
+        return None;
+    }
+    Some((obj_type.parse().ok()?, contents))
+}
+
+fn check_tree(
+    mut bytes: &[u8],
+    problems: &mut Vec<FsckProblemKind>,
+    links: &mut Vec<(Oid, BitObjType)>,
+) {
+    let mut prev: Option<(&[u8], bool)> = None;
+    while !bytes.is_empty() {
+        // <mode> <name>\0<20 byte oid>
+        let space = bytes.iter().position(|&b| b == b' ');
+        let nul = space
+            .and_then(|space| Some(space + 1 + bytes[space + 1..].iter().position(|&b| b == 0)?));
+        let (space, nul) = match (space, nul) {
+            (Some(space), Some(nul)) if bytes.len() >= nul + 21 => (space, nul),
+            _ => return problems.push(FsckProblemKind::BadTree),
+        };
+        let (mode, name) = (&bytes[..space], &bytes[space + 1..nul]);
+        let oid = Oid::new(bytes[nul + 1..nul + 21].try_into().unwrap());
+        bytes = &bytes[nul + 21..];
+
+        let mode_str = String::from_utf8_lossy(mode);
+        if mode.len() > 1 && mode[0] == b'0' {
+            problems.push(FsckProblemKind::ZeroPaddedFilemode);
+        }
+        let mode = match u32::from_str_radix(&mode_str, 8).ok().map(FileMode::try_from) {
+            Some(Ok(mode)) => mode,
+            _ => {
+                problems.push(FsckProblemKind::BadFilemode(mode_str.into_owned()));
+                continue;
+            }
+        };
+
+        if name.is_empty() || name.contains(&b'/') || [&b"."[..], b"..", b".git"].contains(&name) {
+            let name = String::from_utf8_lossy(name).into_owned();
+            problems.push(FsckProblemKind::BadEntryName(name));
+        }
+
+        let is_tree = mode.is_tree();
+        if let Some((prev_name, prev_is_tree)) = prev {
+            // a file and a directory with the same name are also considered duplicates
+            if prev_name == name {
+                problems.push(FsckProblemKind::DuplicateEntries);
+            } else {
+                let (a, b) = (OsStr::from_bytes(prev_name), OsStr::from_bytes(name));
+                if BitPath::path_cmp_explicit(a, prev_is_tree, b, is_tree) != Ordering::Less {
+                    problems.push(FsckProblemKind::TreeNotSorted);
+                }
+            }
+        }
+        prev = Some((name, is_tree));
+
+        // submodule commits live in another repository
+        if !mode.is_gitlink() {
+            links.push((oid, mode.infer_obj_type()));
+        }
+    }
+}
+
+/// Splits the headers of a commit or tag from its message
+fn header_lines(bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let end = bytes.windows(2).position(|w| w == b"\n\n").map_or(bytes.len(), |i| i + 1);
+    bytes[..end].split(|&b| b == b'\n').filter(|line| !line.is_empty())
+}
+
+fn parse_hex_oid(value: &[u8]) -> Option<Oid> {
+    if value.len() != 40 {
+        return None;
+    }
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// Whether `value` is a valid signature (i.e. `name <email> <timestamp> <+|-hhmm>`)
+fn is_valid_signature(value: &[u8]) -> bool {
+    let value = match std::str::from_utf8(value) {
+        Ok(value) => value,
+        Err(..) => return false,
+    };
+    let (name_and_email, time) = match value.rsplit_once('>') {
+        Some(split) => split,
+        None => return false,
+    };
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let valid_time = match time.strip_prefix(' ').and_then(|time| time.split_once(' ')) {
+        Some((timestamp, tz)) =>
+            is_digits(timestamp)
+                && tz.len() == 5
+                && tz.starts_with(['+', '-'])
+                && is_digits(&tz[1..]),
+        None => false,
+    };
+    valid_time && matches!(name_and_email.split_once('<'), Some((_, email)) if !email.contains('<'))
+}
+
+/// Checks that the next header is `name` returning its value
+fn expect_header<'a>(
+    lines: &mut std::iter::Peekable<impl Iterator<Item = &'a [u8]>>,
+    name: &'static str,
+) -> Result<&'a [u8], FsckProblemKind> {
+    match lines.peek().and_then(|line| header_value(line, name)) {
+        Some(value) => {
+            lines.next();
+            Ok(value)
+        }
+        None => Err(FsckProblemKind::MissingHeader(name)),
+    }
+}
+
+fn header_value<'a>(line: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    line.strip_prefix(name.as_bytes())?.strip_prefix(b" ")
+}
+
+fn check_commit(
+    bytes: &[u8],
+    problems: &mut Vec<FsckProblemKind>,
+    links: &mut Vec<(Oid, BitObjType)>,
+) {
+    let mut lines = header_lines(bytes).peekable();
+    let mut check = || -> Result<(), FsckProblemKind> {
+        let tree = expect_header(&mut lines, "tree")?;
+        let tree = parse_hex_oid(tree).ok_or(FsckProblemKind::BadHeader("tree"))?;
+        links.push((tree, BitObjType::Tree));
+        while let Some(parent) = lines.peek().and_then(|line| header_value(line, "parent")) {
+            let parent = parse_hex_oid(parent).ok_or(FsckProblemKind::BadHeader("parent"))?;
+            links.push((parent, BitObjType::Commit));
+            lines.next();
+        }
+        for header in ["author", "committer"] {
+            if !is_valid_signature(expect_header(&mut lines, header)?) {
+                return Err(FsckProblemKind::BadHeader(header));
+            }
+        }
+        Ok(())
+    };
+    if let Err(problem) = check() {
+        problems.push(problem);
+    }
+}
+
+fn check_tag(
+    bytes: &[u8],
+    problems: &mut Vec<FsckProblemKind>,
+    links: &mut Vec<(Oid, BitObjType)>,
+) {
+    let mut lines = header_lines(bytes).peekable();
+    let mut check = || -> Result<(), FsckProblemKind> {
+        let object = expect_header(&mut lines, "object")?;
+        let object = parse_hex_oid(object).ok_or(FsckProblemKind::BadHeader("object"))?;
+        let obj_type = expect_header(&mut lines, "type")?;
+        let obj_type = std::str::from_utf8(obj_type)
+            .ok()
+            .and_then(|obj_type| obj_type.parse().ok())
+            .ok_or(FsckProblemKind::BadHeader("type"))?;
+        links.push((object, obj_type));
+        expect_header(&mut lines, "tag")?;
+        // the tagger is optional (very old tags don't have one)
+        if let Some(tagger) = lines.peek().and_then(|line| header_value(line, "tagger")) {
+            if !is_valid_signature(tagger) {
+                return Err(FsckProblemKind::BadHeader("tagger"));
+            }
+        }
+        Ok(())
+    };
+    if let Err(problem) = check() {
+        problems.push(problem);
+    }
+}
+
+#[cfg(test)]
+mod tests;
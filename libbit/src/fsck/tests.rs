@@ -0,0 +1,266 @@
+use super::*;
+use crate::gc::RepackOpts;
+use crate::obj::MutableBlob;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+fn loose_path(repo: &BitRepo, oid: Oid) -> PathBuf {
+    let hex = oid.to_hex();
+    repo.objects_dir().as_path().join(&hex[..2]).join(&hex[2..])
+}
+
+/// Writes `contents` (including the header) as the loose object `oid`
+fn write_loose(repo: &BitRepo, oid: Oid, contents: &[u8]) -> BitResult<()> {
+    let path = loose_path(repo, oid);
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(contents)?;
+    if path.exists() {
+        // loose objects are read-only
+        std::fs::remove_file(&path)?;
+    }
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(path, encoder.finish()?)?;
+    Ok(())
+}
+
+fn tree_entry(mode: &str, name: &str, oid: Oid) -> Vec<u8> {
+    let mut bytes = format!("{} {}\0", mode, name).into_bytes();
+    bytes.extend_from_slice(oid.as_ref());
+    bytes
+}
+
+fn tree_problems(entries: &[(&str, &str)]) -> Vec<FsckProblemKind> {
+    let bytes = entries
+        .iter()
+        .flat_map(|&(mode, name)| tree_entry(mode, name, Oid::EMPTY_TREE))
+        .collect::<Vec<_>>();
+    let mut problems = vec![];
+    check_tree(&bytes, &mut problems, &mut vec![]);
+    problems
+}
+
+fn commit_problems(commit: &str) -> Vec<FsckProblemKind> {
+    let mut problems = vec![];
+    check_commit(commit.as_bytes(), &mut problems, &mut vec![]);
+    problems
+}
+
+fn tag_problems(tag: &str) -> Vec<FsckProblemKind> {
+    let mut problems = vec![];
+    check_tag(tag.as_bytes(), &mut problems, &mut vec![]);
+    problems
+}
+
+const TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+const SIGNATURE: &str = "Andy Yu <andyyu2004@gmail.com> 1616061862 +1300";
+
+#[test]
+fn test_fsck_clean_repo() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let report = repo.fsck()?;
+        assert!(report.is_ok());
+        assert!(report.problems.is_empty());
+        assert!(report.dangling.is_empty());
+
+        // the same again but with everything packed
+        repo.repack(RepackOpts { all: true, delete: true, ..Default::default() })?;
+        let report = repo.fsck()?;
+        assert!(report.is_ok());
+        assert!(report.problems.is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_fsck_dangling_and_unreachable_objects() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let blob = repo.write_obj(&MutableBlob::new(b"dangling".to_vec()))?;
+        let referenced = repo.write_obj(&MutableBlob::new(b"in a dangling tree".to_vec()))?;
+        let entry = tree_entry("100644", "file", referenced);
+        let contents = [format!("tree {}\0", entry.len()).into_bytes(), entry].concat();
+        let tree = hash::hash_bytes(&contents);
+        write_loose(&repo, tree, &contents)?;
+
+        let report = repo.fsck()?;
+        assert!(report.is_ok());
+        let mut dangling = vec![(BitObjType::Blob, blob), (BitObjType::Tree, tree)];
+        dangling.sort_by_key(|&(_, oid)| oid);
+        assert_eq!(report.dangling, dangling);
+        let mut unreachable = [dangling, vec![(BitObjType::Blob, referenced)]].concat();
+        unreachable.sort_by_key(|&(_, oid)| oid);
+        assert_eq!(report.unreachable, unreachable);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_fsck_missing_object() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let foo = repo.index()?.std_iter().next().unwrap().oid;
+        std::fs::remove_file(loose_path(&repo, foo))?;
+
+        let report = repo.fsck()?;
+        assert!(!report.is_ok());
+        // the type is known as it is in the tree of HEAD (not just the index)
+        assert_eq!(report.missing, [FsckMissing { obj_type: Some(BitObjType::Blob), oid: foo }]);
+        assert_eq!(report.missing[0].to_string(), format!("missing blob {}", foo));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_fsck_hash_mismatch() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let blob = repo.write_obj(&MutableBlob::new(b"original".to_vec()))?;
+        write_loose(&repo, blob, b"blob 8\0tampered")?;
+
+        let report = repo.fsck()?;
+        assert!(!report.is_ok());
+        let actual = hash::hash_bytes(b"blob 8\0tampered");
+        let expected = FsckProblem {
+            location: FsckLocation::Object(Some(BitObjType::Blob), blob),
+            kind: FsckProblemKind::HashMismatch(actual),
+        };
+        assert_eq!(report.problems, [expected.clone()]);
+        assert_eq!(
+            expected.to_string(),
+            format!("error in blob {}: hashMismatch: contents hash to `{}`", blob, actual)
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_fsck_corrupt_loose_object() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let blob = repo.write_obj(&MutableBlob::new(b"original".to_vec()))?;
+        // the size in the header is wrong
+        write_loose(&repo, blob, b"blob 100\0original")?;
+        let report = repo.fsck()?;
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].location, FsckLocation::Object(None, blob));
+        assert_eq!(report.problems[0].kind.id(), "corruptObject");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_fsck_corrupt_pack() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let index =
+            repo.repack(RepackOpts { all: true, delete: true, ..Default::default() })?.unwrap();
+        let path = repo.pack_objects_dir().as_path().join(format!("pack-{}.pack", index.pack_hash));
+        let mut bytes = std::fs::read(&path)?;
+        // flip a byte in the middle of the first object's compressed data
+        let offset = *index.offsets.iter().min().unwrap() as usize + 4;
+        bytes[offset] ^= 0xff;
+        std::fs::remove_file(&path)?;
+        std::fs::write(&path, bytes)?;
+
+        let report = repo.fsck()?;
+        assert!(!report.is_ok());
+        let kinds = report.problems.iter().map(|problem| problem.kind.id()).collect::<Vec<_>>();
+        assert!(kinds.contains(&"badPackChecksum"));
+        assert!(kinds.contains(&"badCrc"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_check_tree() {
+    assert!(tree_problems(&[("100644", "a"), ("40000", "a.b"), ("40000", "b")]).is_empty());
+    // a directory sorts as if it has a trailing slash
+    assert!(tree_problems(&[("100644", "a.b"), ("40000", "a")]).is_empty());
+    assert_eq!(
+        tree_problems(&[("100644", "b"), ("100644", "a")]),
+        [FsckProblemKind::TreeNotSorted]
+    );
+    assert_eq!(
+        tree_problems(&[("100644", "a"), ("40000", "a")]),
+        [FsckProblemKind::DuplicateEntries]
+    );
+    assert_eq!(
+        tree_problems(&[("100664", "a")]),
+        [FsckProblemKind::BadFilemode("100664".to_owned())]
+    );
+    assert_eq!(tree_problems(&[("040000", "a")]), [FsckProblemKind::ZeroPaddedFilemode]);
+    assert_eq!(
+        tree_problems(&[("100644", ".git")]),
+        [FsckProblemKind::BadEntryName(".git".to_owned())]
+    );
+
+    let mut problems = vec![];
+    check_tree(b"100644 truncated\0abc", &mut problems, &mut vec![]);
+    assert_eq!(problems, [FsckProblemKind::BadTree]);
+}
+
+#[test]
+fn test_check_tree_links() {
+    let blob = hash::hash_bytes(b"blob");
+    let submodule = hash::hash_bytes(b"submodule");
+    let bytes = [
+        tree_entry("100755", "exec", blob),
+        tree_entry("160000", "module", submodule),
+        tree_entry("40000", "tree", Oid::EMPTY_TREE),
+    ]
+    .concat();
+    let mut links = vec![];
+    check_tree(&bytes, &mut vec![], &mut links);
+    assert_eq!(links, [(blob, BitObjType::Blob), (Oid::EMPTY_TREE, BitObjType::Tree)]);
+}
+
+#[test]
+fn test_check_commit() {
+    let valid = format!("tree {TREE}\nauthor {SIGNATURE}\ncommitter {SIGNATURE}\n\nmessage\n");
+    assert!(commit_problems(&valid).is_empty());
+
+    let mut links = vec![];
+    let with_parent = format!(
+        "tree {TREE}\nparent {TREE}\nauthor {SIGNATURE}\ncommitter {SIGNATURE}\nencoding utf-8\n\n"
+    );
+    check_commit(with_parent.as_bytes(), &mut vec![], &mut links);
+    let tree = TREE.parse().unwrap();
+    assert_eq!(links, [(tree, BitObjType::Tree), (tree, BitObjType::Commit)]);
+
+    assert_eq!(
+        commit_problems(&format!("author {SIGNATURE}\ncommitter {SIGNATURE}\n\nmessage")),
+        [FsckProblemKind::MissingHeader("tree")]
+    );
+    assert_eq!(
+        commit_problems(&format!("tree abc\nauthor {SIGNATURE}\ncommitter {SIGNATURE}\n\nmsg")),
+        [FsckProblemKind::BadHeader("tree")]
+    );
+    assert_eq!(
+        commit_problems(&format!("tree {TREE}\ncommitter {SIGNATURE}\n\nmessage")),
+        [FsckProblemKind::MissingHeader("author")]
+    );
+    assert_eq!(
+        commit_problems(&format!("tree {TREE}\nauthor {SIGNATURE}\ncommitter Andy Yu 1616061\n")),
+        [FsckProblemKind::BadHeader("committer")]
+    );
+    // the message can contain anything
+    let message = format!("tree {TREE}\nauthor {SIGNATURE}\ncommitter {SIGNATURE}\n\ntree\n\nx\n");
+    assert!(commit_problems(&message).is_empty());
+}
+
+#[test]
+fn test_check_tag() {
+    let valid = format!("object {TREE}\ntype tree\ntag v1\ntagger {SIGNATURE}\n\nmessage\n");
+    assert!(tag_problems(&valid).is_empty());
+    // very old tags have no tagger
+    assert!(tag_problems(&format!("object {TREE}\ntype tree\ntag v1\n\nmessage\n")).is_empty());
+
+    assert_eq!(
+        tag_problems(&format!("object {TREE}\ntype tree\ntagger {SIGNATURE}\n\nmessage\n")),
+        [FsckProblemKind::MissingHeader("tag")]
+    );
+    assert_eq!(
+        tag_problems(&format!("object {TREE}\ntype nothing\ntag v1\n\nmessage\n")),
+        [FsckProblemKind::BadHeader("type")]
+    );
+    assert_eq!(
+        tag_problems(&format!("object {TREE}\ntype tree\ntag v1\ntagger someone\n\nmsg\n")),
+        [FsckProblemKind::BadHeader("tagger")]
+    );
+}
@@ -0,0 +1,410 @@
+use crate::bitmap::BITMAP_EXT;
+use crate::error::{BitGenericError, BitResult};
+use crate::midx::MULTI_PACK_INDEX_FILE_NAME;
+use crate::obj::Oid;
+use crate::pack::{PackBuilder, PackIndex, PackIndexReader, PACK_EXT, PACK_IDX_EXT};
+use crate::path::BitPath;
+use crate::refs::BitRefDbBackend;
+use crate::repo::BitRepo;
+use crate::serialize::Deserialize;
+use filebuffer::FileBuffer;
+use rustc_hash::FxHashSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// The default value of `gc.auto`
+pub const DEFAULT_GC_AUTO: i64 = 6700;
+/// The default value of `gc.autoPackLimit`
+pub const DEFAULT_GC_AUTO_PACK_LIMIT: i64 = 50;
+/// The default value of `gc.pruneExpire`
+pub const DEFAULT_GC_PRUNE_EXPIRE: &str = "2.weeks.ago";
+
+/// a pack with a `.keep` file alongside it is never deleted by a repack
+const KEEP_EXT: &str = "keep";
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RepackOpts {
+    /// pack every reachable object into a single pack (rather than just the loose objects)
+    pub all: bool,
+    /// delete the packs made redundant by the new pack and the loose objects that it contains
+    pub delete: bool,
+    /// write a bitmap index for the new pack (only useful along with `all`)
+    pub bitmaps: bool,
+    /// along with `all` and `delete`, don't lose the unreachable objects of the deleted packs
+    /// (`repack -A`): they are written out as loose objects with the modification time of their
+    /// pack so that `prune` decides when they go
+    pub unpack_unreachable: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcOpts {
+    /// only run if there are enough loose objects or packs to make it worthwhile
+    /// (see `gc.auto` and `gc.autoPackLimit`)
+    pub auto: bool,
+    /// prune unreachable loose objects older than this (`None` uses `gc.pruneExpire`)
+    pub prune_expire: Option<Expiry>,
+}
+
+/// A cutoff for pruning objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    Never,
+    /// objects last modified at or before this time expire
+    At(SystemTime),
+}
+
+impl Expiry {
+    /// whether something last modified at `time` has expired
+    pub fn has_expired(self, time: SystemTime) -> bool {
+        match self {
+            Self::Never => false,
+            Self::At(cutoff) => time <= cutoff,
+        }
+    }
+}
+
+impl FromStr for Expiry {
+    type Err = BitGenericError;
+
+    /// Parses `never`, `now` (or `all`) and relative times such as `2.weeks.ago` or `3 days ago`
+    fn from_str(s: &str) -> BitResult<Self> {
+        let s = s.trim();
+        match s {
+            "never" => return Ok(Self::Never),
+            "now" | "all" => return Ok(Self::At(SystemTime::now())),
+            _ => {}
+        }
+
+        let words = s.split(|c: char| c == '.' || c.is_whitespace()).collect::<Vec<_>>();
+        let (n, unit) = match words[..] {
+            [n, unit, "ago"] => (n, unit),
+            _ => bail!("invalid expiry date `{}`", s),
+        };
+        let n = n.parse::<u64>().map_err(|_| anyhow!("invalid expiry date `{}`", s))?;
+        let seconds = match unit.trim_end_matches('s') {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            "week" => 7 * 24 * 60 * 60,
+            "month" => 30 * 24 * 60 * 60,
+            "year" => 365 * 24 * 60 * 60,
+            _ => bail!("invalid expiry date `{}`", s),
+        };
+        let ago = Duration::from_secs(n.saturating_mul(seconds));
+        Ok(Self::At(SystemTime::now().checked_sub(ago).unwrap_or(SystemTime::UNIX_EPOCH)))
+    }
+}
+
+impl BitRepo {
+    /// The target of every ref, every object in a reflog and every object in the index
+    /// (some of which may not exist)
+    pub(crate) fn root_oids(&self) -> BitResult<Vec<Oid>> {
+        let mut roots = FxHashSet::default();
+        for r in self.ls_refs()? {
+            roots.extend(self.try_fully_resolve_ref(r)?);
+            let reflog = self.refdb()?.read_reflog(r)?;
+            for entry in reflog.entries() {
+                roots.insert(entry.old_oid);
+                roots.insert(entry.new_oid);
+            }
+        }
+        roots.extend(self.index()?.std_iter().map(|entry| entry.oid));
+
+        let mut roots = roots.into_iter().filter(|oid| !oid.is_unknown()).collect::<Vec<_>>();
+        roots.sort();
+        Ok(roots)
+    }
+
+    /// The objects that must be kept by gc
+    fn gc_roots(&self) -> BitResult<Vec<Oid>> {
+        // reflogs may refer to objects that have since been pruned
+        let mut existing = vec![];
+        for oid in self.root_oids()? {
+            if self.obj_exists(oid)? {
+                existing.push(oid);
+            }
+        }
+        Ok(existing)
+    }
+
+    fn reachable_object_set(&self) -> BitResult<FxHashSet<Oid>> {
+        let roots = self.gc_roots()?;
+        Ok(self.reachable_objects(&roots, &[])?.into_iter().map(|(oid, _)| oid).collect())
+    }
+
+    /// All loose objects (and the files they are stored in)
+    pub(crate) fn loose_objects(&self) -> BitResult<Vec<(Oid, PathBuf)>> {
+        let mut objects = vec![];
+        for fanout in std::fs::read_dir(self.objects_dir())? {
+            let fanout = fanout?;
+            let prefix = fanout.file_name();
+            let prefix = match prefix.to_str() {
+                Some(prefix) if prefix.len() == 2 && fanout.file_type()?.is_dir() => prefix,
+                _ => continue,
+            };
+
+            for entry in std::fs::read_dir(fanout.path())? {
+                let entry = entry?;
+                let name = entry.file_name();
+                // ignore anything that isn't an object (e.g. leftover temporary files)
+                let oid = name.to_str().and_then(|name| format!("{prefix}{name}").parse().ok());
+                if let Some(oid) = oid {
+                    objects.push((oid, entry.path()));
+                }
+            }
+        }
+        Ok(objects)
+    }
+
+    /// The paths of all the packs in the repository
+    pub(crate) fn pack_paths(&self) -> BitResult<Vec<PathBuf>> {
+        let pack_dir = self.pack_objects_dir();
+        if !pack_dir.try_exists()? {
+            return Ok(vec![]);
+        }
+
+        let mut packs = vec![];
+        for entry in std::fs::read_dir(pack_dir)? {
+            let path = entry?.path();
+            if path.extension() == Some(PACK_EXT.as_ref()) {
+                packs.push(path);
+            }
+        }
+        packs.sort();
+        Ok(packs)
+    }
+
+    /// The oids of every object that is in a pack
+    fn packed_oids(&self) -> BitResult<FxHashSet<Oid>> {
+        let mut oids = FxHashSet::default();
+        for pack in self.pack_paths()? {
+            let idx = File::open(pack.with_extension(PACK_IDX_EXT))?;
+            oids.extend(PackIndex::deserialize(BufReader::new(idx))?.oids);
+        }
+        Ok(oids)
+    }
+
+    /// Packs objects into a new pack (`bit repack`), returning the index of the new pack
+    /// (or `None` if there was nothing to pack).
+    /// Without `all` only the loose objects are packed.
+    pub fn repack(&self, opts: RepackOpts) -> BitResult<Option<PackIndex>> {
+        let existing_packs = self.pack_paths()?;
+        let objects = if opts.all {
+            let mut objects = self.reachable_objects(&self.gc_roots()?, &[])?;
+            // the blobs a partial clone is missing stay with the promisor remote
+            if self.is_partial_clone() {
+                let mut present = Vec::with_capacity(objects.len());
+                for (oid, path) in objects {
+                    if self.obj_exists(oid)? {
+                        present.push((oid, path));
+                    }
+                }
+                objects = present;
+            }
+            objects
+        } else {
+            let packed = self.packed_oids()?;
+            let mut loose = self
+                .loose_objects()?
+                .into_iter()
+                .map(|(oid, _)| oid)
+                .filter(|oid| !packed.contains(oid))
+                .collect::<Vec<_>>();
+            loose.sort();
+            loose.into_iter().map(|oid| (oid, BitPath::EMPTY)).collect()
+        };
+
+        // anything else in the packs that are replaced is unreachable
+        let reachable = if opts.unpack_unreachable {
+            objects.iter().map(|&(oid, _)| oid).collect()
+        } else {
+            FxHashSet::default()
+        };
+
+        let index = if objects.is_empty() {
+            None
+        } else {
+            let pack_dir = self.pack_objects_dir();
+            std::fs::create_dir_all(pack_dir)?;
+            let builder = PackBuilder::new(self.clone(), objects).bitmaps(opts.bitmaps);
+            Some(builder.write_to_dir(pack_dir)?)
+        };
+
+        if opts.delete {
+            if opts.all {
+                let new_pack = index.as_ref().map(|index| self.pack_path(index));
+                let mut redundant = vec![];
+                for pack in &existing_packs {
+                    if Some(pack) == new_pack.as_ref() || pack.with_extension(KEEP_EXT).exists() {
+                        continue;
+                    }
+                    if opts.unpack_unreachable {
+                        let mtime = std::fs::metadata(pack)?.modified()?;
+                        self.unpack_unreachable(pack, mtime, &reachable)?;
+                    }
+                    redundant.push(pack);
+                }
+                if !redundant.is_empty() {
+                    // the multi-pack-index would refer to packs that no longer exist
+                    remove_if_exists(&self.pack_objects_dir().join(MULTI_PACK_INDEX_FILE_NAME))?;
+                }
+                for pack in redundant {
+                    // the pack goes first as a pack without its index is considered an error
+                    remove_if_exists(pack)?;
+                    remove_if_exists(&pack.with_extension(PACK_IDX_EXT))?;
+                    remove_if_exists(&pack.with_extension(BITMAP_EXT))?;
+                }
+            }
+            self.refresh_odb()?;
+            self.prune_packed()?;
+        } else {
+            self.refresh_odb()?;
+        }
+        Ok(index)
+    }
+
+    /// Writes the objects of the pack at `pack` that aren't `reachable` out as loose objects
+    /// that were last modified at `mtime`
+    fn unpack_unreachable(
+        &self,
+        pack: &Path,
+        mtime: SystemTime,
+        reachable: &FxHashSet<Oid>,
+    ) -> BitResult<()> {
+        let index = PackIndexReader::new(FileBuffer::open(pack.with_extension(PACK_IDX_EXT))?)?;
+        for i in 0..index.len() {
+            let oid = index.read_oid_at(i)?;
+            if reachable.contains(&oid) {
+                continue;
+            }
+            // an object that is already loose keeps its own modification time
+            let path = self.loose_obj_path(oid);
+            if path.try_exists()? {
+                continue;
+            }
+            self.write_obj(&self.read_obj_raw(oid)?)?;
+            File::open(&path)?.set_modified(mtime)?;
+        }
+        Ok(())
+    }
+
+    fn loose_obj_path(&self, oid: Oid) -> PathBuf {
+        let hex = oid.to_hex();
+        self.objects_dir().as_path().join(&hex[..2]).join(&hex[2..])
+    }
+
+    fn pack_path(&self, index: &PackIndex) -> PathBuf {
+        self.pack_objects_dir().as_path().join(format!("pack-{}.{}", index.pack_hash, PACK_EXT))
+    }
+
+    /// Removes loose objects that are also in a pack (`bit prune-packed`), returning how many
+    /// were removed
+    pub fn prune_packed(&self) -> BitResult<usize> {
+        let packed = self.packed_oids()?;
+        let mut pruned = 0;
+        for (oid, path) in self.loose_objects()? {
+            if packed.contains(&oid) {
+                std::fs::remove_file(path)?;
+                pruned += 1;
+            }
+        }
+        self.remove_empty_fanout_dirs()?;
+        Ok(pruned)
+    }
+
+    /// Removes loose objects that are unreachable and were last modified before `expire`
+    /// (`bit prune`), returning the oids of the removed objects.
+    /// Objects referenced by the reflogs or the index are considered reachable.
+    pub fn prune(&self, expire: Expiry) -> BitResult<Vec<Oid>> {
+        if expire == Expiry::Never {
+            return Ok(vec![]);
+        }
+
+        let reachable = self.reachable_object_set()?;
+        let mut pruned = vec![];
+        for (oid, path) in self.loose_objects()? {
+            let modified = std::fs::metadata(&path)?.modified()?;
+            if reachable.contains(&oid) || !expire.has_expired(modified) {
+                continue;
+            }
+            std::fs::remove_file(path)?;
+            pruned.push(oid);
+        }
+        self.remove_empty_fanout_dirs()?;
+        pruned.sort();
+        Ok(pruned)
+    }
+
+    fn remove_empty_fanout_dirs(&self) -> BitResult<()> {
+        for entry in std::fs::read_dir(self.objects_dir())? {
+            let entry = entry?;
+            let is_fanout = entry.file_name().len() == 2 && entry.file_type()?.is_dir();
+            if is_fanout && std::fs::read_dir(entry.path())?.next().is_none() {
+                std::fs::remove_dir(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether there are enough loose objects or packs for `bit gc --auto` to do anything
+    pub fn needs_gc(&self) -> BitResult<bool> {
+        let config = self.config();
+        let auto = config.gc_auto();
+        // `gc.auto = 0` disables automatic gc entirely
+        if auto <= 0 {
+            return Ok(false);
+        }
+
+        let pack_limit = config.gc_auto_pack_limit();
+        if pack_limit > 0 {
+            let packs = self
+                .pack_paths()?
+                .into_iter()
+                .filter(|pack| !pack.with_extension(KEEP_EXT).exists())
+                .count();
+            if packs as i64 > pack_limit {
+                return Ok(true);
+            }
+        }
+        Ok(self.loose_objects()?.len() as i64 > auto)
+    }
+
+    /// Packs refs, repacks everything reachable into a single pack and prunes old unreachable
+    /// objects (`bit gc`). Unreachable objects in packs are written out as loose objects first so
+    /// they're only pruned once they expire. Returns whether anything was done (which is only not
+    /// the case for `auto` when it isn't needed).
+    pub fn gc(&self, opts: GcOpts) -> BitResult<bool> {
+        if opts.auto && !self.needs_gc()? {
+            return Ok(false);
+        }
+
+        let expire = match opts.prune_expire {
+            Some(expire) => expire,
+            None => self.config().gc_prune_expire().parse()?,
+        };
+
+        self.refdb()?.pack_refs(true, true)?;
+        self.repack(RepackOpts {
+            all: true,
+            delete: true,
+            bitmaps: self.config().write_bitmaps(),
+            unpack_unreachable: true,
+        })?;
+        self.prune(expire)?;
+        Ok(true)
+    }
+}
+
+fn remove_if_exists(path: &Path) -> BitResult<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,226 @@
+use super::*;
+use crate::obj::MutableBlob;
+
+fn loose_oids(repo: &BitRepo) -> BitResult<Vec<Oid>> {
+    let mut oids = repo.loose_objects()?.into_iter().map(|(oid, _)| oid).collect::<Vec<_>>();
+    oids.sort();
+    Ok(oids)
+}
+
+fn sorted(objects: Vec<(Oid, BitPath)>) -> Vec<Oid> {
+    let mut oids = objects.into_iter().map(|(oid, _)| oid).collect::<Vec<_>>();
+    oids.sort();
+    oids
+}
+
+#[test]
+fn test_parse_expiry() -> BitResult<()> {
+    assert_eq!("never".parse::<Expiry>()?, Expiry::Never);
+
+    let now = SystemTime::now();
+    let at = |s: &str| -> BitResult<Duration> {
+        match s.parse::<Expiry>()? {
+            Expiry::At(time) => Ok(now.duration_since(time).unwrap_or_default()),
+            Expiry::Never => panic!("expected a time"),
+        }
+    };
+    assert!(at("now")? < Duration::from_secs(60));
+    let two_weeks = at("2.weeks.ago")?.as_secs();
+    assert!((14 * 24 * 60 * 60..14 * 24 * 60 * 60 + 60).contains(&two_weeks));
+    let three_days = at("3 days ago")?.as_secs();
+    assert!((3 * 24 * 60 * 60..3 * 24 * 60 * 60 + 60).contains(&three_days));
+    assert!(at("1.hour.ago")?.as_secs() >= 60 * 60);
+
+    assert!("yesterday".parse::<Expiry>().is_err());
+    assert!("2.fortnights.ago".parse::<Expiry>().is_err());
+    assert!("x.days.ago".parse::<Expiry>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_repack_loose_objects() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let loose = loose_oids(&repo)?;
+        let index = repo.repack(RepackOpts::default())?.expect("there are loose objects to pack");
+        assert_eq!(index.oids, loose);
+        // without `-d` the loose objects are left alone
+        assert_eq!(loose_oids(&repo)?, loose);
+
+        // everything is already packed
+        assert!(repo.repack(RepackOpts::default())?.is_none());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_repack_all_consolidates_packs() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        repo.repack(RepackOpts { delete: true, ..Default::default() })?;
+        assert!(loose_oids(&repo)?.is_empty());
+
+        touch!(repo: "newfile" < "new content");
+        bit_commit_all!(repo);
+        repo.repack(RepackOpts { delete: true, ..Default::default() })?;
+        assert_eq!(repo.pack_paths()?.len(), 2);
+
+        let head = repo.fully_resolve_head()?;
+        let expected = sorted(repo.reachable_objects(&[head], &[])?);
+        let index = repo
+            .repack(RepackOpts { all: true, delete: true, bitmaps: true, ..Default::default() })?
+            .unwrap();
+        assert_eq!(index.oids, expected);
+        assert_eq!(repo.pack_paths()?, [repo.pack_path(&index)]);
+        assert!(loose_oids(&repo)?.is_empty());
+        assert!(repo.pack_bitmap_index().is_some());
+
+        // the objects are all still readable (from the new pack)
+        for oid in expected {
+            repo.read_obj(oid)?;
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn test_repack_keeps_kept_packs() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let kept = repo.repack(RepackOpts::default())?.unwrap();
+        let kept_path = repo.pack_path(&kept);
+        std::fs::write(kept_path.with_extension(KEEP_EXT), "")?;
+
+        touch!(repo: "newfile" < "new content");
+        bit_commit_all!(repo);
+        let index =
+            repo.repack(RepackOpts { all: true, delete: true, ..Default::default() })?.unwrap();
+        assert_eq!(repo.pack_paths()?.len(), 2);
+        assert!(kept_path.exists());
+        assert!(repo.pack_path(&index).exists());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_repack_unpacks_unreachable_objects() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let unreachable = repo.write_obj(&MutableBlob::new(b"unreachable".to_vec()))?;
+        let old = repo.repack(RepackOpts { delete: true, ..Default::default() })?.unwrap();
+        let mtime = std::fs::metadata(repo.pack_path(&old))?.modified()?;
+        assert!(loose_oids(&repo)?.is_empty());
+
+        let opts =
+            RepackOpts { all: true, delete: true, unpack_unreachable: true, ..Default::default() };
+        let index = repo.repack(opts)?.unwrap();
+        assert_eq!(repo.pack_paths()?, [repo.pack_path(&index)]);
+        assert!(!index.oids.contains(&unreachable));
+        // the unreachable object survives the pack it was in (as old as the pack)
+        assert_eq!(loose_oids(&repo)?, [unreachable]);
+        assert_eq!(std::fs::metadata(repo.loose_obj_path(unreachable))?.modified()?, mtime);
+        assert_eq!(repo.prune(Expiry::At(mtime))?, [unreachable]);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_gc_with_default_expiry_replaces_recent_packs() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let unreachable = repo.write_obj(&MutableBlob::new(b"unreachable".to_vec()))?;
+        repo.repack(RepackOpts { delete: true, ..Default::default() })?;
+        touch!(repo: "newfile" < "new content");
+        bit_commit_all!(repo);
+        repo.repack(RepackOpts { delete: true, ..Default::default() })?;
+        assert_eq!(repo.pack_paths()?.len(), 2);
+
+        repo.with_raw_local_config(|config| config.set("gc", "autoPackLimit", 1))?;
+        assert!(repo.gc(GcOpts::default())?);
+        assert_eq!(repo.pack_paths()?.len(), 1);
+        assert!(!repo.needs_gc()?);
+        // the recent unreachable object is loose until it expires
+        assert_eq!(loose_oids(&repo)?, [unreachable]);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_prune_packed() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let loose = loose_oids(&repo)?;
+        repo.repack(RepackOpts::default())?;
+        let unpacked = repo.write_obj(&MutableBlob::new(b"not packed".to_vec()))?;
+
+        assert_eq!(repo.prune_packed()?, loose.len());
+        assert_eq!(loose_oids(&repo)?, [unpacked]);
+        assert_eq!(repo.prune_packed()?, 0);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_prune_unreachable_objects() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let old_head = repo.fully_resolve_head()?;
+        touch!(repo: "newfile" < "new content");
+        bit_commit_all!(repo);
+        let new_head = repo.fully_resolve_head()?;
+        // the new commit is now only reachable from the reflog
+        bit_reset!(repo: --hard old_head);
+
+        let unreachable = repo.write_obj(&MutableBlob::new(b"unreachable".to_vec()))?;
+        touch!(repo: "staged" < "only in the index");
+        bit_add!(repo: "staged");
+
+        // the unreachable object is too new to expire
+        assert!(repo.prune("1.day.ago".parse()?)?.is_empty());
+        assert!(repo.prune(Expiry::Never)?.is_empty());
+
+        assert_eq!(repo.prune("now".parse()?)?, [unreachable]);
+        assert!(!repo.obj_exists(unreachable)?);
+        assert!(repo.obj_exists(new_head)?);
+        let staged = repo.index()?.std_iter().find(|entry| entry.path == p!("staged")).unwrap();
+        assert!(repo.obj_exists(staged.oid)?);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_gc_auto_threshold() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        // the sample repository is well under the default threshold
+        assert!(!repo.gc(GcOpts { auto: true, ..Default::default() })?);
+
+        repo.with_raw_local_config(|config| config.set("gc", "auto", 1))?;
+        assert!(repo.needs_gc()?);
+        repo.with_raw_local_config(|config| config.set("gc", "auto", 0))?;
+        assert!(!repo.needs_gc()?);
+
+        repo.with_raw_local_config(|config| {
+            config.set("gc", "auto", 1)?;
+            config.set("gc", "autoPackLimit", 1)
+        })?;
+        repo.repack(RepackOpts { delete: true, ..Default::default() })?;
+        assert!(!repo.needs_gc()?);
+        touch!(repo: "newfile" < "new content");
+        bit_commit_all!(repo);
+        repo.repack(RepackOpts { delete: true, ..Default::default() })?;
+        // two packs and no loose objects
+        assert!(repo.needs_gc()?);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_gc() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let expected = sorted(repo.reachable_objects(&[head], &[])?);
+        let unreachable = repo.write_obj(&MutableBlob::new(b"unreachable".to_vec()))?;
+
+        assert!(repo.gc(GcOpts { auto: false, prune_expire: Some("now".parse()?) })?);
+        assert!(loose_oids(&repo)?.is_empty());
+        assert!(!repo.obj_exists(unreachable)?);
+        let packs = repo.pack_paths()?;
+        assert_eq!(packs.len(), 1);
+        assert!(repo.bitdir.join("packed-refs").exists());
+        assert_eq!(sorted(repo.reachable_objects(&[head], &[])?), expected);
+        Ok(())
+    })
+}
@@ -0,0 +1,263 @@
+use crate::config::xdg_config_home;
+use crate::error::BitResult;
+use crate::index::BitIndex;
+use crate::path::BitPath;
+use crate::pathspec::Pathspec;
+use crate::repo::BitRepo;
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::path::{Path, PathBuf};
+
+pub const GITIGNORE_FILE_NAME: &str = ".gitignore";
+
+/// Stack of gitignore rules for a worktree.
+/// Rules are consulted in the following order of decreasing precedence
+///  - `.gitignore` files (files in deeper directories take precedence over their parents)
+///  - `$GIT_DIR/info/exclude`
+///  - `core.excludesFile` (defaulting to `$XDG_CONFIG_HOME/git/ignore` or `~/.config/git/ignore`)
+/// Within a single file, the last matching pattern wins.
+/// As in git, it is not possible to re-include a path if any of its parent directories are excluded.
+pub struct IgnoreStack {
+    workdir: BitPath,
+    /// worktree wide ignore rules in order of increasing precedence
+    global: Vec<IgnoreFile>,
+    /// lazily loaded `.gitignore` for each directory (relative to the worktree root)
+    dirs: FxHashMap<BitPath, Option<IgnoreFile>>,
+    /// memoized matches for directories as every path in a directory must check its parents
+    dir_matches: FxHashMap<BitPath, Option<IgnoreMatch>>,
+}
+
+/// The rule that determined the ignore status of a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnoreMatch {
+    /// the ignore file containing the pattern
+    pub source: Option<PathBuf>,
+    /// the (1-indexed) line number of the pattern in its source file
+    pub line_number: Option<usize>,
+    pub pattern: String,
+    /// whether the pattern is a negated pattern (i.e. starts with `!`)
+    pub is_whitelist: bool,
+}
+
+impl IgnoreMatch {
+    pub fn is_ignore(&self) -> bool {
+        !self.is_whitelist
+    }
+}
+
+/// A parsed ignore file along with the line number of each of its patterns
+struct IgnoreFile {
+    gitignore: Gitignore,
+    /// the line number of each pattern, if a pattern occurs more than once then only the last
+    /// occurrence matters as that's the one that takes precedence
+    line_numbers: FxHashMap<String, usize>,
+}
+
+impl IgnoreFile {
+    /// Parse the ignore file at `path` whose patterns are relative to `root`
+    fn parse(root: &Path, path: &Path) -> BitResult<Self> {
+        let mut builder = GitignoreBuilder::new(root);
+        let mut line_numbers = FxHashMap::default();
+        let contents = std::fs::read(path)?;
+        for (i, line) in String::from_utf8_lossy(&contents).lines().enumerate() {
+            let line = if i == 0 { line.trim_start_matches('\u{feff}') } else { line };
+            if let Err(err) = builder.add_line(Some(path.to_path_buf()), line) {
+                warn!("failed to parse ignore file `{}`: {}", path.display(), err);
+                continue;
+            }
+            // this is the same as what `Glob::original` will be
+            let pattern = if line.ends_with("\\ ") { line } else { line.trim_end() };
+            line_numbers.insert(pattern.to_owned(), i + 1);
+        }
+        Ok(Self { gitignore: builder.build()?, line_numbers })
+    }
+
+    fn matched(&self, path: &Path, is_dir: bool) -> Option<IgnoreMatch> {
+        match self.gitignore.matched(path, is_dir) {
+            Match::None => None,
+            Match::Ignore(glob) | Match::Whitelist(glob) => Some(IgnoreMatch {
+                source: glob.from().map(Path::to_path_buf),
+                line_number: self.line_numbers.get(glob.original()).copied(),
+                pattern: glob.original().to_owned(),
+                is_whitelist: glob.is_whitelist(),
+            }),
+        }
+    }
+}
+
+impl IgnoreStack {
+    pub fn new(repo: &BitRepo) -> BitResult<Self> {
+        let workdir = repo.workdir;
+        let excludes_file = match repo.config().excludes_file() {
+            Some(path) => match path.strip_prefix("~/") {
+                Some(relative) => dirs::home_dir().map(|home| home.join(relative)),
+                None => Some(PathBuf::from(path)),
+            },
+            None => xdg_config_home().map(|config_home| config_home.join("git/ignore")),
+        };
+
+        let info_exclude = repo.bitdir.join("info/exclude").as_path().to_path_buf();
+        let mut global = vec![];
+        for path in excludes_file.into_iter().chain(Some(info_exclude)) {
+            if !path.try_exists()? {
+                continue;
+            }
+            // global patterns are relative to the root of the worktree (not the location of the file)
+            global.push(IgnoreFile::parse(&workdir, &path)?);
+        }
+
+        Ok(Self { workdir, global, dirs: Default::default(), dir_matches: Default::default() })
+    }
+
+    /// Returns whether `path` (relative to the worktree root) is ignored.
+    /// This does not account for whether the path is tracked or not.
+    pub fn is_ignored(&mut self, path: BitPath, is_dir: bool) -> BitResult<bool> {
+        Ok(self.check(path, is_dir)?.map_or(false, |m| m.is_ignore()))
+    }
+
+    /// Find the rule that determines the ignore status of `path` (relative to the worktree root)
+    pub fn check(&mut self, path: BitPath, is_dir: bool) -> BitResult<Option<IgnoreMatch>> {
+        debug_assert!(path.is_relative());
+        if is_dir {
+            return self.check_dir(path);
+        }
+
+        if let Some(m) = self.check_excluded_parent(path)? {
+            return Ok(Some(m));
+        }
+        self.matched(path, false)
+    }
+
+    fn check_dir(&mut self, dir: BitPath) -> BitResult<Option<IgnoreMatch>> {
+        if let Some(m) = self.dir_matches.get(&dir) {
+            return Ok(m.clone());
+        }
+
+        let m = match self.check_excluded_parent(dir)? {
+            Some(m) => Some(m),
+            None => self.matched(dir, true)?,
+        };
+        self.dir_matches.insert(dir, m.clone());
+        Ok(m)
+    }
+
+    /// Returns the match that excluded the closest parent directory of `path` (if any)
+    // an excluded parent directory can't be overridden by any rules below it
+    fn check_excluded_parent(&mut self, path: BitPath) -> BitResult<Option<IgnoreMatch>> {
+        match path.parent().filter(|parent| !parent.is_empty()) {
+            Some(parent) => Ok(self.check_dir(parent)?.filter(IgnoreMatch::is_ignore)),
+            None => Ok(None),
+        }
+    }
+
+    /// Match `path` against the ignore rules without considering its parent directories
+    fn matched(&mut self, path: BitPath, is_dir: bool) -> BitResult<Option<IgnoreMatch>> {
+        let absolute = self.workdir.join(path);
+
+        // `.gitignore` files from the deepest directory upwards
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            let file = self.load_dir(current)?;
+            if let Some(m) = file.and_then(|file| file.matched(&absolute, is_dir)) {
+                return Ok(Some(m));
+            }
+            dir = current.parent();
+        }
+
+        Ok(self.global.iter().rev().find_map(|file| file.matched(&absolute, is_dir)))
+    }
+
+    fn load_dir(&mut self, dir: BitPath) -> BitResult<Option<&IgnoreFile>> {
+        if !self.dirs.contains_key(&dir) {
+            let dir_path = self.workdir.join(dir);
+            let path = dir_path.join(GITIGNORE_FILE_NAME);
+            let file =
+                if path.try_exists()? { Some(IgnoreFile::parse(&dir_path, &path)?) } else { None };
+            self.dirs.insert(dir, file);
+        }
+        Ok(self.dirs[&dir].as_ref())
+    }
+}
+
+impl BitIndex {
+    /// Returns all untracked paths that are ignored.
+    /// Ignored directories that contain no tracked files are returned as a single entry with
+    /// a trailing slash (like git) rather than listing their contents.
+    pub fn ignored_paths(&self, pathspec: Pathspec) -> BitResult<Vec<BitPath>> {
+        let repo = self.repo();
+        let mut ignores = IgnoreStack::new(&repo)?;
+        let tracked = self.entries().keys().map(|(path, _)| *path).collect::<FxHashSet<_>>();
+        let mut tracked_dirs = FxHashSet::default();
+        for path in &tracked {
+            let mut parent = path.parent();
+            while let Some(dir) = parent {
+                if dir.is_empty() || !tracked_dirs.insert(dir) {
+                    break;
+                }
+                parent = dir.parent();
+            }
+        }
+
+        let mut ignored = vec![];
+        let mut walk =
+            walkdir::WalkDir::new(repo.workdir).min_depth(1).sort_by_file_name().into_iter();
+        while let Some(entry) = walk.next() {
+            let entry = entry?;
+            let is_dir = entry.file_type().is_dir();
+            if is_dir && BitPath::DOT_GIT == entry.file_name() {
+                walk.skip_current_dir();
+                continue;
+            }
+
+            let path = BitPath::intern(repo.to_relative_path(entry.path())?);
+            if tracked.contains(&path) || !ignores.is_ignored(path, is_dir)? {
+                continue;
+            }
+
+            if !pathspec.matches_path(path) {
+                continue;
+            }
+
+            if is_dir {
+                // the contents of the directory is inspected individually if it contains tracked files
+                if tracked_dirs.contains(&path) {
+                    continue;
+                }
+                walk.skip_current_dir();
+                ignored.push(BitPath::intern(format!("{path}/")));
+            } else {
+                ignored.push(path);
+            }
+        }
+        Ok(ignored)
+    }
+}
+
+impl BitRepo {
+    /// Find the rule (if any) that determines the ignore status of each path.
+    /// Tracked paths are never considered ignored.
+    pub fn check_ignore(
+        &self,
+        paths: &[BitPath],
+    ) -> BitResult<Vec<(BitPath, Option<IgnoreMatch>)>> {
+        let tracked =
+            self.index()?.entries().keys().map(|(path, _)| *path).collect::<FxHashSet<BitPath>>();
+        let mut ignores = IgnoreStack::new(self)?;
+        paths
+            .iter()
+            .map(|&path| {
+                let path = if path.is_absolute() {
+                    BitPath::intern(self.to_relative_path(&path)?)
+                } else {
+                    path
+                };
+                if tracked.contains(&path) {
+                    return Ok((path, None));
+                }
+                let is_dir = self.to_absolute_path(path).is_dir();
+                Ok((path, ignores.check(path, is_dir)?))
+            })
+            .collect()
+    }
+}
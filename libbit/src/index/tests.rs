@@ -83,6 +83,7 @@ impl BitRepo {
 
     // creates an empty repository in a temporary directory and initializes it
     pub fn with_empty_repo<R>(f: impl FnOnce(BitRepo) -> BitResult<R>) -> BitResult<R> {
+        crate::test_utils::isolate_user_config_dir();
         let basedir = tempfile::tempdir()?;
         let res = BitRepo::init_load(basedir.path(), f)?;
         Ok(res)
@@ -84,6 +84,8 @@ thread_local! {
         EMPTY => "",
         HEAD => "HEAD",
         MERGE_HEAD => "MERGE_HEAD",
+        ORIG_HEAD => "ORIG_HEAD",
+        REBASE_MERGE => "rebase-merge",
         DOT_GIT => ".git",
         DOT_BIT => ".bit",
         REMOVED => "removed",
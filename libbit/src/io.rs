@@ -359,6 +359,50 @@ pub trait WriteExt: Write {
         self.write_all(oid.as_bytes())
     }
 
+    /// inverse of [`ReadExt::read_le_varint_with_shift`]
+    /// `k` is packed into the `init_shift` bits following the MSB of the first byte
+    fn write_le_varint_with_shift(&mut self, k: u8, init_shift: u64, n: u64) -> io::Result<()> {
+        assert!(init_shift < 8);
+        let anti_shift = 7 - init_shift;
+        debug_assert!((k as u64) < 1 << init_shift);
+        // the first byte contains `k` and the lowest `anti_shift` bits of `n`
+        let mut byte = ((k as u64) << anti_shift) | (n & ((1 << anti_shift) - 1));
+        let mut n = n >> anti_shift;
+        while n != 0 {
+            self.write_u8(byte as u8 | 0x80)?;
+            byte = n & 0x7f;
+            n >>= 7;
+        }
+        self.write_u8(byte as u8)
+    }
+
+    /// inverse of [`ReadExt::read_le_varint`]
+    fn write_le_varint(&mut self, n: u64) -> io::Result<()> {
+        self.write_le_varint_with_shift(0, 0, n)
+    }
+
+    /// alias for `write_le_varint` (the inverse of [`ReadExt::read_size`])
+    #[inline]
+    fn write_size(&mut self, size: u64) -> io::Result<()> {
+        self.write_le_varint(size)
+    }
+
+    /// inverse of [`ReadExt::read_offset`]
+    // big-endian groups of 7 bits where each continuation byte implicitly adds one
+    fn write_offset(&mut self, mut offset: u64) -> io::Result<()> {
+        let mut buf = [0u8; 10];
+        let mut i = buf.len() - 1;
+        buf[i] = (offset & 0x7f) as u8;
+        offset >>= 7;
+        while offset != 0 {
+            offset -= 1;
+            i -= 1;
+            buf[i] = 0x80 | (offset & 0x7f) as u8;
+            offset >>= 7;
+        }
+        self.write_all(&buf[i..])
+    }
+
     /// write `data` prefixed by its serialized size in bytes as a u32
     fn write_with_size(&mut self, data: impl Serialize) -> BitResult<()> {
         let mut buf = vec![];
@@ -67,3 +67,20 @@ fn test_read_le_packed_header_only() -> io::Result<()> {
     assert_eq!(bytes.read_le_packed(header)?, 0);
     Ok(())
 }
+
+#[quickcheck]
+fn test_write_then_read_le_varint_with_shift(k: u8, n: u64) -> io::Result<()> {
+    let k = k & 0b111;
+    let mut buf = vec![];
+    buf.write_le_varint_with_shift(k, 3, n)?;
+    assert_eq!((&buf[..]).read_le_varint_with_shift(3)?, (k, n));
+    Ok(())
+}
+
+#[quickcheck]
+fn test_write_then_read_offset(offset: u64) -> io::Result<()> {
+    let mut buf = vec![];
+    buf.write_offset(offset)?;
+    assert_eq!((&buf[..]).read_offset()?, offset);
+    Ok(())
+}
@@ -9,12 +9,12 @@ pub use tree_iter::*;
 pub use worktree_tree_iter::WorktreeTreeIter;
 
 use crate::error::{BitErrorExt, BitGenericError, BitResult};
+use crate::gitignore::IgnoreStack;
 use crate::index::{BitIndex, BitIndexEntry, IndexEntryIterator};
-use crate::obj::{FileMode, Oid, TreeEntry, Treeish};
+use crate::obj::{FileMode, Oid, TreeEntry, Treeish, WritableObject};
 use crate::path::BitPath;
 use crate::repo::BitRepo;
 use fallible_iterator::Peekable;
-use ignore::gitignore::Gitignore;
 use ignore::{Walk, WalkBuilder};
 use rustc_hash::FxHashSet;
 use std::cmp::Ordering;
@@ -101,6 +101,10 @@ pub trait BitEntry {
         // if it's not in the object store, then it must live on disk so we just read it from there
         // if the oid is not known, then it's definitely on disk (as otherwise it would have a known `oid`)
         if oid.is_known() {
+            // a partial clone would otherwise fetch the object from the promisor remote
+            if let Some(bytes) = self.read_unstored_from_worktree(repo)? {
+                return Ok(bytes);
+            }
             match repo.read_obj(oid) {
                 Ok(obj) => return Ok(obj.into_blob().to_vec()),
                 Err(err) => err.try_into_obj_not_found_err()?,
@@ -109,6 +113,20 @@ pub trait BitEntry {
 
         Ok(repo.read_blob_from_worktree(self.path())?.to_vec())
     }
+
+    /// Reads the contents of the entry from the worktree of a partial clone if they are missing
+    /// from the object store (and so would be fetched from the promisor remote)
+    fn read_unstored_from_worktree(&self, repo: &BitRepo) -> BitResult<Option<Vec<u8>>> {
+        let oid = self.oid();
+        if !repo.is_partial_clone()
+            || repo.obj_exists(oid)?
+            || repo.to_absolute_path(self.path()).symlink_metadata().is_err()
+        {
+            return Ok(None);
+        }
+        let blob = repo.read_blob_from_worktree(self.path())?;
+        Ok((blob.hash()? == oid).then(|| blob.to_vec()))
+    }
 }
 
 /// wrapper around `TreeIter` that skips the tree entries
@@ -168,17 +186,14 @@ struct WorktreeRawIter {
     repo: BitRepo,
     // this is significantly faster than using bitpath as key for some reason
     tracked: FxHashSet<&'static OsStr>,
-    // TODO ignoring all nonroot ignores for now
-    // not sure what the correct collection for this is? some kind of tree where gitignores know their "parent" gitignore?
-    ignore: Vec<Gitignore>,
+    ignore: IgnoreStack,
     jwalk: jwalk::DirEntryIter<((), ())>,
 }
 
 impl WorktreeRawIter {
     pub fn new(index: &BitIndex) -> BitResult<Self> {
         let repo = index.repo();
-        // ignoring any gitignore errors for now
-        let ignore = vec![Gitignore::new(repo.workdir.join(".gitignore").as_path()).0];
+        let ignore = IgnoreStack::new(&repo)?;
         //? we collect it into a hashmap for faster lookup?
         // not sure if this is actually better than just looking up in the index's entries
         let tracked = index.entries().keys().map(|(path, _)| path.as_os_str()).collect();
@@ -209,12 +224,10 @@ impl WorktreeRawIter {
         Ok(Self { repo, ignore, tracked, jwalk })
     }
 
-    // we need to explicitly ignore our root `.bit/.git` directories
-    // TODO testing
-    fn is_ignored(&self, entry: &DirEntry) -> BitResult<bool> {
+    fn is_ignored(&mut self, entry: &DirEntry) -> BitResult<bool> {
         debug_assert!(entry.path.is_absolute());
 
-        let relative = self.repo.to_relative_path(&entry.path)?;
+        let relative = BitPath::intern(self.repo.to_relative_path(&entry.path)?);
         debug_assert!(
             relative.iter().all(|component| BitPath::DOT_GIT != component),
             "git directories should be filtered out by now"
@@ -224,29 +237,7 @@ impl WorktreeRawIter {
             return Ok(false);
         }
 
-        for ignore in &self.ignore {
-            // TODO the matcher wants a path relative to where the gitignore file is
-            // currently just assuming its the repo root (which all paths are relative to)
-            // `ignore.matched_path` doesn't work here as it doesn't seem to match directories
-            // e.g.
-            // tree! {
-            //     ignoreme {
-            //         a
-            //     }
-            // }
-            //
-            // gitignore! {
-            //     ignoreme
-            // }
-            // path ignoreme/a will not be ignored by `matched_path`
-            // using `matched_path_or_any_parents` for now
-            if ignore.matched_path_or_any_parents(&entry.path, entry.file_type.is_dir()).is_ignore()
-            {
-                return Ok(true);
-            }
-        }
-
-        Ok(false)
+        self.ignore.is_ignored(relative, entry.file_type.is_dir())
     }
 }
 
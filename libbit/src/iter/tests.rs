@@ -91,6 +91,70 @@ fn test_simple_root_gitignore_ignore_directory() -> BitResult<()> {
     })
 }
 
+#[test]
+fn test_nested_gitignore_file() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let index = repo.index()?;
+        mkdir!(repo: "dir");
+        touch!(repo: "dir/.gitignore" < "*.log\n");
+        touch!(repo: "dir/a.log");
+        touch!(repo: "dir/a.txt");
+        // the nested .gitignore doesn't apply to its parent
+        touch!(repo: "b.log");
+        let entries = index.worktree_iter()?.map(|entry| Ok(entry.path)).collect::<Vec<_>>()?;
+        assert_eq!(entries, ["b.log", "dir/.gitignore", "dir/a.txt"]);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_nested_gitignore_overrides_parent() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let index = repo.index()?;
+        gitignore!(repo: {
+            "*.log"
+            ".gitignore"
+        });
+        mkdir!(repo: "dir");
+        touch!(repo: "dir/.gitignore" < "!keep.log\n");
+        touch!(repo: "dir/keep.log");
+        touch!(repo: "dir/other.log");
+        let entries = index.worktree_iter()?.map(|entry| Ok(entry.path)).collect::<Vec<_>>()?;
+        assert_eq!(entries, ["dir/.gitignore", "dir/keep.log"]);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_gitignore_cannot_reinclude_file_in_excluded_directory() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let index = repo.index()?;
+        gitignore!(repo: {
+            "dir/"
+            "!dir/keep"
+            ".gitignore"
+        });
+        mkdir!(repo: "dir");
+        touch!(repo: "dir/keep");
+        assert_eq!(index.worktree_iter()?.count()?, 0);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_info_exclude() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let index = repo.index()?;
+        std::fs::create_dir_all(repo.bitdir.join("info"))?;
+        std::fs::write(repo.bitdir.join("info/exclude"), "excluded\n")?;
+        touch!(repo: "excluded");
+        touch!(repo: "included");
+        let entries = index.worktree_iter()?.map(|entry| Ok(entry.path)).collect::<Vec<_>>()?;
+        assert_eq!(entries, ["included"]);
+        Ok(())
+    })
+}
+
 macro_rules! next_entries {
     ($iter:expr) => {{
         let entries = $iter.next()?.unwrap();
@@ -56,8 +56,8 @@ extern crate anyhow;
 #[cfg(test)]
 #[macro_use]
 pub mod test_utils;
-mod cache;
 mod cherrypick;
+mod chunk_format;
 mod fs;
 mod graph;
 mod protocol;
@@ -72,28 +72,42 @@ mod macros;
 #[macro_use]
 mod debug;
 
+pub mod bitmap;
+pub mod cache;
 pub mod checkout;
 pub mod cmd;
 pub mod commit;
+pub mod commit_graph;
 pub mod config;
 pub mod diff;
 pub mod error;
 pub mod format;
+pub mod fsck;
+pub mod gc;
+pub mod gitignore;
 pub mod hash;
 pub mod index;
 pub mod iter;
 pub mod merge;
+pub mod midx;
 pub mod obj;
 pub mod pack;
+pub mod partial_clone;
 pub mod path;
 pub mod pathspec;
+pub mod progress;
+pub mod rebase;
 pub mod refs;
 pub mod repo;
 pub mod reset;
 pub mod rev;
 pub mod serialize;
+pub mod shallow;
+pub mod stash;
 pub mod status;
 
+pub mod daemon;
+pub mod receive_pack;
 pub mod remote;
 pub mod upload_pack;
 pub mod xdiff;
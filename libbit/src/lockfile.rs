@@ -119,6 +119,34 @@ impl Lockfile {
         Self::open(path, flags)?.with_mut_inner(f)
     }
 
+    /// Locks every path in `paths` and runs `f` with mutable access to all of the locks.
+    /// If `f` succeeds then every lock (that wasn't explicitly rolled back) is committed,
+    /// otherwise none of them are.
+    pub fn with_mut_all<R>(
+        paths: impl IntoIterator<Item = impl AsRef<Path>>,
+        flags: LockfileFlags,
+        f: impl FnOnce(&mut [Self]) -> BitResult<R>,
+    ) -> BitResult<R> {
+        // if any of the paths can't be locked then the locks taken so far are dropped (and so
+        // rolled back)
+        let mut lockfiles =
+            paths.into_iter().map(|path| Self::open(path, flags)).collect::<BitResult<Vec<_>>>()?;
+        match f(&mut lockfiles) {
+            Ok(r) => {
+                for lockfile in &mut lockfiles {
+                    lockfile.commit().with_context(|| {
+                        anyhow!("failed to write lockfile to `{}`", lockfile.path.display())
+                    })?;
+                }
+                Ok(r)
+            }
+            Err(err) => {
+                lockfiles.iter().for_each(Self::rollback);
+                Err(err)
+            }
+        }
+    }
+
     /// runs a function under the lock having mutable access to the underlying file
     /// if the closure returns an `Err` then the transaction is rolled back, otherwise it is
     /// committed to disk
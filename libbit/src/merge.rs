@@ -1,4 +1,5 @@
 use crate::checkout::CheckoutOpts;
+use crate::commit_graph::{CommitInfo, CommitInfoReader, GENERATION_NUMBER_INFINITY};
 use crate::error::{BitError, BitResult};
 use crate::fs::UniquePath;
 use crate::index::{BitIndexEntry, BitIndexInner, Conflicts, MergeStage};
@@ -8,10 +9,10 @@ use crate::path::BitPath;
 use crate::pathspec::Pathspec;
 use crate::peel::Peel;
 use crate::refs::BitRef;
-use crate::repo::BitRepo;
+use crate::repo::{BitRepo, RepoState};
 use crate::rev::Revspec;
 use crate::xdiff;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 use std::fmt::{self, Debug, Display, Formatter};
@@ -51,6 +52,7 @@ impl BitRepo {
     }
 
     pub fn merge(&self, their_head_ref: BitRef, opts: MergeOpts) -> BitResult<MergeResults> {
+        self.ensure_not_rebasing()?;
         MergeCtxt::new(self, their_head_ref, opts)?.merge()
     }
 
@@ -60,12 +62,35 @@ impl BitRepo {
         base: Option<Arc<Commit>>,
         opts: MergeOpts,
     ) -> BitResult<MergeResults> {
+        self.ensure_not_rebasing()?;
         MergeCtxt::new(self, their_head_ref, opts)?.merge_with_base(base)
     }
 
+    fn ensure_not_rebasing(&self) -> BitResult<()> {
+        ensure!(
+            self.repo_state() != RepoState::Rebasing,
+            "cannot merge while in the middle of a rebase (use `bit rebase --abort` to stop)"
+        );
+        Ok(())
+    }
+
     pub fn merge_rev(&self, their_head: &Revspec, opts: MergeOpts) -> BitResult<MergeResults> {
         self.merge(self.resolve_rev(their_head)?, opts)
     }
+
+    /// 3-way merge the tree of the commit `theirs` into the index and worktree using `base_tree`
+    /// as the merge base. Nothing is committed and `HEAD` is not moved.
+    /// Their side of any conflicts is labelled with `their_desc`.
+    pub(crate) fn merge_uncommitted(
+        &self,
+        base_tree: Oid,
+        theirs: Oid,
+        their_desc: &str,
+    ) -> BitResult<MergeResults> {
+        let mut ctxt = MergeCtxt::new(self, BitRef::Direct(theirs), MergeOpts::NO_EDIT)?;
+        ctxt.their_head_desc = their_desc.to_owned();
+        ctxt.merge_uncommitted(base_tree)
+    }
 }
 
 /// The conflicts that prevent a merge from occurring (not the "merge conflicts")
@@ -252,14 +277,7 @@ impl MergeCtxt {
         }
 
         self.merge_commits(merge_base, &self.our_head_commit()?, &self.their_head_commit()?)?;
-
-        if !self.uncommitted.is_empty() {
-            // Restore index to pre-merge snapshot and check it out to also restore the worktree to inital pre-merge state
-            // We perform a safe checkout as we don't want to overwrite unstaged files
-            **self.repo.index_mut()? = self.initial_index_snapshot.clone();
-            self.repo.checkout_index(CheckoutOpts::default())?;
-            bail!(BitError::MergeConflict(MergeConflicts { uncommitted: self.uncommitted }))
-        }
+        self.rollback_if_uncommitted()?;
 
         if self.index()?.has_conflicts() {
             return Ok(MergeResults::Conflicts(self.index()?.conflicts()));
@@ -285,6 +303,30 @@ impl MergeCtxt {
         Ok(MergeResults::Merge(MergeSummary {}))
     }
 
+    fn merge_uncommitted(mut self, base_tree: Oid) -> BitResult<MergeResults> {
+        self.pre_merge_checks()?;
+        let our_tree = self.our_head_commit()?.tree_oid();
+        self.merge_trees(base_tree, our_tree, self.their_head_commit()?.tree_oid())?;
+        self.rollback_if_uncommitted()?;
+
+        if self.index()?.has_conflicts() {
+            return Ok(MergeResults::Conflicts(self.index()?.conflicts()));
+        }
+        Ok(MergeResults::Merge(MergeSummary {}))
+    }
+
+    fn rollback_if_uncommitted(&mut self) -> BitResult<()> {
+        if !self.uncommitted.is_empty() {
+            // Restore index to pre-merge snapshot and check it out to also restore the worktree to inital pre-merge state
+            // We perform a safe checkout as we don't want to overwrite unstaged files
+            **self.repo.index_mut()? = self.initial_index_snapshot.clone();
+            self.repo.checkout_index(CheckoutOpts::default())?;
+            let uncommitted = std::mem::take(&mut self.uncommitted);
+            bail!(BitError::MergeConflict(MergeConflicts { uncommitted }))
+        }
+        Ok(())
+    }
+
     fn merge_commits(
         &mut self,
         merge_base: Option<Arc<Commit>>,
@@ -296,6 +338,7 @@ impl MergeCtxt {
     }
 
     pub fn merge_trees(&mut self, base_tree: Oid, our_tree: Oid, their_tree: Oid) -> BitResult<()> {
+        self.prefetch_merge_objects(base_tree, our_tree, their_tree)?;
         self.merge_from_iterators(
             self.tree_iter(base_tree),
             self.tree_iter(our_tree),
@@ -303,6 +346,40 @@ impl MergeCtxt {
         )
     }
 
+    /// Fetch the blobs that merging the trees will read that are missing from a partial clone
+    fn prefetch_merge_objects(
+        &self,
+        base_tree: Oid,
+        our_tree: Oid,
+        their_tree: Oid,
+    ) -> BitResult<()> {
+        if !self.repo.is_partial_clone() {
+            return Ok(());
+        }
+        let walk = self.repo.walk_tree_iterators([
+            Box::new(self.tree_iter(base_tree)),
+            Box::new(self.tree_iter(our_tree)),
+            Box::new(self.tree_iter(their_tree)),
+        ]);
+        let oids = walk.fold(vec![], |mut oids, [base, ours, theirs]| {
+            let blob_oid = |entry: Option<BitIndexEntry>| {
+                entry.filter(|entry| entry.is_blob()).map(|entry| entry.oid())
+            };
+            let (base, ours, theirs) = (blob_oid(base), blob_oid(ours), blob_oid(theirs));
+            // only their side is needed when just they changed it, and all three are needed
+            // when both sides changed it
+            if ours != theirs && base != theirs {
+                if base == ours {
+                    oids.extend(theirs);
+                } else {
+                    oids.extend([base, ours, theirs].into_iter().flatten());
+                }
+            }
+            Ok(oids)
+        })?;
+        self.repo.prefetch_objects(oids)
+    }
+
     /// 3-way merge the iterators and write the changes to the index
     fn merge_from_iterators(
         &mut self,
@@ -685,7 +762,7 @@ bitflags! {
 
 #[derive(Debug)]
 struct CommitNode {
-    commit: Arc<Commit>,
+    info: CommitInfo,
     index: usize,
 }
 
@@ -702,10 +779,10 @@ impl PartialEq for CommitNode {
 }
 
 impl std::ops::Deref for CommitNode {
-    type Target = Commit;
+    type Target = CommitInfo;
 
     fn deref(&self) -> &Self::Target {
-        &self.commit
+        &self.info
     }
 }
 
@@ -714,12 +791,14 @@ impl Eq for CommitNode {
 
 impl Ord for CommitNode {
     // we want this cmp to suit a maxheap
-    // so we want the most recent (largest timestamp) commit to be >= and the smallest index to be >=
+    // so we want the highest generation commit to be >=, followed by the most recent (largest
+    // timestamp) commit and then the smallest index.
+    // Unlike timestamps, generations are guaranteed to be larger than those of all ancestors so
+    // this ordering is topological (commits not in the commit-graph have infinite generation).
     fn cmp(&self, other: &Self) -> Ordering {
-        self.commit
-            .committer
-            .time
-            .cmp(&other.commit.committer.time)
+        self.generation
+            .cmp(&other.generation)
+            .then_with(|| self.commit_time.cmp(&other.commit_time))
             .then_with(|| other.index.cmp(&self.index))
             .then_with(|| bug!("index should be unique"))
     }
@@ -727,7 +806,8 @@ impl Ord for CommitNode {
 
 pub struct MergeBaseCtxt {
     repo: BitRepo,
-    candidates: Vec<Arc<Commit>>,
+    commits: CommitInfoReader,
+    candidates: Vec<Oid>,
     pqueue: BinaryHeap<CommitNode>,
     node_flags: FxHashMap<Oid, NodeFlags>,
     index: usize,
@@ -737,28 +817,30 @@ impl MergeBaseCtxt {
     pub fn still_interesting(&self) -> bool {
         // interesting if pqueue still contains any non-stale nodes
         // otherwise, everything will be stale from here on so we can stop
-        self.pqueue.iter().any(|node| !self.node_flags[&node.oid()].contains(NodeFlags::STALE))
+        self.pqueue.iter().any(|node| !self.node_flags[&node.oid].contains(NodeFlags::STALE))
     }
 
-    fn mk_node(&mut self, commit: Arc<Commit>) -> CommitNode {
+    fn mk_node(&mut self, info: CommitInfo) -> CommitNode {
         let index = self.index;
         self.index += 1;
-        CommitNode { index, commit }
+        CommitNode { index, info }
     }
 
     fn merge_bases_all(mut self, a: &Arc<Commit>, b: &Arc<Commit>) -> BitResult<Vec<Arc<Commit>>> {
-        self.build_candidates(a.clone(), b.clone())?;
+        // the roots are looked up again so they have generation numbers if they are in the graph
+        self.build_candidates(self.commits.read(a.oid())?, self.commits.read(b.oid())?)?;
         let node_flags = &self.node_flags;
-        self.candidates.retain(|node| !node_flags[&node.oid()].contains(NodeFlags::STALE));
+        self.candidates.retain(|oid| !node_flags[oid].contains(NodeFlags::STALE));
         // TODO I think it's possible for the candidate set at this point to still be incorrect (i.e. it include some non-BCA nodes)
         // but haven't found the cases that cause this
-        Ok(self.candidates)
+        // only the merge bases themselves need to be parsed
+        self.candidates.iter().map(|&oid| self.repo.read_obj_commit(oid)).collect()
     }
 
-    fn build_candidates(&mut self, a: Arc<Commit>, b: Arc<Commit>) -> BitResult<()> {
-        let mut push_init = |commit, flags| {
-            let node = self.mk_node(commit);
-            self.node_flags.entry(node.oid()).or_default().insert(flags);
+    fn build_candidates(&mut self, a: CommitInfo, b: CommitInfo) -> BitResult<()> {
+        let mut push_init = |info, flags| {
+            let node = self.mk_node(info);
+            self.node_flags.entry(node.oid).or_default().insert(flags);
             self.pqueue.push(node);
         };
 
@@ -771,8 +853,7 @@ impl MergeBaseCtxt {
                 None => break,
             };
 
-            let flags = self.node_flags.get_mut(&node.oid()).unwrap();
-            let parents = node.commit.parents.clone();
+            let flags = self.node_flags.get_mut(&node.oid).unwrap();
             // unset the result bit, as we don't want to propogate the result flag
             let mut parent_flags = *flags & !NodeFlags::RESULT;
 
@@ -782,17 +863,17 @@ impl MergeBaseCtxt {
                 // add to the candidate set only if it is neither a result or stale
                 if !flags.intersects(NodeFlags::RESULT | NodeFlags::STALE) {
                     flags.insert(NodeFlags::RESULT);
-                    self.candidates.push(node.commit);
+                    self.candidates.push(node.oid);
                 }
             }
 
-            for &parent in &parents {
+            for &parent in &node.parents {
                 let pflags = self.node_flags.entry(parent).or_default();
                 if *pflags == parent_flags {
                     continue;
                 }
-                let parent = self.repo.read_obj_commit(parent)?;
                 pflags.insert(parent_flags);
+                let parent = self.commits.read(parent)?;
                 let parent_node = self.mk_node(parent);
                 self.pqueue.push(parent_node);
             }
@@ -803,8 +884,10 @@ impl MergeBaseCtxt {
 
 impl Commit {
     fn find_merge_bases(self: &Arc<Self>, other: &Arc<Commit>) -> BitResult<Vec<Arc<Commit>>> {
+        let repo = self.owner();
         MergeBaseCtxt {
-            repo: self.owner(),
+            commits: CommitInfoReader::new(repo.clone())?,
+            repo,
             candidates: Default::default(),
             node_flags: Default::default(),
             pqueue: Default::default(),
@@ -824,5 +907,45 @@ impl Commit {
     }
 }
 
+impl BitRepo {
+    /// Whether `ancestor` is reachable from the commit `oid` (a commit is considered to be its own
+    /// ancestor)
+    pub(crate) fn is_descendant_of(&self, oid: Oid, ancestor: Oid) -> BitResult<bool> {
+        if oid == ancestor {
+            return Ok(true);
+        }
+
+        let commits = CommitInfoReader::new(self.clone())?;
+        let target = commits.read(ancestor)?;
+        let start = commits.read(oid)?;
+        // without generation numbers there's nothing to prune the search with
+        if target.generation == GENERATION_NUMBER_INFINITY
+            || start.generation == GENERATION_NUMBER_INFINITY
+        {
+            return Ok(self.merge_base(ancestor, oid)?.map(|base| base.oid()) == Some(ancestor));
+        }
+
+        // the ancestors of a commit all have strictly smaller generations, so there's no need to
+        // look past any commit with a generation that's not larger than the target's
+        let mut seen = FxHashSet::default();
+        let mut stack = vec![start];
+        while let Some(info) = stack.pop() {
+            for &parent in &info.parents {
+                if parent == ancestor {
+                    return Ok(true);
+                }
+                if !seen.insert(parent) {
+                    continue;
+                }
+                let parent = commits.read(parent)?;
+                if parent.generation > target.generation {
+                    stack.push(parent);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,374 @@
+use crate::chunk_format::*;
+use crate::error::BitResult;
+use crate::hash::OID_SIZE;
+use crate::io::{HashWriter, WriteExt, WriteExtSized};
+use crate::lockfile::{Lockfile, LockfileFlags};
+use crate::obj::{Oid, PartialOid};
+use crate::pack::*;
+use crate::path::BitPath;
+use crate::repo::BitRepo;
+use crate::serialize::{Deserialize, Serialize};
+use anyhow::Context;
+use filebuffer::FileBuffer;
+use std::cmp::Reverse;
+use std::io::{BufRead, BufWriter, Write};
+use std::ops::{Deref, Range};
+use std::time::SystemTime;
+
+// The format is documented at https://git-scm.com/docs/gitformat-pack#_multi_pack_index_midx_files_have_the_following_format
+// A multi-pack-index is an index of the objects in a set of packs (all the packs in the
+// repository when written by us), so an object can be found with a single binary search instead
+// of probing the index of every pack.
+
+pub const MULTI_PACK_INDEX_FILE_NAME: &str = "multi-pack-index";
+const MIDX_SIGNATURE: &[u8; 4] = b"MIDX";
+const MIDX_VERSION: u8 = 1;
+/// the oid version for sha1
+const MIDX_OID_VERSION: u8 = 1;
+const MIDX_HEADER_SIZE: usize = 12;
+const OBJECT_OFFSET_SIZE: usize = 8;
+const LARGE_OFFSET_SIZE: usize = 8;
+/// set in an object offset if the offset is actually an index into the large offsets chunk
+const LARGE_OFFSET_FLAG: u32 = 0x80000000;
+
+const CHUNK_PACK_NAMES: u32 = u32::from_be_bytes(*b"PNAM");
+const CHUNK_OBJECT_OFFSETS: u32 = u32::from_be_bytes(*b"OOFF");
+const CHUNK_LARGE_OFFSETS: u32 = u32::from_be_bytes(*b"LOFF");
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultiPackIndex {
+    /// the names of the index files of the packs that are covered (in sorted order)
+    pub pack_names: Vec<String>,
+    /// the (sorted) oids of all the objects in the packs
+    pub oids: Vec<Oid>,
+    /// `(pack_id, offset)` for each oid where `pack_id` is the index into `pack_names`
+    pub offsets: Vec<(u32, u64)>,
+}
+
+/// A pack to be covered by a multi-pack-index
+pub struct MultiPackIndexPack<B = FileBuffer> {
+    /// the name of the index file of the pack (`pack-<hash>.idx`)
+    pub name: String,
+    pub index: PackIndexReader<B>,
+    /// when the pack was last modified
+    pub mtime: SystemTime,
+}
+
+impl MultiPackIndex {
+    pub fn new<B: Deref<Target = [u8]>>(mut packs: Vec<MultiPackIndexPack<B>>) -> BitResult<Self> {
+        packs.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut entries = vec![];
+        for (pack_id, pack) in packs.iter().enumerate() {
+            for i in 0..pack.index.len() {
+                let oid = pack.index.read_oid_at(i)?;
+                let offset = pack.index.offset_at(i)?;
+                entries.push((oid, Reverse(pack.mtime), pack_id as u32, offset));
+            }
+        }
+        // as with git, if an object is in multiple packs then the most recently modified pack
+        // is the one that's used
+        entries.sort();
+        entries.dedup_by_key(|&mut (oid, ..)| oid);
+
+        Ok(Self {
+            pack_names: packs.into_iter().map(|pack| pack.name).collect(),
+            oids: entries.iter().map(|&(oid, ..)| oid).collect(),
+            offsets: entries.iter().map(|&(_, _, pack_id, offset)| (pack_id, offset)).collect(),
+        })
+    }
+}
+
+impl Serialize for MultiPackIndex {
+    fn serialize(&self, writer: &mut dyn Write) -> BitResult<()> {
+        let mut writer = BufWriter::new(HashWriter::new_sha1(writer));
+
+        let mut pack_names = vec![];
+        for name in &self.pack_names {
+            pack_names.extend_from_slice(name.as_bytes());
+            pack_names.push(0);
+        }
+        // the chunk is padded to a multiple of 4 bytes
+        pack_names.resize((pack_names.len() + 3) / 4 * 4, 0);
+
+        let large_offsets = self
+            .offsets
+            .iter()
+            .map(|&(_, offset)| offset)
+            .filter(|&offset| offset > MAX_OFFSET)
+            .collect::<Vec<_>>();
+
+        let n = self.oids.len();
+        let mut chunks = vec![
+            (CHUNK_PACK_NAMES, pack_names.len()),
+            (CHUNK_OID_FANOUT, FANOUT_SIZE as usize),
+            (CHUNK_OID_LOOKUP, n * OID_SIZE),
+            (CHUNK_OBJECT_OFFSETS, n * OBJECT_OFFSET_SIZE),
+        ];
+        if !large_offsets.is_empty() {
+            chunks.push((CHUNK_LARGE_OFFSETS, large_offsets.len() * LARGE_OFFSET_SIZE));
+        }
+
+        writer.write_all(MIDX_SIGNATURE)?;
+        writer.write_u8(MIDX_VERSION)?;
+        writer.write_u8(MIDX_OID_VERSION)?;
+        writer.write_u8(chunks.len() as u8)?;
+        // number of base multi-pack-index files
+        writer.write_u8(0)?;
+        writer.write_u32(self.pack_names.len() as u32)?;
+
+        write_chunk_lookup(&mut writer, MIDX_HEADER_SIZE, &chunks)?;
+
+        writer.write_all(&pack_names)?;
+        writer.write_iter(PackIndex::build_fanout(&self.oids))?;
+        writer.write_iter(&self.oids)?;
+        let mut large_offset_index = 0;
+        for &(pack_id, offset) in &self.offsets {
+            writer.write_u32(pack_id)?;
+            if offset > MAX_OFFSET {
+                writer.write_u32(LARGE_OFFSET_FLAG | large_offset_index)?;
+                large_offset_index += 1;
+            } else {
+                writer.write_u32(offset as u32)?;
+            }
+        }
+        for offset in large_offsets {
+            writer.write_u64(offset)?;
+        }
+
+        match writer.into_inner() {
+            Ok(writer) => writer.write_hash()?,
+            Err(..) => bail!("hash writer flush failed while writing multi-pack-index"),
+        };
+        Ok(())
+    }
+}
+
+impl Deserialize for MultiPackIndex {
+    fn deserialize(mut reader: impl BufRead) -> BitResult<Self>
+    where
+        Self: Sized,
+    {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let reader = MultiPackIndexReader::new(bytes)?;
+        reader.verify_checksum()?;
+        Ok(Self {
+            pack_names: reader.pack_names().to_vec(),
+            oids: (0..reader.len()).map(|index| reader.oid_at(index)).collect(),
+            offsets: (0..reader.len())
+                .map(|index| reader.object_offset_at(index))
+                .collect::<BitResult<_>>()?,
+        })
+    }
+}
+
+/// Reads a multi-pack-index directly from its bytes (usually memory mapped).
+/// As with [PackIndexReader], lookups only require a shared reference.
+pub struct MultiPackIndexReader<B> {
+    bytes: B,
+    pack_names: Vec<String>,
+    fanout: [u32; FANOUT_ENTRYC],
+    oid_lookup: OidLookup,
+    object_offsets: Range<usize>,
+    large_offsets: Option<Range<usize>>,
+}
+
+impl<B: Deref<Target = [u8]>> MultiPackIndexReader<B> {
+    pub fn new(bytes: B) -> BitResult<Self> {
+        ensure!(bytes.len() >= MIDX_HEADER_SIZE + OID_SIZE, "multi-pack-index is truncated");
+        ensure!(&bytes[..4] == MIDX_SIGNATURE, "invalid multi-pack-index signature");
+        ensure!(bytes[4] == MIDX_VERSION, "unsupported multi-pack-index version `{}`", bytes[4]);
+        ensure!(
+            bytes[5] == MIDX_OID_VERSION,
+            "unsupported multi-pack-index oid version `{}`",
+            bytes[5]
+        );
+        ensure!(bytes[7] == 0, "multi-pack-index chains are not supported");
+        let chunkc = bytes[6] as usize;
+        let packc = read_u32_at(&bytes, 8) as usize;
+        let chunks = ChunkLookup::parse("multi-pack-index", &bytes, MIDX_HEADER_SIZE, chunkc)?;
+
+        let pack_names = bytes[chunks.required(CHUNK_PACK_NAMES)?]
+            .split(|&b| b == 0)
+            .take(packc)
+            .map(|name| Ok(std::str::from_utf8(name)?.to_owned()))
+            .collect::<BitResult<Vec<_>>>()?;
+        ensure!(pack_names.len() == packc, "multi-pack-index is missing some pack names");
+
+        let fanout = chunks.read_fanout(&bytes)?;
+        let n = fanout[FANOUT_ENTRYC - 1] as usize;
+        let oid_lookup = chunks.read_oid_lookup(n)?;
+        let object_offsets = chunks.required(CHUNK_OBJECT_OFFSETS)?;
+        ensure!(
+            object_offsets.len() == n * OBJECT_OFFSET_SIZE,
+            "multi-pack-index object offsets are invalid"
+        );
+        // any other chunks (such as the reverse index) are optional so we just ignore them
+        let large_offsets = chunks.get(CHUNK_LARGE_OFFSETS);
+
+        Ok(Self { bytes, pack_names, fanout, oid_lookup, object_offsets, large_offsets })
+    }
+
+    pub fn pack_names(&self) -> &[String] {
+        &self.pack_names
+    }
+
+    /// the number of objects in the index
+    pub fn len(&self) -> usize {
+        self.fanout[FANOUT_ENTRYC - 1] as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn oid_at(&self, index: usize) -> Oid {
+        self.oid_lookup.oid_at(&self.bytes, index)
+    }
+
+    /// returns `(pack_id, offset)` of the object at `index`
+    pub fn object_offset_at(&self, index: usize) -> BitResult<(u32, u64)> {
+        let entry = self.object_offsets.start + index * OBJECT_OFFSET_SIZE;
+        let pack_id = read_u32_at(&self.bytes, entry);
+        ensure!(
+            (pack_id as usize) < self.pack_names.len(),
+            "multi-pack-index has invalid pack id `{}`",
+            pack_id
+        );
+
+        let offset = read_u32_at(&self.bytes, entry + 4);
+        if offset & LARGE_OFFSET_FLAG == 0 {
+            return Ok((pack_id, offset as u64));
+        }
+
+        let large_offsets = match &self.large_offsets {
+            Some(large_offsets) => large_offsets,
+            None => bail!("multi-pack-index is missing the large offsets chunk"),
+        };
+        let large_offset_index = (offset & !LARGE_OFFSET_FLAG) as usize;
+        let start = large_offsets.start + large_offset_index * LARGE_OFFSET_SIZE;
+        ensure!(start < large_offsets.end, "multi-pack-index has an invalid large offset");
+        Ok((pack_id, read_u64_at(&self.bytes, start)))
+    }
+
+    /// returns the index of `oid` if it's found, otherwise the index where it would be inserted
+    fn find_oid_index(&self, oid: Oid) -> Result<usize, usize> {
+        self.oid_lookup.find_oid_index(&self.bytes, &self.fanout, oid)
+    }
+
+    /// returns `(pack_id, offset)` of the object with oid `oid` if it's in the index
+    pub fn find_oid(&self, oid: Oid) -> BitResult<Option<(u32, u64)>> {
+        match self.find_oid_index(oid) {
+            Ok(index) => self.object_offset_at(index).map(Some),
+            Err(..) => Ok(None),
+        }
+    }
+
+    /// returns a list of oids that start with `prefix`
+    pub fn prefix_matches(&self, prefix: PartialOid) -> BitResult<Vec<Oid>> {
+        // the prefix extended with zeroes sorts before every oid with the prefix
+        let start = self.find_oid_index(prefix.into_oid()?).unwrap_or_else(|idx| idx);
+        let mut matches = vec![];
+        for index in start..self.len() {
+            let oid = self.oid_at(index);
+            if !oid.has_prefix(prefix)? {
+                break;
+            }
+            matches.push(oid);
+        }
+        Ok(matches)
+    }
+
+    pub fn verify_checksum(&self) -> BitResult<()> {
+        verify_checksum("multi-pack-index", &self.bytes)
+    }
+}
+
+impl BitRepo {
+    pub(crate) fn multi_pack_index_path(&self) -> BitPath {
+        self.pack_objects_dir().join(MULTI_PACK_INDEX_FILE_NAME)
+    }
+
+    /// Write a multi-pack-index that covers every pack in the repository
+    pub fn write_multi_pack_index(&self) -> BitResult<MultiPackIndex> {
+        let pack_dir = self.pack_objects_dir();
+        let mut packs = vec![];
+        if pack_dir.try_exists()? {
+            for entry in std::fs::read_dir(pack_dir)? {
+                let idx_path = entry?.path();
+                let pack_path = idx_path.with_extension(PACK_EXT);
+                if idx_path.extension() != Some(PACK_IDX_EXT.as_ref()) || !pack_path.try_exists()? {
+                    continue;
+                }
+
+                // the index is read in place rather than deserialized as only the oids and
+                // (possibly 64-bit) offsets are needed
+                let index =
+                    PackIndexReader::new(FileBuffer::open(&idx_path)?).with_context(|| {
+                        format!("failed to read pack index `{}`", idx_path.display())
+                    })?;
+                packs.push(MultiPackIndexPack {
+                    name: idx_path.file_name().unwrap().to_string_lossy().into_owned(),
+                    index,
+                    mtime: pack_path.metadata()?.modified()?,
+                });
+            }
+        }
+        ensure!(!packs.is_empty(), "no packfiles to index");
+
+        let midx = MultiPackIndex::new(packs)?;
+        Lockfile::with_mut(
+            self.multi_pack_index_path(),
+            LockfileFlags::SET_READONLY,
+            |lockfile| midx.serialize(lockfile),
+        )?;
+        self.refresh_odb()?;
+        Ok(midx)
+    }
+
+    /// Check the multi-pack-index is well formed and agrees with the indexes of the packs it covers
+    pub fn verify_multi_pack_index(&self) -> BitResult<()> {
+        let path = self.multi_pack_index_path();
+        ensure!(path.try_exists()?, "no multi-pack-index found");
+        let midx = MultiPackIndexReader::new(FileBuffer::open(path)?)?;
+        midx.verify_checksum()?;
+        ensure!(midx.pack_names().is_sorted(), "multi-pack-index pack names are not sorted");
+
+        let pack_indexes = midx
+            .pack_names()
+            .iter()
+            .map(|name| {
+                let path = self.pack_objects_dir().join(name);
+                ensure!(path.try_exists()?, "multi-pack-index refers to missing pack `{}`", name);
+                PackIndexReader::new(FileBuffer::open(path)?)
+            })
+            .collect::<BitResult<Vec<_>>>()?;
+
+        for index in 0..midx.len() {
+            let oid = midx.oid_at(index);
+            ensure!(
+                index == 0 || midx.oid_at(index - 1) < oid,
+                "multi-pack-index oid lookup is not sorted at index `{}`",
+                index
+            );
+            // look up each object from scratch to check the fanout is consistent too
+            let (pack_id, offset) = midx
+                .find_oid(oid)?
+                .ok_or_else(|| anyhow!("failed to find object `{}` in multi-pack-index", oid))?;
+            let (_crc, expected) = pack_indexes[pack_id as usize]
+                .find_oid_crc_offset(oid)
+                .with_context(|| format!("object `{}` is not in its pack", oid))?;
+            ensure!(
+                offset == expected,
+                "incorrect offset for object `{}` (expected `{}`, found `{}`)",
+                oid,
+                expected,
+                offset
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,206 @@
+use super::*;
+use crate::obj::BitObject;
+use crate::pack::PackBuilder;
+use std::io::BufReader;
+use std::str::FromStr;
+use std::time::Duration;
+
+fn test_pack_index() -> BitResult<PackIndex> {
+    PackIndex::deserialize(BufReader::new(&include_bytes!("../../tests/files/pack.idx")[..]))
+}
+
+fn index_reader(index: &PackIndex) -> BitResult<PackIndexReader<Vec<u8>>> {
+    let mut bytes = vec![];
+    index.serialize(&mut bytes)?;
+    PackIndexReader::new(bytes)
+}
+
+fn serialize(midx: &MultiPackIndex) -> BitResult<Vec<u8>> {
+    let mut bytes = vec![];
+    midx.serialize(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[test]
+fn test_serde_multi_pack_index() -> BitResult<()> {
+    let midx = MultiPackIndex {
+        pack_names: vec!["pack-a.idx".to_owned(), "pack-bc.idx".to_owned()],
+        oids: vec![
+            Oid::from_str("0000000000000000000000000000000000000001")?,
+            Oid::from_str("10c05e8bc1bc8f4ee0a2b2fd5e8d30b1f3d3f4b1")?,
+            Oid::from_str("ff00000000000000000000000000000000000000")?,
+        ],
+        offsets: vec![(0, 12), (1, MAX_OFFSET + 1), (1, 0x1234_5678_9abc)],
+    };
+    let bytes = serialize(&midx)?;
+    assert_eq!(MultiPackIndex::deserialize_unbuffered(&bytes[..])?, midx);
+    Ok(())
+}
+
+#[test]
+fn test_multi_pack_index_lookup() -> BitResult<()> {
+    let pack_index = test_pack_index()?;
+    let midx = MultiPackIndex::new(vec![MultiPackIndexPack {
+        name: "pack.idx".to_owned(),
+        index: index_reader(&pack_index)?,
+        mtime: SystemTime::now(),
+    }])?;
+    let reader = MultiPackIndexReader::new(serialize(&midx)?)?;
+    reader.verify_checksum()?;
+    assert_eq!(reader.len(), pack_index.oids.len());
+
+    let idx_reader = PackIndexReader::new(&include_bytes!("../../tests/files/pack.idx")[..])?;
+    for &oid in &pack_index.oids {
+        let (_crc, offset) = idx_reader.find_oid_crc_offset(oid)?;
+        assert_eq!(reader.find_oid(oid)?, Some((0, offset)));
+    }
+    assert_eq!(reader.find_oid(Oid::UNKNOWN)?, None);
+    Ok(())
+}
+
+#[test]
+fn test_multi_pack_index_large_offsets() -> BitResult<()> {
+    let pack_index = PackIndex::from_sorted(
+        [
+            (Oid::from_str("0000000000000000000000000000000000000001")?, 0, 12),
+            (Oid::from_str("10c05e8bc1bc8f4ee0a2b2fd5e8d30b1f3d3f4b1")?, 0, MAX_OFFSET + 1),
+            (Oid::from_str("ff00000000000000000000000000000000000000")?, 0, 0x1234_5678_9abc),
+        ],
+        Oid::UNKNOWN,
+    );
+    let midx = MultiPackIndex::new(vec![MultiPackIndexPack {
+        name: "pack.idx".to_owned(),
+        index: index_reader(&pack_index)?,
+        mtime: SystemTime::now(),
+    }])?;
+    assert_eq!(midx.offsets, [(0, 12), (0, MAX_OFFSET + 1), (0, 0x1234_5678_9abc)]);
+
+    let reader = MultiPackIndexReader::new(serialize(&midx)?)?;
+    assert!(reader.large_offsets.is_some());
+    for (&oid, &offset) in midx.oids.iter().zip(&midx.offsets) {
+        assert_eq!(reader.find_oid(oid)?, Some(offset));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_multi_pack_index_prefix_matches() -> BitResult<()> {
+    let pack_index = test_pack_index()?;
+    let midx = MultiPackIndex::new(vec![MultiPackIndexPack {
+        name: "pack.idx".to_owned(),
+        index: index_reader(&pack_index)?,
+        mtime: SystemTime::now(),
+    }])?;
+    let reader = MultiPackIndexReader::new(serialize(&midx)?)?;
+    for &oid in pack_index.oids.iter().step_by(50) {
+        let prefix = PartialOid::from_str(&oid.to_hex()[..4])?;
+        let expected = pack_index
+            .oids
+            .iter()
+            .copied()
+            .filter(|oid| oid.has_prefix(prefix).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(reader.prefix_matches(prefix)?, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_multi_pack_index_prefers_most_recent_pack() -> BitResult<()> {
+    let pack_index = test_pack_index()?;
+    let mut newer = pack_index.clone();
+    newer.offsets.iter_mut().for_each(|offset| *offset += 1);
+    let now = SystemTime::now();
+    let midx = MultiPackIndex::new(vec![
+        MultiPackIndexPack {
+            name: "pack-b.idx".to_owned(),
+            index: index_reader(&newer)?,
+            mtime: now,
+        },
+        MultiPackIndexPack {
+            name: "pack-a.idx".to_owned(),
+            index: index_reader(&pack_index)?,
+            mtime: now - Duration::from_secs(60),
+        },
+    ])?;
+    assert_eq!(midx.pack_names, vec!["pack-a.idx", "pack-b.idx"]);
+    assert_eq!(midx.oids, pack_index.oids);
+    assert!(midx.offsets.iter().all(|&(pack_id, _)| pack_id == 1));
+    Ok(())
+}
+
+#[test]
+fn test_multi_pack_index_detects_corruption() -> BitResult<()> {
+    let midx = MultiPackIndex::new(vec![MultiPackIndexPack {
+        name: "pack.idx".to_owned(),
+        index: index_reader(&test_pack_index()?)?,
+        mtime: SystemTime::now(),
+    }])?;
+    let mut bytes = serialize(&midx)?;
+    let n = bytes.len();
+    bytes[n / 2] ^= 0xff;
+    assert!(MultiPackIndexReader::new(&bytes[..]).and_then(|r| r.verify_checksum()).is_err());
+    assert!(MultiPackIndexReader::new(&bytes[..4]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_write_multi_pack_index_without_packs() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let err = repo.write_multi_pack_index().unwrap_err();
+        assert_eq!(err.to_string(), "no packfiles to index");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_read_objects_through_multi_pack_index() -> BitResult<()> {
+    BitRepo::with_sample_repo(|source| {
+        let head = source.fully_resolve_head()?;
+        let objects = source.reachable_objects(&[head], &[])?;
+        let (first, second) = objects.split_at(objects.len() / 2);
+
+        BitRepo::with_empty_repo(|repo| {
+            let pack_dir = repo.pack_objects_dir();
+            std::fs::create_dir_all(pack_dir)?;
+            PackBuilder::new(source.clone(), first.to_vec()).write_to_dir(pack_dir)?;
+            PackBuilder::new(source.clone(), second.to_vec()).write_to_dir(pack_dir)?;
+
+            let midx = repo.write_multi_pack_index()?;
+            assert_eq!(midx.pack_names.len(), 2);
+            assert_eq!(midx.oids.len(), objects.len());
+            assert!(repo.multi_pack_index_path().try_exists()?);
+            repo.verify_multi_pack_index()?;
+
+            for &(oid, _) in &objects {
+                assert!(repo.obj_exists(oid)?);
+                assert_eq!(repo.read_obj_header(oid)?, source.read_obj_header(oid)?);
+                assert_eq!(repo.read_obj(oid)?.obj_ty(), source.read_obj(oid)?.obj_ty());
+                let prefix = PartialOid::from_str(&oid.to_hex()[..10])?;
+                assert_eq!(repo.expand_prefix(prefix)?, oid);
+            }
+            Ok(())
+        })
+    })
+}
+
+#[test]
+fn test_verify_multi_pack_index_detects_corruption() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let objects = repo.reachable_objects(&[head], &[])?;
+        let pack_dir = repo.pack_objects_dir();
+        std::fs::create_dir_all(pack_dir)?;
+        PackBuilder::new(repo.clone(), objects).write_to_dir(pack_dir)?;
+        repo.write_multi_pack_index()?;
+
+        let path = repo.multi_pack_index_path();
+        let mut bytes = std::fs::read(path)?;
+        let n = bytes.len();
+        bytes[n - 1] ^= 0xff;
+        std::fs::remove_file(path)?;
+        std::fs::write(path, bytes)?;
+        assert!(repo.verify_multi_pack_index().is_err());
+        Ok(())
+    })
+}
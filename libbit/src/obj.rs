@@ -21,7 +21,7 @@ use crate::serialize::{DeserializeSized, Serialize};
 use num_enum::TryFromPrimitive;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::fs::Metadata;
-use std::io::{BufRead, Cursor, Write};
+use std::io::{BufRead, Cursor, Read, Write};
 use std::os::unix::prelude::PermissionsExt;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -41,6 +41,18 @@ impl BitPackObjRaw {
     }
 }
 
+impl Serialize for BitPackObjRaw {
+    fn serialize(&self, writer: &mut dyn Write) -> BitResult<()> {
+        Ok(writer.write_all(&self.bytes)?)
+    }
+}
+
+impl WritableObject for BitPackObjRaw {
+    fn obj_ty(&self) -> BitObjType {
+        self.obj_type
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BitObjCached {
     oid: Oid,
@@ -87,6 +99,19 @@ impl BitRawObj {
         let cached = BitObjCached::new(oid, raw.obj_type, raw.bytes.len() as u64);
         Self { cached, stream: Box::new(Cursor::new(raw.bytes)) }
     }
+
+    /// reads the remainder of the stream into memory
+    pub fn into_pack_raw(mut self) -> BitResult<BitPackObjRaw> {
+        let mut bytes = Vec::with_capacity(self.cached.size as usize);
+        self.stream.read_to_end(&mut bytes)?;
+        ensure_eq!(
+            bytes.len() as u64,
+            self.cached.size,
+            "object `{}` has an incorrect size in its header",
+            self.cached.oid
+        );
+        Ok(BitPackObjRaw { obj_type: self.cached.obj_type, bytes })
+    }
 }
 
 impl Debug for BitPackObjRaw {
@@ -104,7 +129,7 @@ impl Display for BitObjKind {
             BitObjKind::Blob(blob) => Display::fmt(&blob, f),
             BitObjKind::Commit(commit) => Display::fmt(&commit, f),
             BitObjKind::Tree(tree) => Display::fmt(&tree, f),
-            BitObjKind::Tag(_) => todo!(),
+            BitObjKind::Tag(tag) => Display::fmt(&tag, f),
         }
     }
 }
@@ -246,6 +271,13 @@ impl BitObjKind {
         }
     }
 
+    pub fn try_into_tag(self) -> BitResult<Arc<Tag>> {
+        match self {
+            Self::Tag(tag) => Ok(tag),
+            _ => Err(anyhow!("expected tag found `{}`", self.obj_type())),
+        }
+    }
+
     pub fn into_blob(self) -> Arc<Blob> {
         match self {
             BitObjKind::Blob(blob) => blob,
@@ -408,6 +440,8 @@ pub(crate) fn read_obj_header(mut reader: impl BufRead) -> BitResult<BitObjHeade
 #[cfg(test)]
 mod commit_tests;
 #[cfg(test)]
+mod tag_tests;
+#[cfg(test)]
 mod tests;
 #[cfg(test)]
 mod tree_tests;
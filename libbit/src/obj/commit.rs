@@ -11,6 +11,7 @@ use std::fmt::{self, Display, Formatter};
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 use std::ops::Deref;
+use std::path::Path;
 use std::process::Command;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -143,10 +144,19 @@ impl BitRepo {
         tree: Oid,
         parents: CommitParents,
         message: CommitMessage,
+    ) -> BitResult<MutableCommit> {
+        self.mk_commit_with_author(tree, parents, message, self.user_signature()?)
+    }
+
+    fn mk_commit_with_author(
+        &self,
+        tree: Oid,
+        parents: CommitParents,
+        message: CommitMessage,
+        author: BitSignature,
     ) -> BitResult<MutableCommit> {
         ensure!(self.read_obj_header(tree)?.obj_type == BitObjType::Tree);
-        let author = self.user_signature()?;
-        let committer = author.clone();
+        let committer = self.user_signature()?;
 
         for &parent in &parents {
             let parent = self.read_obj(parent)?;
@@ -173,18 +183,45 @@ impl BitRepo {
         self.write_obj(&commit)
     }
 
-    pub fn read_commit_msg(&self) -> BitResult<CommitMessage> {
-        let editor = match std::env::var("EDITOR") {
-            Ok(editor) => editor,
-            Err(..) => bail!("$EDITOR variable not set"),
+    /// same as [`Self::write_commit`] but keeps the author of some existing commit
+    /// (i.e. when the commit is being rewritten)
+    pub(crate) fn write_commit_with_author(
+        &self,
+        tree: Oid,
+        parents: CommitParents,
+        message: CommitMessage,
+        author: BitSignature,
+    ) -> BitResult<Oid> {
+        let commit = self.mk_commit_with_author(tree, parents, message, author)?;
+        self.write_obj(&commit)
+    }
+
+    /// Opens `path` in the user's editor (`core.editor`, falling back to `$EDITOR`) and waits
+    /// for it to exit
+    pub(crate) fn launch_editor(&self, path: impl AsRef<Path>) -> BitResult<()> {
+        let editor = match self.config().editor().or_else(|| std::env::var("EDITOR").ok()) {
+            Some(editor) => editor,
+            None => bail!("$EDITOR variable not set"),
         };
+        // the editor may include arguments so let the shell deal with it as git does
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", editor))
+            .arg(&editor)
+            .arg(path.as_ref())
+            .status()?;
+        ensure!(status.success(), "there was a problem with the editor `{}`", editor);
+        Ok(())
+    }
+
+    pub fn read_commit_msg(&self) -> BitResult<CommitMessage> {
         let template = r#"
 # Please; enter the commit message for your changes. Lines starting
 # with '#' will be ignored, and an empty message aborts the commit."#;
         let editmsg_filepath = self.bitdir.join("COMMIT_EDITMSG");
         let mut editmsg_file = File::create(editmsg_filepath)?;
         write!(editmsg_file, "{template}")?;
-        Command::new(editor).arg(editmsg_filepath).status()?;
+        self.launch_editor(editmsg_filepath)?;
         let mut msg = String::new();
         for line in BufReader::new(File::open(editmsg_filepath)?).lines() {
             let line = line?;
@@ -1,10 +1,18 @@
-use super::{BitObjCached, BitObject, ImmutableBitObject};
+use super::{BitObjCached, BitObjKind, ImmutableBitObject, WritableObject};
 use crate::error::BitResult;
+use crate::obj::{BitObjType, BitObject, Oid};
+use crate::peel::Peel;
 use crate::repo::{BitRepo, BitRepoWeakRef};
 use crate::serialize::{Deserialize, Serialize};
+use crate::signature::BitSignature;
+use std::fmt::{self, Display, Formatter};
 use std::io::prelude::*;
 use std::ops::Deref;
 
+// signatures are appended to the end of the tag message (unlike commits which store them in a header)
+const PGP_SIGNATURE_BEGIN: &str = "-----BEGIN PGP SIGNATURE-----";
+const SSH_SIGNATURE_BEGIN: &str = "-----BEGIN SSH SIGNATURE-----";
+
 #[derive(PartialEq, Clone, Debug)]
 pub struct Tag {
     owner: BitRepoWeakRef,
@@ -13,20 +21,146 @@ pub struct Tag {
 }
 
 #[derive(PartialEq, Clone, Debug)]
-pub struct MutableTag {}
+pub struct MutableTag {
+    /// the object being tagged
+    pub object: Oid,
+    /// the type of the object being tagged
+    pub obj_type: BitObjType,
+    /// the name of the tag
+    pub tag: String,
+    /// very old tags don't have a tagger so this is optional
+    pub tagger: Option<BitSignature>,
+    pub message: String,
+    /// the (armored) signature including the begin and end markers
+    pub signature: Option<String>,
+}
+
+impl MutableTag {
+    pub fn new(
+        object: Oid,
+        obj_type: BitObjType,
+        tag: String,
+        tagger: Option<BitSignature>,
+        message: String,
+    ) -> Self {
+        Self { object, obj_type, tag, tagger, message, signature: None }
+    }
+}
+
+impl BitRepo {
+    fn mk_tag(&self, target: Oid, name: &str, message: String) -> BitResult<MutableTag> {
+        let obj_type = self.read_obj_header(target)?.obj_type;
+        let tagger = self.user_signature()?;
+        Ok(MutableTag::new(target, obj_type, name.to_owned(), Some(tagger), message))
+    }
+
+    /// create and write an annotated tag object to the odb
+    pub fn write_tag(&self, target: Oid, name: &str, message: String) -> BitResult<Oid> {
+        let tag = self.mk_tag(target, name, message)?;
+        self.write_obj(&tag)
+    }
+}
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut buf = vec![];
+        self.serialize(&mut buf).map_err(|_| fmt::Error)?;
+        write!(f, "{}", String::from_utf8_lossy(&buf))
+    }
+}
+
+impl WritableObject for MutableTag {
+    fn obj_ty(&self) -> BitObjType {
+        BitObjType::Tag
+    }
+}
 
 impl Serialize for MutableTag {
-    fn serialize(&self, _writer: &mut dyn Write) -> BitResult<()> {
-        todo!()
+    fn serialize(&self, writer: &mut dyn Write) -> BitResult<()> {
+        writeln!(writer, "object {}", self.object)?;
+        writeln!(writer, "type {}", self.obj_type)?;
+        writeln!(writer, "tag {}", self.tag)?;
+        if let Some(tagger) = &self.tagger {
+            writeln!(writer, "tagger {tagger}")?;
+        }
+        writeln!(writer)?;
+        write!(writer, "{}", self.message)?;
+        if let Some(signature) = &self.signature {
+            write!(writer, "{signature}")?;
+        }
+        Ok(())
     }
 }
 
 impl Deserialize for MutableTag {
-    fn deserialize(mut _reader: impl BufRead) -> BitResult<Self>
+    fn deserialize(mut reader: impl BufRead) -> BitResult<Self>
     where
         Self: Sized,
     {
-        todo!()
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        // the message may be in some other encoding (see the `encoding` header)
+        let s = String::from_utf8_lossy(&bytes);
+
+        let (header, body) = s.split_once("\n\n").unwrap_or((&*s, ""));
+
+        let mut builder = TagBuilder::default();
+        for line in header.lines() {
+            let (field, value) = match line.split_once(' ') {
+                Some(split) => split,
+                None => bail!("failed to parse tag header line `{}`", line),
+            };
+
+            match field {
+                "object" => builder.object = Some(value.parse()?),
+                "type" => builder.obj_type = Some(value.parse()?),
+                "tag" => builder.tag = Some(value.to_owned()),
+                "tagger" => builder.tagger = Some(value.parse()?),
+                _ => debug!(
+                    "ignoring unknown field `{}` when parsing tag (field has value `{}`)",
+                    field, value
+                ),
+            }
+        }
+
+        let signature_start = [PGP_SIGNATURE_BEGIN, SSH_SIGNATURE_BEGIN]
+            .iter()
+            .filter_map(|marker| body.find(marker))
+            // the signature must start on its own line
+            .filter(|&idx| idx == 0 || body.as_bytes()[idx - 1] == b'\n')
+            .min();
+
+        let (message, signature) = match signature_start {
+            Some(idx) => (&body[..idx], Some(body[idx..].to_owned())),
+            None => (body, None),
+        };
+
+        builder.message = Some(message.to_owned());
+        builder.signature = signature;
+        builder.build()
+    }
+}
+
+#[derive(Default)]
+struct TagBuilder {
+    object: Option<Oid>,
+    obj_type: Option<BitObjType>,
+    tag: Option<String>,
+    tagger: Option<BitSignature>,
+    message: Option<String>,
+    signature: Option<String>,
+}
+
+impl TagBuilder {
+    fn build(self) -> BitResult<MutableTag> {
+        Ok(MutableTag {
+            object: self.object.ok_or_else(|| anyhow!("tag missing object"))?,
+            obj_type: self.obj_type.ok_or_else(|| anyhow!("tag missing type"))?,
+            tag: self.tag.ok_or_else(|| anyhow!("tag missing tag name"))?,
+            tagger: self.tagger,
+            message: self.message.ok_or_else(|| anyhow!("tag missing message"))?,
+            signature: self.signature,
+        })
     }
 }
 
@@ -40,7 +174,7 @@ impl Deref for Tag {
 
 impl BitObject for Tag {
     fn obj_cached(&self) -> &BitObjCached {
-        todo!()
+        &self.cached
     }
 
     fn owner(&self) -> BitRepo {
@@ -55,3 +189,15 @@ impl ImmutableBitObject for Tag {
         Self { owner, cached, inner }
     }
 }
+
+// peels through any number of (nested) tags to the first non-tag object
+impl Peel for Tag {
+    type Peeled = BitObjKind;
+
+    fn peel(&self, repo: &BitRepo) -> BitResult<Self::Peeled> {
+        match repo.read_obj(self.object)? {
+            BitObjKind::Tag(tag) => tag.peel(repo),
+            obj => Ok(obj),
+        }
+    }
+}
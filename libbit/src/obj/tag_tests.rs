@@ -0,0 +1,124 @@
+use super::*;
+use crate::error::BitResult;
+use crate::peel::Peel;
+use crate::refs::{BitRef, SymbolicRef};
+use crate::repo::BitRepo;
+use crate::serialize::Deserialize;
+use crate::test_utils::*;
+use quickcheck::{Arbitrary, Gen};
+
+impl Arbitrary for MutableTag {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Self::new(
+            Arbitrary::arbitrary(g),
+            BitObjType::Commit,
+            generate_random_string(1..20),
+            Arbitrary::arbitrary(g),
+            format!("{}\n", generate_sane_string_with_newlines(1..100)),
+        )
+    }
+}
+
+#[quickcheck]
+fn test_serde_tag(tag: MutableTag) -> BitResult<()> {
+    test_serde!(tag)
+}
+
+#[test]
+fn test_parse_signed_tag() -> BitResult<()> {
+    let bytes = b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+type tree
+tag signed
+tagger Andy Yu <andyyu2004@gmail.com> 1616061862 +1300
+
+a signed tag
+with a body
+-----BEGIN PGP SIGNATURE-----
+
+iQEzBAABCAAdFiEEaBcdefghijklmnopqrstuvwxyz0123456789
+=abcd
+-----END PGP SIGNATURE-----
+";
+    let tag = MutableTag::deserialize_unbuffered(&bytes[..])?;
+    assert_eq!(tag.object, Oid::EMPTY_TREE);
+    assert_eq!(tag.obj_type, BitObjType::Tree);
+    assert_eq!(tag.tag, "signed");
+    assert_eq!(tag.tagger.as_ref().unwrap().name, "Andy Yu");
+    assert_eq!(tag.message, "a signed tag\nwith a body\n");
+    assert!(tag.signature.as_ref().unwrap().starts_with("-----BEGIN PGP SIGNATURE-----"));
+
+    // must roundtrip byte for byte otherwise the hash will change
+    let mut buf = vec![];
+    tag.serialize(&mut buf)?;
+    assert_eq!(&buf[..], &bytes[..]);
+    Ok(())
+}
+
+#[test]
+fn test_parse_tag_without_tagger() -> BitResult<()> {
+    let bytes = b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+type tree
+tag ancient
+
+some old tag
+";
+    let tag = MutableTag::deserialize_unbuffered(&bytes[..])?;
+    assert!(tag.tagger.is_none());
+    assert!(tag.signature.is_none());
+    assert_eq!(tag.message, "some old tag\n");
+    Ok(())
+}
+
+#[test]
+fn test_annotated_tag_peels_to_commit() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let sym = repo.bit_create_tag("v1", &rev!("HEAD"), Some("release\n".to_owned()), false)?;
+        assert_eq!(sym, SymbolicRef::new_tag("v1"));
+
+        let tag_oid = repo.fully_resolve_ref(sym)?;
+        assert_ne!(tag_oid, head);
+        let tag = repo.read_obj(tag_oid)?.try_into_tag()?;
+        assert_eq!(tag.object, head);
+        assert_eq!(tag.obj_type, BitObjType::Commit);
+        assert_eq!(tag.tag, "v1");
+        assert_eq!(tag_oid.peel(&repo)?.oid(), head);
+        assert_eq!(repo.resolve_rev_to_commit(&rev!("v1"))?.oid(), head);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_create_and_delete_lightweight_tag() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let sym = repo.bit_create_tag("light", &rev!("HEAD^"), None, false)?;
+        assert_eq!(repo.read_ref(sym)?, BitRef::Direct(repo.fully_resolve_rev(&rev!("HEAD^"))?));
+        assert_eq!(repo.bit_list_tags()?, vec![sym]);
+
+        // creating an existing tag should fail without force
+        assert!(repo.bit_create_tag("light", &rev!("HEAD"), None, false).is_err());
+        repo.bit_create_tag("light", &rev!("HEAD"), None, true)?;
+        assert_eq!(repo.read_ref(sym)?, BitRef::Direct(head));
+
+        assert_eq!(repo.bit_delete_tag("light")?, head);
+        assert!(repo.bit_list_tags()?.is_empty());
+        assert!(repo.bit_delete_tag("light").is_err());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_parse_tag_with_non_utf8_message() -> BitResult<()> {
+    let bytes = b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+type tree
+tag latin1
+encoding ISO-8859-1
+
+caf\xe9
+";
+    let tag = MutableTag::deserialize_unbuffered(&bytes[..])?;
+    assert_eq!(tag.tag, "latin1");
+    assert_eq!(tag.message, "caf\u{fffd}\n");
+    Ok(())
+}
@@ -49,16 +49,16 @@ impl Treeish for BitObjKind {
         match self {
             BitObjKind::Commit(commit) => commit.peel(repo),
             BitObjKind::Tree(tree) => Ok(tree),
-            BitObjKind::Tag(..) => todo!(),
+            BitObjKind::Tag(tag) => tag.peel(repo)?.treeish(repo),
             BitObjKind::Blob(..) => bug!("blob is not treeish"),
         }
     }
 
-    fn treeish_oid(&self, _repo: &BitRepo) -> BitResult<Oid> {
+    fn treeish_oid(&self, repo: &BitRepo) -> BitResult<Oid> {
         match self {
             BitObjKind::Commit(commit) => Ok(commit.tree_oid()),
             BitObjKind::Tree(tree) => Ok(tree.oid()),
-            BitObjKind::Tag(..) => todo!(),
+            BitObjKind::Tag(tag) => tag.peel(repo)?.treeish_oid(repo),
             BitObjKind::Blob(..) => bug!("blob is not treeish"),
         }
     }
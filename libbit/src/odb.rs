@@ -1,40 +1,36 @@
+use crate::cache::CacheStats;
 use crate::error::{BitError, BitResult, BitResultExt};
 use crate::iter::DirIter;
 use crate::lockfile::{Lockfile, LockfileFlags};
+use crate::midx::{MultiPackIndexReader, MULTI_PACK_INDEX_FILE_NAME};
 use crate::obj::{self, *};
-use crate::pack::{Pack, PACK_EXT, PACK_IDX_EXT};
+use crate::pack::{DeltaBaseCache, Pack, PACK_EXT, PACK_IDX_EXT};
 use crate::path::BitPath;
 use crate::repo::BIT_PACK_OBJECTS_PATH;
 use arrayvec::ArrayVec;
 use fallible_iterator::FallibleIterator;
+use filebuffer::FileBuffer;
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use parking_lot::RwLock;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-
-//? questionable name, questionable macro is there a better way to express this pattern
-macro_rules! process {
-    ($expr:expr) => {
-        match $expr {
-            Ok(obj) => return Ok(obj),
-            Err(err) if err.is_fatal() => return Err(err),
-            Err(..) => continue,
-        }
-    };
-}
+use std::sync::Arc;
 
 pub struct BitObjDb {
     // backends will be searched in order
     backends: ArrayVec<Box<dyn BitObjDbBackend>, 2>,
+    delta_base_cache: Arc<DeltaBaseCache>,
 }
 
 impl BitObjDb {
-    pub fn new(objects_path: BitPath) -> BitResult<Self> {
+    /// `delta_base_cache_limit` is the maximum number of bytes of delta bases to cache
+    pub fn new(objects_path: BitPath, delta_base_cache_limit: u64) -> BitResult<Self> {
+        let delta_base_cache = Arc::new(DeltaBaseCache::new(delta_base_cache_limit));
         Ok(Self {
             // the ordering of these actually matters for performance significantly
             // I think in most repositories that have been pulled from git(hub|lab) etc have been packed
@@ -43,11 +39,16 @@ impl BitObjDb {
             // so in terms of chance we will have a much higher success rate if we look in pack first
             // and save the wasted work from searching in loose.
             backends: arrayvec![
-                Box::new(BitPackedObjDb::new(objects_path)?),
+                Box::new(BitPackedObjDb::new(objects_path, Arc::clone(&delta_base_cache))?),
                 Box::new(BitLooseObjDb::new(objects_path)) as Box<dyn BitObjDbBackend>,
             ],
+            delta_base_cache,
         })
     }
+
+    pub fn delta_base_cache_stats(&self) -> CacheStats {
+        self.delta_base_cache.stats()
+    }
 }
 
 macro_rules! backend_method {
@@ -247,23 +248,96 @@ impl BitObjDbBackend for BitLooseObjDb {
 
 struct BitPackedObjDb {
     objects_path: BitPath,
-    /// { packfile-path -> (packfile, idxfile) }
-    packs: RwLock<HashMap<BitPath, Pack>>,
+    // packs can be read through a shared reference so the write lock is only taken on `refresh`
+    packs: RwLock<PackSet>,
+    /// shared by all packs
+    delta_base_cache: Arc<DeltaBaseCache>,
+}
+
+#[derive(Default)]
+struct PackSet {
+    /// { packfile-path -> pack }
+    packs: HashMap<BitPath, Pack>,
+    midx: Option<LoadedMultiPackIndex>,
+}
+
+struct LoadedMultiPackIndex {
+    reader: MultiPackIndexReader<FileBuffer>,
+    /// the path of the packfile of each pack id
+    pack_paths: Vec<BitPath>,
+    /// the packs that don't need to be searched individually
+    covered: HashSet<BitPath>,
+}
+
+impl PackSet {
+    /// Find the pack containing `oid` and its offset in the pack.
+    /// The multi-pack-index is searched first, and then any packs that it doesn't cover.
+    fn locate(&self, oid: Oid) -> BitResult<Option<(&Pack, u64)>> {
+        if let Some(midx) = &self.midx {
+            if let Some((pack_id, offset)) = midx.reader.find_oid(oid)? {
+                return Ok(Some((&self.packs[&midx.pack_paths[pack_id as usize]], offset)));
+            }
+        }
+
+        for (path, pack) in &self.packs {
+            if self.midx.as_ref().map_or(false, |midx| midx.covered.contains(path)) {
+                continue;
+            }
+            match pack.obj_offset(oid) {
+                Ok(offset) => return Ok(Some((pack, offset))),
+                Err(err) if err.is_fatal() => return Err(err),
+                Err(..) => continue,
+            }
+        }
+        Ok(None)
+    }
 }
 
 impl BitPackedObjDb {
-    pub fn new(objects_path: BitPath) -> BitResult<Self> {
-        let this = Self { objects_path, packs: Default::default() };
+    pub fn new(objects_path: BitPath, delta_base_cache: Arc<DeltaBaseCache>) -> BitResult<Self> {
+        let this = Self { objects_path, packs: Default::default(), delta_base_cache };
         this.refresh()?;
         Ok(this)
     }
 
     fn read_raw_pack_obj(&self, oid: Oid) -> BitResult<BitPackObjRaw> {
         trace!("BitPackedObjDb::read_raw(id: {})", oid);
-        for pack in self.packs.write().values_mut() {
-            process!(pack.read_obj_raw(oid));
+        match self.packs.read().locate(oid)? {
+            Some((pack, offset)) => pack.read_obj_raw_at(offset),
+            None => bail!(BitError::ObjectNotFound(oid.into())),
         }
-        bail!(BitError::ObjectNotFound(oid.into()))
+    }
+
+    fn load_multi_pack_index(
+        &self,
+        pack_dir: &Path,
+        packs: &HashMap<BitPath, Pack>,
+    ) -> BitResult<Option<LoadedMultiPackIndex>> {
+        let path = pack_dir.join(MULTI_PACK_INDEX_FILE_NAME);
+        if !path.try_exists()? {
+            return Ok(None);
+        }
+
+        let reader = MultiPackIndexReader::new(FileBuffer::open(&path)?)?;
+        let mut pack_paths = vec![];
+        for name in reader.pack_names() {
+            let pack_path = pack_dir.join(name).with_extension(PACK_EXT);
+            match packs.get_key_value(pack_path.as_path()) {
+                Some((&pack_path, _)) => pack_paths.push(pack_path),
+                None => {
+                    // the multi-pack-index is stale (e.g. a pack was deleted without rewriting it)
+                    // but all the packs can still be searched without it
+                    warn!(
+                        "ignoring multi-pack-index `{}` as it refers to missing pack `{}`",
+                        path.display(),
+                        name
+                    );
+                    return Ok(None);
+                }
+            }
+        }
+        let covered = pack_paths.iter().copied().collect();
+        Ok(Some(LoadedMultiPackIndex { reader, pack_paths, covered }))
     }
 }
 
@@ -276,10 +350,10 @@ impl BitObjDbBackend for BitPackedObjDb {
 
     fn read_header(&self, id: BitId) -> BitResult<BitObjHeader> {
         let oid = self.expand_id(id)?;
-        for pack in self.packs.write().values_mut() {
-            process!(pack.read_obj_header(oid));
+        match self.packs.read().locate(oid)? {
+            Some((pack, offset)) => pack.read_obj_header_at(offset),
+            None => bail!(BitError::ObjectNotFound(id)),
         }
-        bail!(BitError::ObjectNotFound(id))
     }
 
     fn write(&self, _obj: &dyn WritableObject) -> BitResult<Oid> {
@@ -288,19 +362,25 @@ impl BitObjDbBackend for BitPackedObjDb {
 
     fn exists(&self, id: BitId) -> BitResult<bool> {
         let oid = self.expand_id(id)?;
-        Ok(self.packs.write().values_mut().any(|pack| pack.obj_exists(oid).unwrap_or_default()))
+        Ok(self.packs.read().locate(oid)?.is_some())
     }
 
     fn prefix_candidates(&self, prefix: PartialOid) -> BitResult<Vec<Oid>> {
-        Ok(self
-            .packs
-            .write()
-            .values_mut()
-            .map(|pack| pack.prefix_matches(prefix))
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
-            .flatten()
-            .collect())
+        let packs = self.packs.read();
+        let mut candidates = match &packs.midx {
+            Some(midx) => midx.reader.prefix_matches(prefix)?,
+            None => vec![],
+        };
+        for (path, pack) in &packs.packs {
+            if packs.midx.as_ref().map_or(false, |midx| midx.covered.contains(path)) {
+                continue;
+            }
+            candidates.extend(pack.prefix_matches(prefix)?);
+        }
+        // the same object may be in multiple packs
+        candidates.sort();
+        candidates.dedup();
+        Ok(candidates)
     }
 
     fn refresh(&self) -> BitResult<()> {
@@ -311,12 +391,14 @@ impl BitObjDbBackend for BitPackedObjDb {
         }
 
         let mut packs = self.packs.write();
+        // packs may have been deleted by a repack
+        packs.packs.retain(|path, _| path.exists());
 
         for entry in std::fs::read_dir(pack_dir)? {
             let entry = entry?;
             let pack_path = entry.path();
             if pack_path.extension() != Some(PACK_EXT.as_ref())
-                || packs.contains_key(pack_path.as_path())
+                || packs.packs.contains_key(pack_path.as_path())
             {
                 continue;
             }
@@ -328,9 +410,13 @@ impl BitObjDbBackend for BitPackedObjDb {
                 pack_path.display()
             );
             let pack_path = BitPath::intern(pack_path);
-            packs.insert(pack_path, Pack::new(pack_path, idx)?);
+            let pack = Pack::new(pack_path, idx, Arc::clone(&self.delta_base_cache))?;
+            packs.packs.insert(pack_path, pack);
         }
 
+        // the multi-pack-index may have been rewritten so it's always reloaded
+        packs.midx = self.load_multi_pack_index(&pack_dir, &packs.packs)?;
+
         Ok(())
     }
 }
@@ -1,41 +1,63 @@
+mod builder;
+mod delta_base_cache;
 mod indexer;
 mod writer;
 
+pub(crate) use self::builder::name_hash;
+pub use self::builder::{PackBuilder, DEFAULT_DELTA_DEPTH, DEFAULT_DELTA_WINDOW};
+pub use self::delta_base_cache::{DeltaBaseCache, DEFAULT_DELTA_BASE_CACHE_LIMIT};
 pub use self::indexer::{IndexPackOpts, PackIndexer};
 pub(crate) use self::writer::PackWriter;
 
 use crate::delta::Delta;
 use crate::error::{BitError, BitErrorExt, BitGenericError, BitResult, BitResultExt};
-use crate::hash::{Crc32, MakeHash, SHA1Hash, OID_SIZE};
+use crate::hash::{Crc32, SHA1Hash, OID_SIZE};
 use crate::io::*;
 use crate::iter::BitIterator;
 use crate::obj::*;
+use crate::path::BitPath;
 use crate::serialize::{BufReadSeek, Deserialize, DeserializeSized, Serialize};
 use fallible_iterator::FallibleIterator;
+use filebuffer::FileBuffer;
 use flate2::{Decompress, FlushDecompress};
 use num_traits::{FromPrimitive, ToPrimitive};
-use rustc_hash::FxHashMap;
-use std::collections::hash_map::RawEntryMut;
+use std::cmp::Ordering;
 use std::fmt::{self, Debug, Formatter};
-use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, SeekFrom, Write};
+use std::io::{BufRead, BufWriter, Cursor, SeekFrom, Write};
 use std::mem::MaybeUninit;
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
+use std::sync::Arc;
 
 pub const PACK_SIGNATURE: &[u8; 4] = b"PACK";
 pub const PACK_EXT: &str = "pack";
 pub const PACK_IDX_EXT: &str = "idx";
 const PACK_IDX_MAGIC: u32 = 0xff744f63;
-const FANOUT_ENTRYC: usize = 256;
+pub(crate) const FANOUT_ENTRYC: usize = 256;
 const FANOUT_ENTRY_SIZE: u64 = 4;
-const FANOUT_SIZE: u64 = FANOUT_ENTRYC as u64 * FANOUT_ENTRY_SIZE;
+pub(crate) const FANOUT_SIZE: u64 = FANOUT_ENTRYC as u64 * FANOUT_ENTRY_SIZE;
 const PACK_IDX_HEADER_SIZE: u64 = 8;
 const CRC_SIZE: u64 = 4;
 const OFFSET_SIZE: u64 = 4;
 const EXT_OFFSET_SIZE: u64 = 8;
 /// maximum 31 bit number (highest bit represents it uses a large offset in the EXT layer)
-const MAX_OFFSET: u64 = 0x7fffffff;
+pub(crate) const MAX_OFFSET: u64 = 0x7fffffff;
+
+/// Binary search for `oid` in `oids` which is a sorted table of oids (as raw bytes).
+/// Returns the index of `oid` if it's found, otherwise the index where it would be inserted.
+pub(crate) fn search_oid_table(oids: &[u8], oid: Oid) -> Result<usize, usize> {
+    let oid_at = |i: usize| &oids[i * OID_SIZE..(i + 1) * OID_SIZE];
+    let (mut lo, mut hi) = (0, oids.len() / OID_SIZE);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match oid_at(mid).cmp(oid.as_bytes()) {
+            Ordering::Less => lo = mid + 1,
+            Ordering::Greater => hi = mid,
+            Ordering::Equal => return Ok(mid),
+        }
+    }
+    Err(lo)
+}
 
 impl BitPackObjRaw {
     fn expand_with_delta_bytes(&self, delta_bytes: &[u8]) -> BitResult<Self> {
@@ -68,47 +90,52 @@ impl Debug for BitPackObjRawDeltified {
     }
 }
 
+/// A packfile and its index, both memory mapped.
+/// All reads go through a shared reference so objects can be read from the same pack concurrently.
 pub struct Pack {
-    pack_reader: PackfileReader<BufferedFileStream>,
-    idx_reader: PackIndexReader<BufferedFileStream>,
-    pack_obj_cache: FxHashMap<u64, BitPackObjRaw>,
+    path: BitPath,
+    pack: FileBuffer,
+    idx: PackIndexReader<FileBuffer>,
+    /// number of objects in the packfile
+    objectc: u32,
+    delta_base_cache: Arc<DeltaBaseCache>,
 }
 
 impl Pack {
-    pub fn new(pack: impl AsRef<Path>, idx: impl AsRef<Path>) -> BitResult<Self> {
-        let pack_reader = File::open(pack)
-            .map(BufReader::new)
-            .map_err(Into::into)
-            .and_then(PackfileReader::new)?;
-        let idx_reader = File::open(idx)
-            .map(BufReader::new)
-            .map_err(Into::into)
-            .and_then(PackIndexReader::new)?;
-        Ok(Self { pack_reader, idx_reader, pack_obj_cache: Default::default() })
-    }
-
+    pub fn new(
+        path: BitPath,
+        idx: impl AsRef<Path>,
+        delta_base_cache: Arc<DeltaBaseCache>,
+    ) -> BitResult<Self> {
+        let pack = FileBuffer::open(path)?;
+        let objectc = PackfileReader::<&[u8]>::parse_header(&pack[..])?;
+        let idx = PackIndexReader::new(FileBuffer::open(idx)?)?;
+        Ok(Self { path, pack, idx, objectc, delta_base_cache })
+    }
+
+    /// returns a new reader over the packfile (this is cheap as it's just a cursor over the mmap)
     #[inline]
-    pub fn pack_reader(&mut self) -> &mut PackfileReader<BufferedFileStream> {
-        &mut self.pack_reader
+    pub fn pack_reader(&self) -> PackfileReader<Cursor<&[u8]>> {
+        PackfileReader { reader: Cursor::new(&self.pack[..]), objectc: self.objectc }
     }
 
     #[inline]
-    pub fn idx_reader(&mut self) -> &mut PackIndexReader<BufferedFileStream> {
-        &mut self.idx_reader
+    pub fn idx_reader(&self) -> &PackIndexReader<FileBuffer> {
+        &self.idx
     }
 
     #[inline]
-    pub fn obj_crc_offset(&mut self, oid: Oid) -> BitResult<(u32, u64)> {
+    pub fn obj_crc_offset(&self, oid: Oid) -> BitResult<(u32, u64)> {
         self.idx_reader().find_oid_crc_offset(oid)
     }
 
     #[inline]
-    pub fn obj_offset(&mut self, oid: Oid) -> BitResult<u64> {
+    pub fn obj_offset(&self, oid: Oid) -> BitResult<u64> {
         self.obj_crc_offset(oid).map(|(_crc, offset)| offset)
     }
 
     /// returns a list of oids that start with `prefix`
-    pub fn prefix_matches(&mut self, prefix: PartialOid) -> BitResult<Vec<Oid>> {
+    pub fn prefix_matches(&self, prefix: PartialOid) -> BitResult<Vec<Oid>> {
         trace!("prefix_matches(prefix: {})", prefix);
         let extended = prefix.into_oid()?;
         let r = match self.obj_offset(extended) {
@@ -128,7 +155,7 @@ impl Pack {
         r
     }
 
-    pub fn obj_exists(&mut self, oid: Oid) -> BitResult<bool> {
+    pub fn obj_exists(&self, oid: Oid) -> BitResult<bool> {
         // TODO this pattern is a little unpleasant
         // do something about it if it pops up any more
         // maybe some magic with a different error type could work
@@ -140,7 +167,7 @@ impl Pack {
     }
 
     pub fn expand_raw_obj(
-        &mut self,
+        &self,
         raw_kind: BitPackObjRawDeltified,
         base_offset: u64,
     ) -> BitResult<BitPackObjRaw> {
@@ -148,30 +175,34 @@ impl Pack {
         let (base, delta_bytes) = match raw_kind {
             BitPackObjRawDeltified::Raw(raw) => return Ok(raw),
             BitPackObjRawDeltified::Ofs(offset, delta) =>
-                (self.read_obj_raw_at(base_offset - offset)?, delta),
-            BitPackObjRawDeltified::Ref(base_oid, delta) => (self.read_obj_raw(base_oid)?, delta),
+                (self.read_delta_base_at(base_offset - offset)?, delta),
+            BitPackObjRawDeltified::Ref(base_oid, delta) =>
+                (self.read_delta_base_at(self.obj_offset(base_oid)?)?, delta),
         };
 
         trace!("expand_raw_obj:base={:?}; delta_len={}", base, delta_bytes.len());
         base.expand_with_delta_bytes(&delta_bytes)
     }
 
+    /// returns the fully expanded object at `offset` that is the base of some delta
+    fn read_delta_base_at(&self, offset: u64) -> BitResult<Arc<BitPackObjRaw>> {
+        if let Some(base) = self.delta_base_cache.get(self.path, offset) {
+            return Ok(base);
+        }
+        let base = Arc::new(self.read_obj_raw_at(offset)?);
+        self.delta_base_cache.insert(self.path, offset, Arc::clone(&base));
+        Ok(base)
+    }
+
     /// returns fully expanded raw object at offset
-    pub fn read_obj_raw_at(&mut self, offset: u64) -> BitResult<BitPackObjRaw> {
+    pub fn read_obj_raw_at(&self, offset: u64) -> BitResult<BitPackObjRaw> {
         trace!("read_obj_raw_at(offset: {})", offset);
-        match self.pack_obj_cache.get(&offset) {
-            Some(raw) => Ok(raw.clone()),
-            None => {
-                let raw = self.pack_reader().read_obj_from_offset_raw(offset)?;
-                let expanded = self.expand_raw_obj(raw, offset)?;
-                self.pack_obj_cache.insert(offset, expanded.clone());
-                Ok(expanded)
-            }
-        }
+        let raw = self.pack_reader().read_obj_from_offset_raw(offset)?;
+        self.expand_raw_obj(raw, offset)
     }
 
     /// returns fully expanded raw object with oid
-    pub fn read_obj_raw(&mut self, oid: Oid) -> BitResult<BitPackObjRaw> {
+    pub fn read_obj_raw(&self, oid: Oid) -> BitResult<BitPackObjRaw> {
         trace!("read_obj_raw(oid: {})", oid);
         let offset = self.obj_offset(oid)?;
         trace!("read_obj_raw(oid: {}): found object at offset `{}`)", oid, offset);
@@ -179,16 +210,16 @@ impl Pack {
         Ok(raw)
     }
 
-    pub fn read_obj_header(&mut self, oid: Oid) -> BitResult<BitObjHeader> {
+    pub fn read_obj_header(&self, oid: Oid) -> BitResult<BitObjHeader> {
         let (crc, offset) = self.obj_crc_offset(oid)?;
         trace!("read_obj_header(oid: {}); crc={}; offset={}", oid, crc, offset);
         let header = self.read_obj_header_at(offset)?;
         Ok(header)
     }
 
-    fn read_obj_header_at(&mut self, offset: u64) -> BitResult<BitObjHeader> {
+    pub fn read_obj_header_at(&self, offset: u64) -> BitResult<BitObjHeader> {
         trace!("read_obj_header_at(offset: {})", offset);
-        let reader = self.pack_reader();
+        let mut reader = self.pack_reader();
         let header = reader.read_header_from_offset(offset)?;
         // can we assume base_header definitely has same type?
         let base_header = match header.obj_type {
@@ -201,7 +232,7 @@ impl Pack {
                 self.read_obj_header_at(offset - ofs)
             }
             BitPackObjType::RefDelta => {
-                let oid = self.pack_reader().read_oid()?;
+                let oid = reader.read_oid()?;
                 self.read_obj_header(oid)
             }
         }?;
@@ -217,12 +248,45 @@ pub struct PackIndex {
     pub fanout: [u32; FANOUT_ENTRYC],
     pub oids: Vec<Oid>,
     pub crcs: Vec<u32>,
+    /// offsets that don't fit in 31 bits have the MSB set, the rest is an index into `ext_offsets`
     pub offsets: Vec<u32>,
+    /// the 64-bit offsets of objects that are beyond `MAX_OFFSET` in the pack
+    pub ext_offsets: Vec<u64>,
     pub pack_hash: SHA1Hash,
 }
 
 impl PackIndex {
-    fn build_fanout(oids: &[Oid]) -> [u32; FANOUT_ENTRYC] {
+    /// Builds an index from `(oid, crc, offset)` entries which must be sorted by oid
+    pub(crate) fn from_sorted(
+        entries: impl IntoIterator<Item = (Oid, u32, u64)>,
+        pack_hash: SHA1Hash,
+    ) -> Self {
+        let mut oids = vec![];
+        let mut crcs = vec![];
+        let mut offsets = vec![];
+        let mut ext_offsets = vec![];
+        for (oid, crc, offset) in entries {
+            oids.push(oid);
+            crcs.push(crc);
+            if offset > MAX_OFFSET {
+                offsets.push(ext_offsets.len() as u32 | !MAX_OFFSET as u32);
+                ext_offsets.push(offset);
+            } else {
+                offsets.push(offset as u32);
+            }
+        }
+        debug_assert!(oids.is_sorted());
+        let fanout = Self::build_fanout(&oids);
+        Self { fanout, oids, crcs, offsets, ext_offsets, pack_hash }
+    }
+
+    /// The offset in the packfile of the object at `index`
+    pub fn offset(&self, index: usize) -> u64 {
+        let offset = self.offsets[index] as u64;
+        if offset > MAX_OFFSET { self.ext_offsets[(offset & MAX_OFFSET) as usize] } else { offset }
+    }
+
+    pub(crate) fn build_fanout(oids: &[Oid]) -> [u32; FANOUT_ENTRYC] {
         let mut fanout = [0; FANOUT_ENTRYC];
         for oid in oids {
             fanout[oid[0] as usize] += 1;
@@ -234,11 +298,11 @@ impl PackIndex {
     }
 }
 
-pub struct PackIndexReader<R> {
-    reader: R,
+/// Reads a pack index directly from its bytes (usually memory mapped) without any
+/// intermediate buffering, so lookups only require a shared reference.
+pub struct PackIndexReader<B> {
+    bytes: B,
     fanout: [u32; FANOUT_ENTRYC],
-    oid_cache: FxHashMap<u64, Vec<Oid>>,
-    crc_offset_cache: FxHashMap<Oid, (u32, u64)>,
     /// number of oids
     n: u64,
 }
@@ -251,63 +315,65 @@ pub enum Layer {
     Ext = 3,
 }
 
-impl<R: BufReadSeek> PackIndexReader<R> {
-    pub fn new(mut reader: R) -> BitResult<Self> {
+impl<B: Deref<Target = [u8]>> PackIndexReader<B> {
+    pub fn new(bytes: B) -> BitResult<Self> {
+        let mut reader = &bytes[..];
         PackIndex::parse_header(&mut reader)?;
         let fanout = reader.read_array::<u32, FANOUT_ENTRYC>()?;
+        // lookups index the oid layer using the fanout so it must never decrease (its last
+        // entry is the number of objects, which bounds it by the size check below)
+        ensure!(
+            fanout.windows(2).all(|w| w[0] <= w[1]),
+            "pack index has a corrupted fanout table"
+        );
         let n = fanout[FANOUT_ENTRYC - 1] as u64;
-        Ok(Self {
-            reader,
-            fanout,
-            n,
-            oid_cache: Default::default(),
-            crc_offset_cache: Default::default(),
-        })
+        let this = Self { bytes, fanout, n };
+        // the extended offset layer is variable length, but everything before it is not
+        // (and there is a trailer of two hashes after it)
+        let min_len = this.offset_of(Layer::Ext, 0) + 2 * OID_SIZE as u64;
+        ensure!(this.bytes.len() as u64 >= min_len, "pack index is truncated");
+        Ok(this)
     }
-}
 
-impl<R: BufReadSeek> PackIndexReader<R> {
-    /// returns the offset of the object with oid `oid` in the packfile
-    pub fn find_oid_crc_offset(&mut self, oid: Oid) -> BitResult<(u32, u64)> {
-        match self.crc_offset_cache.get(&oid) {
-            Some(&crc_offset) => Ok(crc_offset),
-            None => {
-                let crc_offset = self.find_oid_crc_offset_inner(oid)?;
-                self.crc_offset_cache.insert(oid, crc_offset);
-                Ok(crc_offset)
-            }
-        }
-        // the following is nicer as we can avoid calculating the hash twice
-        // it's violating the borrow checker in it's current form though
-        // match self.crc_offset_cache.entry(oid) {
-        //     Entry::Occupied(entry) => Ok(*entry.get()),
-        //     Entry::Vacant(entry) => self
-        //         .find_oid_crc_offset_inner(oid)
-        //         .map(|crc_offset| entry.insert(crc_offset))
-        //         .copied(),
-        // }
-    }
-
-    fn find_oid_crc_offset_inner(&mut self, oid: Oid) -> BitResult<(u32, u64)> {
+    /// returns the crc and the offset of the object with oid `oid` in the packfile
+    pub fn find_oid_crc_offset(&self, oid: Oid) -> BitResult<(u32, u64)> {
         trace!("PackIndexReader::find_oid_crc_offset(oid: {})", oid);
         let index = self.find_oid_index(oid)?;
         debug_assert_eq!(oid, self.read_from(Layer::Oid, index)?);
         let crc = self.read_from::<u32>(Layer::Crc, index)?;
-        let mut offset = self.read_from::<u32>(Layer::Ofs, index)? as u64;
+        let offset = self.offset_at(index)?;
         trace!("PackIndexReader::find_oid_crc_offset(..) -> ({}, {})", crc, offset);
+        Ok((crc, offset))
+    }
+
+    /// the number of objects in the pack
+    pub fn len(&self) -> u64 {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
 
+    /// the hash of the packfile (the first hash of the trailer)
+    pub fn pack_hash(&self) -> BitResult<SHA1Hash> {
+        let mut trailer = &self.bytes[self.bytes.len() - 2 * OID_SIZE..];
+        trailer.read_oid()
+    }
+
+    /// returns the offset in the packfile of the object at `index` (in oid order)
+    pub fn offset_at(&self, index: u64) -> BitResult<u64> {
+        let offset = self.read_from::<u32>(Layer::Ofs, index)? as u64;
         if offset > MAX_OFFSET {
             let ext_index = offset & MAX_OFFSET;
-            offset = self.read_from(Layer::Ext, ext_index)?;
+            return self.read_from(Layer::Ext, ext_index);
         }
-
-        Ok((crc, offset))
+        Ok(offset)
     }
 
     /// returns the offset of the start of the layer relative to the start of
     /// the pack index in bytes
-    pub fn offset_of(&mut self, layer: Layer, index: u64) -> u64 {
-        debug_assert!(layer < Layer::Ext);
+    pub fn offset_of(&self, layer: Layer, index: u64) -> u64 {
         const SIZE: [u64; 4] = [OID_SIZE as u64, CRC_SIZE, OFFSET_SIZE, EXT_OFFSET_SIZE];
         let layer = layer.to_usize().unwrap();
         let base = PACK_IDX_HEADER_SIZE
@@ -317,23 +383,26 @@ impl<R: BufReadSeek> PackIndexReader<R> {
     }
 
     /// read from layer at index (index is not the same as byte offset)
-    pub fn read_from<T: Deserialize>(&mut self, layer: Layer, index: u64) -> BitResult<T> {
-        let offset = self.offset_of(layer, index);
-        self.seek(SeekFrom::Start(offset))?;
-        self.read_type()
+    pub fn read_from<T: Deserialize>(&self, layer: Layer, index: u64) -> BitResult<T> {
+        let offset = self.offset_of(layer, index) as usize;
+        let mut bytes = match self.bytes.get(offset..) {
+            Some(bytes) => bytes,
+            None => bail!("pack index is truncated (attempted to read at offset `{}`)", offset),
+        };
+        bytes.read_type()
     }
 
-    pub fn read_oid_at(&mut self, index: u64) -> BitResult<Oid> {
+    pub fn read_oid_at(&self, index: u64) -> BitResult<Oid> {
         self.read_from(Layer::Oid, index)
     }
 
-    pub fn oid_iter(&mut self, start: u64) -> impl BitIterator<Oid> + '_ {
-        struct OidIter<'a, R> {
-            reader: &'a mut PackIndexReader<R>,
+    pub fn oid_iter(&self, start: u64) -> impl BitIterator<Oid> + '_ {
+        struct OidIter<'a, B> {
+            reader: &'a PackIndexReader<B>,
             index: u64,
         }
 
-        impl<'a, R: BufReadSeek> FallibleIterator for OidIter<'a, R> {
+        impl<'a, B: Deref<Target = [u8]>> FallibleIterator for OidIter<'a, B> {
             type Error = BitGenericError;
             type Item = Oid;
 
@@ -351,7 +420,7 @@ impl<R: BufReadSeek> PackIndexReader<R> {
     }
 
     /// return the index of `oid` in the Oid layer of the packindex (unit is sizeof::<Oid>)
-    fn find_oid_index(&mut self, oid: Oid) -> BitResult<u64> {
+    fn find_oid_index(&self, oid: Oid) -> BitResult<u64> {
         // fanout has 256 elements
         // example
         // [
@@ -378,38 +447,19 @@ impl<R: BufReadSeek> PackIndexReader<R> {
         let low = if prefix == 0 { 0 } else { self.fanout[prefix - 1] } as u64;
         let high = self.fanout[prefix] as u64;
 
-        self.seek(SeekFrom::Start(PACK_IDX_HEADER_SIZE + FANOUT_SIZE + low * OID_SIZE as u64))?;
-
-        let search = |oids: &[Oid]| match oids.binary_search(&oid) {
+        // binary search the oids directly in the mapped bytes to avoid copying them out
+        let start = self.offset_of(Layer::Oid, low) as usize;
+        let oids = match self.bytes.get(start..start + (high - low) as usize * OID_SIZE) {
+            Some(oids) => oids,
+            None => bail!("pack index is truncated (fanout entry `{}` is out of bounds)", prefix),
+        };
+        match search_oid_table(oids, oid) {
             Ok(idx) => Ok(low + idx as u64),
             Err(idx) => Err(anyhow!(BitError::ObjectNotFoundInPackIndex(oid, low + idx as u64))),
-        };
-
-        let hash = low.mk_fx_hash();
-        match self.oid_cache.raw_entry_mut().from_key_hashed_nocheck(hash, &low) {
-            RawEntryMut::Occupied(entry) => search(entry.get()),
-            RawEntryMut::Vacant(entry) => {
-                let oids = self.reader.read_vec((high - low) as usize).unwrap();
-                search(entry.insert_hashed_nocheck(hash, low, oids).1)
-            }
         }
     }
 }
 
-impl<R> Deref for PackIndexReader<R> {
-    type Target = R;
-
-    fn deref(&self) -> &Self::Target {
-        &self.reader
-    }
-}
-
-impl<R> DerefMut for PackIndexReader<R> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.reader
-    }
-}
-
 impl Serialize for PackIndex {
     fn serialize(&self, writer: &mut dyn Write) -> BitResult<()> {
         let mut writer = BufWriter::new(HashWriter::new_sha1(writer));
@@ -419,6 +469,7 @@ impl Serialize for PackIndex {
         writer.write_iter(&self.oids)?;
         writer.write_iter(&self.crcs)?;
         writer.write_iter(&self.offsets)?;
+        writer.write_iter(&self.ext_offsets)?;
         writer.write_oid(self.pack_hash)?;
 
         match writer.into_inner() {
@@ -445,16 +496,24 @@ impl Deserialize for PackIndex {
 
         let crcs = r.read_vec::<u32>(n)?;
         let offsets = r.read_vec::<u32>(n)?;
+        // every offset with the MSB set refers to an entry in the 64-bit offset layer
+        let ext_len = offsets.iter().filter(|&&offset| offset as u64 > MAX_OFFSET).count();
+        let ext_offsets = r.read_vec::<u64>(ext_len)?;
+        ensure!(
+            offsets
+                .iter()
+                .filter(|&&offset| offset as u64 > MAX_OFFSET)
+                .all(|&offset| (offset as u64 & MAX_OFFSET) < ext_len as u64),
+            "pack index has an out of bounds 64-bit offset"
+        );
 
-        // TODO 8-byte offsets for large packfiles
-        // let big_offsets = todo!();
         let pack_hash = r.read_oid()?;
         let hash = r.finalize_sha1();
         let idx_hash = r.read_oid()?;
 
         ensure_eq!(idx_hash, hash);
-        assert!(r.is_at_eof()?, "todo parse level 5 fanout for large indexes");
-        Ok(Self { fanout, oids, crcs, offsets, pack_hash })
+        ensure!(r.is_at_eof()?, "unexpected trailing data in pack index");
+        Ok(Self { fanout, oids, crcs, offsets, ext_offsets, pack_hash })
     }
 }
 
@@ -474,7 +533,7 @@ pub struct PackfileReader<R> {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, FromPrimitive, ToPrimitive)]
-enum BitPackObjType {
+pub(crate) enum BitPackObjType {
     Commit   = 1,
     Tree     = 2,
     Blob     = 3,
@@ -501,6 +560,17 @@ impl From<BitPackObjType> for BitObjType {
     }
 }
 
+impl From<BitObjType> for BitPackObjType {
+    fn from(obj_type: BitObjType) -> BitPackObjType {
+        match obj_type {
+            BitObjType::Commit => BitPackObjType::Commit,
+            BitObjType::Tree => BitPackObjType::Tree,
+            BitObjType::Blob => BitPackObjType::Blob,
+            BitObjType::Tag => BitPackObjType::Tag,
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 struct BitPackObjHeader {
     obj_type: BitPackObjType,
@@ -0,0 +1,338 @@
+use super::*;
+use crate::bitmap::PackBitmapIndex;
+use crate::delta::Delta;
+use crate::error::BitResult;
+use crate::io::{HashWriter, WriteExt};
+use crate::obj::{BitObjHeader, BitObjType, BitPackObjRaw, Oid};
+use crate::path::BitPath;
+use crate::progress::{ProgressPhase, ProgressSink};
+use crate::repo::BitRepo;
+use crate::serialize::Serialize;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use rustc_hash::{FxHashMap, FxHashSet};
+use sha1::Sha1;
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// the number of preceding objects that are considered as a delta base for each object
+pub const DEFAULT_DELTA_WINDOW: usize = 10;
+/// the maximum length of a chain of deltas
+pub const DEFAULT_DELTA_DEPTH: usize = 50;
+/// objects smaller than this are not worth deltifying
+const MIN_DELTA_SIZE: usize = 50;
+/// signature + version + object count
+const PACK_HEADER_SIZE: u64 = 12;
+/// the size of the chunks that [`PackBuilder::stream`] sends the pack in
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Builds a (version 2) packfile and its index from a set of objects.
+/// Objects are written in the order they are given, and are stored as `OFS_DELTA`s where that is
+/// worthwhile (in which case the base is always written before the delta).
+pub struct PackBuilder {
+    repo: BitRepo,
+    /// each object is paired with the hash of the path it was found at
+    /// (see [`BitRepo::reachable_objects`])
+    objects: Vec<(Oid, u32)>,
+    window: usize,
+    depth: usize,
+    /// whether `write_to_dir` writes a bitmap index for the pack too
+    bitmaps: bool,
+    progress: ProgressSink,
+}
+
+struct ChannelWriter(mpsc::Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // the receiver is only dropped early when sending the pack fails
+        self.0.blocking_send(buf.to_vec()).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Only the header of each object is kept around, its contents are read again when it's written
+/// (unless it's stored as a delta) so the objects being packed don't all need to fit in memory
+struct PackEntry {
+    oid: Oid,
+    name_hash: u32,
+    obj_type: BitObjType,
+    size: u64,
+    /// the index of the delta base and the serialized delta
+    delta: Option<(usize, Vec<u8>)>,
+    depth: usize,
+}
+
+impl PackBuilder {
+    pub fn new(repo: BitRepo, objects: impl IntoIterator<Item = (Oid, BitPath)>) -> Self {
+        let mut seen = FxHashSet::default();
+        // objects found using bitmaps have no path, but the bitmap index remembers its hash
+        let bitmaps = repo.pack_bitmap_index();
+        // duplicate entries would make for an invalid pack
+        let objects = objects
+            .into_iter()
+            .filter(|&(oid, _)| seen.insert(oid))
+            .map(|(oid, path)| (oid, object_name_hash(bitmaps.as_deref(), oid, path)))
+            .collect();
+        Self {
+            repo,
+            objects,
+            window: DEFAULT_DELTA_WINDOW,
+            depth: DEFAULT_DELTA_DEPTH,
+            bitmaps: false,
+            progress: Default::default(),
+        }
+    }
+
+    /// The number of objects to try as a delta base for each object (`0` disables deltas)
+    pub fn window(self, window: usize) -> Self {
+        Self { window, ..self }
+    }
+
+    /// The maximum length of a delta chain (`0` disables deltas)
+    pub fn depth(self, depth: usize) -> Self {
+        Self { depth, ..self }
+    }
+
+    /// Also write reachability bitmaps (`pack-<hash>.bitmap`) when writing to a directory.
+    /// Only commits whose history is entirely within the pack get a bitmap.
+    pub fn bitmaps(self, bitmaps: bool) -> Self {
+        Self { bitmaps, ..self }
+    }
+
+    /// Report the progress of reading the objects as [`ProgressPhase::Counting`]
+    pub fn progress(self, progress: ProgressSink) -> Self {
+        Self { progress, ..self }
+    }
+
+    /// Writes the packfile into `writer` returning the index for it
+    pub fn write(self, writer: impl Write) -> BitResult<PackIndex> {
+        let total = self.objects.len() as u64;
+        let mut counting = self.progress.begin(ProgressPhase::Counting, Some(total));
+        let mut entries = self
+            .objects
+            .iter()
+            .map(|&(oid, name_hash)| {
+                let BitObjHeader { obj_type, size } = self.repo.read_obj_header(oid)?;
+                counting.inc();
+                Ok(PackEntry { oid, name_hash, obj_type, size, delta: None, depth: 0 })
+            })
+            .collect::<BitResult<Vec<_>>>()?;
+        counting.finish();
+        self.find_deltas(&mut entries)?;
+        PackEntryWriter::new(&self.repo, writer, &entries).write()
+    }
+
+    /// Builds the pack on a blocking thread, sending it through the returned receiver in chunks as
+    /// it is written so the whole pack is never held in memory.
+    /// The handle resolves to the index of the pack, and must be awaited even if the pack isn't
+    /// received in full as the thread holds onto the repository until it is done.
+    pub fn stream(self) -> (mpsc::Receiver<Vec<u8>>, JoinHandle<BitResult<PackIndex>>) {
+        // a few chunks may be buffered so building the pack can get a little ahead of sending it
+        let (tx, rx) = mpsc::channel(4);
+        let handle = tokio::task::spawn_blocking(move || {
+            let mut writer = BufWriter::with_capacity(STREAM_CHUNK_SIZE, ChannelWriter(tx));
+            let pack_index = self.write(&mut writer)?;
+            writer.flush()?;
+            Ok(pack_index)
+        });
+        (rx, handle)
+    }
+
+    /// Writes `pack-<hash>.pack` and the corresponding `pack-<hash>.idx` into `dir`
+    /// (and `pack-<hash>.bitmap` if enabled)
+    pub fn write_to_dir(self, dir: impl AsRef<Path>) -> BitResult<PackIndex> {
+        let dir = dir.as_ref();
+        let repo = self.repo.clone();
+        // the name hashes are kept in the bitmap index so packs built from it can use them
+        let name_hashes =
+            self.bitmaps.then(|| self.objects.iter().copied().collect::<FxHashMap<_, _>>());
+        let mut pack_file = tempfile::NamedTempFile::new_in(dir)?;
+        let mut writer = BufWriter::new(pack_file.as_file_mut());
+        let pack_index = self.write(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+
+        let mut idx_file = tempfile::NamedTempFile::new_in(dir)?;
+        pack_index.serialize(&mut idx_file)?;
+
+        // the pack must exist before the index that points to it
+        let name = format!("pack-{}", pack_index.pack_hash);
+        let pack_path = dir.join(&name).with_extension(PACK_EXT);
+        pack_file.persist(&pack_path)?;
+        idx_file.persist(dir.join(&name).with_extension(PACK_IDX_EXT))?;
+        if let Some(name_hashes) = name_hashes {
+            repo.write_pack_bitmap_index(&pack_path, &name_hashes)?;
+        }
+        Ok(pack_index)
+    }
+
+    /// Chooses a delta base for each object by sliding a window over the objects sorted such that
+    /// similar objects are likely to be near each other.
+    fn find_deltas(&self, entries: &mut [PackEntry]) -> BitResult<()> {
+        if self.window == 0 || self.depth == 0 {
+            return Ok(());
+        }
+
+        // objects are grouped by type then by (a hash of) their name as files with the same name are
+        // likely to be different versions of the same file.
+        // Larger objects come first as deltas that remove data are smaller than those that add data.
+        let mut order = (0..entries.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| {
+            let entry = &entries[i];
+            (entry.obj_type as u8, entry.name_hash, Reverse(entry.size))
+        });
+
+        // only the objects in the window are held in memory
+        let mut window = VecDeque::<(usize, BitPackObjRaw)>::with_capacity(self.window);
+        for i in order {
+            let target = self.repo.read_obj_raw(entries[i].oid)?;
+            if target.bytes.len() >= MIN_DELTA_SIZE {
+                if let Some((j, delta)) = self.find_best_delta(entries, &window, &target)? {
+                    entries[i].depth = entries[j].depth + 1;
+                    entries[i].delta = Some((j, delta));
+                }
+            }
+
+            if window.len() == self.window {
+                window.pop_front();
+            }
+            window.push_back((i, target));
+        }
+        Ok(())
+    }
+
+    /// Finds the smallest worthwhile delta for `target` against the objects in the window
+    fn find_best_delta(
+        &self,
+        entries: &[PackEntry],
+        window: &VecDeque<(usize, BitPackObjRaw)>,
+        target: &BitPackObjRaw,
+    ) -> BitResult<Option<(usize, Vec<u8>)>> {
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        for (j, base) in window.iter().rev() {
+            if base.obj_type != target.obj_type || entries[*j].depth >= self.depth {
+                continue;
+            }
+            // a delta between objects of very different sizes is unlikely to be any good
+            if base.bytes.len() < target.bytes.len() / 32 {
+                continue;
+            }
+
+            let mut delta = vec![];
+            Delta::compute(&base.bytes, &target.bytes).serialize(&mut delta)?;
+            // a delta must at least halve the size of the object to be worth the indirection
+            let max_size = best.as_ref().map_or(target.bytes.len() / 2, |(_, d)| d.len());
+            if delta.len() < max_size {
+                best = Some((*j, delta));
+            }
+        }
+        Ok(best)
+    }
+}
+
+/// git's `pack_name_hash`: the last characters are the most significant
+/// so paths that share a suffix (e.g. the same file name or extension) sort close together
+pub(crate) fn name_hash(path: BitPath) -> u32 {
+    path.as_bytes()
+        .iter()
+        .filter(|c| !c.is_ascii_whitespace())
+        .fold(0, |hash, &c| (hash >> 2).wrapping_add((c as u32) << 24))
+}
+
+/// The name hash of an object, falling back to the one recorded in the bitmap index for objects
+/// without a path (which is how [`BitRepo::bitmap_reachable_objects`] returns them)
+fn object_name_hash(bitmaps: Option<&PackBitmapIndex>, oid: Oid, path: BitPath) -> u32 {
+    match bitmaps.and_then(|bitmaps| bitmaps.name_hash(oid)) {
+        Some(hash) if path.is_empty() => hash,
+        _ => name_hash(path),
+    }
+}
+
+struct PackEntryWriter<'e, W: Write> {
+    repo: &'e BitRepo,
+    writer: HashWriter<Sha1, W>,
+    entries: &'e [PackEntry],
+    /// the offset and crc of each entry that has been written
+    written: Vec<Option<(u64, u32)>>,
+    offset: u64,
+}
+
+impl<'e, W: Write> PackEntryWriter<'e, W> {
+    fn new(repo: &'e BitRepo, writer: W, entries: &'e [PackEntry]) -> Self {
+        Self {
+            repo,
+            writer: HashWriter::new_sha1(writer),
+            entries,
+            written: vec![None; entries.len()],
+            offset: PACK_HEADER_SIZE,
+        }
+    }
+
+    fn write(mut self) -> BitResult<PackIndex> {
+        self.writer.write_all(PACK_SIGNATURE)?;
+        self.writer.write_u32(2)?;
+        self.writer.write_u32(self.entries.len().try_into()?)?;
+        for i in 0..self.entries.len() {
+            self.write_entry(i)?;
+        }
+        let pack_hash = self.writer.write_hash()?;
+
+        let mut sorted = self
+            .entries
+            .iter()
+            .zip(self.written)
+            .map(|(entry, written)| {
+                let (offset, crc) = written.expect("every entry was written");
+                (entry.oid, crc, offset)
+            })
+            .collect::<Vec<_>>();
+        sorted.sort_by_key(|&(oid, ..)| oid);
+        Ok(PackIndex::from_sorted(sorted, pack_hash))
+    }
+
+    /// writes the entry at index `i` (if it hasn't been already) returning its offset
+    fn write_entry(&mut self, i: usize) -> BitResult<u64> {
+        if let Some((offset, _)) = self.written[i] {
+            return Ok(offset);
+        }
+
+        let entries = self.entries;
+        let entry = &entries[i];
+        let mut buf = vec![];
+        match &entry.delta {
+            Some((base, delta)) => {
+                // the delta refers to its base by a (negative) relative offset
+                let base_offset = self.write_entry(*base)?;
+                let obj_type = BitPackObjType::OfsDelta as u8;
+                buf.write_le_varint_with_shift(obj_type, 3, delta.len() as u64)?;
+                buf.write_offset(self.offset - base_offset)?;
+                let mut encoder = ZlibEncoder::new(&mut buf, Compression::default());
+                encoder.write_all(delta)?;
+                encoder.finish()?;
+            }
+            None => {
+                let raw = self.repo.read_obj_raw(entry.oid)?;
+                let obj_type = BitPackObjType::from(raw.obj_type) as u8;
+                buf.write_le_varint_with_shift(obj_type, 3, raw.bytes.len() as u64)?;
+                let mut encoder = ZlibEncoder::new(&mut buf, Compression::default());
+                encoder.write_all(&raw.bytes)?;
+                encoder.finish()?;
+            }
+        }
+
+        let offset = self.offset;
+        self.writer.write_all(&buf)?;
+        self.written[i] = Some((offset, crc32fast::hash(&buf)));
+        self.offset += buf.len() as u64;
+        Ok(offset)
+    }
+}
@@ -0,0 +1,47 @@
+use crate::cache::{CacheStats, LruCache, Sharded};
+use crate::obj::BitPackObjRaw;
+use crate::path::BitPath;
+use std::sync::Arc;
+
+/// The default value of `core.deltaBaseCacheLimit` (same as git's)
+pub const DEFAULT_DELTA_BASE_CACHE_LIMIT: u64 = 96 * 1024 * 1024;
+
+/// Identifies an object by its location in a particular pack
+type DeltaBaseKey = (BitPath, u64);
+
+/// A least recently used cache of the fully expanded bases of deltas.
+/// Objects that are close together in history tend to share long delta chains, and without
+/// this each read would have to expand every base in the chain again.
+/// The cache is shared by all packs and is bounded by the total size of the cached objects.
+#[derive(Debug)]
+pub struct DeltaBaseCache {
+    shards: Sharded<LruCache<DeltaBaseKey, Arc<BitPackObjRaw>>>,
+}
+
+impl Default for DeltaBaseCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_DELTA_BASE_CACHE_LIMIT)
+    }
+}
+
+impl DeltaBaseCache {
+    /// Create a cache that holds at most `limit` bytes of objects (a limit of `0` disables it)
+    pub fn new(limit: u64) -> Self {
+        Self { shards: Sharded::new(limit, LruCache::new) }
+    }
+
+    pub fn get(&self, pack: BitPath, offset: u64) -> Option<Arc<BitPackObjRaw>> {
+        self.shards.shard((pack, offset)).get((pack, offset))
+    }
+
+    /// Cache `raw`, evicting the least recently used objects to stay within the limit.
+    /// Objects that are larger than the limit of their shard by themselves are not cached.
+    pub fn insert(&self, pack: BitPath, offset: u64, raw: Arc<BitPackObjRaw>) {
+        let size = raw.bytes.len() as u64;
+        self.shards.shard((pack, offset)).insert((pack, offset), raw, size);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.shards.fold(CacheStats::default(), |acc, shard| acc + shard.stats())
+    }
+}
@@ -2,6 +2,7 @@ use super::*;
 use crate::error::BitResult;
 use crate::io::{BufReadExt, HashReader, ReadExt};
 use crate::obj::{BitPackObjRaw, Oid};
+use crate::progress::{ProgressPhase, ProgressSink};
 use crate::serialize::Serialize;
 use rustc_hash::FxHashMap;
 use sha1::Sha1;
@@ -15,12 +16,14 @@ pub struct PackIndexer<R> {
     oid_to_offset: FxHashMap<Oid, u64>,
     /// oid -> (offset, crc)
     sorted: BTreeMap<Oid, (u64, u32)>,
+    progress: ProgressSink,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct IndexPackOpts {
     pub index_file_path: Option<PathBuf>,
     pub verbose: bool,
+    pub progress: ProgressSink,
 }
 
 impl PackIndexer<FileBufferReader> {
@@ -29,7 +32,7 @@ impl PackIndexer<FileBufferReader> {
     pub fn write_pack_index(path: impl AsRef<Path>, opts: IndexPackOpts) -> BitResult<PackIndex> {
         let path = path.as_ref();
         let reader = FileBufferReader::new(path)?;
-        let indexer = PackIndexer::new(reader)?;
+        let indexer = PackIndexer::new(reader)?.progress(opts.progress);
         let pack_index = indexer.index_pack()?;
         let mut tmp_file = tempfile::NamedTempFile::new_in(path.parent().unwrap())?;
         pack_index.serialize(&mut tmp_file)?;
@@ -51,12 +54,19 @@ impl<R: BufRead> PackIndexer<R> {
             raw_objects: Default::default(),
             oid_to_offset: Default::default(),
             sorted: Default::default(),
+            progress: Default::default(),
         })
     }
 
+    /// Report the progress of indexing as [`ProgressPhase::ResolvingDeltas`]
+    pub(crate) fn progress(self, progress: ProgressSink) -> Self {
+        Self { progress, ..self }
+    }
+
     /// TODO parallelize
     pub(crate) fn index_pack(mut self) -> BitResult<PackIndex> {
         let n = self.pack_reader.objectc as usize;
+        let mut progress = self.progress.begin(ProgressPhase::ResolvingDeltas, Some(n as u64));
         for i in 0..self.pack_reader.objectc {
             let offset = self.pack_reader.bytes_hashed() as u64;
             let (crc, deltified) = self.pack_reader.read_pack_obj_with_crc()?;
@@ -73,7 +83,9 @@ impl<R: BufRead> PackIndexer<R> {
             self.oid_to_offset.insert(oid, offset);
             self.raw_objects.insert(offset, raw_pack_obj);
             self.sorted.insert(oid, (offset, crc));
+            progress.inc();
         }
+        progress.finish();
 
         let mut reader = self.pack_reader.reader;
         let pack_hash = reader.finalize_sha1();
@@ -87,17 +99,8 @@ impl<R: BufRead> PackIndexer<R> {
             pack_hash
         );
 
-        let mut oids = Vec::with_capacity(n);
-        let mut offsets = Vec::with_capacity(n);
-        let mut crcs = Vec::with_capacity(n);
-        for (oid, (offset, crc)) in self.sorted {
-            oids.push(oid);
-            crcs.push(crc);
-            assert!(offset < MAX_OFFSET, "todo ext layer");
-            offsets.push(offset as u32);
-        }
-        let fanout = PackIndex::build_fanout(&oids);
-        Ok(PackIndex { fanout, oids, crcs, offsets, pack_hash })
+        let entries = self.sorted.into_iter().map(|(oid, (offset, crc))| (oid, crc, offset));
+        Ok(PackIndex::from_sorted(entries, pack_hash))
     }
 
     fn expand_deltas(
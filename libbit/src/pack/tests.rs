@@ -5,8 +5,10 @@ use crate::repo::{BitRepo, BitRepoWeakRef};
 use crate::signature::{BitEpochTime, BitSignature, BitTime, BitTimeZoneOffset};
 use lazy_static::lazy_static;
 use quickcheck::Arbitrary;
-use std::io::Cursor;
+use rayon::prelude::*;
+use std::io::BufReader;
 use std::str::FromStr;
+use std::sync::Arc;
 
 // got this number by inspecting last entry of the fanout table
 const PACK_LEN: u64 = 11076;
@@ -23,7 +25,7 @@ impl BitObjKind {
 }
 
 impl Pack {
-    pub(crate) fn read_obj(&mut self, repo: BitRepoWeakRef, oid: Oid) -> BitResult<BitObjKind> {
+    pub(crate) fn read_obj(&self, repo: BitRepoWeakRef, oid: Oid) -> BitResult<BitObjKind> {
         trace!("read_obj(oid: {}) ", oid);
         let raw = self.read_obj_raw(oid)?;
         BitObjKind::from_raw_pack_obj(repo, oid, raw)
@@ -34,14 +36,17 @@ impl Arbitrary for PackIndex {
     fn arbitrary(g: &mut quickcheck::Gen) -> Self {
         let mut oids = Vec::<Oid>::arbitrary(g);
         oids.sort();
-        let mut offsets = Vec::with_capacity(oids.len());
-        let mut crcs = Vec::with_capacity(oids.len());
-        for _ in 0..oids.len() {
-            offsets.push(Arbitrary::arbitrary(g));
-            crcs.push(Arbitrary::arbitrary(g));
-        }
-        let fanout = Self::build_fanout(&oids);
-        Self { oids, fanout, crcs, offsets, pack_hash: Oid::UNKNOWN }
+        oids.dedup();
+        let entries = oids
+            .into_iter()
+            .map(|oid| {
+                // some offsets are large enough to need the 64-bit offset layer
+                let offset =
+                    if bool::arbitrary(g) { u32::arbitrary(g) as u64 } else { u64::arbitrary(g) };
+                (oid, u32::arbitrary(g), offset)
+            })
+            .collect::<Vec<_>>();
+        Self::from_sorted(entries, Oid::UNKNOWN)
     }
 }
 
@@ -50,6 +55,17 @@ fn test_serde_pack_index(pack_index: PackIndex) -> BitResult<()> {
     test_serde!(pack_index)
 }
 
+#[quickcheck]
+fn test_pack_index_reader_resolves_64_bit_offsets(pack_index: PackIndex) -> BitResult<()> {
+    let mut bytes = vec![];
+    pack_index.serialize(&mut bytes)?;
+    let reader = PackIndexReader::new(bytes)?;
+    for (i, &oid) in pack_index.oids.iter().enumerate() {
+        assert_eq!(reader.find_oid_crc_offset(oid)?, (pack_index.crcs[i], pack_index.offset(i)));
+    }
+    Ok(())
+}
+
 #[test]
 fn test_deserialize_pack_idx_is_ok() -> BitResult<()> {
     let bytes = include_bytes!("../../tests/files/pack.idx") as &[u8];
@@ -57,10 +73,20 @@ fn test_deserialize_pack_idx_is_ok() -> BitResult<()> {
     Ok(())
 }
 
+#[test]
+fn test_pack_index_reader_rejects_decreasing_fanout() -> BitResult<()> {
+    let mut bytes = include_bytes!("../../tests/files/pack.idx").to_vec();
+    // make the fanout entry for `0x00` larger than the one for `0x01`
+    let start = PACK_IDX_HEADER_SIZE as usize;
+    bytes[start..start + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert!(PackIndexReader::new(bytes).is_err());
+    Ok(())
+}
+
 #[test]
 fn test_pack_idx_find_oid_start() -> BitResult<()> {
-    let mut cursor = Cursor::new(include_bytes!("../../tests/files/pack.idx"));
-    let index = PackIndexReader::new(&mut cursor)?.find_oid_index(
+    let bytes = &include_bytes!("../../tests/files/pack.idx")[..];
+    let index = PackIndexReader::new(bytes)?.find_oid_index(
         // this hash is the first oid in sorted list
         Oid::from_str("0004a3cf85dbcbfbef916599145a0c370bb78cf5").unwrap(),
     )?;
@@ -69,8 +95,8 @@ fn test_pack_idx_find_oid_start() -> BitResult<()> {
 }
 #[test]
 fn test_pack_idx_find_oid_end() -> BitResult<()> {
-    let mut cursor = Cursor::new(include_bytes!("../../tests/files/pack.idx"));
-    let index = PackIndexReader::new(&mut cursor)?.find_oid_index(
+    let bytes = &include_bytes!("../../tests/files/pack.idx")[..];
+    let index = PackIndexReader::new(bytes)?.find_oid_index(
         // this hash is the last oid in sorted list
         Oid::from_str("fffc6e8cf5f6798732a6031ebf24d2f6aaa60e47").unwrap(),
     )?;
@@ -79,7 +105,15 @@ fn test_pack_idx_find_oid_end() -> BitResult<()> {
 }
 
 fn pack() -> BitResult<Pack> {
-    Pack::new(BitPath::intern("tests/files/pack.pack"), BitPath::intern("tests/files/pack.idx"))
+    pack_with_delta_base_cache(Default::default())
+}
+
+fn pack_with_delta_base_cache(delta_base_cache: Arc<DeltaBaseCache>) -> BitResult<Pack> {
+    Pack::new(
+        BitPath::intern("tests/files/pack.pack"),
+        BitPath::intern("tests/files/pack.idx"),
+        delta_base_cache,
+    )
 }
 
 lazy_static! {
@@ -107,7 +141,7 @@ fn test_find_offset_in_pack() -> BitResult<()> {
 
 #[test]
 fn test_read_type_and_size_from_offset_in_pack() -> BitResult<()> {
-    let mut pack = pack()?;
+    let pack = pack()?;
     let (_crc, offset) = pack.idx_reader().find_oid_crc_offset(*HEAD_OID)?;
     let header = pack.pack_reader().read_header_from_offset(offset)?;
     assert_eq!(header.obj_type, BitPackObjType::Commit);
@@ -118,7 +152,7 @@ fn test_read_type_and_size_from_offset_in_pack() -> BitResult<()> {
 #[test]
 fn test_read_pack_undeltified_oid() -> BitResult<()> {
     BitRepo::with_empty_repo(|repo| {
-        let mut pack = pack()?;
+        let pack = pack()?;
         let obj = pack.read_obj(repo.downgrade(), *HEAD_OID)?;
         let commit = MutableCommit {
             tree: "2a09245f13365a5d812a9d463595d815062b7d42".into(),
@@ -151,7 +185,7 @@ fn test_read_pack_undeltified_oid() -> BitResult<()> {
 #[test]
 fn test_read_pack_deltified_oid() -> BitResult<()> {
     BitRepo::with_empty_repo(|repo| {
-        let mut pack = pack()?;
+        let pack = pack()?;
         let obj = pack.read_obj(repo.downgrade(), *TREE_OID)?;
         let tree = MutableTree::new(
             vec![
@@ -228,7 +262,7 @@ fn test_read_pack_deltified_oid() -> BitResult<()> {
 #[test]
 fn test_read_pack_deltified_oid2() -> BitResult<()> {
     BitRepo::with_empty_repo(|repo| {
-        let mut pack = pack()?;
+        let pack = pack()?;
         let obj = pack.read_obj(repo.downgrade(), *SRC_TREE_OID)?;
         let tree = MutableTree::new(
             vec![
@@ -383,8 +417,8 @@ fn test_read_pack_deltified_oid2() -> BitResult<()> {
 
 #[test]
 fn test_pack_idx_find_oid_offset_end() -> BitResult<()> {
-    let mut cursor = Cursor::new(include_bytes!("../../tests/files/pack.idx"));
-    let (_crc, pack_idx) = PackIndexReader::new(&mut cursor)?.find_oid_crc_offset(
+    let bytes = &include_bytes!("../../tests/files/pack.idx")[..];
+    let (_crc, pack_idx) = PackIndexReader::new(bytes)?.find_oid_crc_offset(
         // this hash is the last oid in sorted list
         Oid::from_str("fffc6e8cf5f6798732a6031ebf24d2f6aaa60e47").unwrap(),
     )?;
@@ -395,7 +429,7 @@ fn test_pack_idx_find_oid_offset_end() -> BitResult<()> {
 
 #[test]
 fn read_pack_read_zero_size_obj() -> BitResult<()> {
-    let mut pack = pack()?;
+    let pack = pack()?;
     let blob = pack.read_obj_raw(Oid::EMPTY_BLOB)?;
     assert_eq!(blob, BitPackObjRaw { obj_type: BitObjType::Blob, bytes: vec![] });
     Ok(())
@@ -405,7 +439,7 @@ fn read_pack_read_zero_size_obj() -> BitResult<()> {
 // and the size remains the type of the expanded tree
 #[test]
 fn test_packed_header_is_expanded() -> BitResult<()> {
-    let mut pack = pack()?;
+    let pack = pack()?;
     let header = pack.read_obj_header("2a09245f13365a5d812a9d463595d815062b7d42".into())?;
     assert_eq!(header.obj_type, BitObjType::Tree);
     assert_eq!(header.size, 138);
@@ -437,9 +471,9 @@ fn test_pack_index_generation() -> BitResult<()> {
 fn test_read_entire_pack_with_index_by_offset() -> BitResult<()> {
     let pack_index =
         PackIndex::deserialize(BufReader::new(&include_bytes!("../../tests/files/pack.idx")[..]))?;
-    let mut pack = pack()?;
-    for offset in pack_index.offsets {
-        pack.read_obj_raw_at(offset as u64)?;
+    let pack = pack()?;
+    for i in 0..pack_index.oids.len() {
+        pack.read_obj_raw_at(pack_index.offset(i))?;
     }
     Ok(())
 }
@@ -448,13 +482,96 @@ fn test_read_entire_pack_with_index_by_offset() -> BitResult<()> {
 fn test_read_entire_pack_with_index_by_oid() -> BitResult<()> {
     let pack_index =
         PackIndex::deserialize(BufReader::new(&include_bytes!("../../tests/files/pack.idx")[..]))?;
-    let mut pack = pack()?;
+    let pack = pack()?;
     for oid in pack_index.oids {
         pack.read_obj_raw(oid)?;
     }
     Ok(())
 }
 
+#[test]
+fn test_read_pack_concurrently() -> BitResult<()> {
+    let pack_index =
+        PackIndex::deserialize(BufReader::new(&include_bytes!("../../tests/files/pack.idx")[..]))?;
+    let pack = pack()?;
+    let expected =
+        pack_index.oids.iter().map(|&oid| pack.read_obj_raw(oid)).collect::<BitResult<Vec<_>>>()?;
+    let actual = pack_index
+        .oids
+        .par_iter()
+        .map(|&oid| pack.read_obj_raw(oid))
+        .collect::<BitResult<Vec<_>>>()?;
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+fn test_delta_base_cache_hits() -> BitResult<()> {
+    let cache = Arc::new(DeltaBaseCache::default());
+    let pack = pack_with_delta_base_cache(Arc::clone(&cache))?;
+    // the tree is 3 levels deltified so the first read has to expand (and cache) each base
+    let tree = pack.read_obj_raw(*TREE_OID)?;
+    let stats = cache.stats();
+    assert_eq!(stats.hits, 0);
+    assert!(stats.misses > 0);
+    assert_eq!(stats.entries as u64, stats.misses);
+
+    // the second read only has to apply the final delta to the cached base
+    assert_eq!(pack.read_obj_raw(*TREE_OID)?, tree);
+    assert_eq!(cache.stats().hits, 1);
+    assert_eq!(cache.stats().misses, stats.misses);
+    Ok(())
+}
+
+#[test]
+fn test_delta_base_cache_respects_limit() -> BitResult<()> {
+    let pack_index =
+        PackIndex::deserialize(BufReader::new(&include_bytes!("../../tests/files/pack.idx")[..]))?;
+    let limit = 64 * 1024;
+    let cache = Arc::new(DeltaBaseCache::new(limit));
+    let pack = pack_with_delta_base_cache(Arc::clone(&cache))?;
+    let uncached = pack_with_delta_base_cache(Arc::new(DeltaBaseCache::new(0)))?;
+    for &oid in &pack_index.oids {
+        assert_eq!(pack.read_obj_raw(oid)?, uncached.read_obj_raw(oid)?);
+        assert!(cache.stats().size <= limit);
+    }
+    assert!(cache.stats().hits > 0);
+    Ok(())
+}
+
+#[test]
+fn test_delta_base_cache_evicts_least_recently_used() {
+    let path = BitPath::intern("pack");
+    let raw = |n| Arc::new(BitPackObjRaw { obj_type: BitObjType::Blob, bytes: vec![0; n] });
+    let cache = DeltaBaseCache::new(10);
+    cache.insert(path, 0, raw(4));
+    cache.insert(path, 1, raw(4));
+    // using `0` makes `1` the least recently used
+    assert!(cache.get(path, 0).is_some());
+    cache.insert(path, 2, raw(4));
+    assert!(cache.get(path, 1).is_none());
+    assert!(cache.get(path, 0).is_some());
+    assert!(cache.get(path, 2).is_some());
+    // too big to ever fit
+    cache.insert(path, 3, raw(11));
+    assert!(cache.get(path, 3).is_none());
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (3, 2));
+    assert_eq!((stats.entries, stats.size), (2, 8));
+}
+
+#[test]
+fn test_delta_base_cache_disabled() -> BitResult<()> {
+    let cache = Arc::new(DeltaBaseCache::new(0));
+    let pack = pack_with_delta_base_cache(Arc::clone(&cache))?;
+    pack.read_obj_raw(*TREE_OID)?;
+    pack.read_obj_raw(*TREE_OID)?;
+    assert_eq!(cache.stats().hits, 0);
+    assert_eq!(cache.stats().entries, 0);
+    Ok(())
+}
+
 #[test]
 fn test_read_problematic_pack_unbuffered() -> BitResult<()> {
     let pack_bytes = &include_bytes!("../../tests/files/lg2fetchpack")[..];
@@ -505,3 +622,60 @@ fn test_index_another_problematic_pack() -> BitResult<()> {
     indexer.index_pack()?;
     Ok(())
 }
+
+#[test]
+fn test_build_pack_then_index() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let objects = repo.reachable_objects(&[head], &[])?;
+        let mut pack = vec![];
+        let built_index = PackBuilder::new(repo, objects).write(&mut pack)?;
+
+        let pack_index = PackIndexer::new(&pack[..])?.index_pack()?;
+        assert_eq!(pack_index, built_index);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_build_pack_with_deltas() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        let contents = (0..200).map(|i| format!("line {i}\n")).collect::<String>();
+        touch!(repo: "foo" < contents);
+        bit_commit_all!(repo);
+        for i in 0..5 {
+            modify!(repo: "foo" << format!("appended line {i}\n"));
+            bit_commit_all!(repo);
+        }
+
+        let head = repo.fully_resolve_head()?;
+        let objects = repo.reachable_objects(&[head], &[])?;
+
+        let mut pack = vec![];
+        let built_index = PackBuilder::new(repo.clone(), objects.clone()).write(&mut pack)?;
+        let pack_index = PackIndexer::new(&pack[..])?.index_pack()?;
+        assert_eq!(pack_index, built_index);
+
+        let mut undeltified = vec![];
+        PackBuilder::new(repo, objects).window(0).write(&mut undeltified)?;
+        assert!(pack.len() < undeltified.len());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_build_pack_to_dir() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let objects = repo.reachable_objects(&[head], &[])?;
+        let dir = tempfile::tempdir()?;
+        let pack_index = PackBuilder::new(repo, objects).write_to_dir(dir.path())?;
+
+        let name = format!("pack-{}", pack_index.pack_hash);
+        let pack_path = dir.path().join(&name).with_extension(PACK_EXT);
+        let idx_path = dir.path().join(&name).with_extension(PACK_IDX_EXT);
+        assert!(pack_path.exists());
+        assert_eq!(PackIndex::deserialize_unbuffered(std::fs::File::open(idx_path)?)?, pack_index);
+        Ok(())
+    })
+}
@@ -1,11 +1,13 @@
+use super::{IndexPackOpts, PackIndex, PackIndexer, PACK_EXT, PACK_IDX_EXT};
 use crate::error::BitResult;
+use crate::progress::ProgressSink;
 use crate::repo::BitRepo;
 use pin_project_lite::pin_project;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::fs::File;
-use tokio::io::{AsyncWrite, BufWriter};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 
 pin_project! {
     /// This struct literally just writes the data given to it to a randomly named file in .git/objects/pack.
@@ -23,6 +25,39 @@ impl PackWriter {
         let file = BufWriter::new(File::from_std(file));
         Ok(Self { file, path })
     }
+
+    /// Indexes the pack and moves it (and its index) to `pack-<hash>.{pack,idx}`,
+    /// then refreshes the odb so the new objects are visible
+    pub async fn finish(
+        mut self,
+        repo: &BitRepo,
+        progress: &ProgressSink,
+    ) -> BitResult<PackIndex> {
+        self.flush().await?;
+
+        let opts = IndexPackOpts { progress: progress.clone(), ..Default::default() };
+        let pack_index = PackIndexer::write_pack_index(&self.path, opts)?;
+        std::fs::rename(
+            &self.path,
+            self.path.with_file_name(format!("pack-{}.{}", pack_index.pack_hash, PACK_EXT)),
+        )?;
+        std::fs::rename(
+            self.path.with_extension(PACK_IDX_EXT),
+            self.path.with_file_name(format!("pack-{}.{}", pack_index.pack_hash, PACK_IDX_EXT)),
+        )?;
+
+        // Refresh pack odb to include the newly written pack
+        repo.refresh_odb()?;
+        Ok(pack_index)
+    }
+
+    /// Removes whatever has been written so far, for when the pack is never going to be complete
+    pub fn abort(self) -> BitResult<()> {
+        let Self { path, file } = self;
+        drop(file);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
 }
 
 impl AsyncWrite for PackWriter {
@@ -0,0 +1,191 @@
+use crate::error::{BitGenericError, BitResult};
+use crate::obj::Oid;
+use crate::remote::Remote;
+use crate::repo::BitRepo;
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use rustc_hash::FxHashSet;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+// A partial clone is missing some of the objects of its history (e.g. after
+// `clone --filter=blob:none`). The remote it was cloned from is recorded as a promisor
+// (`remote.<name>.promisor`) and any missing object is assumed to be promised by it and is fetched
+// when it is first read.
+
+/// Omits objects from a fetch (`--filter=<spec>`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectFilter {
+    /// `blob:none`: omit every blob
+    BlobNone,
+    /// `blob:limit=<n>[kmg]`: omit blobs that are at least `n` bytes
+    BlobLimit(u64),
+    /// `tree:<depth>`: omit the trees and blobs at least `depth` levels below the root tree
+    /// (`tree:0` omits every tree and blob)
+    TreeDepth(u64),
+}
+
+impl FromStr for ObjectFilter {
+    type Err = BitGenericError;
+
+    fn from_str(s: &str) -> BitResult<Self> {
+        let invalid = || anyhow!("invalid filter `{}`", s);
+        if s == "blob:none" {
+            return Ok(Self::BlobNone);
+        }
+        if let Some(limit) = s.strip_prefix("blob:limit=") {
+            let (digits, shift) = match limit.char_indices().last().ok_or_else(invalid)? {
+                (i, 'k' | 'K') => (&limit[..i], 10),
+                (i, 'm' | 'M') => (&limit[..i], 20),
+                (i, 'g' | 'G') => (&limit[..i], 30),
+                _ => (limit, 0),
+            };
+            let limit = digits.parse::<u64>().map_err(|_| invalid())?;
+            return limit.checked_mul(1 << shift).map(Self::BlobLimit).ok_or_else(invalid);
+        }
+        if let Some(depth) = s.strip_prefix("tree:") {
+            return depth.parse().map(Self::TreeDepth).map_err(|_| invalid());
+        }
+        Err(invalid())
+    }
+}
+
+impl Display for ObjectFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectFilter::BlobNone => write!(f, "blob:none"),
+            ObjectFilter::BlobLimit(limit) => write!(f, "blob:limit={}", limit),
+            ObjectFilter::TreeDepth(depth) => write!(f, "tree:{}", depth),
+        }
+    }
+}
+
+/// Serializes fetching from the promisor remote.
+/// Only one fetch is in flight at a time and any thread that wants to fetch meanwhile waits for it
+/// to finish (as it may well provide the objects that thread is missing).
+#[derive(Debug, Default)]
+pub(crate) struct PromisorFetches {
+    state: Mutex<PromisorFetchState>,
+    finished: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct PromisorFetchState {
+    fetching: bool,
+    /// the missing objects that the promisor remote was asked for but didn't provide
+    unpromised: FxHashSet<Oid>,
+}
+
+impl PromisorFetches {
+    /// Waits for the fetch in flight (if any) to finish
+    fn wait(&self) -> MutexGuard<'_, PromisorFetchState> {
+        let mut state = self.state.lock();
+        while state.fetching {
+            self.finished.wait(&mut state);
+        }
+        state
+    }
+}
+
+impl BitRepo {
+    /// The remote that promises to provide the objects missing from a partial clone
+    pub fn promisor_remote(&self) -> Option<Remote> {
+        self.ls_remotes().find(|remote| remote.promisor)
+    }
+
+    pub fn is_partial_clone(&self) -> bool {
+        self.promisor_remote().is_some()
+    }
+
+    /// Fetch whichever of `oids` are missing from a partial clone in a single request (rather
+    /// than one request per object as they are read).
+    /// This is best effort as reading the objects will fetch them anyway, so failures are ignored.
+    pub fn prefetch_objects(&self, oids: impl IntoIterator<Item = Oid>) -> BitResult<()> {
+        let remote = match self.promisor_remote() {
+            Some(remote) => remote,
+            None => return Ok(()),
+        };
+
+        let mut seen = FxHashSet::default();
+        let mut missing = vec![];
+        for oid in oids {
+            if oid.is_known() && seen.insert(oid) && !self.obj_exists(oid)? {
+                missing.push(oid);
+            }
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        // each object is retried individually when it is read if this fails
+        if let Err(err) = self.fetch_promised_objects(&remote, &missing) {
+            debug!("failed to prefetch {} objects: {}", missing.len(), err);
+        }
+        Ok(())
+    }
+
+    /// Try to fetch the missing object `oid` from the promisor remote, returning whether it was
+    /// fetched
+    pub(crate) fn fetch_promised_object(&self, oid: Oid) -> BitResult<bool> {
+        let remote = match self.promisor_remote() {
+            Some(remote) => remote,
+            None => return Ok(false),
+        };
+        // a failed fetch is retried the next time the object is read
+        if let Err(err) = self.fetch_promised_objects(&remote, &[oid]) {
+            debug!("failed to fetch promised object `{}`: {}", oid, err);
+        }
+        self.obj_exists(oid)
+    }
+
+    /// Fetches whichever of `oids` are still missing from `remote` once any fetch in flight has
+    /// finished.
+    /// Objects the remote has already answered without are not asked for again.
+    fn fetch_promised_objects(&self, remote: &Remote, oids: &[Oid]) -> BitResult<()> {
+        let fetches = self.promisor_fetches();
+        let mut state = fetches.wait();
+        let mut missing = vec![];
+        for &oid in oids {
+            if !state.unpromised.contains(&oid) && !self.obj_exists(oid)? {
+                missing.push(oid);
+            }
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        state.fetching = true;
+        drop(state);
+        let res = self.fetch_objects_blocking(remote, &missing);
+
+        let mut state = fetches.state.lock();
+        state.fetching = false;
+        fetches.finished.notify_all();
+        // only a successful fetch tells us the remote doesn't have the object, errors may well
+        // be transient
+        if res.is_ok() {
+            for oid in missing {
+                if !self.obj_exists(oid)? {
+                    state.unpromised.insert(oid);
+                }
+            }
+        }
+        res
+    }
+
+    /// Fetches `oids` from `remote` on a separate thread (with its own runtime) as objects are
+    /// read from synchronous code that may itself be running within a runtime
+    fn fetch_objects_blocking(&self, remote: &Remote, oids: &[Oid]) -> BitResult<()> {
+        std::thread::scope(|scope| {
+            let fetch = scope.spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?
+                    .block_on(self.fetch_objects(remote, oids))
+            });
+            fetch.join().map_err(|_| anyhow!("panicked while fetching from the promisor remote"))?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests;
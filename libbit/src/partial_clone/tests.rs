@@ -0,0 +1,156 @@
+use super::ObjectFilter;
+use crate::error::BitResult;
+use crate::obj::{MutableBlob, Oid, WritableObject};
+use crate::remote::{FetchOpts, DEFAULT_REMOTE};
+use crate::repo::BitRepo;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn blob_oid(contents: &str) -> BitResult<Oid> {
+    MutableBlob::new(contents.as_bytes().to_vec()).hash()
+}
+
+/// A remote where `foo` is `0`, `1` and then `2`, and `dir/big` is large in the first commit only
+fn remote_with_history() -> BitResult<TempDir> {
+    let remote = tempfile::tempdir()?;
+    BitRepo::init_load(remote.path(), |repo| {
+        mkdir!(repo: "dir");
+        touch!(repo: "foo" < "0");
+        touch!(repo: "dir/big" < &"x".repeat(2000));
+        bit_commit_all!(repo);
+        for i in 1..3 {
+            touch!(repo: "foo" < &i.to_string());
+            touch!(repo: "dir/big" < "small");
+            bit_commit_all!(repo);
+        }
+        // `git-upload-pack` ignores filters otherwise, and protocol v1 only allows wanting
+        // advertised objects by default
+        repo.with_raw_local_config(|config| {
+            config.set("uploadpack", "allowfilter", true)?;
+            config.set("uploadpack", "allowanysha1inwant", true)
+        })
+    })?;
+    Ok(remote)
+}
+
+fn partial_clone(remote: &Path, filter: ObjectFilter, protocol_version: i64) -> BitResult<TempDir> {
+    let local = tempfile::tempdir()?;
+    BitRepo::init_load(local.path(), |repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.to_str().unwrap())?;
+        repo.with_raw_local_config(|config| config.set("protocol", "version", protocol_version))?;
+        repo.clone_origin_blocking(&FetchOpts { filter: Some(filter), ..Default::default() })
+    })?;
+    Ok(local)
+}
+
+fn test_blob_none_clone_fetches_missing_blobs(protocol_version: i64) -> BitResult<()> {
+    let remote = remote_with_history()?;
+    let local = partial_clone(remote.path(), ObjectFilter::BlobNone, protocol_version)?;
+    assert_eq!(std::fs::read_to_string(local.path().join("foo"))?, "2");
+    assert_eq!(std::fs::read_to_string(local.path().join("dir/big"))?, "small");
+
+    BitRepo::find(local.path(), |repo| {
+        let remote = repo.promisor_remote().expect("clone should record the promisor remote");
+        assert_eq!(remote.partial_clone_filter, Some(ObjectFilter::BlobNone));
+        assert!(repo.is_partial_clone());
+
+        // the blobs of older commits are only fetched when they are read
+        let old_blob = blob_oid("0")?;
+        assert!(!repo.obj_exists(old_blob)?);
+        assert!(repo.fsck()?.is_ok());
+        assert_eq!(repo.read_obj(old_blob)?.into_blob().to_vec(), b"0");
+        assert!(repo.obj_exists(old_blob)?);
+
+        repo.prefetch_objects([blob_oid("1")?, blob_oid(&"x".repeat(2000))?])?;
+        assert!(repo.obj_exists(blob_oid("1")?)?);
+        assert!(repo.obj_exists(blob_oid(&"x".repeat(2000))?)?);
+        assert!(repo.fsck()?.is_ok());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_blob_none_clone_fetches_missing_blobs_with_protocol_v1() -> BitResult<()> {
+    test_blob_none_clone_fetches_missing_blobs(1)
+}
+
+#[test]
+fn test_blob_none_clone_fetches_missing_blobs_with_protocol_v2() -> BitResult<()> {
+    test_blob_none_clone_fetches_missing_blobs(2)
+}
+
+#[test]
+fn test_blob_limit_clone() -> BitResult<()> {
+    let remote = remote_with_history()?;
+    let local = partial_clone(remote.path(), ObjectFilter::BlobLimit(1024), 2)?;
+    BitRepo::find(local.path(), |repo| {
+        assert!(repo.obj_exists(blob_oid("0")?)?);
+        assert!(!repo.obj_exists(blob_oid(&"x".repeat(2000))?)?);
+        assert!(repo.fsck()?.is_ok());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_tree_depth_clone_fetches_missing_trees() -> BitResult<()> {
+    let remote = remote_with_history()?;
+    let local = partial_clone(remote.path(), ObjectFilter::TreeDepth(0), 2)?;
+    assert_eq!(std::fs::read_to_string(local.path().join("foo"))?, "2");
+    assert_eq!(std::fs::read_to_string(local.path().join("dir/big"))?, "small");
+
+    BitRepo::find(local.path(), |repo| {
+        let root = repo.read_obj_commit(repo.fully_resolve_head()?)?.parents[0];
+        let tree = repo.read_obj_commit(root)?.tree;
+        assert!(!repo.obj_exists(tree)?);
+        assert!(repo.read_obj_tree(tree)?.entries.iter().any(|entry| entry.path == "foo"));
+        assert!(repo.fsck()?.is_ok());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_failed_fetch_of_promised_object_is_retried() -> BitResult<()> {
+    let remote = remote_with_history()?;
+    let local = partial_clone(remote.path(), ObjectFilter::BlobNone, 2)?;
+    let moved = remote.path().with_extension("moved");
+
+    BitRepo::find(local.path(), |repo| {
+        // the remote being unreachable doesn't mean it doesn't have the object
+        std::fs::rename(remote.path(), &moved)?;
+        assert!(repo.read_obj(blob_oid("0")?).is_err());
+        std::fs::rename(&moved, remote.path())?;
+        assert_eq!(repo.read_obj(blob_oid("0")?)?.into_blob().to_vec(), b"0");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_filter_requires_promisor_remote() -> BitResult<()> {
+    let remote = remote_with_history()?;
+    let local = tempfile::tempdir()?;
+    BitRepo::init_load(local.path(), |repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.path().to_str().unwrap())?;
+        let opts = FetchOpts { filter: Some(ObjectFilter::BlobNone), ..Default::default() };
+        let err = repo.fetch_with_opts_blocking(DEFAULT_REMOTE, &opts).unwrap_err();
+        assert!(err.to_string().contains("promisor"));
+        assert!(!repo.is_partial_clone());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_parse_object_filter() -> BitResult<()> {
+    assert_eq!("blob:none".parse::<ObjectFilter>()?, ObjectFilter::BlobNone);
+    assert_eq!("blob:limit=100".parse::<ObjectFilter>()?, ObjectFilter::BlobLimit(100));
+    assert_eq!("blob:limit=2k".parse::<ObjectFilter>()?, ObjectFilter::BlobLimit(2048));
+    assert_eq!("blob:limit=1M".parse::<ObjectFilter>()?, ObjectFilter::BlobLimit(1 << 20));
+    assert_eq!("tree:0".parse::<ObjectFilter>()?, ObjectFilter::TreeDepth(0));
+    assert!("blob:limit=".parse::<ObjectFilter>().is_err());
+    assert!("blob:limit=k".parse::<ObjectFilter>().is_err());
+    assert!("sparse:oid=abc".parse::<ObjectFilter>().is_err());
+
+    for filter in ["blob:none", "blob:limit=100", "tree:1"] {
+        assert_eq!(filter.parse::<ObjectFilter>()?.to_string(), filter);
+    }
+    Ok(())
+}
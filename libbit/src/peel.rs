@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use crate::error::BitResult;
-use crate::obj::{BitObject, Commit, Oid, Tree};
+use crate::obj::{BitObjKind, BitObject, Commit, Oid, Tree};
 use crate::repo::BitRepo;
 
 // experimental
@@ -15,11 +15,15 @@ pub trait Peel {
 // as we can just use treeish for that
 // furthermore, we often want the tree oid given an commit_oid
 // however, this is sort of subtle/arbitrary and probably not great design
+// annotated tags are peeled through to the commit they point at
 impl Peel for Oid {
     type Peeled = Arc<Commit>;
 
     fn peel(&self, repo: &BitRepo) -> BitResult<Self::Peeled> {
-        repo.read_obj(*self)?.try_into_commit()
+        match repo.read_obj(*self)? {
+            BitObjKind::Tag(tag) => tag.peel(repo)?.try_into_commit(),
+            obj => obj.try_into_commit(),
+        }
     }
 }
 
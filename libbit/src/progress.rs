@@ -0,0 +1,143 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Long running operations (fetching, indexing packs and checking out) report their progress to
+// the `ProgressSink` in their options. The sink does nothing unless it is given a `Progress`
+// implementation (e.g. the progress bars that the cli renders).
+
+/// A phase of a long running operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressPhase {
+    /// reading the objects that are to be packed
+    Counting,
+    /// receiving a pack from the remote
+    Receiving,
+    /// indexing a pack, which expands the deltas within it
+    ResolvingDeltas,
+    /// writing files to the worktree
+    CheckingOut,
+}
+
+impl Display for ProgressPhase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgressPhase::Counting => write!(f, "Counting objects"),
+            ProgressPhase::Receiving => write!(f, "Receiving objects"),
+            ProgressPhase::ResolvingDeltas => write!(f, "Resolving deltas"),
+            ProgressPhase::CheckingOut => write!(f, "Checking out files"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProgressUpdate {
+    pub phase: ProgressPhase,
+    /// the number of objects (or files) done so far
+    pub done: u64,
+    /// the total number of objects (or files) if it is known
+    pub total: Option<u64>,
+    /// the number of bytes transferred so far (only when receiving)
+    pub bytes: u64,
+    /// the time since the phase began
+    pub elapsed: Duration,
+}
+
+impl ProgressUpdate {
+    /// Bytes per second since the phase began
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 { 0.0 } else { self.bytes as f64 / secs }
+    }
+
+    pub fn percentage(&self) -> Option<u64> {
+        self.total.map(|total| if total == 0 { 100 } else { self.done * 100 / total })
+    }
+}
+
+/// Receives the progress of long running operations.
+/// Updates may be very frequent so implementations should throttle anything expensive.
+pub trait Progress: Send + Sync {
+    fn update(&self, update: &ProgressUpdate);
+
+    /// The phase is complete, `update` is its final state
+    fn finish(&self, update: &ProgressUpdate) {
+        self.update(update)
+    }
+
+    /// A progress message from the remote (e.g. `Counting objects: 50% (1/2)\r`) which may end
+    /// with either `\r` or `\n`
+    fn remote_message(&self, _message: &str) {}
+}
+
+/// Where to report progress to, the default sink reports nothing
+#[derive(Clone, Default)]
+pub struct ProgressSink(Option<Arc<dyn Progress>>);
+
+impl Debug for ProgressSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ProgressSink").field(&self.0.as_ref().map(|_| "..")).finish()
+    }
+}
+
+impl ProgressSink {
+    pub fn new(progress: impl Progress + 'static) -> Self {
+        Self(Some(Arc::new(progress)))
+    }
+
+    /// Start reporting `phase` which consists of `total` objects (or files) if that is known
+    pub fn begin(&self, phase: ProgressPhase, total: Option<u64>) -> PhaseProgress {
+        let update =
+            ProgressUpdate { phase, done: 0, total, bytes: 0, elapsed: Duration::default() };
+        let phase = PhaseProgress { progress: self.0.clone(), update, start: Instant::now() };
+        phase.report();
+        phase
+    }
+
+    pub fn remote_message(&self, message: &str) {
+        if let Some(progress) = &self.0 {
+            progress.remote_message(message)
+        }
+    }
+}
+
+/// The progress of the current phase, which is reported on each change
+pub struct PhaseProgress {
+    progress: Option<Arc<dyn Progress>>,
+    update: ProgressUpdate,
+    start: Instant,
+}
+
+impl PhaseProgress {
+    pub fn set_total(&mut self, total: u64) {
+        self.update.total = Some(total);
+        self.report();
+    }
+
+    /// One more object (or file) is done
+    pub fn inc(&mut self) {
+        self.update.done += 1;
+        self.report();
+    }
+
+    pub fn add_bytes(&mut self, bytes: usize) {
+        self.update.bytes += bytes as u64;
+        self.report();
+    }
+
+    pub fn finish(mut self) {
+        if let Some(progress) = &self.progress {
+            self.update.elapsed = self.start.elapsed();
+            progress.finish(&self.update);
+        }
+    }
+
+    fn report(&self) {
+        if let Some(progress) = &self.progress {
+            progress.update(&ProgressUpdate { elapsed: self.start.elapsed(), ..self.update });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,130 @@
+use super::*;
+use crate::checkout::CheckoutOpts;
+use crate::error::BitResult;
+use crate::pack::{PackBuilder, PackIndexer};
+use crate::remote::FetchOpts;
+use crate::repo::BitRepo;
+use parking_lot::Mutex;
+
+/// Records the final state of each phase and whether anything came from the remote
+#[derive(Clone, Default)]
+struct RecordedProgress {
+    finished: Arc<Mutex<Vec<ProgressUpdate>>>,
+    remote_messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl Progress for RecordedProgress {
+    fn update(&self, update: &ProgressUpdate) {
+        assert!(update.total.map_or(true, |total| update.done <= total));
+    }
+
+    fn finish(&self, update: &ProgressUpdate) {
+        self.finished.lock().push(*update);
+    }
+
+    fn remote_message(&self, message: &str) {
+        self.remote_messages.lock().push(message.to_owned());
+    }
+}
+
+impl RecordedProgress {
+    fn finished(&self, phase: ProgressPhase) -> Vec<ProgressUpdate> {
+        self.finished.lock().iter().filter(|update| update.phase == phase).copied().collect()
+    }
+}
+
+#[test]
+fn test_clone_progress() -> BitResult<()> {
+    let remote = tempfile::tempdir()?;
+    BitRepo::init_load(remote.path(), |repo| {
+        mkdir!(repo: "dir");
+        touch!(repo: "foo" < "foo");
+        touch!(repo: "dir/bar" < "bar");
+        bit_commit_all!(repo);
+        Ok(())
+    })?;
+
+    let progress = RecordedProgress::default();
+    let local = tempfile::tempdir()?;
+    let opts = FetchOpts { progress: ProgressSink::new(progress.clone()), ..Default::default() };
+    BitRepo::clone_with_opts_blocking(local.path(), remote.path().to_str().unwrap(), opts)?;
+
+    let receiving = progress.finished(ProgressPhase::Receiving);
+    assert_eq!(receiving.len(), 1);
+    assert!(receiving[0].bytes > 0);
+
+    // a commit, two trees and two blobs
+    let resolving = progress.finished(ProgressPhase::ResolvingDeltas);
+    assert_eq!(resolving.len(), 1);
+    assert_eq!((resolving[0].done, resolving[0].total), (5, Some(5)));
+
+    let checking_out = progress.finished(ProgressPhase::CheckingOut);
+    assert_eq!(checking_out.len(), 1);
+    assert_eq!((checking_out[0].done, checking_out[0].total), (2, Some(2)));
+    Ok(())
+}
+
+#[test]
+fn test_checkout_progress() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let master = repo.fully_resolve_head()?;
+        let n = repo.index()?.len() as u64;
+        bit_checkout!(repo: &rev!(commit! {}))?;
+
+        let progress = RecordedProgress::default();
+        let opts =
+            CheckoutOpts { progress: ProgressSink::new(progress.clone()), ..Default::default() };
+        repo.checkout_tree_with_opts(master, opts)?;
+        let checking_out = progress.finished(ProgressPhase::CheckingOut);
+        assert_eq!(checking_out.len(), 1);
+        assert_eq!((checking_out[0].done, checking_out[0].total), (n, Some(n)));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_pack_progress() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let objects = repo.reachable_objects(&[head], &[])?;
+        let n = objects.len() as u64;
+
+        let progress = RecordedProgress::default();
+        let mut pack = vec![];
+        PackBuilder::new(repo, objects)
+            .progress(ProgressSink::new(progress.clone()))
+            .write(&mut pack)?;
+        PackIndexer::new(&pack[..])?.progress(ProgressSink::new(progress.clone())).index_pack()?;
+
+        let counting = progress.finished(ProgressPhase::Counting);
+        assert_eq!((counting[0].done, counting[0].total), (n, Some(n)));
+        let resolving = progress.finished(ProgressPhase::ResolvingDeltas);
+        assert_eq!((resolving[0].done, resolving[0].total), (n, Some(n)));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_silent_progress_sink() {
+    // there's nothing to report to but it shouldn't matter to the caller
+    let mut phase = ProgressSink::default().begin(ProgressPhase::Receiving, None);
+    phase.add_bytes(42);
+    phase.inc();
+    phase.finish();
+}
+
+#[test]
+fn test_progress_update() {
+    let update = ProgressUpdate {
+        phase: ProgressPhase::Receiving,
+        done: 1,
+        total: Some(4),
+        bytes: 3000,
+        elapsed: Duration::from_secs(2),
+    };
+    assert_eq!(update.percentage(), Some(25));
+    assert_eq!(update.throughput(), 1500.0);
+    assert_eq!(ProgressUpdate { total: None, ..update }.percentage(), None);
+    assert_eq!(ProgressUpdate { total: Some(0), done: 0, ..update }.percentage(), Some(100));
+    assert_eq!(ProgressUpdate { elapsed: Duration::ZERO, ..update }.throughput(), 0.0);
+}
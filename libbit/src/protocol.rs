@@ -1,18 +1,26 @@
-use crate::error::BitResult;
+use crate::error::{BitError, BitResult};
+use crate::hash::OID_SIZE;
 use crate::obj::Oid;
-use crate::pack::{PackIndexer, PackWriter, PACK_EXT, PACK_IDX_EXT};
+use crate::pack::{BitPackObjType, PackBuilder, PackWriter, PACK_SIGNATURE};
+use crate::progress::{ProgressPhase, ProgressSink};
 use crate::refs::SymbolicRef;
 use crate::repo::BitRepo;
 use async_trait::async_trait;
+use flate2::{Decompress, FlushDecompress, Status};
 use parse_display::{Display, FromStr};
-use std::collections::HashSet;
-use tokio::io::{self, AsyncBufRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::collections::{HashMap, HashSet};
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 pub type Capabilities = HashSet<Capability>;
 
-const SIDEBAND_DATA: u8 = 1;
-const SIDEBAND_PROGRESS: u8 = 2;
-const SIDEBAND_ERROR: u8 = 3;
+/// the value of the `agent` capability that we send
+pub(crate) const AGENT: &str = concat!("bit/", env!("CARGO_PKG_VERSION"));
+
+pub(crate) const SIDEBAND_DATA: u8 = 1;
+pub(crate) const SIDEBAND_PROGRESS: u8 = 2;
+pub(crate) const SIDEBAND_ERROR: u8 = 3;
+/// the maximum packet length with `side-band-64k` is 65520 (including the length and sideband bytes)
+const SIDEBAND_64K_MAX_DATA_LEN: usize = 65515;
 
 #[derive(Debug, Display, FromStr, Hash, PartialEq, Eq)]
 #[display(style = "kebab-case")]
@@ -43,6 +51,70 @@ pub enum Capability {
     AllowTipSha1InWant,
     AllowReachableSha1InWant,
     Filter,
+    // the following capabilities are specific to `receive-pack`
+    ReportStatus,
+    #[display("report-status-v2")]
+    ReportStatusV2,
+    DeleteRefs,
+    Quiet,
+    Atomic,
+    PushOptions,
+}
+
+/// The capabilities advertised by a protocol v2 server.
+/// Each capability may have a value (e.g. `fetch=shallow filter` or `agent=git/2.39.0`).
+#[derive(Debug, Default)]
+pub struct CapabilitiesV2 {
+    capabilities: HashMap<String, Option<String>>,
+}
+
+impl CapabilitiesV2 {
+    pub fn contains(&self, key: &str) -> bool {
+        self.capabilities.contains_key(key)
+    }
+
+    /// The value of the capability `key` (`None` if it is not advertised or has no value)
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.capabilities.get(key)?.as_deref()
+    }
+
+    /// Whether `command` supports `feature`, e.g. `fetch=shallow wait-for-done`
+    pub fn command_supports(&self, command: &str, feature: &str) -> bool {
+        self.value(command).map_or(false, |features| features.split(' ').any(|f| f == feature))
+    }
+
+    // capability     =  key ["=" value]
+    // key            =  1*(ALPHA | DIGIT | "-_")
+    // value          =  1*(ALPHA | DIGIT | " -_.,?\/{}[]()<>!@#$%^&*+=:;")
+    fn parse_capability(line: &str) -> BitResult<(String, Option<String>)> {
+        // the value may contain `=` itself so only the first one separates the key
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (line, None),
+        };
+        ensure!(
+            !key.is_empty()
+                && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'),
+            "invalid capability `{}`",
+            line
+        );
+        ensure!(
+            value.map_or(true, |value| !value.is_empty()),
+            "empty value for capability `{}`",
+            key
+        );
+        Ok((key.to_owned(), value.map(str::to_owned)))
+    }
+}
+
+/// A pkt-line including the special packets that only exist in protocol v2
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    Flush,
+    /// separates the sections of a command and its response
+    Delim,
+    ResponseEnd,
+    Data(Vec<u8>),
 }
 
 // 0103f1b89a201e9329e6df48f8d6cf320781570c936a HEADmulti_ack thin-pack side-band side-band-64k ofs-delta shallow deepen-since deepen-not deepen-relative no-progress include-tag multi_ack_detailed symref=HEAD:refs/heads/main object-format=sha
@@ -66,13 +138,46 @@ pub trait BitProtocolRead: AsyncBufRead + Unpin + Send {
         }
         let mut contents = vec![0; n - 4];
         assert_eq!(self.read_exact(&mut contents).await?, n - 4);
+        // the remote may give up at any point by sending an error line instead of what was expected
+        //   error-line     =  PKT-LINE("ERR" SP explanation-text)
+        if let Some(msg) = contents.strip_prefix(b"ERR ") {
+            bail!(BitError::RemoteErr(String::from_utf8_lossy(msg).trim_end().to_owned()))
+        }
         Ok(contents)
     }
 
+    /// Receive a packet which may be one of the special packets of protocol v2
+    async fn recv_v2_packet(&mut self) -> BitResult<Packet> {
+        let mut length = [0; 4];
+        self.read_exact(&mut length).await?;
+        let n = usize::from_str_radix(std::str::from_utf8(&length)?, 16)?;
+        match n {
+            0 => Ok(Packet::Flush),
+            1 => Ok(Packet::Delim),
+            2 => Ok(Packet::ResponseEnd),
+            3 => bail!("invalid packet length `{}`", n),
+            _ => Ok(Packet::Data(self.read_contents_with_parsed_len(n).await?)),
+        }
+    }
+
+    /// Receive the capability advertisement of a protocol v2 server (after the `version 2` line)
+    async fn recv_v2_capabilities(&mut self) -> BitResult<CapabilitiesV2> {
+        let mut capabilities = HashMap::new();
+        loop {
+            let packet = self.recv_packet().await?;
+            if packet.is_empty() {
+                break Ok(CapabilitiesV2 { capabilities });
+            }
+            let line = std::str::from_utf8(&packet)?;
+            let (key, value) = CapabilitiesV2::parse_capability(line.trim_end_matches('\n'))?;
+            capabilities.insert(key, value);
+        }
+    }
+
     /// Start receiving the pack file in protocol v1.
     /// This will consume any remaining ACK/NAK.
     /// Assumes `side-band-64k` capability.
-    async fn recv_pack(&mut self, repo: BitRepo) -> BitResult<()> {
+    async fn recv_pack(&mut self, repo: BitRepo, progress: &ProgressSink) -> BitResult<()> {
         let mut writer = PackWriter::new(&repo).await?;
         let mut packet = self.recv_packet().await?;
 
@@ -84,6 +189,7 @@ pub trait BitProtocolRead: AsyncBufRead + Unpin + Send {
             }
         }
 
+        let mut receiving = progress.begin(ProgressPhase::Receiving, None);
         loop {
             if packet.is_empty() {
                 break;
@@ -91,31 +197,100 @@ pub trait BitProtocolRead: AsyncBufRead + Unpin + Send {
 
             let (sideband, data) = packet[..].split_first().unwrap();
             match *sideband {
-                SIDEBAND_DATA => writer.write_all(data).await?,
-                SIDEBAND_PROGRESS => eprint!("{}", std::str::from_utf8(data)?),
-                SIDEBAND_ERROR => todo!(),
+                SIDEBAND_DATA => {
+                    writer.write_all(data).await?;
+                    receiving.add_bytes(data.len());
+                }
+                SIDEBAND_PROGRESS => progress.remote_message(&String::from_utf8_lossy(data)),
+                SIDEBAND_ERROR => {
+                    // don't leave the partial pack lying around
+                    writer.abort()?;
+                    let msg = String::from_utf8_lossy(data).trim_end().to_owned();
+                    bail!(BitError::RemoteSidebandError(msg))
+                }
                 _ => bail!("invalid sideband byte `{:x}`", sideband),
             }
 
             packet = self.recv_packet().await?;
         }
-        writer.flush().await?;
-
-        let pack_index = PackIndexer::write_pack_index(&writer.path, Default::default())?;
-        std::fs::rename(
-            &writer.path,
-            writer.path.with_file_name(format!("pack-{}.{}", pack_index.pack_hash, PACK_EXT)),
-        )?;
-        std::fs::rename(
-            writer.path.with_extension(PACK_IDX_EXT),
-            writer.path.with_file_name(format!("pack-{}.{}", pack_index.pack_hash, PACK_IDX_EXT)),
-        )?;
-
-        // Refresh pack odb to include the newly written pack
-        repo.refresh_odb()?;
+        receiving.finish();
+        writer.finish(&repo, progress).await?;
         Ok(())
     }
 
+    /// Reads a raw (non-sideband) pack from the stream, copying it into `pack` as it arrives,
+    /// and returns the number of objects in the pack.
+    /// The other end doesn't necessarily close the connection after sending the pack,
+    /// so the pack is parsed just enough to tell where it ends.
+    async fn recv_raw_pack(
+        &mut self,
+        pack: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> BitResult<u32> {
+        let mut header = [0; 12];
+        self.read_exact(&mut header).await?;
+        ensure!(&header[..4] == PACK_SIGNATURE, "invalid pack signature");
+        pack.write_all(&header).await?;
+        let objectc = u32::from_be_bytes(header[8..12].try_into().unwrap());
+
+        for _ in 0..objectc {
+            // the type and the size of the object are encoded as a varint
+            let mut byte = self.recv_pack_byte(pack).await?;
+            let obj_type = BitPackObjType::try_from_u8((byte >> 4) & 0x07)?;
+            while byte & 0x80 != 0 {
+                byte = self.recv_pack_byte(pack).await?;
+            }
+
+            match obj_type {
+                BitPackObjType::OfsDelta => while self.recv_pack_byte(pack).await? & 0x80 != 0 {},
+                BitPackObjType::RefDelta => {
+                    let mut base = [0; OID_SIZE];
+                    self.read_exact(&mut base).await?;
+                    pack.write_all(&base).await?;
+                }
+                _ => {}
+            }
+            self.recv_zlib_stream(pack).await?;
+        }
+
+        let mut hash = [0; OID_SIZE];
+        self.read_exact(&mut hash).await?;
+        pack.write_all(&hash).await?;
+        Ok(objectc)
+    }
+
+    async fn recv_pack_byte(
+        &mut self,
+        pack: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> BitResult<u8> {
+        let byte = self.read_u8().await?;
+        pack.write_u8(byte).await?;
+        Ok(byte)
+    }
+
+    /// Reads exactly one zlib stream, copying the compressed bytes into `pack`
+    async fn recv_zlib_stream(
+        &mut self,
+        pack: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> BitResult<()> {
+        let mut decompress = Decompress::new(true);
+        // the decompressed data is only needed to find the end of the stream
+        let mut out = vec![0; 8192];
+        loop {
+            let buf = self.fill_buf().await?;
+            ensure!(!buf.is_empty(), "unexpected end of pack");
+            let before = decompress.total_in();
+            let status = decompress.decompress(buf, &mut out, FlushDecompress::None)?;
+            let consumed = (decompress.total_in() - before) as usize;
+            pack.write_all(&buf[..consumed]).await?;
+            self.consume(consumed);
+            match status {
+                Status::StreamEnd => break Ok(()),
+                Status::BufError if consumed == 0 => bail!("corrupt zlib stream in pack"),
+                Status::Ok | Status::BufError => {}
+            }
+        }
+    }
+
     /// Receive a message which is a collection of packets deliminated by a flush packet.
     async fn recv_message(&mut self) -> BitResult<Vec<Vec<u8>>> {
         let mut packets = vec![];
@@ -149,6 +324,60 @@ pub trait BitProtocolWrite: AsyncWrite + Unpin + Send {
         self.flush().await
     }
 
+    /// Writes `bytes` to sideband `band`, splitting it over as many packets as necessary.
+    /// Assumes `side-band-64k` capability.
+    async fn write_sideband(&mut self, band: u8, bytes: &[u8]) -> io::Result<()> {
+        for chunk in bytes.chunks(SIDEBAND_64K_MAX_DATA_LEN) {
+            let mut packet = Vec::with_capacity(1 + chunk.len());
+            packet.push(band);
+            packet.extend_from_slice(chunk);
+            self.write_packet(&packet).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the pack built by `builder` as it's built (over sideband 1 if `sideband`)
+    async fn write_pack(&mut self, builder: PackBuilder, sideband: bool) -> BitResult<()> {
+        let (mut pack, building) = builder.stream();
+        let sent = async {
+            while let Some(chunk) = pack.recv().await {
+                if sideband {
+                    self.write_sideband(SIDEBAND_DATA, &chunk).await?;
+                } else {
+                    self.write_all(&chunk).await?;
+                }
+            }
+            io::Result::Ok(())
+        }
+        .await;
+        // stops the builder if sending failed
+        drop(pack);
+        building.await??;
+        Ok(sent?)
+    }
+
+    /// Lets the other end know why we are giving up (instead of just hanging up)
+    async fn write_error_line(&mut self, msg: &str) -> io::Result<()> {
+        self.write_packet(format!("ERR {}\n", msg).as_bytes()).await?;
+        self.flush().await
+    }
+
+    #[inline]
+    async fn write_delim_packet(&mut self) -> io::Result<()> {
+        self.write_all(b"0001").await
+    }
+
+    /// Writes a protocol v2 command with the given arguments (each of which should end in `\n`)
+    async fn write_v2_command(&mut self, command: &str, args: &[String]) -> io::Result<()> {
+        self.write_packet(format!("command={}\n", command).as_bytes()).await?;
+        self.write_packet(format!("agent={}\n", AGENT).as_bytes()).await?;
+        self.write_delim_packet().await?;
+        for arg in args {
+            self.write_packet(arg.as_bytes()).await?;
+        }
+        self.write_flush_packet().await
+    }
+
     async fn want(&mut self, oid: Oid) -> io::Result<()> {
         self.write_packet(format!("want {oid}\n").as_bytes()).await
     }
@@ -0,0 +1,546 @@
+use crate::checkout::CheckoutOpts;
+use crate::error::BitResult;
+use crate::index::{Conflicts, MergeStage};
+use crate::iter::BitEntry;
+use crate::merge::MergeResults;
+use crate::obj::{BitObject, CommitMessage, Oid};
+use crate::path::BitPath;
+use crate::pathspec::Pathspec;
+use crate::peel::Peel;
+use crate::refs::{BitRef, RefUpdateCause, SymbolicRef};
+use crate::repo::{BitRepo, RepoState};
+use fallible_iterator::FallibleIterator;
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+// The state of an in progress rebase lives in `.git/rebase-merge` using the same files as git
+const HEAD_NAME: &str = "head-name";
+const ONTO: &str = "onto";
+const ORIG_HEAD: &str = "orig-head";
+const TODO: &str = "git-rebase-todo";
+const DONE: &str = "done";
+const INTERACTIVE: &str = "interactive";
+// the commit we stopped at due to conflicts or an `edit`
+const STOPPED_SHA: &str = "stopped-sha";
+// exists iff we stopped due to an `edit` (and so `HEAD` should be amended on `--continue`)
+const AMEND: &str = "amend";
+
+const DETACHED_HEAD: &str = "detached HEAD";
+
+const TODO_HELP: &str = "
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\", but discard this commit's log message
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+# If you remove a line here THAT COMMIT WILL BE LOST.
+# However, if you remove everything, the rebase will be aborted.
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebaseAction {
+    Pick,
+    Reword,
+    Edit,
+    Squash,
+    Fixup,
+    Drop,
+}
+
+impl RebaseAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RebaseAction::Pick => "pick",
+            RebaseAction::Reword => "reword",
+            RebaseAction::Edit => "edit",
+            RebaseAction::Squash => "squash",
+            RebaseAction::Fixup => "fixup",
+            RebaseAction::Drop => "drop",
+        }
+    }
+
+    /// whether the commit is melded into the previous one
+    fn is_squash(self) -> bool {
+        matches!(self, RebaseAction::Squash | RebaseAction::Fixup)
+    }
+}
+
+impl FromStr for RebaseAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let action = match s {
+            "p" | "pick" => RebaseAction::Pick,
+            "r" | "reword" => RebaseAction::Reword,
+            "e" | "edit" => RebaseAction::Edit,
+            "s" | "squash" => RebaseAction::Squash,
+            "f" | "fixup" => RebaseAction::Fixup,
+            "d" | "drop" => RebaseAction::Drop,
+            _ => bail!("unknown rebase command `{}`", s),
+        };
+        Ok(action)
+    }
+}
+
+impl Display for RebaseAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A line of the todo list
+#[derive(Debug, Clone, PartialEq)]
+pub struct RebaseStep {
+    pub action: RebaseAction,
+    pub commit: Oid,
+    /// only there to help the user (it is ignored when parsing)
+    pub subject: String,
+}
+
+impl Display for RebaseStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.action, self.commit.short(), self.subject)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RebaseOpts {
+    /// replay the commits onto `onto` rather than onto `upstream`
+    pub onto: Option<Oid>,
+    /// open the todo list in the editor before starting
+    pub interactive: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RebaseResults {
+    /// `HEAD` is already based on `onto`
+    UpToDate,
+    /// every commit was replayed and the branch has been moved to the result
+    Done,
+    /// stopped as `commit` could not be applied cleanly
+    Conflicts { commit: Oid, conflicts: Conflicts },
+    /// stopped as requested by an `edit` of `commit`
+    Edit { commit: Oid },
+}
+
+#[derive(Debug)]
+struct RebaseState {
+    dir: BitPath,
+    /// the branch that is being rebased (`None` if `HEAD` was detached)
+    head_name: Option<SymbolicRef>,
+    onto: Oid,
+    orig_head: Oid,
+    interactive: bool,
+    todo: Vec<RebaseStep>,
+    done: Vec<RebaseStep>,
+}
+
+impl RebaseState {
+    fn load(repo: &BitRepo) -> BitResult<Self> {
+        ensure!(repo.repo_state() == RepoState::Rebasing, "no rebase in progress");
+        let dir = repo.rebase_dir();
+        let read = |name: &str| -> BitResult<String> {
+            Ok(std::fs::read_to_string(dir.join(name))?.trim_end().to_owned())
+        };
+        // git only creates `done` once the first step is taken
+        let done = if dir.join(DONE).exists() { read(DONE)? } else { String::new() };
+
+        let head_name = match read(HEAD_NAME)?.as_str() {
+            DETACHED_HEAD => None,
+            name => Some(SymbolicRef::intern_valid(name)?),
+        };
+        Ok(Self {
+            dir,
+            head_name,
+            onto: read(ONTO)?.parse()?,
+            orig_head: read(ORIG_HEAD)?.parse()?,
+            interactive: dir.join(INTERACTIVE).exists(),
+            todo: repo.parse_rebase_todo(&read(TODO)?)?,
+            done: repo.parse_rebase_todo(&done)?,
+        })
+    }
+
+    fn save(&self) -> BitResult<()> {
+        std::fs::create_dir_all(self.dir)?;
+        let head_name = match self.head_name {
+            Some(sym) => sym.path().as_str(),
+            None => DETACHED_HEAD,
+        };
+        std::fs::write(self.dir.join(HEAD_NAME), format!("{}\n", head_name))?;
+        std::fs::write(self.dir.join(ONTO), format!("{}\n", self.onto))?;
+        std::fs::write(self.dir.join(ORIG_HEAD), format!("{}\n", self.orig_head))?;
+        std::fs::write(self.dir.join(TODO), fmt_steps(&self.todo))?;
+        std::fs::write(self.dir.join(DONE), fmt_steps(&self.done))?;
+        if self.interactive {
+            std::fs::write(self.dir.join(INTERACTIVE), "")?;
+        }
+        Ok(())
+    }
+
+    fn stop_at(&self, commit: Oid, amend: bool) -> BitResult<()> {
+        std::fs::write(self.dir.join(STOPPED_SHA), format!("{}\n", commit))?;
+        if amend {
+            std::fs::write(self.dir.join(AMEND), "")?;
+        }
+        Ok(())
+    }
+
+    fn clear_stop(&self) -> BitResult<()> {
+        for name in [STOPPED_SHA, AMEND] {
+            let path = self.dir.join(name);
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the step we stopped at (which is the last step that was moved into `done`)
+    fn current_step(&self) -> Option<&RebaseStep> {
+        if self.dir.join(STOPPED_SHA).exists() { self.done.last() } else { None }
+    }
+}
+
+fn fmt_steps(steps: &[RebaseStep]) -> String {
+    steps.iter().map(|step| format!("{}\n", step)).collect()
+}
+
+impl BitRepo {
+    /// Replay the commits reachable from `HEAD` but not from `upstream` onto `opts.onto`
+    /// (defaulting to `upstream`) and then move the current branch to the result.
+    pub fn rebase(&self, upstream: Oid, opts: RebaseOpts) -> BitResult<RebaseResults> {
+        match self.repo_state() {
+            RepoState::None => {}
+            RepoState::Merging => bail!("cannot rebase while in the middle of a merge"),
+            RepoState::Rebasing =>
+                bail!("a rebase is already in progress (use `--continue`, `--skip`, or `--abort`)"),
+        }
+
+        let staged = self.diff_head_index(Pathspec::MATCH_ALL)?;
+        let unstaged = self.diff_index_worktree(Pathspec::MATCH_ALL)?;
+        ensure!(
+            staged.is_empty() && unstaged.modified.is_empty() && unstaged.deleted.is_empty(),
+            "cannot rebase as you have uncommitted changes, please commit or stash them"
+        );
+
+        let head_name = match self.read_head()? {
+            BitRef::Symbolic(sym) => Some(sym),
+            BitRef::Direct(..) => None,
+        };
+        let orig_head = self.fully_resolve_head()?;
+        let onto = opts.onto.unwrap_or(upstream);
+
+        // merge commits are not replayed (which linearizes the history) as with git
+        let commits = self
+            .revwalk_builder()
+            .roots_iter([orig_head])?
+            .excluding(smallvec![upstream.peel(self)?])
+            .build()?
+            .collect::<Vec<_>>()?;
+        let todo = commits
+            .iter()
+            .rev()
+            .filter(|commit| commit.parents.len() < 2)
+            .map(|commit| RebaseStep {
+                action: RebaseAction::Pick,
+                commit: commit.oid(),
+                subject: commit.message.subject.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        // replaying the commits would just recreate them exactly iff every pick is a
+        // fast-forward, i.e. they form a chain of single parent commits starting at `onto`
+        let mut base = onto;
+        for commit in commits.iter().rev() {
+            if commit.parents.as_slice() != [base] {
+                break;
+            }
+            base = commit.oid();
+        }
+        if base == orig_head && !opts.interactive {
+            return Ok(RebaseResults::UpToDate);
+        }
+
+        let mut state = RebaseState {
+            dir: self.rebase_dir(),
+            head_name,
+            onto,
+            orig_head,
+            interactive: opts.interactive,
+            todo,
+            done: vec![],
+        };
+        state.save()?;
+
+        if opts.interactive {
+            match self.edit_rebase_todo(&state) {
+                Ok(todo) if !todo.is_empty() => state.todo = todo,
+                Ok(..) => {
+                    self.rmdir_all(state.dir)?;
+                    bail!("nothing to do")
+                }
+                Err(err) => {
+                    self.rmdir_all(state.dir)?;
+                    return Err(err);
+                }
+            }
+            state.save()?;
+        }
+
+        // `reset --hard ORIG_HEAD` undoes the rebase (once it's finished) as with git
+        self.refdb()?.set_ref(SymbolicRef::ORIG_HEAD, BitRef::Direct(orig_head))?;
+
+        // checkout before moving `HEAD` as the checkout is relative to `HEAD`
+        self.checkout_tree_with_opts(onto, CheckoutOpts::default())?;
+        self.update_ref(
+            SymbolicRef::HEAD,
+            onto,
+            RefUpdateCause::Rebase { step: "start", message: format!("checkout {}", onto) },
+        )?;
+        self.run_rebase(state)
+    }
+
+    /// Continue a rebase that stopped, committing the (resolved) changes in the index first
+    pub fn rebase_continue(&self) -> BitResult<RebaseResults> {
+        let state = RebaseState::load(self)?;
+        ensure!(
+            !self.index()?.has_conflicts(),
+            "you must resolve all conflicts and add them to the index before continuing"
+        );
+
+        if let Some(step) = state.current_step().cloned() {
+            if state.dir.join(AMEND).exists() {
+                self.rebase_amend_head()?;
+            } else {
+                self.rebase_commit_index(&step)?;
+            }
+        }
+        state.clear_stop()?;
+        self.run_rebase(state)
+    }
+
+    /// Continue a rebase that stopped without applying the current commit
+    pub fn rebase_skip(&self) -> BitResult<RebaseResults> {
+        let state = RebaseState::load(self)?;
+        self.rebase_reset_hard()?;
+        state.clear_stop()?;
+        self.run_rebase(state)
+    }
+
+    /// Stop rebasing and restore the branch (and worktree) to how it was before the rebase
+    pub fn rebase_abort(&self) -> BitResult<()> {
+        let state = RebaseState::load(self)?;
+        self.rebase_reset_hard()?;
+        self.checkout_tree_with_opts(state.orig_head, CheckoutOpts::default())?;
+
+        // the branch itself only moves once the rebase is finished, so `HEAD` just needs to
+        // be pointed back at it
+        let (to, message) = match state.head_name {
+            Some(branch) => (BitRef::Symbolic(branch), format!("returning to {}", branch)),
+            None => (BitRef::Direct(state.orig_head), format!("returning to {}", state.orig_head)),
+        };
+        self.update_ref(SymbolicRef::HEAD, to, RefUpdateCause::Rebase { step: "abort", message })?;
+        self.rmdir_all(state.dir)
+    }
+
+    fn rebase_dir(&self) -> BitPath {
+        self.bitdir.join(BitPath::REBASE_MERGE)
+    }
+
+    fn run_rebase(&self, mut state: RebaseState) -> BitResult<RebaseResults> {
+        while !state.todo.is_empty() {
+            let step = state.todo.remove(0);
+            state.done.push(step.clone());
+            state.save()?;
+
+            if step.action == RebaseAction::Drop {
+                continue;
+            }
+
+            let head = self.fully_resolve_head()?;
+            let commit = self.read_obj_commit(step.commit)?;
+            let can_fast_forward = matches!(step.action, RebaseAction::Pick | RebaseAction::Edit)
+                && commit.parents.as_slice() == [head];
+
+            if can_fast_forward {
+                // no need to rewrite the commit as it already has the right parent
+                self.checkout_tree_with_opts(step.commit, CheckoutOpts::default())?;
+                self.rebase_update_head(step.action, step.commit, &step.subject)?;
+            } else {
+                match self.cherrypick_uncommitted(step.commit)? {
+                    MergeResults::Conflicts(conflicts) => {
+                        state.stop_at(step.commit, false)?;
+                        return Ok(RebaseResults::Conflicts { commit: step.commit, conflicts });
+                    }
+                    MergeResults::Merge(..) => self.rebase_commit_index(&step)?,
+                    results => bug!("unexpected cherrypick results `{:?}`", results),
+                }
+            }
+
+            if step.action == RebaseAction::Edit {
+                state.stop_at(step.commit, true)?;
+                return Ok(RebaseResults::Edit { commit: step.commit });
+            }
+        }
+        self.finish_rebase(state)
+    }
+
+    fn finish_rebase(&self, state: RebaseState) -> BitResult<RebaseResults> {
+        if let Some(branch) = state.head_name {
+            let head = self.fully_resolve_head()?;
+            let message = format!("{} onto {}", branch, state.onto);
+            self.update_ref(branch, head, RefUpdateCause::Rebase { step: "finish", message })?;
+            let message = format!("returning to {}", branch);
+            self.update_ref(
+                SymbolicRef::HEAD,
+                branch,
+                RefUpdateCause::Rebase { step: "finish", message },
+            )?;
+        }
+        self.rmdir_all(state.dir)?;
+        Ok(RebaseResults::Done)
+    }
+
+    /// Commit the index as the result of applying `step` on top of `HEAD`
+    fn rebase_commit_index(&self, step: &RebaseStep) -> BitResult<()> {
+        let original = self.read_obj_commit(step.commit)?;
+        let head = self.read_obj_commit(self.fully_resolve_head()?)?;
+        let tree = self.index_mut()?.write_tree()?;
+
+        let oid = if step.action.is_squash() {
+            let message = match step.action {
+                RebaseAction::Fixup => head.message.clone(),
+                _ => self.edit_rebase_message(&format!(
+                    "# This is a combination of 2 commits.\n\n{}\n\n{}",
+                    head.message, original.message
+                ))?,
+            };
+            if tree == head.tree_oid() && message == head.message {
+                // melding in the commit changes nothing so `HEAD` can be kept as is
+                return Ok(());
+            }
+            self.write_commit_with_author(tree, head.parents.clone(), message, head.author.clone())?
+        } else if tree == head.tree_oid() {
+            // the changes of the commit are already upstream so it's dropped
+            return Ok(());
+        } else {
+            let message = match step.action {
+                RebaseAction::Reword => self.edit_rebase_message(&original.message.to_string())?,
+                _ => original.message.clone(),
+            };
+            let parents = smallvec![head.oid()];
+            self.write_commit_with_author(tree, parents, message, original.author.clone())?
+        };
+        self.rebase_update_head(step.action, oid, &step.subject)
+    }
+
+    /// Amend `HEAD` with any changes staged while stopped for an `edit`
+    fn rebase_amend_head(&self) -> BitResult<()> {
+        let head = self.read_obj_commit(self.fully_resolve_head()?)?;
+        let tree = self.index_mut()?.write_tree()?;
+        if tree == head.tree_oid() {
+            return Ok(());
+        }
+        let oid = self.write_commit_with_author(
+            tree,
+            head.parents.clone(),
+            head.message.clone(),
+            head.author.clone(),
+        )?;
+        self.rebase_update_head(RebaseAction::Edit, oid, &head.message.subject)
+    }
+
+    fn rebase_update_head(&self, action: RebaseAction, to: Oid, subject: &str) -> BitResult<()> {
+        let cause = RefUpdateCause::Rebase { step: action.as_str(), message: subject.to_owned() };
+        self.update_ref(SymbolicRef::HEAD, to, cause)
+    }
+
+    /// Throw away any changes to the index and worktree, similar to `reset --hard HEAD`.
+    /// Unlike a forced checkout, untracked files are left alone.
+    fn rebase_reset_hard(&self) -> BitResult<()> {
+        let mut index = self.index_mut()?;
+        let tracked = index.entries().keys().map(|&(path, _)| path).collect::<BTreeSet<_>>();
+        index.read_tree(self.head_tree()?)?;
+
+        // remove the files that a (partially applied) commit added
+        for path in tracked {
+            if index.find_entry((path, MergeStage::NONE)).is_none() && self.path_exists(path)? {
+                self.rm(path)?;
+            }
+        }
+
+        let diff = index.diff_worktree(Pathspec::MATCH_ALL)?;
+        for entry in diff.modified.iter().map(|(old, _)| old).chain(&diff.deleted) {
+            if self.path_exists(entry.path)? {
+                self.rm(entry.path)?;
+            }
+            entry.write_to_disk(self)?;
+        }
+        Ok(())
+    }
+
+    fn edit_rebase_todo(&self, state: &RebaseState) -> BitResult<Vec<RebaseStep>> {
+        let path = state.dir.join(TODO);
+        let header = format!(
+            "\n# Rebase {}..{} onto {} ({} commands)\n#",
+            state.onto.short(),
+            state.orig_head.short(),
+            state.onto.short(),
+            state.todo.len()
+        );
+        std::fs::write(path, format!("{}{}{}", fmt_steps(&state.todo), header, TODO_HELP))?;
+        self.launch_editor(path)?;
+
+        let todo = self.parse_rebase_todo(&std::fs::read_to_string(path)?)?;
+        if let Some(step) = todo.iter().find(|step| step.action != RebaseAction::Drop) {
+            ensure!(!step.action.is_squash(), "cannot `{}` without a previous commit", step.action);
+        }
+        Ok(todo)
+    }
+
+    fn parse_rebase_todo(&self, s: &str) -> BitResult<Vec<RebaseStep>> {
+        s.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut parts = line.splitn(3, ' ');
+                let action = parts.next().unwrap().parse()?;
+                let id = match parts.next() {
+                    Some(id) => id,
+                    None => bail!("missing commit in rebase todo line `{}`", line),
+                };
+                let commit =
+                    if id.len() == 40 { id.parse()? } else { self.expand_prefix(id.parse()?)? };
+                self.ensure_obj_is_commit(commit)?;
+                let subject = parts.next().unwrap_or_default().to_owned();
+                Ok(RebaseStep { action, commit, subject })
+            })
+            .collect()
+    }
+
+    fn edit_rebase_message(&self, message: &str) -> BitResult<CommitMessage> {
+        let path = self.bitdir.join("COMMIT_EDITMSG");
+        let template = "# Please enter the commit message for your changes. Lines starting\n\
+                        # with '#' will be ignored, and an empty message aborts the commit.";
+        std::fs::write(path, format!("{}\n\n{}\n", message, template))?;
+        self.launch_editor(path)?;
+
+        let message = std::fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>()
+            .join("\n");
+        std::fs::remove_file(path)?;
+        let message = message.trim();
+        ensure!(!message.is_empty(), "aborting commit due to empty commit message");
+        message.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,311 @@
+use crate::commit::CommitOpts;
+use crate::error::BitResult;
+use crate::obj::Oid;
+use crate::rebase::{RebaseOpts, RebaseResults};
+use crate::refs::{BitRef, BitRefDbBackend, SymbolicRef};
+use crate::repo::{BitRepo, RepoState};
+
+fn commit_all_with_message(repo: &BitRepo, message: &str) -> BitResult<Oid> {
+    bit_add_all!(repo);
+    repo.commit(CommitOpts { message: Some(message.to_owned()), allow_empty: false })?;
+    repo.fully_resolve_head()
+}
+
+/// Configures the editor to replace whatever it is asked to edit with `contents`
+fn set_editor_output(repo: &BitRepo, contents: &str) -> BitResult<()> {
+    let path = repo.bitdir.join("editor-output");
+    std::fs::write(path, contents)?;
+    repo.with_raw_local_config(|config| config.set("core", "editor", format!("cp {}", path)))
+}
+
+fn master(repo: &BitRepo) -> BitResult<Oid> {
+    repo.fully_resolve_ref(SymbolicRef::MASTER)
+}
+
+/// Sets up `master` and `feature` branches that modify `foo` differently, with `feature` also
+/// adding `bar` in a second commit. `feature` is checked out.
+fn setup_conflicting_branches(repo: &BitRepo) -> BitResult<Oid> {
+    bit_branch!(repo: "feature");
+    modify!(repo: "foo" < "master foo");
+    bit_commit_all!(repo);
+
+    bit_checkout!(repo: "feature")?;
+    modify!(repo: "foo" < "feature foo");
+    bit_commit_all!(repo);
+    touch!(repo: "bar" < "bar");
+    bit_commit_all!(repo);
+    repo.fully_resolve_head()
+}
+
+#[test]
+fn test_simple_rebase() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        bit_checkout!(repo: -b "feature")?;
+        touch!(repo: "bar" < "bar");
+        let feature = commit_all_with_message(&repo, "add bar")?;
+
+        bit_checkout!(repo: "master")?;
+        touch!(repo: "baz" < "baz");
+        bit_commit_all!(repo);
+        let master = master(&repo)?;
+
+        bit_checkout!(repo: "feature")?;
+        assert_eq!(repo.rebase(master, RebaseOpts::default())?, RebaseResults::Done);
+
+        let feature_ref = SymbolicRef::new_branch("feature");
+        assert_eq!(repo.read_head()?, BitRef::Symbolic(feature_ref));
+        let head = repo.read_obj_commit(repo.fully_resolve_head()?)?;
+        assert_ne!(repo.fully_resolve_head()?, feature);
+        assert_eq!(head.parents.as_slice(), [master]);
+        assert_eq!(head.message.subject, "add bar");
+        assert_eq!(cat!(repo: "bar"), "bar");
+        assert_eq!(cat!(repo: "baz"), "baz");
+        assert!(bit_status!(repo).is_empty());
+        assert_eq!(repo.repo_state(), RepoState::None);
+
+        let reflog = repo.refdb()?.read_reflog(feature_ref)?;
+        assert_eq!(
+            reflog[0].message,
+            format!("rebase (finish): refs/heads/feature onto {}", master)
+        );
+
+        // the rebase can be undone
+        bit_reset!(repo: --hard "ORIG_HEAD");
+        assert_eq!(repo.fully_resolve_head()?, feature);
+        assert!(!repo.workdir.join("baz").exists());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_rebase_up_to_date() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        bit_checkout!(repo: -b "feature")?;
+        touch!(repo: "bar" < "bar");
+        let feature = commit_all_with_message(&repo, "add bar")?;
+
+        assert_eq!(repo.rebase(master(&repo)?, RebaseOpts::default())?, RebaseResults::UpToDate);
+        assert_eq!(repo.fully_resolve_head()?, feature);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_rebase_with_uncommitted_changes() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let master = master(&repo)?;
+        bit_checkout!(repo: -b "feature")?;
+        touch!(repo: "bar" < "bar");
+        bit_commit_all!(repo);
+        modify!(repo: "foo" < "uncommitted");
+        assert!(repo.rebase(master, RebaseOpts::default()).is_err());
+        assert_eq!(repo.repo_state(), RepoState::None);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_rebase_conflict_then_continue() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        setup_conflicting_branches(&repo)?;
+        let master = master(&repo)?;
+
+        let results = repo.rebase(master, RebaseOpts::default())?;
+        assert!(matches!(results, RebaseResults::Conflicts { .. }));
+        assert_eq!(repo.repo_state(), RepoState::Rebasing);
+        assert!(!bit_status!(repo).conflicted.is_empty());
+        // other commands should refuse to run while rebasing
+        assert!(bit_merge!(repo: "master").is_err());
+        assert!(repo.rebase(master, RebaseOpts::default()).is_err());
+        assert!(repo.rebase_continue().is_err());
+
+        modify!(repo: "foo" < "resolved foo");
+        bit_add!(repo: "foo");
+        assert_eq!(repo.rebase_continue()?, RebaseResults::Done);
+
+        assert_eq!(repo.repo_state(), RepoState::None);
+        assert_eq!(repo.fully_resolve_rev(&rev!("HEAD~2"))?, master);
+        assert_eq!(cat!(repo: "foo"), "resolved foo");
+        assert_eq!(cat!(repo: "bar"), "bar");
+        assert!(bit_status!(repo).is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_rebase_skip() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        setup_conflicting_branches(&repo)?;
+        let master = master(&repo)?;
+
+        let results = repo.rebase(master, RebaseOpts::default())?;
+        assert!(matches!(results, RebaseResults::Conflicts { .. }));
+        assert_eq!(repo.rebase_skip()?, RebaseResults::Done);
+
+        assert_eq!(repo.fully_resolve_rev(&rev!("HEAD^"))?, master);
+        assert_eq!(cat!(repo: "foo"), "master foo");
+        assert_eq!(cat!(repo: "bar"), "bar");
+        assert!(bit_status!(repo).is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_rebase_abort() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let feature = setup_conflicting_branches(&repo)?;
+        let results = repo.rebase(master(&repo)?, RebaseOpts::default())?;
+        assert!(matches!(results, RebaseResults::Conflicts { .. }));
+
+        repo.rebase_abort()?;
+        assert_eq!(repo.repo_state(), RepoState::None);
+        assert_eq!(repo.read_head()?, BitRef::Symbolic(SymbolicRef::new_branch("feature")));
+        assert_eq!(repo.fully_resolve_head()?, feature);
+        assert_eq!(cat!(repo: "foo"), "feature foo");
+        assert_eq!(cat!(repo: "bar"), "bar");
+        assert!(bit_status!(repo).is_empty());
+        assert!(repo.rebase_abort().is_err());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_rebase_onto() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let master = master(&repo)?;
+        bit_checkout!(repo: -b "base")?;
+        touch!(repo: "base" < "base");
+        let base = commit_all_with_message(&repo, "add base")?;
+
+        bit_checkout!(repo: -b "feature")?;
+        touch!(repo: "bar" < "bar");
+        commit_all_with_message(&repo, "add bar")?;
+
+        let opts = RebaseOpts { onto: Some(master), ..Default::default() };
+        assert_eq!(repo.rebase(base, opts)?, RebaseResults::Done);
+
+        let head = repo.read_obj_commit(repo.fully_resolve_head()?)?;
+        assert_eq!(head.parents.as_slice(), [master]);
+        assert_eq!(head.message.subject, "add bar");
+        assert!(!repo.workdir.join("base").exists());
+        assert_eq!(cat!(repo: "bar"), "bar");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_interactive_rebase() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let master = master(&repo)?;
+        bit_checkout!(repo: -b "feature")?;
+        touch!(repo: "a" < "a");
+        let a = commit_all_with_message(&repo, "add a")?;
+        touch!(repo: "b" < "b");
+        let b = commit_all_with_message(&repo, "add b")?;
+        modify!(repo: "a" < "fixed a");
+        let fix = commit_all_with_message(&repo, "fix a")?;
+        touch!(repo: "c" < "c");
+        let c = commit_all_with_message(&repo, "add c")?;
+
+        // reorder `a` and `b`, fold `fix` into `a`, and drop `c`
+        set_editor_output(
+            repo,
+            &format!("pick {}\n# a comment\np {}\nf {}\ndrop {}\n", b, a.short(), fix, c),
+        )?;
+        let opts = RebaseOpts { interactive: true, ..Default::default() };
+        assert_eq!(repo.rebase(master, opts)?, RebaseResults::Done);
+
+        let head = repo.read_obj_commit(repo.fully_resolve_head()?)?;
+        assert_eq!(head.message.subject, "add a");
+        let parent = repo.read_obj_commit(head.parents[0])?;
+        assert_eq!(parent.message.subject, "add b");
+        assert_eq!(parent.parents.as_slice(), [master]);
+
+        assert_eq!(cat!(repo: "a"), "fixed a");
+        assert_eq!(cat!(repo: "b"), "b");
+        assert!(!repo.workdir.join("c").exists());
+        assert!(bit_status!(repo).is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_interactive_rebase_edit() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let master = master(&repo)?;
+        bit_checkout!(repo: -b "feature")?;
+        touch!(repo: "a" < "a");
+        let a = commit_all_with_message(&repo, "add a")?;
+        touch!(repo: "b" < "b");
+        let b = commit_all_with_message(&repo, "add b")?;
+
+        set_editor_output(&repo, &format!("edit {}\npick {}\n", a, b))?;
+        let opts = RebaseOpts { interactive: true, ..Default::default() };
+        assert_eq!(repo.rebase(master, opts)?, RebaseResults::Edit { commit: a });
+        assert_eq!(repo.repo_state(), RepoState::Rebasing);
+
+        modify!(repo: "a" < "amended a");
+        bit_add!(repo: "a");
+        assert_eq!(repo.rebase_continue()?, RebaseResults::Done);
+
+        let head = repo.read_obj_commit(repo.fully_resolve_head()?)?;
+        assert_eq!(head.message.subject, "add b");
+        let parent = repo.read_obj_commit(head.parents[0])?;
+        assert_eq!(parent.message.subject, "add a");
+        assert_eq!(parent.parents.as_slice(), [master]);
+        assert_eq!(cat!(repo: "a"), "amended a");
+        assert!(bit_status!(repo).is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_interactive_rebase_empty_todo() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let master = master(&repo)?;
+        bit_checkout!(repo: -b "feature")?;
+        touch!(repo: "a" < "a");
+        let a = commit_all_with_message(&repo, "add a")?;
+
+        set_editor_output(&repo, "# nothing\n")?;
+        let opts = RebaseOpts { interactive: true, ..Default::default() };
+        assert!(repo.rebase(master, opts).is_err());
+        assert_eq!(repo.repo_state(), RepoState::None);
+        assert_eq!(repo.fully_resolve_head()?, a);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_interactive_rebase_noop_fixup_keeps_commit() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let master = master(&repo)?;
+        bit_checkout!(repo: -b "feature")?;
+        touch!(repo: "a" < "a");
+        let a = commit_all_with_message(&repo, "add a")?;
+        repo.commit(CommitOpts { message: Some("empty".to_owned()), allow_empty: true })?;
+        let empty = repo.fully_resolve_head()?;
+
+        set_editor_output(&repo, &format!("pick {}\nfixup {}\n", a, empty))?;
+        let opts = RebaseOpts { interactive: true, ..Default::default() };
+        assert_eq!(repo.rebase(master, opts)?, RebaseResults::Done);
+        assert_eq!(repo.fully_resolve_head()?, a);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_rebase_abort_without_done_file() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let feature = setup_conflicting_branches(&repo)?;
+        let results = repo.rebase(master(&repo)?, RebaseOpts::default())?;
+        assert!(matches!(results, RebaseResults::Conflicts { .. }));
+
+        // git does not write `done` until the first step is taken
+        std::fs::remove_file(repo.bitdir.join("rebase-merge/done"))?;
+        repo.rebase_abort()?;
+        assert_eq!(repo.repo_state(), RepoState::None);
+        assert_eq!(repo.fully_resolve_head()?, feature);
+        Ok(())
+    })
+}
@@ -0,0 +1,362 @@
+use crate::error::{BitError, BitResultExt};
+use crate::obj::{BitObjType, Oid};
+use crate::pack::PackWriter;
+use crate::progress::ProgressSink;
+use crate::protocol::{BitProtocolRead, BitProtocolWrite, Capabilities, Capability, AGENT};
+use crate::refs::{self, BitRef, RefCas, RefUpdateCause, SymbolicRef};
+use anyhow::Result;
+use libbit::repo::BitRepo;
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt};
+
+/// `receive.denyCurrentBranch`: what to do with a push that updates the checked out branch of a
+/// non-bare repository (which leaves the index and worktree out of sync with `HEAD`)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DenyCurrentBranch {
+    Refuse,
+    Warn,
+    Ignore,
+}
+
+/// a single `<old> <new> <ref>` line of the command list the client sends
+#[derive(Debug)]
+struct RefCommand {
+    old: Oid,
+    new: Oid,
+    refname: String,
+    /// the reason the command was rejected (sent back to the client with `ng`)
+    error: Option<String>,
+}
+
+impl RefCommand {
+    fn sym(&self) -> SymbolicRef {
+        SymbolicRef::intern(&self.refname)
+    }
+
+    fn is_delete(&self) -> bool {
+        self.new.is_unknown()
+    }
+
+    fn cas(&self) -> RefCas {
+        RefCas { sym: self.sym(), old: self.old, new: self.new }
+    }
+}
+
+// https://github.com/git/git/blob/master/Documentation/technical/pack-protocol.txt
+// (see "Pushing Data To a Server")
+pub struct ReceivePack<R, W> {
+    repo: BitRepo,
+    reader: R,
+    writer: W,
+    /// the capabilities the client requested (only those we recognize)
+    capabilities: Capabilities,
+}
+
+impl<R, W> ReceivePack<R, W>
+where
+    R: BitProtocolRead,
+    W: BitProtocolWrite,
+{
+    pub fn new(repo: BitRepo, reader: R, writer: W) -> Self {
+        Self { repo, reader, writer, capabilities: Default::default() }
+    }
+
+    #[tokio::main]
+    pub async fn run(&mut self) -> Result<()> {
+        self.write_ref_discovery().await?;
+        let mut commands = self.recv_commands().await?;
+        // the client is up to date or was only interested in the refs
+        if commands.is_empty() {
+            return Ok(());
+        }
+
+        // a pack is sent unless every command is a deletion
+        let unpack_result = if commands.iter().all(RefCommand::is_delete) {
+            Ok(())
+        } else {
+            self.recv_pack().await
+        };
+
+        match &unpack_result {
+            Ok(()) => self.update_refs(&mut commands)?,
+            Err(..) =>
+                for command in &mut commands {
+                    command.error = Some("unpacker error".to_owned());
+                },
+        }
+
+        if self.capabilities.contains(&Capability::ReportStatus) {
+            self.write_report_status(&unpack_result, &commands).await?;
+        }
+        unpack_result
+    }
+
+    fn capabilities(&self) -> Vec<Capability> {
+        vec![
+            Capability::ReportStatus,
+            Capability::DeleteRefs,
+            Capability::OfsDelta,
+            Capability::Atomic,
+            Capability::Agent(AGENT.to_owned()),
+        ]
+    }
+
+    /// Unlike `upload-pack`, `HEAD` and peeled tags are not advertised as they can't be pushed to
+    async fn write_ref_discovery(&mut self) -> Result<()> {
+        let mut refs = self.repo.ls_refs()?.into_iter().collect::<Vec<_>>();
+        refs.sort_by_key(|r| r.path());
+
+        let capabilities = self.capabilities().iter().map(ToString::to_string).collect::<Vec<_>>();
+        let mut capabilities = Some(capabilities.join(" "));
+        for r in refs {
+            if r == SymbolicRef::HEAD {
+                continue;
+            }
+            let oid = match self.repo.try_fully_resolve_ref(r)? {
+                Some(oid) => oid,
+                None => continue,
+            };
+            match capabilities.take() {
+                Some(capabilities) =>
+                    self.write(format!("{} {}\0{}\n", oid, r.path(), capabilities)).await?,
+                None => self.write(format!("{} {}\n", oid, r.path())).await?,
+            }
+        }
+
+        // the capabilities still need to be sent if there are no refs
+        if let Some(capabilities) = capabilities {
+            self.write(format!("{} capabilities^{{}}\0{}\n", Oid::UNKNOWN, capabilities)).await?;
+        }
+        Ok(self.writer.write_flush_packet().await?)
+    }
+
+    // update-requests = *shallow ( command-list | push-cert )
+    // command-list    = PKT-LINE(command NUL capability-list) *PKT-LINE(command) flush-pkt
+    // command         = create / delete / update
+    async fn recv_commands(&mut self) -> Result<Vec<RefCommand>> {
+        let mut commands = vec![];
+        // the client may just hang up after reading the refs
+        if self.reader.fill_buf().await?.is_empty() {
+            return Ok(commands);
+        }
+
+        loop {
+            let packet = self.reader.recv_packet().await?;
+            if packet.is_empty() {
+                break Ok(commands);
+            }
+
+            let line = std::str::from_utf8(&packet)?.trim_end_matches('\n');
+            let (command, capabilities) = line.split_once('\0').unwrap_or((line, ""));
+            if commands.is_empty() {
+                // unknown capabilities are ignored as we never advertised them anyway
+                self.capabilities = capabilities
+                    .split_ascii_whitespace()
+                    .filter_map(|capability| capability.parse().ok())
+                    .collect();
+            }
+
+            let mut parts = command.splitn(3, ' ');
+            let (old, new, refname) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(old), Some(new), Some(refname)) => (old, new, refname),
+                _ => bail!("receive-pack: malformed command `{}`", command),
+            };
+            commands.push(RefCommand {
+                old: old.parse()?,
+                new: new.parse()?,
+                refname: refname.to_owned(),
+                error: None,
+            });
+        }
+    }
+
+    async fn recv_pack(&mut self) -> Result<()> {
+        // the pack is written straight into `objects/pack` and indexed from there
+        // rather than being held in memory
+        let mut writer = PackWriter::new(&self.repo).await?;
+        match self.reader.recv_raw_pack(&mut writer).await {
+            // the client sends an empty pack if we already have everything
+            Ok(0) => writer.abort(),
+            Ok(_) => {
+                writer.finish(&self.repo, &ProgressSink::default()).await?;
+                Ok(())
+            }
+            Err(err) => {
+                writer.abort()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Updates the refs as requested by the commands, recording the reason for any rejections.
+    /// If the client asked for an atomic push then this is all or nothing: if any command is
+    /// rejected then no refs are updated. Otherwise, each ref is updated independently.
+    fn update_refs(&self, commands: &mut [RefCommand]) -> Result<()> {
+        // the commits we already have are only listed once for the entire push
+        let tips = self.ref_tips()?;
+        for command in commands.iter_mut() {
+            command.error = self.check_command(command, &tips)?;
+        }
+
+        let refdb = self.repo.refdb()?;
+        if !self.capabilities.contains(&Capability::Atomic) {
+            for command in commands.iter_mut().filter(|command| command.error.is_none()) {
+                if let Err(err) = refdb.compare_and_swap(&[command.cas()], RefUpdateCause::Push) {
+                    command.error = Some(update_failure(&err));
+                }
+            }
+            return Ok(());
+        }
+
+        if commands.iter().any(|command| command.error.is_some()) {
+            for command in commands.iter_mut().filter(|command| command.error.is_none()) {
+                command.error = Some("atomic push failure".to_owned());
+            }
+            return Ok(());
+        }
+
+        // every ref is checked under its lock before any are changed, so a failure here leaves
+        // all of the refs untouched
+        let updates = commands.iter().map(RefCommand::cas).collect::<Vec<_>>();
+        if let Err(err) = refdb.compare_and_swap(&updates, RefUpdateCause::Push) {
+            let stale = match err.downcast_ref::<BitError>() {
+                Some(&BitError::StaleRef(sym)) => Some(sym),
+                _ => None,
+            };
+            for command in commands.iter_mut() {
+                command.error = Some(match stale {
+                    Some(sym) if sym != command.sym() => "atomic push failure".to_owned(),
+                    _ => update_failure(&err),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The commits that our refs point at, everything reachable from which we assume we have
+    fn ref_tips(&self) -> Result<Vec<Oid>> {
+        let mut tips = vec![];
+        for r in self.repo.ls_refs()? {
+            if let Some(tip) = self.repo.try_fully_resolve_ref(r)? {
+                if self.repo.read_obj_header(tip)?.obj_type == BitObjType::Commit {
+                    tips.push(tip);
+                }
+            }
+        }
+        Ok(tips)
+    }
+
+    /// Returns the reason the command should be rejected (if any)
+    /// The ref is checked again under its lock when it's updated, this is just to give a more
+    /// specific reason for rejecting it
+    fn check_command(&self, command: &RefCommand, tips: &[Oid]) -> Result<Option<String>> {
+        let reject = |reason: &str| -> Result<Option<String>> { Ok(Some(reason.to_owned())) };
+
+        if !command.refname.starts_with("refs/") || !refs::is_valid_name(&command.refname) {
+            return reject("funny refname");
+        }
+
+        let sym = command.sym();
+        let current = self.repo.try_fully_resolve_ref(sym)?.unwrap_or(Oid::UNKNOWN);
+        if current != command.old {
+            return reject("stale info");
+        }
+
+        if command.is_delete() {
+            if current.is_unknown() {
+                return reject("ref does not exist");
+            }
+            if self.repo.config().deny_deletes() {
+                return reject("deletion prohibited");
+            }
+        } else if !self.is_connected(command.new, tips)? {
+            return reject("missing necessary objects");
+        }
+
+        let is_update = !command.old.is_unknown() && !command.is_delete();
+        if is_update
+            && self.repo.config().deny_non_fast_forwards()
+            && !self.repo.is_ancestor(command.old, command.new)?
+        {
+            return reject("non-fast-forward");
+        }
+
+        if self.is_checked_out(sym)? {
+            match self.repo.config().deny_current_branch() {
+                DenyCurrentBranch::Refuse => return reject("branch is currently checked out"),
+                DenyCurrentBranch::Warn =>
+                    warn!("receive-pack: updating the currently checked out branch `{}`", sym),
+                DenyCurrentBranch::Ignore => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether everything reachable from `oid` is present, assuming that everything reachable from
+    /// `tips` (our existing refs) is
+    fn is_connected(&self, oid: Oid, tips: &[Oid]) -> Result<bool> {
+        if !self.repo.obj_exists(oid)? {
+            return Ok(false);
+        }
+
+        // a missing commit or tree fails the walk, but blobs are only listed so must be checked
+        let objects = match self.repo.reachable_objects(&[oid], tips) {
+            Ok(objects) => objects,
+            Err(..) => return Ok(false),
+        };
+        for (oid, _) in objects {
+            if !self.repo.obj_exists(oid)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn is_checked_out(&self, sym: SymbolicRef) -> Result<bool> {
+        if self.repo.config().bare().unwrap_or(false) {
+            return Ok(false);
+        }
+        Ok(self.repo.read_head()? == BitRef::Symbolic(sym))
+    }
+
+    // report-status = unpack-status 1*(command-status) flush-pkt
+    // unpack-status = PKT-LINE("unpack" SP unpack-result)
+    // command-status = command-ok / command-fail
+    // command-ok = PKT-LINE("ok" SP refname)
+    // command-fail = PKT-LINE("ng" SP refname SP error-msg)
+    async fn write_report_status(
+        &mut self,
+        unpack_result: &Result<()>,
+        commands: &[RefCommand],
+    ) -> Result<()> {
+        match unpack_result {
+            Ok(()) => self.write(b"unpack ok\n").await?,
+            Err(err) => self.write(format!("unpack {}\n", first_line(err))).await?,
+        }
+        for command in commands {
+            match &command.error {
+                None => self.write(format!("ok {}\n", command.refname)).await?,
+                Some(error) => self.write(format!("ng {} {}\n", command.refname, error)).await?,
+            }
+        }
+        Ok(self.writer.write_flush_packet().await?)
+    }
+
+    #[inline]
+    async fn write(&mut self, bytes: impl AsRef<[u8]>) -> io::Result<()> {
+        self.writer.write_packet(bytes.as_ref()).await
+    }
+}
+
+/// The reason reported to the client for a ref that couldn't be updated.
+/// Only the first line of the error is used as status lines can't contain newlines.
+fn update_failure(err: &anyhow::Error) -> String {
+    if err.is_stale_ref_err() {
+        "stale info".to_owned()
+    } else {
+        format!("failed to update ref: {}", first_line(err))
+    }
+}
+
+fn first_line(err: &anyhow::Error) -> String {
+    err.to_string().lines().next().unwrap_or_default().to_owned()
+}
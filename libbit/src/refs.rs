@@ -1,3 +1,4 @@
+mod packed_refs;
 mod ref_decorations;
 mod refdb;
 mod reflog;
@@ -5,6 +6,7 @@ mod reflog;
 use crate::error::{BitGenericError, BitResult};
 use crate::obj::{BitObjKind, Oid, Tree, Treeish};
 use crate::path::BitPath;
+use crate::peel::Peel;
 use crate::repo::BitRepo;
 use crate::serialize::{Deserialize, Serialize};
 use lazy_static::lazy_static;
@@ -16,6 +18,7 @@ use std::io::prelude::*;
 use std::str::FromStr;
 use std::sync::Arc;
 
+pub use packed_refs::*;
 pub use ref_decorations::*;
 pub use refdb::*;
 pub use reflog::*;
@@ -134,7 +137,9 @@ impl PartialOrd for SymbolicRef {
 
 impl SymbolicRef {
     pub const HEAD: Self = Self { path: BitPath::HEAD, kind: SymbolicRefKind::Head };
+    pub const ORIG_HEAD: Self = Self { path: BitPath::ORIG_HEAD, kind: SymbolicRefKind::Head };
     pub const MASTER: Self = Self { path: BitPath::MASTER, kind: SymbolicRefKind::Branch };
+    pub const STASH: Self = Self { path: BitPath::REFS_STASH, kind: SymbolicRefKind::Stash };
 
     pub fn new(path: BitPath) -> Self {
         debug_assert!(path.is_relative());
@@ -154,7 +159,7 @@ impl SymbolicRef {
     }
 
     fn calculate_kind(path: BitPath) -> SymbolicRefKind {
-        if path == BitPath::HEAD {
+        if path == BitPath::HEAD || path == BitPath::ORIG_HEAD {
             SymbolicRefKind::Head
         } else if path.starts_with(BitPath::REFS_HEADS) {
             SymbolicRefKind::Branch
@@ -195,6 +200,11 @@ impl SymbolicRef {
             .expect("we just set the prefix, must be valid")
     }
 
+    pub fn new_tag(name: &str) -> Self {
+        Self::intern_valid(format!("refs/tags/{name}"))
+            .expect("we just set the prefix, must be valid")
+    }
+
     // returns an abbreviated representation of the reference
     pub fn short(&self) -> &'static str {
         const PREFIXES: &[BitPath] =
@@ -249,11 +259,15 @@ impl BitRef {
 
     pub fn resolve_to_tree(self, repo: &BitRepo) -> BitResult<Arc<Tree>> {
         let oid = repo.fully_resolve_ref(self)?;
-        match repo.read_obj(oid)? {
+        let obj = match repo.read_obj(oid)? {
+            BitObjKind::Tag(tag) => tag.peel(repo)?,
+            obj => obj,
+        };
+        match obj {
             BitObjKind::Blob(..) => bail!("blob type is not treeish"),
             BitObjKind::Commit(commit) => commit.tree.treeish(repo),
             BitObjKind::Tree(tree) => Ok(tree),
-            BitObjKind::Tag(..) => todo!(),
+            BitObjKind::Tag(..) => unreachable!("tags are fully peeled"),
         }
     }
 
@@ -0,0 +1,123 @@
+use super::SymbolicRef;
+use crate::error::{BitGenericError, BitResult};
+use crate::obj::Oid;
+use crate::path::BitPath;
+use crate::serialize::{Deserialize, Serialize};
+use std::collections::btree_map::{self, BTreeMap};
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+/// the traits we support (`peeled` and `fully-peeled` means that every ref that points at
+/// an annotated tag is followed by a `^<oid>` line containing the object it peels to)
+const PACKED_REFS_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted ";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PackedRef {
+    pub oid: Oid,
+    /// the (non-tag) object that `oid` peels to, only present if `oid` is an annotated tag
+    pub peeled: Option<Oid>,
+}
+
+/// In memory representation of `.git/packed-refs`
+// refs are kept sorted by name as the file is expected to be sorted
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PackedRefs {
+    refs: BTreeMap<BitPath, PackedRef>,
+}
+
+impl PackedRefs {
+    pub fn get(&self, sym: SymbolicRef) -> Option<PackedRef> {
+        self.refs.get(&sym.path()).copied()
+    }
+
+    pub fn contains(&self, sym: SymbolicRef) -> bool {
+        self.refs.contains_key(&sym.path())
+    }
+
+    pub fn insert(&mut self, sym: SymbolicRef, packed: PackedRef) -> Option<PackedRef> {
+        self.refs.insert(sym.path(), packed)
+    }
+
+    pub fn remove(&mut self, sym: SymbolicRef) -> Option<PackedRef> {
+        self.refs.remove(&sym.path())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SymbolicRef, PackedRef)> + '_ {
+        self.refs.iter().map(|(&path, &packed)| (SymbolicRef::new(path), packed))
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.refs.len()
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.refs.is_empty()
+    }
+}
+
+impl FromStr for PackedRefs {
+    type Err = BitGenericError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut refs = BTreeMap::<BitPath, PackedRef>::new();
+        let mut prev = None;
+        for line in s.lines() {
+            // the header is the only comment we expect, but git ignores all of them
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            if let Some(peeled) = line.strip_prefix('^') {
+                let path = match prev {
+                    Some(path) => path,
+                    None => bail!("peeled line `{}` in packed-refs has no preceding ref", line),
+                };
+                let packed = refs.get_mut(&path).expect("`prev` must have been inserted");
+                packed.peeled = Some(peeled.parse()?);
+                continue;
+            }
+
+            let (oid, name) = match line.split_once(' ') {
+                Some(split) => split,
+                None => bail!("malformed line in packed-refs `{}`", line),
+            };
+            let path = BitPath::intern(name);
+            match refs.entry(path) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(PackedRef { oid: oid.parse()?, peeled: None });
+                }
+                btree_map::Entry::Occupied(..) => bail!("duplicate ref `{}` in packed-refs", name),
+            }
+            prev = Some(path);
+        }
+        Ok(Self { refs })
+    }
+}
+
+impl Deserialize for PackedRefs {
+    fn deserialize(mut reader: impl BufRead) -> BitResult<Self>
+    where
+        Self: Sized,
+    {
+        let mut s = String::new();
+        reader.read_to_string(&mut s)?;
+        Self::from_str(&s)
+    }
+}
+
+impl Serialize for PackedRefs {
+    fn serialize(&self, writer: &mut dyn Write) -> BitResult<()> {
+        writeln!(writer, "{PACKED_REFS_HEADER}")?;
+        for (path, packed) in &self.refs {
+            writeln!(writer, "{} {}", packed.oid, path)?;
+            if let Some(peeled) = packed.peeled {
+                writeln!(writer, "^{peeled}")?;
+            }
+        }
+        Ok(())
+    }
+}
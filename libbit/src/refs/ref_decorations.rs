@@ -1,6 +1,7 @@
 use std::collections::{BTreeSet, HashMap, HashSet};
 
 use super::*;
+use crate::obj::BitObject;
 use owo_colors::colors::*;
 use owo_colors::{OwoColorize, Style, Styled};
 
@@ -10,10 +11,10 @@ impl SymbolicRef {
         match self.kind() {
             SymbolicRefKind::Head => style.fg::<BrightCyan>(),
             SymbolicRefKind::Branch => style.fg::<Green>(),
-            SymbolicRefKind::Tag => todo!(),
+            SymbolicRefKind::Tag => style.fg::<Yellow>(),
             SymbolicRefKind::Remote => style.fg::<Red>(),
             SymbolicRefKind::Unknown => unreachable!(),
-            SymbolicRefKind::Stash => todo!(),
+            SymbolicRefKind::Stash => style.fg::<Magenta>(),
         }
         .bold()
         .style(value)
@@ -87,7 +88,10 @@ impl BitRepo {
             if handled.contains(sym) {
                 continue;
             }
-            let (oid, decoration) = self.calculate_decoration(*sym)?;
+            let (oid, decoration) = match self.calculate_decoration(*sym)? {
+                Some(decoration) => decoration,
+                None => continue,
+            };
             if let RefDecoration::Symbolic(_, branch) = decoration {
                 handled.insert(branch);
             }
@@ -96,11 +100,24 @@ impl BitRepo {
         Ok(decorations)
     }
 
-    fn calculate_decoration(&self, sym: SymbolicRef) -> BitResult<(Oid, RefDecoration)> {
+    /// The commit decorated by `sym` (`None` for tags of trees and blobs which never decorate
+    /// anything)
+    fn calculate_decoration(&self, sym: SymbolicRef) -> BitResult<Option<(Oid, RefDecoration)>> {
         match self.partially_resolve_ref(sym)? {
-            BitRef::Direct(oid) => Ok((oid, RefDecoration::Branch(sym))),
+            // annotated tags should decorate the commit they point to rather than the tag object
+            BitRef::Direct(oid) if sym.kind() == SymbolicRefKind::Tag => {
+                let target = match self.read_obj(oid)? {
+                    BitObjKind::Tag(tag) => tag.peel(self)?,
+                    obj => obj,
+                };
+                Ok(match target {
+                    BitObjKind::Commit(commit) => Some((commit.oid(), RefDecoration::Branch(sym))),
+                    _ => None,
+                })
+            }
+            BitRef::Direct(oid) => Ok(Some((oid, RefDecoration::Branch(sym)))),
             BitRef::Symbolic(inner_sym) => match self.resolve_ref(inner_sym)? {
-                BitRef::Direct(oid) => Ok((oid, RefDecoration::Symbolic(sym, inner_sym))),
+                BitRef::Direct(oid) => Ok(Some((oid, RefDecoration::Symbolic(sym, inner_sym)))),
                 BitRef::Symbolic(_) => todo!("double symbolic ref"),
             },
         }
@@ -1,16 +1,30 @@
-use super::{BitRef, BitReflog, SymbolicRef};
+use super::{BitRef, BitReflog, PackedRef, PackedRefs, SymbolicRef, SymbolicRefKind};
 use crate::error::{BitError, BitErrorExt, BitResult};
 use crate::lockfile::{Filelock, Lockfile, LockfileFlags};
 use crate::merge::MergeStrategy;
-use crate::obj::Oid;
+use crate::obj::{BitObjKind, BitObject, Oid};
 use crate::path::BitPath;
+use crate::peel::Peel;
 use crate::repo::{BitRepo, BitRepoWeakRef};
 use crate::serialize::{Deserialize, Serialize};
 use crate::signature::BitSignature;
 use std::collections::BTreeSet;
 use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io;
 use std::path::Path;
 
+pub const PACKED_REFS_FILE_NAME: &str = "packed-refs";
+
+/// An update of `sym` from `old` to `new` that is only applied if `sym` still points at `old`.
+/// [`Oid::UNKNOWN`] as `old` means that `sym` must not exist, and as `new` that it is deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefCas {
+    pub sym: SymbolicRef,
+    pub old: Oid,
+    pub new: Oid,
+}
+
 pub struct BitRefDb {
     repo: BitRepoWeakRef,
     bitdir: BitPath,
@@ -41,10 +55,191 @@ impl BitRefDb {
     /// Set the symbolic reference `sym` to point at reference to `to` which
     /// may itself be symbolic reference or a direct reference.
     /// This will create `sym` if it doesn't exist
-    fn set_ref(&self, sym: SymbolicRef, to: BitRef) -> BitResult<()> {
-        let validated = self.validate(to)?;
+    pub(crate) fn set_ref(&self, sym: SymbolicRef, to: BitRef) -> BitResult<()> {
+        let validated = self.validate_target(sym, to)?;
         self.set_ref_unvalidated(sym, validated)
     }
+
+    fn packed_refs_path(&self) -> BitPath {
+        self.join(PACKED_REFS_FILE_NAME)
+    }
+
+    /// Reads `packed-refs` (which is considered empty if it doesn't exist)
+    pub fn read_packed_refs(&self) -> BitResult<PackedRefs> {
+        // `packed-refs` is only ever replaced by renaming its lockfile over it,
+        // so it's safe to read without taking the lock
+        match File::open(self.packed_refs_path()) {
+            Ok(file) => PackedRefs::deserialize_unbuffered(file),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(PackedRefs::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Runs `f` under the `packed-refs` lock, rewriting the file if `f` succeeds
+    fn with_packed_refs_mut<R>(
+        &self,
+        f: impl FnOnce(&mut PackedRefs) -> BitResult<R>,
+    ) -> BitResult<R> {
+        Lockfile::with_mut(self.packed_refs_path(), LockfileFlags::empty(), |lockfile| {
+            let mut packed_refs = match lockfile.file() {
+                Some(file) => PackedRefs::deserialize_unbuffered(file)?,
+                None => PackedRefs::default(),
+            };
+            let r = f(&mut packed_refs)?;
+            packed_refs.serialize(lockfile)?;
+            Ok(r)
+        })
+    }
+
+    /// All refs that are stored as a file under `refs/`
+    fn ls_loose_refs(&self) -> BitResult<Vec<SymbolicRef>> {
+        let mut refs = vec![];
+        for entry in walkdir::WalkDir::new(self.join("refs")) {
+            let entry = entry?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let path = entry.path();
+            refs.push(SymbolicRef::intern_valid(path.strip_prefix(self.bitdir)?)?);
+        }
+        Ok(refs)
+    }
+
+    /// Applies either every update in `updates` or none of them.
+    /// All the refs are locked before any of them are checked, so nothing else can move them
+    /// between being checked and being updated.
+    /// Fails with [`BitError::StaleRef`] if any of the refs don't have their expected value.
+    pub fn compare_and_swap(&self, updates: &[RefCas], cause: RefUpdateCause) -> BitResult<()> {
+        let paths = updates.iter().map(|update| self.join(update.sym.path));
+        Lockfile::with_mut_all(paths, LockfileFlags::SET_READONLY, |lockfiles| {
+            // `packed-refs` is only read once every ref is locked
+            let packed_refs = self.read_packed_refs()?;
+            for (update, lockfile) in updates.iter().zip(&*lockfiles) {
+                let current = match lockfile.file() {
+                    Some(file) => match BitRef::deserialize_unbuffered(file)? {
+                        BitRef::Direct(oid) => oid,
+                        BitRef::Symbolic(..) =>
+                            bail!("refusing to update symbolic ref `{}`", update.sym),
+                    },
+                    None => packed_refs.get(update.sym).map_or(Oid::UNKNOWN, |packed| packed.oid),
+                };
+                ensure!(current == update.old, BitError::StaleRef(update.sym));
+            }
+
+            // as in `delete`, packed refs must be removed before the loose refs
+            let deleted = updates.iter().filter(|update| update.new.is_unknown());
+            if deleted.clone().any(|update| packed_refs.contains(update.sym)) {
+                self.with_packed_refs_mut(|packed_refs| {
+                    for update in deleted {
+                        packed_refs.remove(update.sym);
+                    }
+                    Ok(())
+                })?;
+            }
+
+            for (update, lockfile) in updates.iter().zip(lockfiles) {
+                if !update.new.is_unknown() {
+                    BitRef::Direct(update.new).serialize(lockfile)?;
+                    continue;
+                }
+                // the loose ref is removed while it's still locked
+                if lockfile.file().is_some() {
+                    std::fs::remove_file(self.join(update.sym.path))?;
+                }
+                lockfile.rollback();
+            }
+            Ok(())
+        })?;
+
+        for update in updates {
+            if update.new.is_unknown() {
+                let log_path = self.join_log(update.sym.path);
+                if log_path.try_exists()? {
+                    std::fs::remove_file(log_path)?;
+                }
+            } else {
+                self.log_update(update.sym, update.old, update.new, &cause)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the update of `sym` in its reflog (and in `HEAD`'s if it points at `sym`)
+    fn log_update(
+        &self,
+        sym: SymbolicRef,
+        old_oid: Oid,
+        new_oid: Oid,
+        cause: &RefUpdateCause,
+    ) -> BitResult<()> {
+        let repo = self.repo();
+        let committer = repo.user_signature()?;
+        let cause_str = cause.to_string();
+
+        // TODO not sure this is completely correct behaviour, but it at least works for commits
+        // if HEAD points to the ref being updated, then we also record the same update in HEAD's log
+        if let BitRef::Symbolic(head) = repo.read_head()? {
+            if head == sym {
+                let (committer, cause_str) = (committer.clone(), cause_str.clone());
+                self.log(SymbolicRef::HEAD, old_oid, new_oid, committer, cause_str)?;
+            }
+        }
+
+        self.log(sym, old_oid, new_oid, committer, cause_str)
+    }
+
+    /// Moves loose refs into `packed-refs`.
+    /// Only tags (and refs that are already packed) are moved unless `all` is set, as branches
+    /// are expected to change frequently. The loose files are removed afterwards if `prune` is set.
+    /// `refs/stash` is never packed as it is rewritten on every stash operation.
+    pub fn pack_refs(&self, all: bool, prune: bool) -> BitResult<()> {
+        let repo = self.repo();
+        let packed = self.with_packed_refs_mut(|packed_refs| {
+            let mut packed = vec![];
+            for sym in self.ls_loose_refs()? {
+                if sym == SymbolicRef::STASH {
+                    continue;
+                }
+                if !all && sym.kind() != SymbolicRefKind::Tag && !packed_refs.contains(sym) {
+                    continue;
+                }
+
+                let path = self.join(sym.path);
+                // symbolic refs (e.g. `refs/remotes/origin/HEAD`) can't be packed
+                let oid = match BitRef::deserialize_unbuffered(File::open(path)?)? {
+                    BitRef::Direct(oid) => oid,
+                    BitRef::Symbolic(..) => continue,
+                };
+                let peeled = match repo.read_obj(oid)? {
+                    BitObjKind::Tag(tag) => Some(tag.peel(&repo)?.oid()),
+                    _ => None,
+                };
+                packed_refs.insert(sym, PackedRef { oid, peeled });
+                packed.push((sym, oid));
+            }
+            Ok(packed)
+        })?;
+
+        if !prune {
+            return Ok(());
+        }
+
+        for (sym, oid) in packed {
+            let path = self.join(sym.path);
+            Lockfile::with_readonly(path, LockfileFlags::empty(), |lockfile| {
+                // only remove the loose ref if it wasn't updated after it was packed
+                let file = match lockfile.file() {
+                    Some(file) => file,
+                    None => return Ok(()),
+                };
+                if BitRef::deserialize_unbuffered(file)? == BitRef::Direct(oid) {
+                    std::fs::remove_file(path)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
 }
 
 pub type Refs = BTreeSet<SymbolicRef>;
@@ -73,13 +268,25 @@ pub trait BitRefDbBackend {
     fn validate(&self, reference: BitRef) -> BitResult<BitRef> {
         match reference {
             BitRef::Direct(oid) => {
-                self.repo().ensure_obj_is_commit(oid)?;
+                self.repo().ensure_obj_is_commitish(oid)?;
                 Ok(reference)
             }
             BitRef::Symbolic(sym) => self.expand_symref(sym).map(BitRef::Symbolic),
         }
     }
 
+    /// Like [`Self::validate`] but for the value of `sym`.
+    /// Tags may point at any object (e.g. git.git tags a blob and linux tags a tree).
+    fn validate_target(&self, sym: SymbolicRef, reference: BitRef) -> BitResult<BitRef> {
+        match reference {
+            BitRef::Direct(oid) if sym.kind() == SymbolicRefKind::Tag => {
+                self.repo().ensure_obj_exists(oid)?;
+                Ok(reference)
+            }
+            _ => self.validate(reference),
+        }
+    }
+
     /// partially resolve means resolves the reference one layer
     /// e.g. HEAD -> refs/heads/master
     fn partially_resolve(&self, reference: BitRef) -> BitResult<BitRef> {
@@ -121,13 +328,14 @@ pub trait BitRefDbBackend {
     fn log(
         &self,
         sym: SymbolicRef,
+        old_oid: Oid,
         new_oid: Oid,
         committer: BitSignature,
         msg: String,
     ) -> BitResult<()> {
         // TODO consider caching each reflog that has been read (by holding onto the guard)
         // only necessary if multiple writes will be done successively (such as rebase perhaps)
-        self.read_reflog(sym)?.append(new_oid, committer, msg);
+        self.read_reflog(sym)?.append(old_oid, new_oid, committer, msg);
         Ok(())
     }
 }
@@ -155,39 +363,64 @@ impl BitRefDbBackend for BitRefDb {
 
     fn read(&self, sym: SymbolicRef) -> BitResult<BitRef> {
         let expanded = self.expand_symref(sym)?;
-        Lockfile::with_readonly(self.join(expanded.path), LockfileFlags::SET_READONLY, |lockfile| {
+        let path = self.join(expanded.path);
+        // loose refs take precedence over packed refs
+        if !path.try_exists()? {
+            return match self.read_packed_refs()?.get(expanded) {
+                Some(packed) => self.validate_target(expanded, BitRef::Direct(packed.oid)),
+                None => bail!(BitError::NonExistentSymRef(sym)),
+            };
+        }
+
+        Lockfile::with_readonly(path, LockfileFlags::SET_READONLY, |lockfile| {
             let file = lockfile.file().unwrap_or_else(|| panic!("ref `{sym}` does not exist"));
             let r = BitRef::deserialize_unbuffered(file)?;
-            self.validate(r)
+            self.validate_target(expanded, r)
         })
     }
 
     fn update(&self, sym: SymbolicRef, to: BitRef, cause: RefUpdateCause) -> BitResult<()> {
         let repo = self.repo();
+        // the previous value is read from the ref itself rather than the last reflog entry, as the
+        // reflog may be missing or out of date (e.g. the ref was only in `packed-refs`)
+        let old_oid = repo.try_fully_resolve_ref(sym)?.unwrap_or(Oid::UNKNOWN);
         self.set_ref(sym, to)?;
         let new_oid = repo.fully_resolve_ref(to)?;
-        let committer = repo.user_signature()?;
+        self.log_update(sym, old_oid, new_oid, &cause)
+    }
 
-        let cause_str = cause.to_string();
+    fn delete(&self, sym: SymbolicRef) -> BitResult<()> {
+        let expanded = self.expand_symref(sym)?;
+        ensure!(expanded != SymbolicRef::HEAD, "refusing to delete `HEAD`");
+        let path = self.join(expanded.path);
+        let is_loose = path.try_exists()?;
 
-        // TODO not sure this is completely correct behaviour, but it at least works for commits
-        // if HEAD points to the ref being updated, then we also record the same update in HEAD's log
-        if let BitRef::Symbolic(head) = repo.read_head()? {
-            if head == sym {
-                self.log(SymbolicRef::HEAD, new_oid, committer.clone(), cause_str.clone())?;
-            }
+        // the packed ref must be removed first, otherwise removing the loose ref would expose
+        // the (possibly stale) packed value
+        if self.read_packed_refs()?.contains(expanded) {
+            self.with_packed_refs_mut(|packed_refs| {
+                packed_refs.remove(expanded);
+                Ok(())
+            })?;
         }
 
-        self.log(sym, new_oid, committer, cause_str)?;
-        Ok(())
-    }
+        if is_loose {
+            // hold the lock while deleting to avoid racing with any concurrent writers
+            Lockfile::with_readonly(path, LockfileFlags::empty(), |_| {
+                std::fs::remove_file(path)?;
+                Ok(())
+            })?;
+        }
 
-    fn delete(&self, _sym: SymbolicRef) -> BitResult<()> {
-        todo!()
+        let log_path = self.join_log(expanded.path);
+        if log_path.try_exists()? {
+            std::fs::remove_file(log_path)?;
+        }
+        Ok(())
     }
 
     fn exists(&self, sym: SymbolicRef) -> BitResult<bool> {
-        Ok(self.join(sym.path).try_exists()?)
+        Ok(self.join(sym.path).try_exists()? || self.read_packed_refs()?.contains(sym))
     }
 
     // read_reflog is probably not a great method to have
@@ -200,16 +433,13 @@ impl BitRefDbBackend for BitRefDb {
 
     fn ls_refs(&self) -> BitResult<Refs> {
         let mut refs = btreeset! { SymbolicRef::HEAD };
-        let refs_dir = self.join("refs");
-        for entry in walkdir::WalkDir::new(refs_dir) {
-            let entry = entry?;
-            if entry.file_type().is_dir() {
-                continue;
-            }
-            let path = entry.path();
-            let sym = SymbolicRef::intern_valid(path.strip_prefix(self.bitdir)?)?;
+        for sym in self.ls_loose_refs()? {
             assert!(refs.insert(sym), "inserted duplicate ref `{sym}`");
         }
+        // a ref may be both loose and packed
+        for (sym, _) in self.read_packed_refs()?.iter() {
+            refs.insert(SymbolicRef::new_valid(sym.path())?);
+        }
         Ok(refs)
     }
 
@@ -219,9 +449,10 @@ impl BitRefDbBackend for BitRefDb {
         const PREFIXES: &[BitPath] =
             &[BitPath::EMPTY, BitPath::REFS_HEADS, BitPath::REFS_TAGS, BitPath::REFS_REMOTES];
 
+        let packed_refs = self.read_packed_refs()?;
         for prefix in PREFIXES {
             let path = prefix.join(sym.path);
-            if self.join(path).try_exists()? {
+            if self.join(path).try_exists()? || packed_refs.contains(SymbolicRef::new(path)) {
                 return SymbolicRef::new_valid(path);
             }
         }
@@ -245,6 +476,10 @@ pub enum RefUpdateCause {
     Reset { target: BitRef },
     Merge { theirs: BitRef, strategy: MergeStrategy },
     Fetch { to: BitRef },
+    Push,
+    NewTag { target: Oid },
+    Stash { message: String },
+    Rebase { step: &'static str, message: String },
 }
 
 impl Display for RefUpdateCause {
@@ -264,6 +499,11 @@ impl Display for RefUpdateCause {
                 MergeStrategy::Recursive => write!(f, "merge `{theirs}`: recursive"),
             },
             RefUpdateCause::Fetch { to: _ } => write!(f, "fetch"),
+            RefUpdateCause::Push => write!(f, "update by push"),
+            RefUpdateCause::NewTag { target } => write!(f, "tag: tagging {target}"),
+            // the log of `refs/stash` is where the message of each stash lives
+            RefUpdateCause::Stash { message } => write!(f, "{message}"),
+            RefUpdateCause::Rebase { step, message } => write!(f, "rebase ({step}): {message}"),
         }
     }
 }
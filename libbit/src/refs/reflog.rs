@@ -45,14 +45,27 @@ impl BitReflog {
         if index >= self.len() { None } else { Some(&self[index]) }
     }
 
-    pub fn append(&mut self, new_oid: Oid, committer: BitSignature, msg: String) {
-        let old_oid = match self.entries.last() {
-            Some(entry) => entry.new_oid,
-            None => Oid::UNKNOWN,
-        };
+    /// All the entries from oldest to newest
+    pub fn entries(&self) -> &[BitReflogEntry] {
+        &self.entries
+    }
+
+    pub fn append(&mut self, old_oid: Oid, new_oid: Oid, committer: BitSignature, msg: String) {
         self.entries.push(BitReflogEntry { old_oid, new_oid, committer, message: msg })
     }
 
+    /// Removes the entry at `index` (indexed the same way as [`Index`]).
+    /// The entry after it is rewritten to follow on from the one before it.
+    pub fn remove(&mut self, index: usize) -> BitReflogEntry {
+        assert!(index < self.len(), "reflog index `{index}` out of range");
+        let i = self.len() - index - 1;
+        let removed = self.entries.remove(i);
+        if let Some(next) = self.entries.get_mut(i) {
+            next.old_oid = removed.old_oid;
+        }
+        removed
+    }
+
     #[must_use]
     #[inline]
     pub fn len(&self) -> usize {
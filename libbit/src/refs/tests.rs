@@ -1,5 +1,6 @@
 use super::*;
-use crate::error::BitResult;
+use crate::error::{BitError, BitErrorExt, BitResult};
+use crate::obj::MutableBlob;
 use crate::repo::BitRepo;
 use crate::serialize::{Deserialize, Serialize};
 use crate::signature::BitSignature;
@@ -38,6 +39,30 @@ fn test_calculate_ref_decoration() -> BitResult<()> {
     })
 }
 
+#[test]
+fn test_tags_may_point_at_trees_and_blobs() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let tree = repo.head_tree()?;
+        let blob = repo.write_obj(&MutableBlob::new(b"public key".to_vec()))?;
+        let tree_tag = SymbolicRef::new_tag("v1-tree");
+        let blob_tag = SymbolicRef::new_tag("pub-key");
+        repo.update_ref(tree_tag, tree, RefUpdateCause::NewTag { target: tree })?;
+        repo.update_ref(blob_tag, blob, RefUpdateCause::NewTag { target: blob })?;
+        assert_eq!(repo.read_ref(tree_tag)?, BitRef::Direct(tree));
+        assert_eq!(repo.read_ref(blob_tag)?, BitRef::Direct(blob));
+
+        // only tags may point at objects other than commits
+        let branch = symbolic!("refs/heads/tree");
+        assert!(repo.update_ref(branch, tree, RefUpdateCause::NewTag { target: tree }).is_err());
+
+        // and they don't decorate anything
+        let decorations = repo.ref_decorations(&repo.ls_refs()?)?;
+        assert_eq!(decorations.len(), 1);
+        assert!(!decorations.contains_key(&tree));
+        Ok(())
+    })
+}
+
 #[test]
 fn test_ls_refs_on_empty_repo() -> BitResult<()> {
     BitRepo::with_empty_repo(|repo| {
@@ -195,3 +220,168 @@ fn test_reflog_contents_on_commit() -> BitResult<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_deserialize_then_reserialize_packed_refs() -> BitResult<()> {
+    let bytes = b"# pack-refs with: peeled fully-peeled sorted 
+4b825dc642cb6eb9a060e54bf8d69288fbee4904 refs/heads/master
+95a612b0afcae388c4f9fb9ddf4dba489919b766 refs/remotes/origin/master
+4f0b23654b5ffc3a994ec4bf0212ed8dc4358400 refs/tags/v1
+^95a612b0afcae388c4f9fb9ddf4dba489919b766
+";
+    let packed_refs = PackedRefs::deserialize_unbuffered(&bytes[..])?;
+    assert_eq!(packed_refs.len(), 3);
+    assert_eq!(
+        packed_refs.get(symbolic!("refs/tags/v1")),
+        Some(PackedRef {
+            oid: "4f0b23654b5ffc3a994ec4bf0212ed8dc4358400".into(),
+            peeled: Some("95a612b0afcae388c4f9fb9ddf4dba489919b766".into()),
+        })
+    );
+    assert_eq!(packed_refs.get(symbolic!("refs/heads/master")).unwrap().peeled, None);
+
+    let mut buf = vec![];
+    packed_refs.serialize(&mut buf)?;
+    assert_eq!(&buf[..], &bytes[..]);
+    Ok(())
+}
+
+#[test]
+fn test_read_packed_ref() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        std::fs::write(
+            repo.bitdir.join(PACKED_REFS_FILE_NAME),
+            format!("# pack-refs with: peeled fully-peeled sorted \n{head} refs/heads/packed\n"),
+        )?;
+
+        let refdb = repo.refdb()?;
+        let sym = symbolic!("refs/heads/packed");
+        assert!(refdb.exists(sym)?);
+        assert_eq!(refdb.expand_symref(symbolic!("packed"))?, sym);
+        assert_eq!(refdb.read(sym)?, BitRef::Direct(head));
+        assert!(repo.ls_refs()?.contains(&sym));
+        assert_eq!(repo.fully_resolve_rev(&rev!("packed"))?, head);
+
+        // loose refs take precedence over packed ones
+        let parent = repo.fully_resolve_rev(&rev!("HEAD^"))?;
+        repo.update_ref(sym, parent, RefUpdateCause::Reset { target: BitRef::Direct(parent) })?;
+        assert_eq!(refdb.read(sym)?, BitRef::Direct(parent));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_pack_refs_then_delete() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        repo.bit_create_branch("branch", &rev!("HEAD^"))?;
+        let tag =
+            repo.bit_create_tag("annotated", &rev!("HEAD"), Some("msg\n".to_owned()), false)?;
+        let refs = repo.ls_refs()?;
+
+        let refdb = repo.refdb()?;
+        refdb.pack_refs(true, true)?;
+        assert!(!repo.bitdir.join("refs/heads/branch").try_exists()?);
+        assert!(!repo.bitdir.join(tag.path()).try_exists()?);
+        assert_eq!(repo.ls_refs()?, refs);
+        // HEAD is never packed
+        assert_eq!(repo.read_head()?, BitRef::Symbolic(symbolic!("refs/heads/master")));
+
+        let packed_refs = refdb.read_packed_refs()?;
+        assert_eq!(packed_refs.len(), 3);
+        assert_eq!(packed_refs.get(tag).unwrap().peeled, Some(head));
+        assert_eq!(packed_refs.get(symbolic!("refs/heads/master")).unwrap().peeled, None);
+        assert_eq!(repo.fully_resolve_rev(&rev!("master"))?, head);
+
+        repo.bit_delete_tag("annotated")?;
+        assert!(!refdb.exists(tag)?);
+        assert!(!refdb.read_packed_refs()?.contains(tag));
+        assert_eq!(refdb.read_packed_refs()?.len(), 2);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_pack_refs_skips_stash() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        modify!(repo: "foo" < "stashed foo");
+        let stash = repo.stash_push(crate::stash::StashPushOpts::default())?.unwrap();
+
+        let refdb = repo.refdb()?;
+        refdb.pack_refs(true, true)?;
+        assert!(repo.bitdir.join("refs/stash").try_exists()?);
+        assert!(!refdb.read_packed_refs()?.contains(SymbolicRef::STASH));
+        assert_eq!(repo.fully_resolve_ref(SymbolicRef::STASH)?, stash);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_reflog_old_oid_of_packed_ref() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let parent = repo.fully_resolve_rev(&rev!("HEAD^"))?;
+        repo.bit_create_branch("branch", &rev!("HEAD^"))?;
+        let refdb = repo.refdb()?;
+        refdb.pack_refs(true, true)?;
+        // the ref now only lives in `packed-refs` and has no reflog to fall back on
+        std::fs::remove_file(repo.bitdir.join("logs/refs/heads/branch"))?;
+
+        let head = repo.fully_resolve_head()?;
+        let sym = symbolic!("refs/heads/branch");
+        repo.update_ref(sym, head, RefUpdateCause::Reset { target: BitRef::Direct(head) })?;
+        let reflog = refdb.read_reflog(sym)?;
+        assert_eq!(reflog.len(), 1);
+        assert_eq!(reflog[0].old_oid, parent);
+        assert_eq!(reflog[0].new_oid, head);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_compare_and_swap_is_all_or_nothing() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let parent = repo.fully_resolve_rev(&rev!("HEAD^"))?;
+        repo.bit_create_branch("branch", &rev!("HEAD^"))?;
+        let branch = symbolic!("refs/heads/branch");
+        let new_branch = symbolic!("refs/heads/new-branch");
+
+        let refdb = repo.refdb()?;
+        // `branch` isn't where the caller thinks it is, so neither ref is touched
+        let updates = [
+            RefCas { sym: new_branch, old: Oid::UNKNOWN, new: head },
+            RefCas { sym: branch, old: head, new: parent },
+        ];
+        let err = refdb.compare_and_swap(&updates, RefUpdateCause::Push).unwrap_err();
+        assert_eq!(err.try_into_bit_error()?, BitError::StaleRef(branch));
+        assert!(!refdb.exists(new_branch)?);
+        assert_eq!(repo.fully_resolve_ref(branch)?, parent);
+
+        let updates = [
+            RefCas { sym: new_branch, old: Oid::UNKNOWN, new: head },
+            RefCas { sym: branch, old: parent, new: Oid::UNKNOWN },
+        ];
+        refdb.compare_and_swap(&updates, RefUpdateCause::Push)?;
+        assert_eq!(repo.fully_resolve_ref(new_branch)?, head);
+        assert!(!refdb.exists(branch)?);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_compare_and_swap_packed_ref() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let parent = repo.fully_resolve_rev(&rev!("HEAD^"))?;
+        repo.bit_create_branch("branch", &rev!("HEAD^"))?;
+        let branch = symbolic!("refs/heads/branch");
+
+        let refdb = repo.refdb()?;
+        refdb.pack_refs(true, true)?;
+        let update = RefCas { sym: branch, old: parent, new: head };
+        refdb.compare_and_swap(&[update], RefUpdateCause::Push)?;
+        assert_eq!(repo.fully_resolve_ref(branch)?, head);
+        Ok(())
+    })
+}
@@ -1,18 +1,27 @@
+use crate::checkout::CheckoutOpts;
 use crate::config::RemoteConfig;
 use crate::error::{BitGenericError, BitResult};
 use crate::interner::Intern;
+use crate::obj::{BitObjType, Oid};
+use crate::partial_clone::ObjectFilter;
 use crate::path::BitPath;
-use crate::refs::{BitRef, SymbolicRef};
+use crate::progress::ProgressSink;
+use crate::refs::{BitRef, SymbolicRef, SymbolicRefKind};
 use crate::repo::BitRepo;
-use crate::reset::ResetKind;
-use crate::transport::{FileTransport, ProtocolTransport, SshTransport};
+use crate::transport::{
+    FileTransport, GitDaemonTransport, HttpTransport, ProtocolTransport, SshTransport,
+};
+use anyhow::Context;
 use git_url_parse::{GitUrl, Scheme};
-use openssh::Session;
+use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
 use std::path::Path;
 use std::str::FromStr;
+use std::time::SystemTime;
 
 pub const DEFAULT_REMOTE: &str = "origin";
+pub const DEFAULT_UPLOAD_PACK: &str = "git-upload-pack";
+pub const DEFAULT_RECEIVE_PACK: &str = "git-receive-pack";
 
 #[derive(Debug, Clone)]
 pub struct Refspec {
@@ -58,20 +67,30 @@ impl Refspec {
         }?;
         Some(SymbolicRef::new(path))
     }
-}
 
-impl FromStr for Refspec {
-    type Err = BitGenericError;
+    /// Every ref that `match_ref` matches starts with this prefix
+    pub fn ref_prefix(&self) -> BitPath {
+        self.src
+    }
 
-    // very rough implementation, doesn't capture full semantics of refspecs
-    fn from_str(mut s: &str) -> BitResult<Self> {
-        let forced = if &s[0..1] == "+" {
-            s = &s[1..];
-            true
-        } else {
-            false
-        };
-        let (src, dst) = s.split_once(':').ok_or_else(|| anyhow!("missing `:` in refspec"))?;
+    /// Parses a refspec given to push, which (unlike a fetch refspec) may omit `:dst` to mean that
+    /// `dst` is the same as `src`, or have an empty `src` to delete `dst`
+    pub fn parse_push(s: &str) -> BitResult<Self> {
+        let (forced, s) = Self::strip_forced(s);
+        match s.split_once(':') {
+            Some((src, dst)) => Self::parse_parts(forced, src, dst),
+            None => Self::parse_parts(forced, s, s),
+        }
+    }
+
+    fn strip_forced(s: &str) -> (bool, &str) {
+        match s.strip_prefix('+') {
+            Some(s) => (true, s),
+            None => (false, s),
+        }
+    }
+
+    fn parse_parts(forced: bool, src: &str, dst: &str) -> BitResult<Self> {
         let (src, src_is_glob) = match src.strip_suffix('*') {
             Some(stripped) => (stripped, true),
             None => (src, false),
@@ -89,6 +108,17 @@ impl FromStr for Refspec {
     }
 }
 
+impl FromStr for Refspec {
+    type Err = BitGenericError;
+
+    // very rough implementation, doesn't capture full semantics of refspecs
+    fn from_str(s: &str) -> BitResult<Self> {
+        let (forced, s) = Self::strip_forced(s);
+        let (src, dst) = s.split_once(':').ok_or_else(|| anyhow!("missing `:` in refspec"))?;
+        Self::parse_parts(forced, src, dst)
+    }
+}
+
 impl Display for Refspec {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         if self.forced {
@@ -107,11 +137,34 @@ pub struct Remote {
     pub name: &'static str,
     pub url: GitUrl,
     pub fetch: Refspec,
+    pub upload_pack: Option<String>,
+    pub receive_pack: Option<String>,
+    /// whether the remote provides the objects missing from a partial clone
+    pub promisor: bool,
+    pub partial_clone_filter: Option<ObjectFilter>,
 }
 
 impl Remote {
     fn from_config(name: &'static str, config: RemoteConfig) -> Self {
-        Self { name, url: config.url, fetch: config.fetch }
+        Self {
+            name,
+            url: config.url,
+            fetch: config.fetch,
+            upload_pack: config.upload_pack,
+            receive_pack: config.receive_pack,
+            promisor: config.promisor.unwrap_or(false),
+            partial_clone_filter: config.partial_clone_filter,
+        }
+    }
+
+    /// The program that serves packs on the remote end
+    pub fn upload_pack(&self) -> &str {
+        self.upload_pack.as_deref().unwrap_or(DEFAULT_UPLOAD_PACK)
+    }
+
+    /// The program that receives packs (and updates refs) on the remote end
+    pub fn receive_pack(&self) -> &str {
+        self.receive_pack.as_deref().unwrap_or(DEFAULT_RECEIVE_PACK)
     }
 }
 
@@ -133,8 +186,139 @@ impl FetchSummary {
     pub const EMPTY_REMOTE: Self = Self { head_symref: None, status: FetchStatus::EmptyRemote };
 }
 
+/// Options that limit how much history is fetched (making the repository shallow) and which
+/// objects are fetched (making the repository a partial clone)
+#[derive(Debug, Clone, Default)]
+pub struct FetchOpts {
+    /// fetch at most this many commits of the history of each remote ref
+    pub depth: Option<u32>,
+    /// deepen the history of a shallow repository by this many commits
+    pub deepen: Option<u32>,
+    /// only fetch commits more recent than this
+    pub shallow_since: Option<SystemTime>,
+    /// don't fetch commits reachable from these remote branches or tags
+    pub shallow_exclude: Vec<String>,
+    /// fetch the rest of the history of a shallow repository
+    pub unshallow: bool,
+    /// omit the objects that don't pass this filter (only for promisor remotes)
+    pub filter: Option<ObjectFilter>,
+    /// where to report the progress of receiving and indexing the pack (and of the checkout when
+    /// cloning)
+    pub progress: ProgressSink,
+}
+
+impl FetchOpts {
+    /// Whether the shallow boundary is to be moved (as opposed to just respected)
+    pub fn deepens(&self) -> bool {
+        self.depth.is_some()
+            || self.deepen.is_some()
+            || self.shallow_since.is_some()
+            || !self.shallow_exclude.is_empty()
+            || self.unshallow
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PushOpts {
+    /// allow updates that are not fast-forwards
+    pub force: bool,
+    /// allow updates that are not fast-forwards, but only if the remote ref is still where our
+    /// remote tracking branch says it is (i.e. we won't overwrite anything we haven't seen)
+    pub force_with_lease: bool,
+    /// either every ref is updated or none of them are
+    pub atomic: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushStatus {
+    /// the update was (or is about to be) sent to the remote and was not rejected
+    Ok,
+    UpToDate,
+    /// the remote ref is not an ancestor of what we are pushing and the push was not forced
+    RejectedNonFastForward,
+    /// `--force-with-lease` was given but the remote ref has moved since we last fetched it
+    RejectedStale,
+    /// the remote refused the update for the given reason
+    RemoteRejected(String),
+    /// the push was atomic and some other update was rejected
+    AtomicPushFailed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushUpdate {
+    /// the local ref being pushed, `None` if the remote ref is being deleted
+    pub src: Option<SymbolicRef>,
+    pub dst: SymbolicRef,
+    /// the current value of the remote ref ([`Oid::UNKNOWN`] if it doesn't exist)
+    pub old: Oid,
+    /// the value the remote ref is updated to ([`Oid::UNKNOWN`] if it is being deleted)
+    pub new: Oid,
+    /// whether the update is not a fast-forward
+    pub forced: bool,
+    pub status: PushStatus,
+}
+
+impl PushUpdate {
+    pub fn is_rejected(&self) -> bool {
+        !matches!(self.status, PushStatus::Ok | PushStatus::UpToDate)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PushSummary {
+    pub updates: Vec<PushUpdate>,
+}
+
+impl PushSummary {
+    pub fn is_ok(&self) -> bool {
+        !self.updates.iter().any(PushUpdate::is_rejected)
+    }
+}
+
+/// Connects to `$remote` and evaluates `$body` with the transport.
+/// `$program` is what is run on the remote end for ssh and local remotes (`remote.uploadpack` or
+/// `remote.receivepack`), while `$service` is the service requested from git daemons and http
+/// servers (where the server decides what runs the service).
+macro_rules! with_transport {
+    ($repo:expr, $remote:expr, $program:expr, $service:expr, |$transport:ident| $body:expr) => {{
+        let repo = $repo;
+        let remote = $remote;
+        match remote.url.scheme {
+            Scheme::Ssh => {
+                let mut $transport = SshTransport::new(repo, &remote.url, $program).await?;
+                let res = $body;
+                // the error from talking to the remote explains more than the exit status
+                let exited = $transport.wait().await;
+                res.and_then(|res| exited.map(|()| res))
+            }
+            Scheme::File => {
+                let mut $transport = FileTransport::new(repo, &remote.url, $program).await?;
+                $body
+            }
+            Scheme::Git => {
+                let mut $transport = GitDaemonTransport::new(repo, &remote.url, $service).await?;
+                $body
+            }
+            Scheme::Http | Scheme::Https => {
+                let mut $transport = HttpTransport::new(repo, &remote.url, $service).await?;
+                $body
+            }
+            Scheme::Unspecified => todo!("unspecified url scheme for remote"),
+            _ => bail!("unsupported scheme `{}`", remote.url.scheme),
+        }
+    }};
+}
+
 impl BitRepo {
     pub fn clone_blocking(into: impl AsRef<Path>, url: impl AsRef<str>) -> BitResult<()> {
+        Self::clone_with_opts_blocking(into, url, FetchOpts::default())
+    }
+
+    pub fn clone_with_opts_blocking(
+        into: impl AsRef<Path>,
+        url: impl AsRef<str>,
+        opts: FetchOpts,
+    ) -> BitResult<()> {
         let into = into.as_ref();
         let exists = into.exists();
         if exists {
@@ -146,7 +330,7 @@ impl BitRepo {
 
         Self::init_load(into, |repo| {
             repo.add_remote(DEFAULT_REMOTE, url)?;
-            repo.clone_origin_blocking()
+            repo.clone_origin_blocking(&opts)
         })
         .or_else(|err| {
             if !exists {
@@ -157,13 +341,20 @@ impl BitRepo {
     }
 
     #[tokio::main]
-    pub async fn clone_origin_blocking(&self) -> BitResult<()> {
-        self.clone_origin().await
+    pub async fn clone_origin_blocking(&self, opts: &FetchOpts) -> BitResult<()> {
+        self.clone_origin(opts).await
     }
 
-    pub async fn clone_origin(&self) -> BitResult<()> {
+    pub async fn clone_origin(&self, opts: &FetchOpts) -> BitResult<()> {
+        if let Some(filter) = opts.filter {
+            // the remote must be known to be a promisor before anything is checked out
+            self.with_raw_local_config(|config| {
+                config.set_subsection("remote", DEFAULT_REMOTE, "promisor", true)?;
+                config.set_subsection("remote", DEFAULT_REMOTE, "partialclonefilter", filter)
+            })?;
+        }
         let remote = self.get_remote(DEFAULT_REMOTE)?;
-        let FetchSummary { head_symref, status } = self.fetch_remote(&remote).await?;
+        let FetchSummary { head_symref, status } = self.fetch_remote(&remote, opts).await?;
 
         let refspec = remote.fetch;
         // TODO probably need to be a bit smarter than just defaulting to master
@@ -176,7 +367,12 @@ impl BitRepo {
         if status == FetchStatus::EmptyRemote {
             return Ok(());
         }
-        self.reset(remote, ResetKind::Hard)?;
+        // a hard reset that reports the progress of the checkout
+        let target = self.fully_resolve_ref(remote)?;
+        let tree = self.read_obj_commit(target)?.tree;
+        let progress = opts.progress.clone();
+        self.checkout_tree_with_opts(tree, CheckoutOpts { progress, ..CheckoutOpts::forced() })?;
+        self.update_current_ref_for_reset(target)?;
         Ok(())
     }
 
@@ -210,24 +406,221 @@ impl BitRepo {
     }
 
     pub async fn fetch(&self, name: &str) -> BitResult<FetchSummary> {
+        self.fetch_with_opts(name, &FetchOpts::default()).await
+    }
+
+    #[tokio::main]
+    pub async fn fetch_with_opts_blocking(
+        &self,
+        name: &str,
+        opts: &FetchOpts,
+    ) -> BitResult<FetchSummary> {
+        self.fetch_with_opts(name, opts).await
+    }
+
+    pub async fn fetch_with_opts(&self, name: &str, opts: &FetchOpts) -> BitResult<FetchSummary> {
         let remote = self.get_remote(name)?;
-        self.fetch_remote(&remote).await
+        self.fetch_remote(&remote, opts).await
     }
 
-    pub async fn fetch_remote(&self, remote: &Remote) -> BitResult<FetchSummary> {
-        match remote.url.scheme {
-            Scheme::Ssh => {
-                let url = &remote.url;
-                let dst = format!("{}@{}", url.user.as_ref().unwrap(), url.host.as_ref().unwrap());
-                let session = Session::connect(dst, openssh::KnownHosts::Add).await?;
-                let mut transport = SshTransport::new(self.clone(), &session, url).await?;
-                transport.fetch(remote).await
+    pub async fn fetch_remote(&self, remote: &Remote, opts: &FetchOpts) -> BitResult<FetchSummary> {
+        ensure!(opts.depth != Some(0) && opts.deepen != Some(0), "depth must be positive");
+        ensure!(
+            opts.depth.is_none() || opts.deepen.is_none(),
+            "`--depth` and `--deepen` are mutually exclusive"
+        );
+        if opts.unshallow {
+            ensure!(
+                opts.depth.is_none() && opts.deepen.is_none(),
+                "`--unshallow` cannot be used with `--depth` or `--deepen`"
+            );
+            ensure!(
+                self.is_shallow()?,
+                "`--unshallow` on a complete repository does not make sense"
+            );
+        }
+
+        ensure!(
+            opts.filter.is_none() || remote.promisor,
+            "`--filter` can only be used with a promisor remote"
+        );
+        // later fetches from a promisor remote keep omitting what the clone omitted
+        let filtered;
+        let opts = match (opts.filter, remote.partial_clone_filter) {
+            (None, Some(filter)) if remote.promisor => {
+                filtered = FetchOpts { filter: Some(filter), ..opts.clone() };
+                &filtered
             }
-            Scheme::File => FileTransport::new(self, &remote.url).await?.fetch(remote).await,
-            Scheme::Https => todo!("todo https"),
-            Scheme::Unspecified => todo!("unspecified url scheme for remote"),
-            _ => bail!("unsupported scheme `{}`", remote.url.scheme),
+            _ => opts,
+        };
+        with_transport!(self, remote, remote.upload_pack(), DEFAULT_UPLOAD_PACK, |transport| {
+            transport.fetch(remote, opts).await
+        })
+    }
+
+    /// Fetch exactly `oids` (and whatever they refer to that passes the filter) from the promisor
+    /// `remote` without updating any refs
+    pub(crate) async fn fetch_objects(&self, remote: &Remote, oids: &[Oid]) -> BitResult<()> {
+        with_transport!(self, remote, remote.upload_pack(), DEFAULT_UPLOAD_PACK, |transport| {
+            transport.fetch_objects(oids).await
+        })
+    }
+
+    #[tokio::main]
+    pub async fn push_blocking(
+        &self,
+        name: &str,
+        refspecs: &[Refspec],
+        opts: PushOpts,
+    ) -> BitResult<PushSummary> {
+        self.push(name, refspecs, opts).await
+    }
+
+    pub async fn push(
+        &self,
+        name: &str,
+        refspecs: &[Refspec],
+        opts: PushOpts,
+    ) -> BitResult<PushSummary> {
+        let remote = self.get_remote(name)?;
+        self.push_remote(&remote, refspecs, opts).await
+    }
+
+    /// Push `refspecs` to `remote`, pushing the current branch to the branch of the same name if
+    /// `refspecs` is empty
+    pub async fn push_remote(
+        &self,
+        remote: &Remote,
+        refspecs: &[Refspec],
+        opts: PushOpts,
+    ) -> BitResult<PushSummary> {
+        let current_branch;
+        let refspecs = match refspecs {
+            [] => {
+                let branch = match self.read_head()? {
+                    BitRef::Symbolic(branch) => branch.path(),
+                    BitRef::Direct(..) =>
+                        bail!("HEAD is detached, specify which ref to push explicitly"),
+                };
+                current_branch = [Refspec { src: branch, dst: branch, forced: false, glob: false }];
+                &current_branch[..]
+            }
+            refspecs => refspecs,
+        };
+
+        with_transport!(self, remote, remote.receive_pack(), DEFAULT_RECEIVE_PACK, |transport| {
+            transport.push(remote, refspecs, opts).await
+        })
+    }
+
+    /// Decide what to update on the remote by matching `refspecs` against our refs and the refs the
+    /// remote advertised (`remote_refs`).
+    /// Updates that we already know will fail are rejected here rather than being sent.
+    pub(crate) fn plan_push(
+        &self,
+        remote: &Remote,
+        remote_refs: &HashMap<SymbolicRef, Oid>,
+        refspecs: &[Refspec],
+        opts: PushOpts,
+    ) -> BitResult<Vec<PushUpdate>> {
+        let mut updates = vec![];
+        for refspec in refspecs {
+            for (src, dst) in self.match_push_refspec(refspec, remote_refs)? {
+                let new = match src {
+                    Some(src) => self.fully_resolve_ref(src)?,
+                    None => Oid::UNKNOWN,
+                };
+                let old = remote_refs.get(&dst).copied().unwrap_or(Oid::UNKNOWN);
+                let forced = !(old.is_unknown() || new.is_unknown() || self.is_ancestor(old, new)?);
+
+                let status = if old == new {
+                    PushStatus::UpToDate
+                } else if opts.force_with_lease && self.expected_remote_oid(remote, dst)? != old {
+                    PushStatus::RejectedStale
+                } else if forced && !(refspec.forced || opts.force || opts.force_with_lease) {
+                    PushStatus::RejectedNonFastForward
+                } else {
+                    PushStatus::Ok
+                };
+                updates.push(PushUpdate { src, dst, old, new, forced, status });
+            }
+        }
+        Ok(updates)
+    }
+
+    /// Returns pairs of local and remote refs that `refspec` refers to
+    fn match_push_refspec(
+        &self,
+        refspec: &Refspec,
+        remote_refs: &HashMap<SymbolicRef, Oid>,
+    ) -> BitResult<Vec<(Option<SymbolicRef>, SymbolicRef)>> {
+        if refspec.src.is_empty() {
+            let dst = expand_remote_ref(refspec.dst, remote_refs).ok_or_else(|| {
+                anyhow!("unable to delete `{}`: remote ref does not exist", refspec.dst)
+            })?;
+            return Ok(vec![(None, dst)]);
+        }
+
+        if refspec.glob {
+            return Ok(self
+                .ls_refs()?
+                .into_iter()
+                .filter_map(|src| Some((Some(src), refspec.match_ref(src)?)))
+                .collect());
+        }
+
+        let mut src = self
+            .validate_ref(SymbolicRef::new(refspec.src))
+            .with_context(|| anyhow!("src refspec `{}` does not match any", refspec.src))?
+            .into_symbolic();
+        // pushing `HEAD` pushes the current branch
+        if src == SymbolicRef::HEAD {
+            if let BitRef::Symbolic(branch) = self.read_head()? {
+                src = branch;
+            }
+        }
+
+        let dst = if refspec.dst == refspec.src {
+            src
+        } else if refspec.dst.starts_with("refs") {
+            SymbolicRef::new(refspec.dst)
+        } else if let Some(dst) = expand_remote_ref(refspec.dst, remote_refs) {
+            dst
+        } else {
+            // a new ref on the remote is of the same kind as the local ref
+            match src.kind() {
+                SymbolicRefKind::Branch => SymbolicRef::new(BitPath::REFS_HEADS.join(refspec.dst)),
+                SymbolicRefKind::Tag => SymbolicRef::new(BitPath::REFS_TAGS.join(refspec.dst)),
+                _ => bail!("destination `{}` of refspec is not a full ref name", refspec.dst),
+            }
+        };
+        ensure!(dst != SymbolicRef::HEAD, "HEAD is detached, specify the ref to push to");
+        Ok(vec![(Some(src), dst)])
+    }
+
+    /// Whether `ancestor` is reachable from `oid` (i.e. whether updating `ancestor` to `oid` is a
+    /// fast-forward)
+    pub(crate) fn is_ancestor(&self, ancestor: Oid, oid: Oid) -> BitResult<bool> {
+        // if we don't have the commit then it can't be in our history
+        if !self.obj_exists(ancestor)? {
+            return Ok(false);
         }
+        if self.read_obj_header(ancestor)?.obj_type != BitObjType::Commit
+            || self.read_obj_header(oid)?.obj_type != BitObjType::Commit
+        {
+            return Ok(false);
+        }
+        self.is_descendant_of(oid, ancestor)
+    }
+
+    /// The value we expect the remote ref `dst` to have, which is what our remote tracking branch
+    /// for it points at (or [`Oid::UNKNOWN`] if we don't have one)
+    fn expected_remote_oid(&self, remote: &Remote, dst: SymbolicRef) -> BitResult<Oid> {
+        let tracking = match remote.fetch.match_ref(dst) {
+            Some(tracking) => tracking,
+            None => return Ok(Oid::UNKNOWN),
+        };
+        Ok(self.try_fully_resolve_ref(tracking)?.unwrap_or(Oid::UNKNOWN))
     }
 
     pub fn ls_remotes(&self) -> impl Iterator<Item = Remote> {
@@ -235,7 +628,24 @@ impl BitRepo {
     }
 }
 
+/// Find the remote ref that `name` refers to (e.g. `master` -> `refs/heads/master`)
+fn expand_remote_ref(
+    name: BitPath,
+    remote_refs: &HashMap<SymbolicRef, Oid>,
+) -> Option<SymbolicRef> {
+    [BitPath::EMPTY, BitPath::REFS_HEADS, BitPath::REFS_TAGS]
+        .into_iter()
+        .map(|prefix| SymbolicRef::new(prefix.join(name)))
+        .find(|sym| remote_refs.contains_key(sym))
+}
+
 #[cfg(test)]
 mod clone_tests;
 #[cfg(test)]
+mod daemon_tests;
+#[cfg(test)]
+mod http_tests;
+#[cfg(test)]
+mod push_tests;
+#[cfg(test)]
 mod tests;
@@ -1,7 +1,10 @@
 use crate::error::BitResult;
 use crate::refs::BitRef;
-use crate::remote::{FetchStatus, DEFAULT_REMOTE};
+use crate::remote::{FetchOpts, FetchStatus, DEFAULT_REMOTE};
 use crate::repo::BitRepo;
+use crate::transport::MULTI_ACK_BATCH_SIZE;
+use std::path::Path;
+use tempfile::TempDir;
 
 #[test]
 fn test_fetch() -> BitResult<()> {
@@ -65,3 +68,80 @@ fn test_clone_cleanup_created_directory_on_failure() -> BitResult<()> {
     assert!(!repo_path.exists());
     Ok(())
 }
+
+/// clone `remote` into a new repository which speaks `protocol_version` to it
+fn clone_with_protocol_version(remote: &Path, protocol_version: i64) -> BitResult<TempDir> {
+    let local = tempfile::tempdir()?;
+    BitRepo::init_load(local.path(), |repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.to_str().unwrap())?;
+        repo.with_raw_local_config(|config| config.set("protocol", "version", protocol_version))?;
+        repo.clone_origin_blocking(&FetchOpts::default())
+    })?;
+    Ok(local)
+}
+
+#[test]
+fn test_clone_with_protocol_v1() -> BitResult<()> {
+    let remote_path = repos_dir!("head-on-main-not-master");
+    let local = clone_with_protocol_version(&remote_path, 1)?;
+    BitRepo::find(local.path(), |repo| {
+        assert_eq!(repo.read_head()?, symbolic_ref!("refs/heads/main"));
+        let fetch_summary = repo.fetch_blocking(DEFAULT_REMOTE)?;
+        assert_eq!(fetch_summary.status, FetchStatus::UpToDate);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_clone_with_protocol_v2() -> BitResult<()> {
+    let remote_path = repos_dir!("head-on-main-not-master");
+    let local = clone_with_protocol_version(&remote_path, 2)?;
+    BitRepo::find(local.path(), |repo| {
+        // the symref comes from `ls-refs` rather than the capabilities
+        assert_eq!(repo.read_head()?, symbolic_ref!("refs/heads/main"));
+        let fetch_summary = repo.fetch_blocking(DEFAULT_REMOTE)?;
+        assert_eq!(fetch_summary.status, FetchStatus::UpToDate);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_clone_empty_repo_with_protocol_v2() -> BitResult<()> {
+    let remote = tempfile::tempdir()?;
+    BitRepo::init(remote.path())?;
+    clone_with_protocol_version(remote.path(), 2)?;
+    Ok(())
+}
+
+// there is enough shared history that the server is ready before we run out of `have`s
+#[test]
+fn test_fetch_with_protocol_v2_negotiation() -> BitResult<()> {
+    let remote = tempfile::tempdir()?;
+    BitRepo::init_load(remote.path(), |repo| {
+        for i in 0..MULTI_ACK_BATCH_SIZE + 8 {
+            touch!(repo: "foo" < &i.to_string());
+            bit_commit_all!(repo);
+        }
+        Ok(())
+    })?;
+    let local = clone_with_protocol_version(remote.path(), 2)?;
+
+    let head = BitRepo::find(remote.path(), |repo| {
+        for i in 0..3 {
+            touch!(repo: "bar" < &i.to_string());
+            bit_commit_all!(repo);
+        }
+        repo.fully_resolve_head()
+    })?;
+
+    BitRepo::find(local.path(), |repo| {
+        let summary = repo.fetch_blocking(DEFAULT_REMOTE)?;
+        assert_eq!(summary.status, FetchStatus::NotUpToDate);
+        let tracking = symbolic_ref!("refs/remotes/origin/master");
+        assert_eq!(repo.fully_resolve_ref(tracking)?, head);
+        for (oid, _) in repo.reachable_objects(&[head], &[])? {
+            assert!(repo.obj_exists(oid)?);
+        }
+        Ok(())
+    })
+}
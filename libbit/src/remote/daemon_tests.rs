@@ -0,0 +1,140 @@
+use crate::daemon::{Daemon, DaemonOpts, EXPORT_OK_FILE};
+use crate::error::BitResult;
+use crate::refs::BitRef;
+use crate::remote::{FetchStatus, DEFAULT_REMOTE};
+use crate::repo::BitRepo;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// Runs a daemon in the background returning the port it is listening on
+fn serve(opts: DaemonOpts) -> BitResult<u16> {
+    let daemon = Daemon::new(opts)?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            tx.send(listener.local_addr().unwrap().port()).unwrap();
+            daemon.serve(listener).await.unwrap();
+        })
+    });
+    Ok(rx.recv()?)
+}
+
+/// Serves the parent directory of `remote` (with everything whitelisted)
+fn serve_parent(remote: &Path, export_all: bool) -> BitResult<String> {
+    let base_path = remote.parent().unwrap().to_path_buf();
+    let port = serve(DaemonOpts {
+        base_path: Some(base_path.clone()),
+        export_all,
+        whitelist: vec![base_path],
+        ..Default::default()
+    })?;
+    Ok(format!("git://127.0.0.1:{}/{}", port, remote.file_name().unwrap().to_str().unwrap()))
+}
+
+#[test]
+fn test_daemon_clone() -> BitResult<()> {
+    let remote_path = repos_dir!("ribble");
+    let url = serve_parent(&remote_path, true)?;
+    let tmpdir = tempfile::tempdir()?;
+    BitRepo::clone_blocking(tmpdir.path(), &url)?;
+
+    assert_eq!(std::fs::read_dir(tmpdir.path())?.count(), 23);
+
+    BitRepo::find(tmpdir.path(), |repo| {
+        let fetch_summary = repo.fetch_blocking(DEFAULT_REMOTE)?;
+        assert!(matches!(fetch_summary.status, FetchStatus::UpToDate));
+        assert_eq!(repo.read_head()?, BitRef::MASTER);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_daemon_only_serves_exported_repositories() -> BitResult<()> {
+    let remote_path = repos_dir!("ribble");
+    let url = serve_parent(&remote_path, false)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let err = BitRepo::clone_blocking(tmpdir.path(), &url).unwrap_err();
+    assert!(err.to_string().contains("access denied or repository not exported"));
+
+    std::fs::write(remote_path.join(".git").join(EXPORT_OK_FILE), "")?;
+    let tmpdir = tempfile::tempdir()?;
+    BitRepo::clone_blocking(tmpdir.path(), &url)?;
+    Ok(())
+}
+
+#[test]
+fn test_daemon_only_serves_whitelisted_repositories() -> BitResult<()> {
+    let remote_path = repos_dir!("ribble");
+    let elsewhere = tempfile::tempdir()?;
+    let port = serve(DaemonOpts {
+        base_path: None,
+        export_all: true,
+        whitelist: vec![elsewhere.path().to_path_buf()],
+        ..Default::default()
+    })?;
+
+    let url = format!("git://127.0.0.1:{}{}", port, remote_path.display());
+    let tmpdir = tempfile::tempdir()?;
+    let err = BitRepo::clone_blocking(tmpdir.path(), &url).unwrap_err();
+    assert!(err.to_string().contains("access denied or repository not exported"));
+    Ok(())
+}
+
+#[test]
+fn test_daemon_does_not_serve_directories_within_repositories() -> BitResult<()> {
+    let remote_path = repos_dir!("ribble");
+    std::fs::create_dir_all(remote_path.join("not-a-repo"))?;
+    let url = serve_parent(&remote_path, true)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let err = BitRepo::clone_blocking(tmpdir.path(), format!("{}/not-a-repo", url)).unwrap_err();
+    assert!(err.to_string().contains("access denied or repository not exported"));
+    Ok(())
+}
+
+#[test]
+fn test_daemon_refuses_connections_beyond_max_connections() -> BitResult<()> {
+    let remote_path = repos_dir!("ribble");
+    let base_path = remote_path.parent().unwrap().to_path_buf();
+    let port = serve(DaemonOpts {
+        base_path: Some(base_path),
+        export_all: true,
+        max_connections: Some(1),
+        ..Default::default()
+    })?;
+
+    // hold the only connection without ever sending a request
+    let _idle = std::net::TcpStream::connect(("127.0.0.1", port))?;
+    let url = format!("git://127.0.0.1:{}/ribble", port);
+    let tmpdir = tempfile::tempdir()?;
+    assert!(BitRepo::clone_blocking(tmpdir.path(), &url).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_daemon_closes_connections_that_time_out() -> BitResult<()> {
+    let remote_path = repos_dir!("ribble");
+    let base_path = remote_path.parent().unwrap().to_path_buf();
+    let port = serve(DaemonOpts {
+        base_path: Some(base_path),
+        export_all: true,
+        init_timeout: Some(Duration::from_millis(100)),
+        max_connections: Some(1),
+        ..Default::default()
+    })?;
+
+    // the idle connection is closed by the daemon which frees up the connection for the clone
+    let mut idle = std::net::TcpStream::connect(("127.0.0.1", port))?;
+    assert_eq!(idle.read(&mut [0; 1])?, 0);
+    std::thread::sleep(Duration::from_millis(100));
+
+    let url = format!("git://127.0.0.1:{}/ribble", port);
+    let tmpdir = tempfile::tempdir()?;
+    BitRepo::clone_blocking(tmpdir.path(), &url)?;
+    Ok(())
+}
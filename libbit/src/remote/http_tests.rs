@@ -0,0 +1,308 @@
+use crate::error::BitResult;
+use crate::refs::BitRef;
+use crate::remote::{FetchOpts, FetchStatus, PushOpts, DEFAULT_REMOTE};
+use crate::repo::BitRepo;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+
+/// Serves the repository at `path` over smart http in the background, much like `git http-backend`
+/// would (but with `git upload-pack` and `git receive-pack` doing all the real work).
+/// Every connection is closed after a single response.
+/// If `auth` is given then requests without exactly that `Authorization` header are rejected.
+/// Anything under `/moved` is redirected to the same path under `/repo`.
+/// Returns the url of the repository.
+fn serve(path: &Path, auth: Option<&'static str>) -> BitResult<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://127.0.0.1:{}/repo", listener.local_addr()?.port());
+    let path = path.to_path_buf();
+    std::thread::spawn(move || -> BitResult<()> {
+        for stream in listener.incoming() {
+            handle(&path, auth, stream?)?;
+        }
+        Ok(())
+    });
+    Ok(url)
+}
+
+fn handle(path: &Path, auth: Option<&str>, mut stream: TcpStream) -> BitResult<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        match line.trim_end().split_once(": ") {
+            Some((name, value)) => headers.insert(name.to_ascii_lowercase(), value.to_owned()),
+            None => break,
+        };
+    }
+    let content_length = headers.get("content-length").map_or(Ok(0), |len| len.parse())?;
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    if auth.is_some() && headers.get("authorization").map(String::as_str) != auth {
+        write!(stream, "HTTP/1.1 401 Unauthorized\r\n")?;
+        write!(stream, "Connection: close\r\nContent-Length: 0\r\n\r\n")?;
+        return Ok(());
+    }
+
+    let target = request_line
+        .split_ascii_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("malformed request line `{}`", request_line.trim_end()))?;
+    if let Some(rest) = target.strip_prefix("/moved/") {
+        write!(stream, "HTTP/1.1 301 Moved Permanently\r\nLocation: /repo/{}\r\n", rest)?;
+        write!(stream, "Connection: close\r\nContent-Length: 0\r\n\r\n")?;
+        return Ok(());
+    }
+
+    let rest = target.strip_prefix("/repo/").unwrap_or_default();
+    let (service, advertise) = match rest.strip_prefix("info/refs?service=") {
+        Some(service) => (service, true),
+        None => (rest, false),
+    };
+    let program = match service {
+        "git-upload-pack" => "upload-pack",
+        "git-receive-pack" => "receive-pack",
+        _ => {
+            write!(stream, "HTTP/1.1 404 Not Found\r\n")?;
+            write!(stream, "Connection: close\r\nContent-Length: 0\r\n\r\n")?;
+            return Ok(());
+        }
+    };
+    let content_type = if advertise {
+        format!("application/x-{}-advertisement", service)
+    } else {
+        format!("application/x-{}-result", service)
+    };
+
+    let mut command = Command::new("git");
+    command.arg(program).arg("--stateless-rpc");
+    // a protocol v2 advertisement doesn't start with the service line
+    let v2 = headers.get("git-protocol").map(String::as_str) == Some("version=2");
+    if v2 {
+        command.env("GIT_PROTOCOL", "version=2");
+    }
+    if advertise {
+        command.arg("--advertise-refs");
+    }
+    let mut child = command.arg(path).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+    child.stdin.take().ok_or_else(|| anyhow!("failed to open stdin"))?.write_all(&body)?;
+    let mut response = child.wait_with_output()?.stdout;
+    if advertise && !v2 {
+        let line = format!("# service={}\n", service);
+        let prefix = format!("{:04x}{}0000", 4 + line.len(), line);
+        response.splice(0..0, prefix.bytes());
+    }
+
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: {}\r\n", content_type)?;
+    write!(stream, "Connection: close\r\nTransfer-Encoding: chunked\r\n\r\n")?;
+    for chunk in response.chunks(8192) {
+        write!(stream, "{:x}\r\n", chunk.len())?;
+        stream.write_all(chunk)?;
+        write!(stream, "\r\n")?;
+    }
+    write!(stream, "0\r\n\r\n")?;
+    Ok(())
+}
+
+/// clone `url` with the given credential helper configured
+fn clone_with_helper(url: &str, helper: Option<&Path>) -> BitResult<TempDir> {
+    clone_with_config(url, helper, 2)
+}
+
+fn clone_with_config(
+    url: &str,
+    helper: Option<&Path>,
+    protocol_version: i64,
+) -> BitResult<TempDir> {
+    let local = tempfile::tempdir()?;
+    BitRepo::init_load(local.path(), |repo| {
+        repo.add_remote(DEFAULT_REMOTE, url)?;
+        repo.with_raw_local_config(|config| config.set("protocol", "version", protocol_version))?;
+        if let Some(helper) = helper {
+            repo.with_raw_local_config(|config| {
+                config.set("credential", "helper", helper.to_str().unwrap())
+            })?;
+        }
+        repo.clone_origin_blocking(&FetchOpts::default())
+    })?;
+    Ok(local)
+}
+
+#[test]
+fn test_http_clone() -> BitResult<()> {
+    let remote_path = repos_dir!("ribble");
+    let url = serve(&remote_path, None)?;
+    let tmpdir = tempfile::tempdir()?;
+    BitRepo::clone_blocking(tmpdir.path(), &url)?;
+
+    assert_eq!(std::fs::read_dir(tmpdir.path())?.count(), 23);
+
+    BitRepo::find(tmpdir.path(), |repo| {
+        let fetch_summary = repo.fetch_blocking(DEFAULT_REMOTE)?;
+        assert!(matches!(fetch_summary.status, FetchStatus::UpToDate));
+        assert_eq!(repo.read_head()?, BitRef::MASTER);
+        Ok(())
+    })
+}
+
+fn fetch_negotiates_over_multiple_requests(protocol_version: i64) -> BitResult<()> {
+    let remote = tempfile::tempdir()?;
+    BitRepo::init_load(remote.path(), |repo| {
+        touch!(repo: "foo" < "foo");
+        bit_commit_all!(repo);
+        Ok(())
+    })?;
+    let url = serve(remote.path(), None)?;
+    let local = clone_with_config(&url, None, protocol_version)?;
+
+    let head = BitRepo::find(remote.path(), |repo| {
+        for i in 0..3 {
+            touch!(repo: "foo" < &i.to_string());
+            bit_commit_all!(repo);
+        }
+        repo.fully_resolve_head()
+    })?;
+
+    BitRepo::find(local.path(), |repo| {
+        let summary = repo.fetch_blocking(DEFAULT_REMOTE)?;
+        assert_eq!(summary.status, FetchStatus::NotUpToDate);
+        let tracking = symbolic_ref!("refs/remotes/origin/master");
+        assert_eq!(repo.fully_resolve_ref(tracking)?, head);
+        // all the new objects were received
+        for (oid, _) in repo.reachable_objects(&[head], &[])? {
+            assert!(repo.obj_exists(oid)?);
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn test_http_fetch_negotiates_over_multiple_requests() -> BitResult<()> {
+    fetch_negotiates_over_multiple_requests(1)
+}
+
+#[test]
+fn test_http_fetch_negotiates_over_multiple_requests_with_protocol_v2() -> BitResult<()> {
+    fetch_negotiates_over_multiple_requests(2)
+}
+
+#[test]
+fn test_http_basic_auth_from_url() -> BitResult<()> {
+    let remote_path = repos_dir!("ribble");
+    // base64 of `user:password`
+    let url = serve(&remote_path, Some("Basic dXNlcjpwYXNzd29yZA=="))?;
+
+    let err = clone_with_helper(&url, None).unwrap_err();
+    assert!(err.to_string().starts_with("authentication failed"));
+
+    let url = url.replacen("http://", "http://user:password@", 1);
+    let local = clone_with_helper(&url, None)?;
+    BitRepo::find(local.path(), |repo| {
+        assert_eq!(repo.read_head()?, BitRef::MASTER);
+        Ok(())
+    })
+}
+
+/// Writes a credential helper into `dir` that always gives out `token` as a bearer token and
+/// appends what it is asked to do (and its input) to `dir/log`
+fn credential_helper(dir: &Path, token: &str) -> BitResult<PathBuf> {
+    let helper = dir.join("credential-helper");
+    let log = dir.join("log");
+    let script = format!(
+        "#!/bin/sh\necho \"$1\" >> {log}\ncat >> {log}\n\
+         echo authtype=Bearer\necho credential={token}\n",
+        log = log.display(),
+    );
+    std::fs::write(&helper, script)?;
+    std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755))?;
+    Ok(helper)
+}
+
+#[test]
+fn test_http_bearer_auth_from_credential_helper() -> BitResult<()> {
+    let remote_path = repos_dir!("ribble");
+    let url = serve(&remote_path, Some("Bearer secret-token"))?;
+
+    let helper_dir = tempfile::tempdir()?;
+    let helper = credential_helper(helper_dir.path(), "secret-token")?;
+    let local = clone_with_helper(&url, Some(&helper))?;
+    BitRepo::find(local.path(), |repo| {
+        assert_eq!(repo.read_head()?, BitRef::MASTER);
+        Ok(())
+    })?;
+
+    // the credentials worked so the helper is asked to store them
+    let log = std::fs::read_to_string(helper_dir.path().join("log"))?;
+    let lines = log.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "get");
+    assert!(lines.contains(&"protocol=http"));
+    assert!(lines.contains(&"store"));
+    assert!(lines.contains(&"credential=secret-token"));
+    assert!(!lines.contains(&"erase"));
+    Ok(())
+}
+
+#[test]
+fn test_http_rejected_credentials_are_erased() -> BitResult<()> {
+    let remote_path = repos_dir!("ribble");
+    let url = serve(&remote_path, Some("Bearer secret-token"))?;
+
+    let helper_dir = tempfile::tempdir()?;
+    let helper = credential_helper(helper_dir.path(), "wrong-token")?;
+    let err = clone_with_helper(&url, Some(&helper)).unwrap_err();
+    assert!(err.to_string().starts_with("authentication failed"));
+
+    let log = std::fs::read_to_string(helper_dir.path().join("log"))?;
+    let lines = log.lines().collect::<Vec<_>>();
+    assert!(lines.contains(&"erase"));
+    assert!(!lines.contains(&"store"));
+    Ok(())
+}
+
+#[test]
+fn test_http_clone_follows_initial_redirect() -> BitResult<()> {
+    let remote_path = repos_dir!("ribble");
+    let url = serve(&remote_path, None)?.replacen("/repo", "/moved", 1);
+    let local = clone_with_helper(&url, None)?;
+    BitRepo::find(local.path(), |repo| {
+        assert_eq!(repo.read_head()?, BitRef::MASTER);
+        let fetch_summary = repo.fetch_blocking(DEFAULT_REMOTE)?;
+        assert!(matches!(fetch_summary.status, FetchStatus::UpToDate));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_http_push() -> BitResult<()> {
+    let remote = tempfile::tempdir()?;
+    BitRepo::init_load(remote.path(), |repo| {
+        repo.with_raw_local_config(|config| config.set("receive", "denyCurrentBranch", "ignore"))?;
+        touch!(repo: "foo" < "foo");
+        bit_commit_all!(repo);
+        Ok(())
+    })?;
+    let url = serve(remote.path(), None)?;
+    let local = clone_with_helper(&url, None)?;
+
+    let head = BitRepo::find(local.path(), |repo| {
+        touch!(repo: "foo" < "bar");
+        bit_commit_all!(repo);
+        let summary = repo.push_blocking(DEFAULT_REMOTE, &[], PushOpts::default())?;
+        assert!(summary.is_ok());
+        repo.fully_resolve_head()
+    })?;
+
+    BitRepo::find(remote.path(), |repo| {
+        assert_eq!(repo.fully_resolve_head()?, head);
+        repo.read_obj_commit(head)?;
+        Ok(())
+    })
+}
@@ -0,0 +1,211 @@
+use crate::error::BitResult;
+use crate::obj::Oid;
+use crate::refs::{RefUpdateCause, SymbolicRef};
+use crate::remote::{PushOpts, PushStatus, Refspec, DEFAULT_REMOTE};
+use crate::repo::BitRepo;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// creates an empty repository that accepts pushes to its checked out branch
+fn pushable_remote() -> BitResult<TempDir> {
+    let remote = tempfile::tempdir()?;
+    BitRepo::init_load(remote.path(), |repo| {
+        repo.with_raw_local_config(|config| config.set("receive", "denyCurrentBranch", "ignore"))
+    })?;
+    Ok(remote)
+}
+
+/// reads a ref of the remote directly as we can't enter another repository from within a test
+fn read_remote_ref(remote: &Path, name: &str) -> BitResult<Option<Oid>> {
+    let path = remote.join(".git").join(name);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(std::fs::read_to_string(path)?.trim_end().parse()?))
+}
+
+fn refspec(s: &str) -> BitResult<Vec<Refspec>> {
+    Ok(vec![Refspec::parse_push(s)?])
+}
+
+#[test]
+fn test_push_to_empty_remote() -> BitResult<()> {
+    let remote = pushable_remote()?;
+    let head = BitRepo::with_minimal_repo(|repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.path().to_str().unwrap())?;
+        let summary = repo.push_blocking(DEFAULT_REMOTE, &[], PushOpts::default())?;
+        assert!(summary.is_ok());
+        assert_eq!(summary.updates.len(), 1);
+
+        let update = &summary.updates[0];
+        let head = repo.fully_resolve_head()?;
+        assert_eq!(update.dst, SymbolicRef::MASTER);
+        assert_eq!(update.old, Oid::UNKNOWN);
+        assert_eq!(update.new, head);
+        assert_eq!(update.status, PushStatus::Ok);
+        assert_eq!(
+            repo.fully_resolve_ref(symbolic_ref!("refs/remotes/origin/master"))?,
+            head,
+            "remote tracking branch should be updated"
+        );
+        Ok(head)
+    })?;
+
+    assert_eq!(read_remote_ref(remote.path(), "refs/heads/master")?, Some(head));
+    // check the remote actually received all the objects by cloning it
+    let clone = tempfile::tempdir()?;
+    BitRepo::clone_blocking(clone.path(), remote.path().to_str().unwrap())?;
+    BitRepo::find(clone.path(), |clone| {
+        assert_eq!(clone.fully_resolve_head()?, head);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_push_up_to_date() -> BitResult<()> {
+    let remote = pushable_remote()?;
+    BitRepo::with_minimal_repo(|repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.path().to_str().unwrap())?;
+        repo.push_blocking(DEFAULT_REMOTE, &[], PushOpts::default())?;
+        let summary = repo.push_blocking(DEFAULT_REMOTE, &[], PushOpts::default())?;
+        assert_eq!(summary.updates[0].status, PushStatus::UpToDate);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_push_fast_forward() -> BitResult<()> {
+    let remote = pushable_remote()?;
+    BitRepo::with_minimal_repo(|repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.path().to_str().unwrap())?;
+        repo.push_blocking(DEFAULT_REMOTE, &[], PushOpts::default())?;
+        let old = repo.fully_resolve_head()?;
+
+        modify!(repo: "foo" < "new foo contents");
+        bit_commit_all!(repo);
+        let summary = repo.push_blocking(DEFAULT_REMOTE, &[], PushOpts::default())?;
+        let update = &summary.updates[0];
+        assert_eq!(update.status, PushStatus::Ok);
+        assert_eq!(update.old, old);
+        assert!(!update.forced);
+        assert_eq!(read_remote_ref(remote.path(), "refs/heads/master")?, Some(update.new));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_push_non_fast_forward() -> BitResult<()> {
+    let remote = pushable_remote()?;
+    BitRepo::with_minimal_repo(|repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.path().to_str().unwrap())?;
+        modify!(repo: "foo" < "first");
+        bit_commit_all!(repo);
+        repo.push_blocking(DEFAULT_REMOTE, &[], PushOpts::default())?;
+        let pushed = repo.fully_resolve_head()?;
+
+        bit_reset!(repo: --hard "HEAD^");
+        modify!(repo: "foo" < "diverged");
+        bit_commit_all!(repo);
+        let diverged = repo.fully_resolve_head()?;
+
+        let summary = repo.push_blocking(DEFAULT_REMOTE, &[], PushOpts::default())?;
+        assert!(!summary.is_ok());
+        assert_eq!(summary.updates[0].status, PushStatus::RejectedNonFastForward);
+        assert_eq!(read_remote_ref(remote.path(), "refs/heads/master")?, Some(pushed));
+
+        // a `+` refspec forces just that ref
+        let refspecs = refspec("+master")?;
+        let summary = repo.push_blocking(DEFAULT_REMOTE, &refspecs, PushOpts::default())?;
+        assert!(summary.is_ok());
+        assert!(summary.updates[0].forced);
+        assert_eq!(read_remote_ref(remote.path(), "refs/heads/master")?, Some(diverged));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_push_force() -> BitResult<()> {
+    let remote = pushable_remote()?;
+    BitRepo::with_minimal_repo(|repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.path().to_str().unwrap())?;
+        modify!(repo: "foo" < "first");
+        bit_commit_all!(repo);
+        repo.push_blocking(DEFAULT_REMOTE, &[], PushOpts::default())?;
+
+        bit_reset!(repo: --hard "HEAD^");
+        let opts = PushOpts { force: true, ..Default::default() };
+        let summary = repo.push_blocking(DEFAULT_REMOTE, &[], opts)?;
+        assert_eq!(summary.updates[0].status, PushStatus::Ok);
+        assert!(summary.updates[0].forced);
+        assert_eq!(
+            read_remote_ref(remote.path(), "refs/heads/master")?,
+            Some(repo.fully_resolve_head()?)
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_push_force_with_lease() -> BitResult<()> {
+    let remote = pushable_remote()?;
+    BitRepo::with_minimal_repo(|repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.path().to_str().unwrap())?;
+        let initial = repo.fully_resolve_head()?;
+        modify!(repo: "foo" < "first");
+        bit_commit_all!(repo);
+        repo.push_blocking(DEFAULT_REMOTE, &[], PushOpts::default())?;
+        let pushed = repo.fully_resolve_head()?;
+        bit_reset!(repo: --hard "HEAD^");
+
+        let opts = PushOpts { force_with_lease: true, ..Default::default() };
+        // pretend we haven't seen the latest state of the remote
+        let tracking = symbolic!("refs/remotes/origin/master");
+        repo.update_ref(tracking, initial, RefUpdateCause::Push)?;
+        let summary = repo.push_blocking(DEFAULT_REMOTE, &[], opts)?;
+        assert_eq!(summary.updates[0].status, PushStatus::RejectedStale);
+        assert_eq!(read_remote_ref(remote.path(), "refs/heads/master")?, Some(pushed));
+
+        repo.update_ref(tracking, pushed, RefUpdateCause::Push)?;
+        let summary = repo.push_blocking(DEFAULT_REMOTE, &[], opts)?;
+        assert_eq!(summary.updates[0].status, PushStatus::Ok);
+        assert_eq!(read_remote_ref(remote.path(), "refs/heads/master")?, Some(initial));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_push_new_branch_then_delete() -> BitResult<()> {
+    let remote = pushable_remote()?;
+    BitRepo::with_minimal_repo(|repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.path().to_str().unwrap())?;
+        let head = repo.fully_resolve_head()?;
+        let refspecs = refspec("master:feature")?;
+        let summary = repo.push_blocking(DEFAULT_REMOTE, &refspecs, PushOpts::default())?;
+        assert_eq!(summary.updates[0].dst, symbolic!("refs/heads/feature"));
+        assert_eq!(read_remote_ref(remote.path(), "refs/heads/feature")?, Some(head));
+        assert_eq!(read_remote_ref(remote.path(), "refs/heads/master")?, None);
+        let tracking = symbolic!("refs/remotes/origin/feature");
+        assert_eq!(repo.fully_resolve_ref(tracking)?, head);
+
+        let refspecs = refspec(":feature")?;
+        let summary = repo.push_blocking(DEFAULT_REMOTE, &refspecs, PushOpts::default())?;
+        let update = &summary.updates[0];
+        assert_eq!(update.status, PushStatus::Ok);
+        assert_eq!(update.src, None);
+        assert_eq!(update.new, Oid::UNKNOWN);
+        assert_eq!(read_remote_ref(remote.path(), "refs/heads/feature")?, None);
+        assert_eq!(repo.try_fully_resolve_ref(tracking)?, None);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_push_delete_nonexistent_remote_ref() -> BitResult<()> {
+    let remote = pushable_remote()?;
+    BitRepo::with_minimal_repo(|repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.path().to_str().unwrap())?;
+        let refspecs = refspec(":nonexistent")?;
+        repo.push_blocking(DEFAULT_REMOTE, &refspecs, PushOpts::default()).unwrap_err();
+        Ok(())
+    })
+}
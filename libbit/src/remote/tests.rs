@@ -57,6 +57,33 @@ fn test_parse_refspec() -> BitResult<()> {
     Ok(())
 }
 
+#[test]
+fn test_parse_invalid_fetch_refspec() {
+    assert!("".parse::<Refspec>().is_err());
+    assert!("+".parse::<Refspec>().is_err());
+    assert!("é".parse::<Refspec>().is_err());
+    // `dst` can only be omitted when pushing
+    assert!("refs/heads/master".parse::<Refspec>().is_err());
+}
+
+#[test]
+fn test_parse_push_refspec() -> BitResult<()> {
+    let refspec = Refspec::parse_push("+master")?;
+    assert_eq!(
+        refspec,
+        Refspec { src: p!("master"), dst: p!("master"), forced: true, glob: false }
+    );
+
+    let refspec = Refspec::parse_push(":refs/heads/feature")?;
+    assert_eq!(
+        refspec,
+        Refspec { src: p!(""), dst: p!("refs/heads/feature"), forced: false, glob: false }
+    );
+
+    assert_eq!(Refspec::parse_push("é")?.src, p!("é"));
+    Ok(())
+}
+
 #[test]
 fn test_match_refspec() -> BitResult<()> {
     let refspec = "+refs/heads/master:refs/remotes/origin/master".parse::<Refspec>()?;
@@ -1,17 +1,23 @@
-use crate::cache::BitObjCache;
+use crate::bitmap::PackBitmapIndex;
+use crate::cache::{BitObjCache, BitObjCacheStats, CacheStats};
+use crate::commit_graph::{CommitGraphReader, COMMIT_GRAPH_FILE_PATH};
 use crate::config::{BitConfig, RemoteConfig};
-use crate::error::{BitError, BitErrorExt, BitGenericError, BitResult};
+use crate::error::{BitError, BitErrorExt, BitGenericError, BitResult, BitResultExt};
 use crate::index::BitIndex;
 use crate::io::ReadExt;
 use crate::merge::MergeStrategy;
 use crate::obj::*;
 use crate::odb::{BitObjDb, BitObjDbBackend};
+use crate::partial_clone::PromisorFetches;
 use crate::path::{self, BitPath};
+use crate::peel::Peel;
 use crate::refs::{BitRef, BitRefDb, BitRefDbBackend, RefUpdateCause, Refs, SymbolicRef};
 use crate::signature::BitSignature;
 use crate::tls;
 use anyhow::Context;
+use filebuffer::FileBuffer;
 use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use rustc_hash::FxHashSet;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
@@ -71,8 +77,12 @@ pub struct RepoCtxt {
     config_filepath: BitPath,
     index_filepath: BitPath,
     config: BitConfig,
-    obj_cache: RwLock<BitObjCache>,
+    obj_cache: BitObjCache,
     odb_cell: OnceLock<BitObjDb>,
+    commit_graph_cell: OnceLock<RwLock<Option<Arc<CommitGraphReader<FileBuffer>>>>>,
+    pack_bitmap_cell: OnceLock<RwLock<Option<Arc<PackBitmapIndex>>>>,
+    shallow_cell: OnceLock<RwLock<Arc<FxHashSet<Oid>>>>,
+    promisor_fetches: PromisorFetches,
     refdb_cell: OnceLock<BitRefDb>,
     index_cell: OnceLock<RwLock<BitIndex>>,
 }
@@ -84,6 +94,7 @@ impl RepoCtxt {
         let config_filepath = BitPath::intern(config_filepath);
         let config = BitConfig::init(config_filepath)?;
         let index_filepath = bitdir.join(BIT_INDEX_FILE_PATH);
+        let obj_cache = BitObjCache::new(config.object_cache_limit().max(0) as u64);
 
         Ok(Arc::new(Self {
             config_filepath,
@@ -92,8 +103,12 @@ impl RepoCtxt {
             index_filepath,
             config,
             odb_cell: Default::default(),
+            commit_graph_cell: Default::default(),
+            pack_bitmap_cell: Default::default(),
+            shallow_cell: Default::default(),
+            promisor_fetches: Default::default(),
             index_cell: Default::default(),
-            obj_cache: Default::default(),
+            obj_cache,
             refdb_cell: Default::default(),
         }))
     }
@@ -103,15 +118,21 @@ impl RepoCtxt {
         &self.config
     }
 
-    fn find_inner(path: &Path) -> BitResult<Arc<Self>> {
-        if path.join(".git").try_exists()? {
-            return Self::load(path);
-        }
-
+    /// The name of the git directory of the repository whose worktree is `path` (if there is one)
+    fn bitdir_name(path: &Path) -> BitResult<Option<&'static str>> {
         // also recognize `.bit` folder as its convenient for having bit repos under tests/repos
         // it is for testing and debugging purposes only
-        if path.join(".bit").try_exists()? {
-            return Self::load_with_bitdir(path, ".bit");
+        for name in [".git", ".bit"] {
+            if path.join(name).try_exists()? {
+                return Ok(Some(name));
+            }
+        }
+        Ok(None)
+    }
+
+    fn find_inner(path: &Path) -> BitResult<Arc<Self>> {
+        if let Some(bitdir) = Self::bitdir_name(path)? {
+            return Self::load_with_bitdir(path, bitdir);
         }
 
         match path.parent() {
@@ -120,6 +141,13 @@ impl RepoCtxt {
         }
     }
 
+    fn open_inner(path: &Path) -> BitResult<Arc<Self>> {
+        match Self::bitdir_name(path)? {
+            Some(bitdir) => Self::load_with_bitdir(path, bitdir),
+            None => Err(anyhow!("not a bit repository: `{}`", path.display())),
+        }
+    }
+
     fn load_with_bitdir(path: impl AsRef<Path>, bitdir: impl AsRef<Path>) -> BitResult<Arc<Self>> {
         let worktree = path
             .as_ref()
@@ -213,12 +241,15 @@ impl RepoCtxt {
 pub enum RepoState {
     None,
     Merging,
+    Rebasing,
 }
 
 impl BitRepo {
     pub fn repo_state(&self) -> RepoState {
         if self.bitdir.join(BitPath::MERGE_HEAD).exists() {
             RepoState::Merging
+        } else if self.bitdir.join(BitPath::REBASE_MERGE).exists() {
+            RepoState::Rebasing
         } else {
             RepoState::None
         }
@@ -288,6 +319,26 @@ impl BitRepo {
         RepoCtxt::find_inner(canonical_path.as_ref())?.enter(f)
     }
 
+    /// like [`BitRepo::find`] but `path` must be the root of the repository (the parent
+    /// directories are not searched)
+    pub fn open<R>(
+        path: impl AsRef<Path>,
+        f: impl FnOnce(BitRepo) -> BitResult<R>,
+    ) -> BitResult<R> {
+        let path = path.as_ref();
+        let canonical_path = path.canonicalize().with_context(|| {
+            format!("failed to open bit repository in nonexistent path `{}`", path.display())
+        })?;
+        RepoCtxt::open_inner(canonical_path.as_ref())?.enter(f)
+    }
+
+    /// The git directory of the repository whose root is exactly `path` (if there is one)
+    /// without loading the repository
+    pub fn bitdir_at(path: impl AsRef<Path>) -> BitResult<Option<PathBuf>> {
+        let path = path.as_ref();
+        Ok(RepoCtxt::bitdir_name(path)?.map(|name| path.join(name)))
+    }
+
     #[inline]
     pub fn refdb(&self) -> BitResult<&BitRefDb> {
         self.refdb_cell.get_or_try_init(|| Ok(BitRefDb::new(self.clone())))
@@ -491,11 +542,24 @@ impl BitRepo {
     // this method must be private to avoid people writing directly the odb directly bypassing the `virtual_write` checks
     #[inline]
     fn odb(&self) -> BitResult<&BitObjDb> {
-        self.odb_cell.get_or_try_init(|| BitObjDb::new(self.objects_dir()))
+        self.odb_cell.get_or_try_init(|| {
+            let delta_base_cache_limit = self.config().delta_base_cache_limit().max(0) as u64;
+            BitObjDb::new(self.objects_dir(), delta_base_cache_limit)
+        })
+    }
+
+    /// Statistics of the cache of parsed objects (for debugging)
+    pub fn obj_cache_stats(&self) -> BitObjCacheStats {
+        self.obj_cache.stats()
+    }
+
+    /// Statistics of the cache of delta bases used when reading packed objects (for debugging)
+    pub fn delta_base_cache_stats(&self) -> BitResult<CacheStats> {
+        Ok(self.odb()?.delta_base_cache_stats())
     }
 
     #[inline]
-    fn objects_dir(&self) -> BitPath {
+    pub(crate) fn objects_dir(&self) -> BitPath {
         self.bitdir.join(BIT_OBJECTS_DIR_PATH)
     }
 
@@ -504,6 +568,74 @@ impl BitRepo {
         self.objects_dir().join(BIT_PACK_OBJECTS_PATH)
     }
 
+    #[inline]
+    pub(crate) fn commit_graph_path(&self) -> BitPath {
+        self.objects_dir().join(COMMIT_GRAPH_FILE_PATH)
+    }
+
+    #[inline]
+    fn commit_graph_lock(&self) -> &RwLock<Option<Arc<CommitGraphReader<FileBuffer>>>> {
+        self.commit_graph_cell.get_or_init(|| RwLock::new(self.load_commit_graph()))
+    }
+
+    /// The commit-graph (if there is a usable one)
+    pub(crate) fn commit_graph(&self) -> Option<Arc<CommitGraphReader<FileBuffer>>> {
+        self.commit_graph_lock().read().clone()
+    }
+
+    pub fn refresh_commit_graph(&self) {
+        *self.commit_graph_lock().write() = self.load_commit_graph();
+    }
+
+    #[inline]
+    fn pack_bitmap_lock(&self) -> &RwLock<Option<Arc<PackBitmapIndex>>> {
+        self.pack_bitmap_cell.get_or_init(|| RwLock::new(self.load_pack_bitmap_index()))
+    }
+
+    /// The reachability bitmaps (if there is a pack with usable ones)
+    pub(crate) fn pack_bitmap_index(&self) -> Option<Arc<PackBitmapIndex>> {
+        self.pack_bitmap_lock().read().clone()
+    }
+
+    pub fn refresh_pack_bitmap_index(&self) {
+        *self.pack_bitmap_lock().write() = self.load_pack_bitmap_index();
+    }
+
+    #[inline]
+    fn shallow_lock(&self) -> BitResult<&RwLock<Arc<FxHashSet<Oid>>>> {
+        self.shallow_cell.get_or_try_init::<_, BitGenericError>(|| {
+            Ok(RwLock::new(Arc::new(self.read_shallow_file()?)))
+        })
+    }
+
+    /// The commits on the boundary of a shallow repository (whose parents we don't have).
+    /// These are treated as if they have no parents.
+    pub fn shallow_commits(&self) -> BitResult<Arc<FxHashSet<Oid>>> {
+        Ok(self.shallow_lock()?.read().clone())
+    }
+
+    pub fn refresh_shallow_commits(&self) -> BitResult<()> {
+        *self.shallow_lock()?.write() = Arc::new(self.read_shallow_file()?);
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn promisor_fetches(&self) -> &PromisorFetches {
+        &self.promisor_fetches
+    }
+
+    /// Reads from the odb, first fetching the object from the promisor remote if it is missing
+    /// from a partial clone
+    fn read_odb<R>(&self, id: BitId, read: impl Fn(&BitObjDb) -> BitResult<R>) -> BitResult<R> {
+        match read(self.odb()?) {
+            Err(err) if err.is_not_found_err() => match id {
+                BitId::Full(oid) if self.fetch_promised_object(oid)? => read(self.odb()?),
+                _ => Err(err),
+            },
+            r => r,
+        }
+    }
+
     /// writes `obj` into the object store returning its full hash
     pub fn write_obj(&self, obj: &dyn WritableObject) -> BitResult<Oid> {
         // TODO cache this object as a write is often followed by an immediate read
@@ -512,7 +644,10 @@ impl BitRepo {
     }
 
     pub fn refresh_odb(&self) -> BitResult<()> {
-        self.odb()?.refresh()
+        self.odb()?.refresh()?;
+        // the pack the bitmaps belong to may have been added or removed
+        self.refresh_pack_bitmap_index();
+        Ok(())
     }
 
     pub fn read_obj(&self, id: impl Into<BitId>) -> BitResult<BitObjKind> {
@@ -520,13 +655,26 @@ impl BitRepo {
         trace!("BitRepo::read_obj(id: {})", id);
         let oid = self.expand_id(id)?;
         if oid == Oid::EMPTY_TREE {
-            Ok(BitObjKind::Tree(Tree::empty(self.downgrade())))
-        } else {
-            self.obj_cache.write().get_or_insert_with(oid, || {
-                let raw = self.odb()?.read_raw(BitId::Full(oid))?;
-                BitObjKind::from_raw(self.downgrade(), raw)
-            })
+            return Ok(BitObjKind::Tree(Tree::empty(self.downgrade())));
+        }
+
+        match self.obj_cache.get(oid) {
+            Some(obj) => Ok(obj),
+            None => {
+                // the cache is not locked while the object is read so reads can happen in parallel
+                let raw = self.read_odb(oid.into(), |odb| odb.read_raw(BitId::Full(oid)))?;
+                let obj = BitObjKind::from_raw(self.downgrade(), raw)?;
+                Ok(self.obj_cache.insert(oid, obj))
+            }
+        }
+    }
+
+    /// reads the uncompressed bytes of an object without parsing it (bypassing the object cache)
+    pub(crate) fn read_obj_raw(&self, oid: Oid) -> BitResult<BitPackObjRaw> {
+        if oid == Oid::EMPTY_TREE {
+            return Ok(BitPackObjRaw { obj_type: BitObjType::Tree, bytes: vec![] });
         }
+        self.read_odb(oid.into(), |odb| odb.read_raw(BitId::Full(oid)))?.into_pack_raw()
     }
 
     pub fn read_obj_tree(&self, id: impl Into<BitId>) -> BitResult<Arc<Tree>> {
@@ -560,6 +708,16 @@ impl BitRepo {
         Ok(())
     }
 
+    /// Similar to [`Self::ensure_obj_is_commit`] but also accepts annotated tags that peel to a commit
+    pub fn ensure_obj_is_commitish(&self, id: impl Into<BitId>) -> BitResult<()> {
+        let oid = self.expand_id(id)?;
+        self.ensure_obj_exists(oid)?;
+        match self.read_obj_header(oid)?.obj_type {
+            BitObjType::Tag => oid.peel(self).map(drop),
+            _ => self.ensure_obj_is_commit(oid),
+        }
+    }
+
     #[must_use = "this call has no side effects (you may want to use `ensure_obj_exists` instead)"]
     // note, the above annotation doesn't really do anything as "question marking" the return value counts as a use so...
     // but nevertheless, its non-useless docs as I've made the mistake already
@@ -568,7 +726,8 @@ impl BitRepo {
     }
 
     pub fn read_obj_header(&self, id: impl Into<BitId>) -> BitResult<BitObjHeader> {
-        self.odb()?.read_header(id.into())
+        let id = id.into();
+        self.read_odb(id, |odb| odb.read_header(id))
     }
 
     /// Read the file at `path` on the worktree into a mutable blob object
@@ -1,3 +1,4 @@
+mod reachable;
 mod revwalk;
 
 pub use revwalk::*;
@@ -87,7 +88,7 @@ impl BitRepo {
                 return Ok(BitRef::Direct(oid));
             }
 
-            let commit = self.read_obj_commit(oid)?;
+            let commit = oid.peel(self)?;
             let parentc = commit.parents.len();
 
             if parentc == 0 {
@@ -0,0 +1,148 @@
+use crate::error::BitResult;
+use crate::obj::{BitObjKind, BitObjType, BitObject, Commit, Oid};
+use crate::path::BitPath;
+use crate::peel::Peel;
+use crate::repo::BitRepo;
+use fallible_iterator::FallibleIterator;
+use rustc_hash::FxHashSet;
+use smallvec::SmallVec;
+use std::sync::Arc;
+
+impl BitRepo {
+    /// Returns the oids of all objects reachable from `include` but not from `exclude`
+    /// (i.e. `git rev-list --objects <include> --not <exclude>`).
+    /// Commits come first (in revwalk order) followed by trees and blobs.
+    /// Each object is paired with the path it was first found at (empty if not found in a tree).
+    /// Only the trees of commits on the boundary of the walk are excluded, so objects that are
+    /// reachable only through older excluded commits may still be included (which is what git does too).
+    /// If there are reachability bitmaps then they are used instead of walking all of history
+    /// (see [`BitRepo::bitmap_reachable_objects`]). Those objects have no path but the bitmap index
+    /// stores the hash of their path, which is all [`PackBuilder`] needs.
+    ///
+    /// [`PackBuilder`]: crate::pack::PackBuilder
+    pub fn reachable_objects(
+        &self,
+        include: &[Oid],
+        exclude: &[Oid],
+    ) -> BitResult<Vec<(Oid, BitPath)>> {
+        if let Some(objects) = self.bitmap_reachable_objects(include, exclude)? {
+            return Ok(objects.into_objects());
+        }
+
+        let mut objects = vec![];
+        let mut seen = FxHashSet::default();
+        let mut roots = vec![];
+        let mut pending_trees = vec![];
+
+        for &oid in include {
+            let mut obj = self.read_obj(oid)?;
+            // annotated tags are sent as is along with whatever they point at
+            while let BitObjKind::Tag(tag) = obj {
+                if seen.insert(tag.oid()) {
+                    objects.push((tag.oid(), BitPath::EMPTY));
+                }
+                obj = self.read_obj(tag.object)?;
+            }
+            match obj {
+                BitObjKind::Commit(commit) => roots.push(commit),
+                BitObjKind::Tree(tree) => pending_trees.push(tree.oid()),
+                BitObjKind::Blob(blob) =>
+                    if seen.insert(blob.oid()) {
+                        objects.push((blob.oid(), BitPath::EMPTY));
+                    },
+                BitObjKind::Tag(..) => unreachable!(),
+            }
+        }
+
+        let excluded =
+            exclude.iter().map(|oid| oid.peel(self)).collect::<BitResult<SmallVec<_>>>()?;
+        let commits = self
+            .revwalk_builder()
+            .roots_iter(roots)?
+            .excluding(excluded.clone())
+            .build()?
+            .collect::<Vec<Arc<Commit>>>()?;
+
+        let included = commits.iter().map(|commit| commit.oid()).collect::<FxHashSet<_>>();
+        let mut boundary = excluded.iter().map(|commit| commit.tree).collect::<Vec<_>>();
+        // the parents of shallow commits don't exist so they can't be on the boundary
+        let shallow = self.shallow_commits()?;
+        for commit in commits.iter().filter(|commit| !shallow.contains(&commit.oid())) {
+            for &parent in &commit.parents {
+                if !included.contains(&parent) {
+                    boundary.push(self.read_obj_commit(parent)?.tree);
+                }
+            }
+        }
+
+        // everything reachable from the boundary is assumed to already be present on the other end
+        let mut discard = vec![];
+        for tree in boundary {
+            self.walk_tree_objects(tree, BitPath::EMPTY, &mut seen, &mut discard)?;
+        }
+
+        for commit in &commits {
+            if seen.insert(commit.oid()) {
+                objects.push((commit.oid(), BitPath::EMPTY));
+            }
+        }
+        for tree in commits.iter().map(|commit| commit.tree).chain(pending_trees) {
+            self.walk_tree_objects(tree, BitPath::EMPTY, &mut seen, &mut objects)?;
+        }
+        Ok(objects)
+    }
+
+    /// The number of objects that [`BitRepo::reachable_objects`] would return
+    /// (which is mostly just counting bits if there are reachability bitmaps)
+    pub fn count_reachable_objects(&self, include: &[Oid], exclude: &[Oid]) -> BitResult<usize> {
+        match self.bitmap_reachable_objects(include, exclude)? {
+            Some(objects) => Ok(objects.len()),
+            None => Ok(self.reachable_objects(include, exclude)?.len()),
+        }
+    }
+
+    /// The number of commits reachable from `include` but not from `exclude`
+    pub fn count_reachable_commits(&self, include: &[Oid], exclude: &[Oid]) -> BitResult<usize> {
+        if let Some(objects) = self.bitmap_reachable_objects(include, exclude)? {
+            return Ok(objects.count(BitObjType::Commit));
+        }
+
+        let excluded =
+            exclude.iter().map(|oid| oid.peel(self)).collect::<BitResult<SmallVec<_>>>()?;
+        self.revwalk_builder()
+            .roots_iter(include.iter().copied())?
+            .excluding(excluded)
+            .build()?
+            .count()
+    }
+
+    fn walk_tree_objects(
+        &self,
+        tree: Oid,
+        path: BitPath,
+        seen: &mut FxHashSet<Oid>,
+        objects: &mut Vec<(Oid, BitPath)>,
+    ) -> BitResult<()> {
+        if !seen.insert(tree) {
+            return Ok(());
+        }
+        // the trees that a partial clone is missing are left to the promisor remote rather than
+        // fetched just to be walked
+        if !self.obj_exists(tree)? && self.is_partial_clone() {
+            return Ok(());
+        }
+        objects.push((tree, path));
+
+        for entry in &self.read_obj_tree(tree)?.entries {
+            if entry.mode.is_tree() {
+                self.walk_tree_objects(entry.oid, path.join(entry.path), seen, objects)?;
+            } else if entry.mode.is_gitlink() {
+                // submodule commits live in another repository
+                continue;
+            } else if seen.insert(entry.oid) {
+                objects.push((entry.oid, path.join(entry.path)));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use super::Revspec;
+use crate::commit_graph::{CommitInfo, CommitInfoReader, GENERATION_NUMBER_INFINITY};
 use crate::error::{BitGenericError, BitResult};
 use crate::obj::{BitObject, Commit, Oid};
 use crate::peel::Peel;
@@ -14,7 +15,8 @@ use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq)]
 struct CommitNode {
-    commit: Arc<Commit>,
+    // read from the commit-graph if possible so commits that are never yielded are never parsed
+    info: CommitInfo,
     // *NOTE* We are reasoning under the assumption that committer timestamps are *non-decreasing*
     // In the absolute worst case all timestamps will be equal but a child can never be committed before parent
     // which is obviously true but maybe wrong systems times can cause issues. In bit, the committin has a check against this,
@@ -75,10 +77,10 @@ impl PartialOrd for CommitNode {
 }
 
 impl Deref for CommitNode {
-    type Target = Commit;
+    type Target = CommitInfo;
 
     fn deref(&self) -> &Self::Target {
-        &self.commit
+        &self.info
     }
 }
 
@@ -91,9 +93,8 @@ impl Ord for CommitNode {
     // we want this cmp to suit a maxheap
     // so we want the most recent (largest timestamp) commit to be >= and the smallest index to be >=
     fn cmp(&self, other: &Self) -> Ordering {
-        self.committer
-            .time
-            .cmp(&other.committer.time)
+        self.commit_time
+            .cmp(&other.commit_time)
             .then_with(|| other.index.cmp(&self.index))
             .then_with(|| bug!())
     }
@@ -170,11 +171,13 @@ impl RevWalkBuilder {
         self
     }
 
-    pub fn build(self) -> RevWalk {
+    pub fn build(self) -> BitResult<RevWalk> {
         let mut this = RevWalk {
+            commits: CommitInfoReader::new(self.repo.clone())?,
             repo: self.repo,
             flags: Default::default(),
             pqueue: Default::default(),
+            uninteresting: Default::default(),
             index: 0,
         };
 
@@ -182,32 +185,39 @@ impl RevWalkBuilder {
             this.enqueue_commit_with_flags(commit, CommitNodeFlags::UNINTERESTING)
         });
         self.roots.into_iter().for_each(|commit| this.enqueue_commit(commit));
-        this
+        Ok(this)
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct RevWalk {
     repo: BitRepo,
+    commits: CommitInfoReader,
     // map of commit oid to their flags
     // I suppose this field name should be doubly plural
     flags: FxHashMap<Oid, CommitNodeFlags>,
     pqueue: BinaryHeap<CommitNode>,
+    // the queued uninteresting commits with known generations ordered by generation (which may
+    // include commits that have since been visited)
+    uninteresting: BinaryHeap<(u32, Oid)>,
     index: usize,
 }
 
 impl RevWalk {
-    pub fn new(roots: SmallVec<[Arc<Commit>; 2]>) -> Self {
+    pub fn new(roots: SmallVec<[Arc<Commit>; 2]>) -> BitResult<Self> {
         debug_assert!(!roots.is_empty());
+        let repo = roots[0].owner();
         let mut this = Self {
-            repo: roots[0].owner(),
+            commits: CommitInfoReader::new(repo.clone())?,
+            repo,
             flags: Default::default(),
             pqueue: Default::default(),
+            uninteresting: Default::default(),
             index: 0,
         };
 
         roots.into_iter().for_each(|commit| this.enqueue_commit(commit));
-        this
+        Ok(this)
     }
 
     fn next_index(&mut self) -> usize {
@@ -216,27 +226,46 @@ impl RevWalk {
         index
     }
 
-    fn mk_node(&mut self, commit: Arc<Commit>) -> CommitNode {
-        CommitNode { commit, index: self.next_index() }
+    fn mk_node(&mut self, info: CommitInfo) -> CommitNode {
+        CommitNode { info, index: self.next_index() }
     }
 
-    fn enqueue_commit_with_flags(&mut self, commit: Arc<Commit>, init_flags: CommitNodeFlags) {
-        let flags = self.flags.entry(commit.oid()).or_default();
+    fn enqueue_with_flags(&mut self, info: CommitInfo, init_flags: CommitNodeFlags) {
+        let flags = self.flags.entry(info.oid).or_default();
         if flags.intersects(CommitNodeFlags::ENQUEUED | CommitNodeFlags::YIELDED) {
             return;
         }
         flags.insert(init_flags | CommitNodeFlags::ENQUEUED);
-        let node = self.mk_node(commit);
+        if flags.contains(CommitNodeFlags::UNINTERESTING) {
+            self.push_uninteresting(&info);
+        }
+        let node = self.mk_node(info);
         self.pqueue.push(node)
     }
 
+    fn push_uninteresting(&mut self, info: &CommitInfo) {
+        if info.generation != GENERATION_NUMBER_INFINITY {
+            self.uninteresting.push((info.generation, info.oid));
+        }
+    }
+
+    fn enqueue_commit_with_flags(&mut self, commit: Arc<Commit>, init_flags: CommitNodeFlags) {
+        let info = self.commits.from_commit(&commit);
+        self.enqueue_with_flags(info, init_flags)
+    }
+
     pub fn enqueue_commit(&mut self, commit: Arc<Commit>) {
         self.enqueue_commit_with_flags(commit, CommitNodeFlags::default())
     }
 
-    fn mark_parents_uninteresting(&mut self, node: &CommitNode) {
-        for &parent in &node.parents {
-            self.flags.entry(parent).or_default().insert(CommitNodeFlags::UNINTERESTING)
+    fn mark_uninteresting(&mut self, info: &CommitInfo) {
+        let flags = self.flags.entry(info.oid).or_default();
+        if flags.contains(CommitNodeFlags::UNINTERESTING) {
+            return;
+        }
+        flags.insert(CommitNodeFlags::UNINTERESTING);
+        if flags.contains(CommitNodeFlags::ENQUEUED) && !flags.contains(CommitNodeFlags::YIELDED) {
+            self.push_uninteresting(info);
         }
     }
 
@@ -245,12 +274,12 @@ impl RevWalk {
             .iter()
             .map(|&rev| repo.fully_resolve_rev(rev)?.peel(repo))
             .collect::<Result<SmallVec<_>, _>>()?;
-        Ok(Self::new(roots))
+        Self::new(roots)
     }
 
     pub fn walk_revspec(repo: &BitRepo, rev: &Revspec) -> BitResult<Self> {
         let root = repo.fully_resolve_rev(rev)?.peel(repo)?;
-        Ok(Self::new(smallvec![root]))
+        Self::new(smallvec![root])
     }
 
     pub fn walk_commit(root: Arc<Commit>) -> BitResult<Self> {
@@ -260,13 +289,53 @@ impl RevWalk {
     pub fn walk_commits(roots: impl IntoIterator<Item = Arc<Commit>>) -> BitResult<Self> {
         let roots = roots.into_iter().collect::<SmallVec<_>>();
         ensure!(!roots.is_empty());
-        Ok(Self::new(roots))
+        Self::new(roots)
+    }
+
+    fn is_uninteresting(&self, node: &CommitNode) -> bool {
+        self.flags[&node.oid].contains(CommitNodeFlags::UNINTERESTING)
     }
 
     fn still_interesting(&self) -> bool {
-        self.pqueue
-            .iter()
-            .any(|node| !self.flags[&node.oid()].contains(CommitNodeFlags::UNINTERESTING))
+        self.pqueue.iter().any(|node| !self.is_uninteresting(node))
+    }
+
+    /// Walk the queued uninteresting commits with a generation larger than `generation` (and the
+    /// uninteresting commits they lead to) until none are left.
+    /// The ancestors of a commit all have a strictly smaller generation, so once the generations of
+    /// the uninteresting commits are below that of an interesting commit, none of them can reach
+    /// it and it's safe to yield.
+    /// Otherwise, skewed committer timestamps could lead to yielding a commit that turns out to be
+    /// reachable from an excluded commit. Commits with unknown generations are not waited on.
+    /// The commits visited here are left in `pqueue` and skipped when they are popped.
+    fn walk_uninteresting_above(&mut self, generation: u32) -> BitResult<()> {
+        while let Some(&(uninteresting_generation, oid)) = self.uninteresting.peek() {
+            if uninteresting_generation <= generation {
+                break;
+            }
+            self.uninteresting.pop();
+            if !self.flags[&oid].contains(CommitNodeFlags::YIELDED) {
+                let info = self.commits.read(oid)?;
+                self.visit(&info)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Enqueue the parents of `info` and mark it as yielded, returning whether it is interesting
+    fn visit(&mut self, info: &CommitInfo) -> BitResult<bool> {
+        let flags = self.flags.entry(info.oid).or_default();
+        flags.insert(CommitNodeFlags::YIELDED);
+        let interesting = !flags.contains(CommitNodeFlags::UNINTERESTING);
+
+        for &parent in &info.parents {
+            let parent = self.commits.read(parent)?;
+            if !interesting {
+                self.mark_uninteresting(&parent);
+            }
+            self.enqueue_with_flags(parent, CommitNodeFlags::default());
+        }
+        Ok(interesting)
     }
 }
 
@@ -283,17 +352,17 @@ impl FallibleIterator for RevWalk {
                 None => return Ok(None),
             };
 
-            for &parent in &node.parents {
-                self.enqueue_commit(self.repo.read_obj_commit(parent)?);
+            // already visited by `walk_uninteresting_above`
+            if self.flags[&node.oid].contains(CommitNodeFlags::YIELDED) {
+                continue;
             }
 
-            let flags = self.flags.entry(node.oid()).or_default();
-            flags.insert(CommitNodeFlags::YIELDED);
+            if node.generation != GENERATION_NUMBER_INFINITY && !self.is_uninteresting(&node) {
+                self.walk_uninteresting_above(node.generation)?;
+            }
 
-            if flags.contains(CommitNodeFlags::UNINTERESTING) {
-                self.mark_parents_uninteresting(&node);
-            } else {
-                return Ok(Some(node.commit));
+            if self.visit(&node)? {
+                return self.repo.read_obj_commit(node.oid).map(Some);
             }
         }
         Ok(None)
@@ -80,7 +80,7 @@ fn test_revwalk_with_exclude() -> BitResult<()> {
             .revwalk_builder()
             .root_revisions(Some(&rev!("master")))?
             .excluding_revisions(Some(&rev!("some-branch")))?
-            .build();
+            .build()?;
         let oids = revwalk.map(|commit| Ok(commit.oid())).collect::<Vec<_>>()?;
         let expected = [
             "9aed6cad276983296289c808f85cdcecdcbc6aff".into(),
@@ -97,7 +97,7 @@ fn test_revwalk_with_exclude() -> BitResult<()> {
             .revwalk_builder()
             .root_revisions(Some(&rev!("some-branch")))?
             .excluding_revisions(Some(&rev!("master")))?
-            .build();
+            .build()?;
         let oids = revwalk.map(|commit| Ok(commit.oid())).collect::<Vec<_>>()?;
         let expected = [
             "e05d3317f7de167d3c66926c4b4d65802aa679fc".into(),
@@ -109,6 +109,56 @@ fn test_revwalk_with_exclude() -> BitResult<()> {
     })
 }
 
+#[test]
+fn test_reachable_objects_excluding_self_is_empty() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        assert!(repo.reachable_objects(&[head], &[head])?.is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_reachable_objects_excluding_parent() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let head = repo.read_obj_commit(repo.fully_resolve_head()?)?;
+        let parent = repo.fully_resolve_rev(&rev!("HEAD^"))?;
+        let objects = repo.reachable_objects(&[head.oid()], &[parent])?;
+        let objects = objects.into_iter().map(|(oid, _)| oid).collect::<Vec<_>>();
+        assert_eq!(objects[0], head.oid());
+        assert!(objects.contains(&head.tree));
+        assert!(!objects.contains(&parent));
+        // every file in the parent commit is empty
+        assert!(!objects.contains(&Oid::EMPTY_BLOB));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_reachable_objects_includes_annotated_tag() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let sym = repo.bit_create_tag("v1", &rev!("HEAD"), Some("release\n".to_owned()), false)?;
+        let tag = repo.fully_resolve_ref(sym)?;
+        let objects = repo.reachable_objects(&[tag], &[])?;
+        let objects = objects.into_iter().map(|(oid, _)| oid).collect::<Vec<_>>();
+        assert_eq!(objects[0], tag);
+        assert_eq!(objects[1], repo.fully_resolve_head()?);
+        assert!(objects.contains(&Oid::EMPTY_BLOB));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_reachable_objects_records_paths() -> BitResult<()> {
+    BitRepo::with_minimal_repo_with_dir(|repo| {
+        let head = repo.fully_resolve_head()?;
+        let objects = repo.reachable_objects(&[head], &[])?;
+        let paths = objects.iter().map(|(_, path)| path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, ["", "", "dir", "dir/bar"]);
+        Ok(())
+    })
+}
+
 // TODO there are bugs in revwalk
 // A - B
 //  \
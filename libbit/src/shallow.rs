@@ -0,0 +1,86 @@
+use crate::error::BitResult;
+use crate::gc::Expiry;
+use crate::lockfile::{Lockfile, LockfileFlags};
+use crate::obj::Oid;
+use crate::path::BitPath;
+use crate::repo::BitRepo;
+use rustc_hash::FxHashSet;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+// A shallow repository is missing the history beyond some commits (e.g. after `clone --depth`).
+// Those commits are listed one per line in `.git/shallow` and history walks must stop at them
+// as their parents aren't expected to exist.
+
+pub const SHALLOW_FILE_PATH: &str = "shallow";
+
+/// Parses the cutoff for `--shallow-since` which is either seconds since the epoch or a relative
+/// time such as `2.weeks.ago`
+pub fn parse_shallow_since(s: &str) -> BitResult<SystemTime> {
+    if let Ok(secs) = s.trim().parse::<u64>() {
+        return Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+    }
+    match s.parse()? {
+        Expiry::At(time) => Ok(time),
+        Expiry::Never => bail!("invalid shallow-since date `{}`", s),
+    }
+}
+
+impl BitRepo {
+    #[inline]
+    pub(crate) fn shallow_path(&self) -> BitPath {
+        self.bitdir.join(SHALLOW_FILE_PATH)
+    }
+
+    /// Whether the repository has incomplete history
+    pub fn is_shallow(&self) -> BitResult<bool> {
+        Ok(!self.shallow_commits()?.is_empty())
+    }
+
+    pub(crate) fn read_shallow_file(&self) -> BitResult<FxHashSet<Oid>> {
+        let path = self.shallow_path();
+        if !path.try_exists()? {
+            return Ok(Default::default());
+        }
+        std::fs::read_to_string(path)?
+            .lines()
+            .map(|line| line.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|err| anyhow!("corrupt shallow file `{}`: {}", path, err))
+    }
+
+    /// Add the new `shallow` commits and remove the `unshallow` commits (that we now have the
+    /// parents of) from the shallow file.
+    /// The shallow file is removed once the repository is no longer shallow.
+    pub(crate) fn update_shallow(&self, shallow: &[Oid], unshallow: &[Oid]) -> BitResult<()> {
+        if shallow.is_empty() && unshallow.is_empty() {
+            return Ok(());
+        }
+
+        let mut commits = self.read_shallow_file()?;
+        commits.extend(shallow);
+        for oid in unshallow {
+            commits.remove(oid);
+        }
+
+        let path = self.shallow_path();
+        if commits.is_empty() {
+            if path.try_exists()? {
+                std::fs::remove_file(path)?;
+            }
+        } else {
+            let mut commits = commits.into_iter().collect::<Vec<_>>();
+            commits.sort();
+            Lockfile::with_mut(path, LockfileFlags::empty(), |lockfile| {
+                commits.iter().try_for_each(|oid| writeln!(lockfile, "{}", oid))?;
+                Ok(())
+            })?;
+        }
+        self.refresh_shallow_commits()?;
+        self.refresh_commit_graph();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
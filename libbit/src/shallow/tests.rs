@@ -0,0 +1,137 @@
+use super::parse_shallow_since;
+use crate::error::BitResult;
+use crate::remote::{FetchOpts, DEFAULT_REMOTE};
+use crate::repo::BitRepo;
+use fallible_iterator::FallibleIterator;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tempfile::TempDir;
+
+/// A remote with `n` commits on master
+fn remote_with_commits(n: usize) -> BitResult<TempDir> {
+    let remote = tempfile::tempdir()?;
+    BitRepo::init_load(remote.path(), |repo| {
+        for i in 0..n {
+            touch!(repo: "foo" < &i.to_string());
+            bit_commit_all!(repo);
+        }
+        Ok(())
+    })?;
+    Ok(remote)
+}
+
+fn shallow_clone(remote: &Path, opts: FetchOpts, protocol_version: i64) -> BitResult<TempDir> {
+    let local = tempfile::tempdir()?;
+    BitRepo::init_load(local.path(), |repo| {
+        repo.add_remote(DEFAULT_REMOTE, remote.to_str().unwrap())?;
+        repo.with_raw_local_config(|config| config.set("protocol", "version", protocol_version))?;
+        repo.clone_origin_blocking(&opts)
+    })?;
+    Ok(local)
+}
+
+fn history_len(repo: &BitRepo) -> BitResult<usize> {
+    let head = repo.fully_resolve_head()?;
+    repo.revwalk_builder().roots_iter([head])?.build()?.count()
+}
+
+fn test_shallow_clone_then_deepen(protocol_version: i64) -> BitResult<()> {
+    let remote = remote_with_commits(5)?;
+    let local = shallow_clone(
+        remote.path(),
+        FetchOpts { depth: Some(1), ..Default::default() },
+        protocol_version,
+    )?;
+
+    BitRepo::find(local.path(), |repo| {
+        assert!(repo.is_shallow()?);
+        let head = repo.fully_resolve_head()?;
+        assert_eq!(repo.read_shallow_file()?.into_iter().collect::<Vec<_>>(), [head]);
+        assert_eq!(history_len(&repo)?, 1);
+        assert!(repo.fsck()?.is_ok());
+
+        let opts = FetchOpts { deepen: Some(2), ..Default::default() };
+        repo.fetch_with_opts_blocking(DEFAULT_REMOTE, &opts)?;
+        assert!(repo.is_shallow()?);
+        assert_eq!(history_len(&repo)?, 3);
+        assert!(repo.fsck()?.is_ok());
+
+        let opts = FetchOpts { unshallow: true, ..Default::default() };
+        repo.fetch_with_opts_blocking(DEFAULT_REMOTE, &opts)?;
+        assert!(!repo.is_shallow()?);
+        assert!(!repo.shallow_path().try_exists()?);
+        assert_eq!(history_len(&repo)?, 5);
+        assert!(repo.fsck()?.is_ok());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_shallow_clone_then_deepen_with_protocol_v1() -> BitResult<()> {
+    test_shallow_clone_then_deepen(1)
+}
+
+#[test]
+fn test_shallow_clone_then_deepen_with_protocol_v2() -> BitResult<()> {
+    test_shallow_clone_then_deepen(2)
+}
+
+#[test]
+fn test_fetch_into_shallow_repo_keeps_boundary() -> BitResult<()> {
+    let remote = remote_with_commits(5)?;
+    let local =
+        shallow_clone(remote.path(), FetchOpts { depth: Some(2), ..Default::default() }, 2)?;
+
+    BitRepo::find(remote.path(), |repo| {
+        touch!(repo: "bar" < "new");
+        bit_commit_all!(repo);
+        Ok(())
+    })?;
+
+    BitRepo::find(local.path(), |repo| {
+        let boundary = repo.read_shallow_file()?;
+        repo.fetch_blocking(DEFAULT_REMOTE)?;
+        // an ordinary fetch only sends the new commit on top of what we have
+        assert_eq!(repo.read_shallow_file()?, boundary);
+        let tracking = symbolic_ref!("refs/remotes/origin/master");
+        let tip = repo.fully_resolve_ref(tracking)?;
+        assert_eq!(repo.revwalk_builder().roots_iter([tip])?.build()?.count()?, 3);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_unshallow_complete_repository() -> BitResult<()> {
+    let remote = remote_with_commits(2)?;
+    let local = shallow_clone(remote.path(), FetchOpts::default(), 2)?;
+    BitRepo::find(local.path(), |repo| {
+        let opts = FetchOpts { unshallow: true, ..Default::default() };
+        let err = repo.fetch_with_opts_blocking(DEFAULT_REMOTE, &opts).unwrap_err();
+        assert!(err.to_string().contains("does not make sense"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_depth_and_deepen_are_mutually_exclusive() -> BitResult<()> {
+    let remote = remote_with_commits(2)?;
+    let local =
+        shallow_clone(remote.path(), FetchOpts { depth: Some(1), ..Default::default() }, 2)?;
+    BitRepo::find(local.path(), |repo| {
+        let opts = FetchOpts { depth: Some(1), deepen: Some(1), ..Default::default() };
+        let err = repo.fetch_with_opts_blocking(DEFAULT_REMOTE, &opts).unwrap_err();
+        assert!(err.to_string().contains("mutually exclusive"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_parse_shallow_since() -> BitResult<()> {
+    assert_eq!(parse_shallow_since("1616061862")?, UNIX_EPOCH + Duration::from_secs(1616061862));
+    let since = parse_shallow_since("2.days.ago")?;
+    let ago = SystemTime::now().duration_since(since)?;
+    assert!(ago >= Duration::from_secs(2 * 24 * 60 * 60));
+    assert!(parse_shallow_since("never").is_err());
+    assert!(parse_shallow_since("yesterday").is_err());
+    Ok(())
+}
@@ -36,6 +36,11 @@ impl BitEpochTime {
     pub fn new(i: i64) -> Self {
         Self(i)
     }
+
+    /// seconds since the unix epoch
+    pub fn seconds(self) -> i64 {
+        self.0
+    }
 }
 
 #[derive(PartialEq, Clone, Debug, Hash, Ord, PartialOrd, Eq, Copy)]
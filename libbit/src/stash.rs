@@ -0,0 +1,252 @@
+use crate::diff::WorkspaceStatus;
+use crate::error::BitResult;
+use crate::index::{BitIndexInner, MergeStage};
+use crate::iter::{BitEntry, TreeEntryIter};
+use crate::merge::MergeResults;
+use crate::obj::{BitObject, Commit, CommitMessage, Oid};
+use crate::pathspec::Pathspec;
+use crate::refs::{BitRef, BitRefDbBackend, RefUpdateCause, SymbolicRef};
+use crate::repo::BitRepo;
+use fallible_iterator::FallibleIterator;
+use std::collections::BTreeSet;
+
+// The layout of a stash is the same as git's so stashes can be shared between the two.
+// `refs/stash` points at the most recent stash and older stashes live in its reflog.
+// Each stash is a commit of the worktree whose first parent is the `HEAD` commit at the time,
+// the second parent is a commit of the index, and the optional third parent is a (parentless)
+// commit of the untracked files.
+
+#[derive(Debug, Clone, Default)]
+pub struct StashPushOpts {
+    /// describes the stash instead of the `HEAD` commit
+    pub message: Option<String>,
+    /// also stash the untracked files (and remove them from the worktree)
+    pub include_untracked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StashEntry {
+    /// the `n` in `stash@{n}`
+    pub index: usize,
+    pub oid: Oid,
+    pub message: String,
+}
+
+impl BitRepo {
+    /// Save the local changes in a new stash and reset the index and worktree to match `HEAD`.
+    /// Returns `None` if there was nothing to stash.
+    pub fn stash_push(&self, opts: StashPushOpts) -> BitResult<Option<Oid>> {
+        let head = match self.try_fully_resolve_ref(SymbolicRef::HEAD)? {
+            Some(head) => head,
+            None => bail!("cannot stash as you do not have the initial commit yet"),
+        };
+
+        let staged = self.diff_head_index(Pathspec::MATCH_ALL)?;
+        let unstaged = self.diff_index_worktree(Pathspec::MATCH_ALL)?;
+        let untracked = if opts.include_untracked { unstaged.new.clone() } else { vec![] };
+        let no_changes = staged.is_empty()
+            && unstaged.modified.is_empty()
+            && unstaged.deleted.is_empty()
+            && untracked.is_empty();
+        if no_changes {
+            return Ok(None);
+        }
+
+        let head_commit = self.read_obj_commit(head)?;
+        let branch = match self.read_head()? {
+            BitRef::Symbolic(sym) => sym.short().to_owned(),
+            BitRef::Direct(..) => "(no branch)".to_owned(),
+        };
+        let head_desc = format!("{}: {} {}", branch, head.short(), head_commit.message.subject);
+
+        let mut index = self.index_mut()?;
+        let snapshot = (**index).clone();
+        let index_tree = index.write_tree()?;
+
+        for &(_, new) in &unstaged.modified {
+            index.add_entry(new)?;
+        }
+        for old in &unstaged.deleted {
+            index.remove_entry(old.key());
+        }
+        let worktree_tree = index.write_tree()?;
+
+        let untracked_tree = if untracked.is_empty() {
+            None
+        } else {
+            **index = BitIndexInner::default();
+            for &entry in &untracked {
+                index.add_entry(entry)?;
+            }
+            Some(index.write_tree()?)
+        };
+        **index = snapshot;
+        drop(index);
+
+        let index_commit = self.write_commit(
+            index_tree,
+            smallvec![head],
+            CommitMessage::new_subject(&format!("index on {}", head_desc))?,
+        )?;
+        let mut parents = smallvec![head, index_commit];
+        if let Some(untracked_tree) = untracked_tree {
+            parents.push(self.write_commit(
+                untracked_tree,
+                smallvec![],
+                CommitMessage::new_subject(&format!("untracked files on {}", head_desc))?,
+            )?);
+        }
+
+        let message = match &opts.message {
+            Some(message) => format!("On {}: {}", branch, message),
+            None => format!("WIP on {}", head_desc),
+        };
+        let stash =
+            self.write_commit(worktree_tree, parents, CommitMessage::new_subject(&message)?)?;
+        self.update_ref(SymbolicRef::STASH, stash, RefUpdateCause::Stash { message })?;
+
+        // We can't force checkout `HEAD` as that would also remove any untracked files that we
+        // are meant to leave alone, so only the paths that were changed are restored
+        let changed = staged
+            .new
+            .iter()
+            .chain(&staged.deleted)
+            .chain(&unstaged.deleted)
+            .chain(staged.modified.iter().chain(&unstaged.modified).map(|(old, _)| old))
+            .map(|entry| entry.path)
+            .collect::<BTreeSet<_>>();
+
+        let mut index = self.index_mut()?;
+        index.read_tree(head_commit.tree_oid())?;
+        for path in changed {
+            if self.path_exists(path)? {
+                self.rm(path)?;
+            }
+            if let Some(entry) = index.find_entry((path, MergeStage::NONE)) {
+                entry.write_to_disk(self)?;
+            }
+        }
+
+        for entry in &untracked {
+            self.rm(entry.path)?;
+        }
+
+        Ok(Some(stash))
+    }
+
+    /// List the stashes, most recent first
+    pub fn stash_list(&self) -> BitResult<Vec<StashEntry>> {
+        let refdb = self.refdb()?;
+        if !refdb.exists(SymbolicRef::STASH)? {
+            return Ok(vec![]);
+        }
+
+        let reflog = refdb.read_reflog(SymbolicRef::STASH)?;
+        Ok((0..reflog.len())
+            .map(|index| StashEntry {
+                index,
+                oid: reflog[index].new_oid,
+                message: reflog[index].message.clone(),
+            })
+            .collect())
+    }
+
+    /// Apply the changes of `stash@{index}` to the index and worktree by merging them with the
+    /// current `HEAD` (using the `HEAD` the stash was made on as the merge base).
+    /// The stash is left in place.
+    pub fn stash_apply(&self, index: usize) -> BitResult<MergeResults> {
+        let stash = self.read_obj_commit(self.stash_oid(index)?)?;
+        ensure!(stash.parents.len() >= 2, "`{}` is not a stash commit", stash.oid());
+        let base = self.read_obj_commit(stash_base(&stash)?)?;
+
+        let untracked = match stash.parents.get(2) {
+            Some(&untracked) => {
+                let tree = self.read_obj_commit(untracked)?.tree_oid();
+                TreeEntryIter::new(self.clone(), tree).collect::<Vec<_>>()?
+            }
+            None => vec![],
+        };
+        for entry in &untracked {
+            ensure!(
+                !self.path_exists(entry.path)?,
+                "untracked file `{}` already exists, refusing to overwrite it",
+                entry.path
+            );
+        }
+
+        let results = self.merge_uncommitted(base.tree_oid(), stash.oid(), "Stashed changes")?;
+
+        for entry in &untracked {
+            entry.write_to_disk(self)?;
+        }
+
+        if let MergeResults::Merge(..) = results {
+            // As with git, the changes are left unstaged with the exception of new files
+            // (as otherwise they would be untracked)
+            let head_tree = self.head_tree()?;
+            let mut index = self.index_mut()?;
+            let merged = index.entries().values().copied().collect::<Vec<_>>();
+            index.read_tree(head_tree)?;
+            for entry in merged {
+                if index.find_entry(entry.key()).is_none() {
+                    index.add_entry(entry)?;
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Apply `stash@{index}` and then drop it if it applied without conflicts
+    pub fn stash_pop(&self, index: usize) -> BitResult<MergeResults> {
+        let results = self.stash_apply(index)?;
+        if let MergeResults::Merge(..) = results {
+            self.stash_drop(index)?;
+        }
+        Ok(results)
+    }
+
+    /// Remove `stash@{index}` from the stash list, returning the oid of the dropped stash
+    pub fn stash_drop(&self, index: usize) -> BitResult<Oid> {
+        let oid = self.stash_oid(index)?;
+        let refdb = self.refdb()?;
+        let mut reflog = refdb.read_reflog(SymbolicRef::STASH)?;
+        reflog.remove(index);
+        let top = reflog.get(0).map(|entry| entry.new_oid);
+        drop(reflog);
+
+        match top {
+            Some(top) => refdb.set_ref(SymbolicRef::STASH, BitRef::Direct(top))?,
+            None => refdb.delete(SymbolicRef::STASH)?,
+        }
+        Ok(oid)
+    }
+
+    /// The changes recorded in `stash@{index}` relative to the commit it was made on
+    pub fn stash_show(&self, index: usize) -> BitResult<WorkspaceStatus> {
+        let stash = self.read_obj_commit(self.stash_oid(index)?)?;
+        let base = self.read_obj_commit(stash_base(&stash)?)?;
+        self.diff_tree_to_tree(base.tree_oid(), stash.tree_oid())
+    }
+
+    fn stash_oid(&self, index: usize) -> BitResult<Oid> {
+        let refdb = self.refdb()?;
+        ensure!(refdb.exists(SymbolicRef::STASH)?, "no stash entries found");
+        let reflog = refdb.read_reflog(SymbolicRef::STASH)?;
+        match reflog.get(index) {
+            Some(entry) => Ok(entry.new_oid),
+            None => bail!("`stash@{{{}}}` does not exist", index),
+        }
+    }
+}
+
+/// The `HEAD` commit that `stash` was made on
+fn stash_base(stash: &Commit) -> BitResult<Oid> {
+    match stash.parents.first() {
+        Some(&base) => Ok(base),
+        None => bail!("`{}` is not a stash commit", stash.oid()),
+    }
+}
+
+#[cfg(test)]
+mod tests;
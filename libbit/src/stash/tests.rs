@@ -0,0 +1,189 @@
+use crate::error::BitResult;
+use crate::merge::MergeResults;
+use crate::pathspec::Pathspec;
+use crate::refs::{BitRefDbBackend, RefUpdateCause, SymbolicRef};
+use crate::repo::BitRepo;
+use crate::stash::StashPushOpts;
+
+#[test]
+fn test_stash_push_with_no_changes() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        assert_eq!(repo.stash_push(StashPushOpts::default())?, None);
+        assert!(repo.stash_list()?.is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_stash_push_commit_layout() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        let head = repo.fully_resolve_head()?;
+        modify!(repo: "foo" < "staged foo");
+        bit_add!(repo: Pathspec::MATCH_ALL);
+        modify!(repo: "foo" < "unstaged foo");
+
+        let stash = repo.stash_push(StashPushOpts::default())?.unwrap();
+        assert_eq!(repo.fully_resolve_ref(SymbolicRef::STASH)?, stash);
+
+        let stash = repo.read_obj_commit(stash)?;
+        assert_eq!(stash.parents.len(), 2);
+        assert_eq!(stash.parents[0], head);
+        assert!(stash.message.subject.starts_with("WIP on master: "));
+
+        let index_commit = repo.read_obj_commit(stash.parents[1])?;
+        assert_eq!(index_commit.parents.as_slice(), &[head]);
+        assert!(index_commit.message.subject.starts_with("index on master: "));
+        let diff = repo.diff_tree_to_tree(repo.head_tree()?, index_commit.tree_oid())?;
+        assert_eq!(diff.modified.len(), 1);
+        let diff = repo.diff_tree_to_tree(index_commit.tree_oid(), stash.tree_oid())?;
+        assert_eq!(diff.modified.len(), 1);
+
+        // everything should have been reset back to `HEAD`
+        assert!(bit_status!(repo).is_empty());
+        assert_eq!(cat!(repo: "foo"), "default foo contents");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_stash_push_then_pop() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        modify!(repo: "foo" < "modified foo");
+        touch!(repo: "bar" < "new bar");
+        bit_add!(repo: "bar");
+        touch!(repo: "untracked" < "untracked");
+
+        repo.stash_push(StashPushOpts::default())?;
+        assert_eq!(cat!(repo: "foo"), "default foo contents");
+        assert!(!repo.workdir.join("bar").exists());
+        // untracked files are left alone unless requested
+        assert_eq!(cat!(repo: "untracked"), "untracked");
+
+        let results = repo.stash_pop(0)?;
+        assert_eq!(results, MergeResults::Merge(Default::default()));
+        assert_eq!(cat!(repo: "foo"), "modified foo");
+        assert_eq!(cat!(repo: "bar"), "new bar");
+        assert!(repo.stash_list()?.is_empty());
+        assert!(!repo.refdb()?.exists(SymbolicRef::STASH)?);
+
+        // only the new file remains staged
+        let status = bit_status!(repo);
+        assert_eq!(status.staged.new.len(), 1);
+        assert!(status.staged.modified.is_empty());
+        assert_eq!(status.unstaged.modified.len(), 1);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_stash_include_untracked() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        touch!(repo: "untracked" < "untracked");
+        let opts = StashPushOpts { include_untracked: true, ..Default::default() };
+        let stash = repo.stash_push(opts)?.unwrap();
+        assert!(!repo.workdir.join("untracked").exists());
+
+        let stash = repo.read_obj_commit(stash)?;
+        assert_eq!(stash.parents.len(), 3);
+        let untracked_commit = repo.read_obj_commit(stash.parents[2])?;
+        assert!(untracked_commit.parents.is_empty());
+        assert!(untracked_commit.message.subject.starts_with("untracked files on master: "));
+
+        repo.stash_apply(0)?;
+        assert_eq!(cat!(repo: "untracked"), "untracked");
+        // `apply` leaves the stash in place
+        assert_eq!(repo.stash_list()?.len(), 1);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_stash_list_and_drop() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        modify!(repo: "foo" < "first");
+        let first = repo
+            .stash_push(StashPushOpts { message: Some("first".to_owned()), ..Default::default() })?
+            .unwrap();
+        modify!(repo: "foo" < "second");
+        let second = repo.stash_push(StashPushOpts::default())?.unwrap();
+        modify!(repo: "foo" < "third");
+        let third = repo.stash_push(StashPushOpts::default())?.unwrap();
+
+        let list = repo.stash_list()?;
+        assert_eq!(list.iter().map(|entry| entry.oid).collect::<Vec<_>>(), [third, second, first]);
+        assert_eq!(list[2].message, "On master: first");
+        assert!(list[0].message.starts_with("WIP on master: "));
+        assert_eq!(repo.fully_resolve_rev(&rev!("stash@{1}"))?, second);
+
+        assert_eq!(repo.stash_drop(1)?, second);
+        let list = repo.stash_list()?;
+        assert_eq!(list.iter().map(|entry| entry.oid).collect::<Vec<_>>(), [third, first]);
+        assert_eq!(repo.fully_resolve_ref(SymbolicRef::STASH)?, third);
+
+        // dropping the most recent stash moves `refs/stash` back to the previous one
+        assert_eq!(repo.stash_drop(0)?, third);
+        assert_eq!(repo.fully_resolve_ref(SymbolicRef::STASH)?, first);
+
+        assert_eq!(repo.stash_drop(0)?, first);
+        assert!(!repo.refdb()?.exists(SymbolicRef::STASH)?);
+        assert!(repo.stash_drop(0).is_err());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_stash_show() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        modify!(repo: "foo" < "modified foo");
+        repo.stash_push(StashPushOpts::default())?;
+        let diff = repo.stash_show(0)?;
+        assert_eq!(diff.modified.len(), 1);
+        assert!(diff.new.is_empty());
+        assert!(diff.deleted.is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_stash_pop_conflict() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        modify!(repo: "foo" < "stashed foo");
+        repo.stash_push(StashPushOpts::default())?;
+
+        modify!(repo: "foo" < "committed foo");
+        bit_commit_all!(repo);
+
+        let conflicts = repo.stash_pop(0)?.into_conflicts();
+        assert_eq!(conflicts.len(), 1);
+        // the stash should not be dropped if it didn't apply cleanly
+        assert_eq!(repo.stash_list()?.len(), 1);
+        assert!(cat!(repo: "foo").contains(">>>>>>> Stashed changes"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_stash_apply_with_local_changes() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        modify!(repo: "foo" < "stashed foo");
+        let stash = repo.stash_push(StashPushOpts::default())?.unwrap();
+        modify!(repo: "foo" < "local foo");
+        assert!(repo.stash_apply(0).is_err());
+        assert_eq!(cat!(repo: "foo"), "local foo");
+        assert_eq!(repo.fully_resolve_ref(SymbolicRef::STASH)?, stash);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_stash_show_non_stash_commit() -> BitResult<()> {
+    BitRepo::with_minimal_repo(|repo| {
+        // the only commit of the repository has no parents
+        let head = repo.fully_resolve_head()?;
+        let cause = RefUpdateCause::Stash { message: "not a stash".to_owned() };
+        repo.update_ref(SymbolicRef::STASH, head, cause)?;
+        let err = repo.stash_show(0).unwrap_err();
+        assert_eq!(err.to_string(), format!("`{}` is not a stash commit", head));
+        Ok(())
+    })
+}
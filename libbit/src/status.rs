@@ -1,9 +1,10 @@
 use crate::diff::WorkspaceStatus;
 use crate::error::BitResult;
 use crate::index::{BitIndex, BitIndexEntry, Conflicts};
+use crate::path::BitPath;
 use crate::pathspec::Pathspec;
 use crate::refs::BitRef;
-use crate::repo::BitRepo;
+use crate::repo::{BitRepo, RepoState};
 use bitflags::bitflags;
 use owo_colors::OwoColorize;
 use std::fmt::{self, Display, Formatter};
@@ -12,10 +13,13 @@ use std::iter::Peekable;
 #[derive(Debug, PartialEq)]
 pub struct BitStatus {
     head: BitRef,
+    state: RepoState,
     flags: BitStatusFlags,
     pub staged: WorkspaceStatus,
     pub unstaged: WorkspaceStatus,
     pub conflicted: Conflicts,
+    /// untracked paths that are ignored (only populated by [`BitRepo::status_with_ignored`])
+    pub ignored: Vec<BitPath>,
 }
 
 bitflags! {
@@ -40,13 +44,24 @@ impl BitRepo {
     pub fn status(&self, pathspec: Pathspec) -> BitResult<BitStatus> {
         self.index_mut()?.status(pathspec)
     }
+
+    /// Same as [`Self::status`] but also collects ignored paths
+    pub fn status_with_ignored(&self, pathspec: Pathspec) -> BitResult<BitStatus> {
+        let mut index = self.index_mut()?;
+        let mut status = index.status(pathspec)?;
+        status.ignored = index.ignored_paths(pathspec)?;
+        Ok(status)
+    }
 }
 
 impl BitIndex {
     pub fn status(&mut self, pathspec: Pathspec) -> BitResult<BitStatus> {
         let repo = self.repo();
         let head = repo.read_head()?;
-        let staged = self.diff_head(pathspec)?;
+        let mut staged = self.diff_head(pathspec)?;
+        if let Some(rename_opts) = repo.default_rename_opts() {
+            staged.detect_renames(&repo, rename_opts)?;
+        }
         let unstaged = self.diff_worktree(pathspec)?;
         let conflicted = self.conflicts();
 
@@ -55,7 +70,8 @@ impl BitIndex {
         let mut flags = BitStatusFlags::default();
         flags.set(BitStatusFlags::INITIAL, is_initial);
 
-        Ok(BitStatus { head, staged, unstaged, conflicted, flags })
+        let state = repo.repo_state();
+        Ok(BitStatus { head, state, staged, unstaged, conflicted, flags, ignored: vec![] })
     }
 }
 
@@ -67,6 +83,7 @@ impl Display for BitStatus {
         self.fmt_staged(f)?;
         self.fmt_unstaged(f)?;
         self.fmt_unmerged(f)?;
+        self.fmt_ignored(f)?;
         self.fmt_summary(f)?;
 
         Ok(())
@@ -101,7 +118,17 @@ impl BitStatus {
         };
         writeln!(f)?;
 
-        if !self.conflicted.is_empty() {
+        if self.state == RepoState::Rebasing {
+            writeln!(f, "You are currently rebasing")?;
+            if self.conflicted.is_empty() {
+                writeln!(f, "  (all conflicts fixed: run `bit rebase --continue`)")?;
+            } else {
+                writeln!(f, "  (fix conflicts and then run `bit rebase --continue`)")?;
+            }
+            writeln!(f, "  (use `bit rebase --skip` to skip this patch)")?;
+            writeln!(f, "  (use `bit rebase --abort` to check out the original branch)")?;
+            writeln!(f)?;
+        } else if !self.conflicted.is_empty() {
             writeln!(f, "You have unmerged paths")?;
             writeln!(f, "  (fix conflicts and run `bit commit`)")?;
             writeln!(f, "  (use `bit merge --abort` to abort the merge)")?;
@@ -116,8 +143,15 @@ impl BitStatus {
         let mut modified =
             self.staged.modified.iter().filter(|(_, new)| !new.is_unmerged()).peekable();
         let mut deleted = filter_unmerged(&self.staged.deleted);
-
-        if new.is_empty() && modified.is_empty() && deleted.is_empty() {
+        let renamed = &self.staged.renamed;
+        let copied = &self.staged.copied;
+
+        if new.is_empty()
+            && modified.is_empty()
+            && deleted.is_empty()
+            && renamed.is_empty()
+            && copied.is_empty()
+        {
             return Ok(());
         }
 
@@ -136,6 +170,16 @@ impl BitStatus {
             writeln!(f, "\t{}:   {}", "deleted".green(), entry.path.green())?;
         }
 
+        for rename in renamed {
+            let paths = format!("{} -> {}", rename.old.path, rename.new.path);
+            writeln!(f, "\t{}:    {}", "renamed".green(), paths.green())?;
+        }
+
+        for copy in copied {
+            let paths = format!("{} -> {}", copy.old.path, copy.new.path);
+            writeln!(f, "\t{}:     {}", "copied".green(), paths.green())?;
+        }
+
         writeln!(f)?;
 
         Ok(())
@@ -190,6 +234,18 @@ impl BitStatus {
         Ok(())
     }
 
+    fn fmt_ignored(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if !self.ignored.is_empty() {
+            writeln!(f, "Ignored files:")?;
+            for path in &self.ignored {
+                writeln!(f, "\t{}", path.red())?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+
     fn fmt_summary(&self, f: &mut Formatter<'_>) -> fmt::Result {
         // print status summary
         // TODO this should only consider the unmerged entries
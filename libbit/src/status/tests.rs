@@ -1,4 +1,5 @@
 use crate::error::BitResult;
+use crate::path::BitPath;
 use crate::pathspec::Pathspec;
 use crate::repo::BitRepo;
 
@@ -197,3 +198,50 @@ fn test_status_staged_new_files_no_head() -> BitResult<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_status_with_ignored() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        touch!(repo: "tracked.log");
+        bit_add!(repo: "tracked.log");
+        gitignore!(repo: {
+            "*.log"
+            "ignored/"
+        });
+        touch!(repo: "foo.log");
+        mkdir!(repo: "ignored");
+        touch!(repo: "ignored/a");
+
+        assert!(bit_status!(repo).ignored.is_empty());
+        let status = repo.status_with_ignored(Pathspec::MATCH_ALL)?;
+        assert_eq!(status.ignored, ["foo.log", "ignored/"]);
+        assert_eq!(status.unstaged.new.len(), 1);
+        assert_eq!(status.unstaged.new[0].path, ".gitignore");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_check_ignore() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        touch!(repo: "tracked.log");
+        bit_add!(repo: "tracked.log");
+        gitignore!(repo: {
+            "*.log"
+            "!keep.log"
+        });
+
+        let paths = ["tracked.log", "foo.log", "keep.log", "foo"].map(BitPath::intern);
+        let matches = repo.check_ignore(&paths)?;
+        assert_eq!(matches[0].1, None);
+        let m = matches[1].1.as_ref().unwrap();
+        assert!(m.is_ignore());
+        assert_eq!(m.pattern, "*.log");
+        assert_eq!(m.line_number, Some(1));
+        let m = matches[2].1.as_ref().unwrap();
+        assert!(!m.is_ignore());
+        assert_eq!(m.line_number, Some(2));
+        assert_eq!(matches[3].1, None);
+        Ok(())
+    })
+}
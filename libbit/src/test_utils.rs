@@ -706,3 +706,13 @@ fn test_tree_macro() -> crate::error::BitResult<()> {
         Ok(())
     })
 }
+
+/// Point `$XDG_CONFIG_HOME` at an empty directory so a developer's `~/.config/git/ignore`
+/// (or config) can't affect the tests. `$HOME` is left alone as commits rely on the identity in `~/.gitconfig`.
+pub fn isolate_user_config_dir() {
+    static ISOLATE: std::sync::Once = std::sync::Once::new();
+    ISOLATE.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("bit-test-xdg-config-{}", std::process::id()));
+        std::env::set_var("XDG_CONFIG_HOME", dir);
+    });
+}
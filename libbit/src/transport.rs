@@ -1,30 +1,66 @@
+mod daemon;
 mod file;
+mod http;
 mod ssh;
 
+pub use daemon::*;
 pub use file::*;
+pub use http::*;
 pub use ssh::*;
 
 use crate::error::BitResult;
-use crate::obj::{BitObject, Oid};
-use crate::protocol::{BitProtocolRead, BitProtocolWrite, Capabilities, Capability};
-use crate::refs::{BitRef, SymbolicRef};
-use crate::remote::{FetchStatus, FetchSummary, Remote};
+use crate::obj::{BitObjType, BitObject, Oid};
+use crate::pack::PackBuilder;
+use crate::partial_clone::ObjectFilter;
+use crate::progress::ProgressSink;
+use crate::protocol::{BitProtocolRead, BitProtocolWrite, Capabilities, Capability, Packet, AGENT};
+use crate::refs::{BitRef, BitRefDbBackend, RefUpdateCause, SymbolicRef};
+use crate::remote::{
+    FetchOpts, FetchStatus, FetchSummary, PushOpts, PushStatus, PushSummary, PushUpdate, Refspec,
+    Remote,
+};
 use crate::repo::BitRepo;
 use fallible_iterator::FallibleIterator;
 use std::collections::HashMap;
-use tokio::io::AsyncBufReadExt;
+use std::time::SystemTime;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
 
 pub const MULTI_ACK_BATCH_SIZE: usize = 32;
+/// the depth that is requested to fetch all the remaining history (`--unshallow`)
+const INFINITE_DEPTH: u32 = 0x7fffffff;
+
+/// Whether to ask the server to speak protocol v2 when running `program`.
+/// Only fetching is defined in v2 so `receive-pack` is always spoken to in v1
+/// (servers that don't understand v2 just ignore the request).
+pub(crate) fn request_protocol_v2(repo: &BitRepo, program: &str) -> bool {
+    repo.config().protocol_version() == 2 && !program.ends_with("receive-pack")
+}
 
 #[async_trait]
 pub trait ProtocolTransport: BitProtocolRead + BitProtocolWrite {
     fn repo(&self) -> &BitRepo;
-    async fn fetch(&mut self, remote: &Remote) -> BitResult<FetchSummary> {
+
+    /// Whether the server forgets everything between requests (protocol v1 over http), in which
+    /// case the shallow boundary is repeated at the start of every response
+    fn is_stateless_rpc(&self) -> bool {
+        false
+    }
+
+    async fn fetch(&mut self, remote: &Remote, opts: &FetchOpts) -> BitResult<FetchSummary> {
         if self.fill_buf().await?.is_empty() {
             bail!("could not read from remote repository")
         }
 
-        let (refs, capabilities) = self.parse_ref_discovery_and_capabilities().await?;
+        let mut packet = self.recv_packet().await?;
+        if packet == b"version 2\n" {
+            return self.fetch_v2(remote, opts).await;
+        }
+        // protocol v1 is the same as v0 except for this line
+        if packet == b"version 1\n" {
+            packet = self.recv_packet().await?;
+        }
+
+        let (refs, capabilities) = self.parse_ref_discovery_and_capabilities(packet).await?;
         if refs.is_empty() {
             return Ok(FetchSummary::EMPTY_REMOTE);
         }
@@ -39,18 +75,33 @@ pub trait ProtocolTransport: BitProtocolRead + BitProtocolWrite {
         );
         ensure!(capabilities.contains(&Capability::OfsDelta), "require `ofs-delta` capability");
 
-        let remote_mapping = refs
-            .into_iter()
-            .filter_map(|(sym, oid)| Some((remote.fetch.match_ref(sym)?, oid)))
-            .collect::<HashMap<_, _>>();
-
-        let fetch_status = self.negotiate_packs(&remote_mapping).await?;
-
-        // TODO check the refspec for forcedness before updating: create a function `try_update_remote_ref`
-        for (&remote, &oid) in &remote_mapping {
-            let to = BitRef::Direct(oid);
-            self.repo().update_ref_for_fetch(remote, to)?;
+        let shallow_args = shallow_args(self.repo(), opts)?;
+        if !shallow_args.is_empty() {
+            ensure!(
+                capabilities.contains(&Capability::Shallow),
+                "the remote does not support shallow fetches"
+            );
+            let required = [
+                (opts.shallow_since.is_some(), Capability::DeepenSince),
+                (!opts.shallow_exclude.is_empty(), Capability::DeepenNot),
+                (opts.deepen.is_some(), Capability::DeepenRelative),
+            ];
+            for (requested, capability) in required {
+                ensure!(
+                    !requested || capabilities.contains(&capability),
+                    "the remote does not support `{}`",
+                    capability
+                );
+            }
         }
+        ensure!(
+            opts.filter.is_none() || capabilities.contains(&Capability::Filter),
+            "the remote does not support `filter`"
+        );
+
+        let remote_mapping = map_remote_refs(remote, refs);
+        let fetch_status = self.negotiate_packs(&remote_mapping, opts, &shallow_args).await?;
+        update_remote_refs(self.repo(), &remote_mapping)?;
 
         let head_symref = capabilities.iter().find_map(|cap| match cap {
             &Capability::Symref(head, sym) if head == SymbolicRef::HEAD => Some(sym),
@@ -60,40 +111,453 @@ pub trait ProtocolTransport: BitProtocolRead + BitProtocolWrite {
         Ok(FetchSummary { head_symref, status: fetch_status })
     }
 
-    async fn negotiate_packs(
+    /// Fetch using protocol v2 (the server has just sent `version 2`)
+    // https://github.com/git/git/blob/master/Documentation/technical/protocol-v2.txt
+    async fn fetch_v2(&mut self, remote: &Remote, opts: &FetchOpts) -> BitResult<FetchSummary> {
+        let capabilities = self.recv_v2_capabilities().await?;
+        ensure!(capabilities.contains("ls-refs"), "require `ls-refs` command");
+        ensure!(capabilities.contains("fetch"), "require `fetch` command");
+
+        let mut shallow_args = shallow_args(self.repo(), opts)?;
+        if !shallow_args.is_empty() {
+            ensure!(
+                capabilities.command_supports("fetch", "shallow"),
+                "the remote does not support shallow fetches"
+            );
+        }
+        // this is a capability in v1 but an argument in v2
+        if opts.deepen.is_some() {
+            shallow_args.push("deepen-relative\n".to_owned());
+        }
+        ensure!(
+            opts.filter.is_none() || capabilities.command_supports("fetch", "filter"),
+            "the remote does not support `filter`"
+        );
+
+        let (refs, head_symref) = self.ls_refs(remote).await?;
+        if refs.is_empty() {
+            // a flush instead of a command ends the session
+            self.write_flush_packet().await?;
+            return Ok(FetchSummary::EMPTY_REMOTE);
+        }
+
+        let remote_mapping = map_remote_refs(remote, refs);
+        let fetch_status = self.negotiate_packs_v2(&remote_mapping, opts, shallow_args).await?;
+        update_remote_refs(self.repo(), &remote_mapping)?;
+        Ok(FetchSummary { head_symref, status: fetch_status })
+    }
+
+    /// Fetch exactly `oids` for a partial clone without negotiating (we want objects that we
+    /// don't have rather than history) or updating any refs.
+    /// Blobs are still omitted (where the remote allows it) so that fetching a missing tree or
+    /// commit doesn't fetch everything it refers to.
+    async fn fetch_objects(&mut self, oids: &[Oid]) -> BitResult<()> {
+        if self.fill_buf().await?.is_empty() {
+            bail!("could not read from remote repository")
+        }
+
+        let mut packet = self.recv_packet().await?;
+        if packet == b"version 2\n" {
+            let capabilities = self.recv_v2_capabilities().await?;
+            ensure!(capabilities.contains("fetch"), "require `fetch` command");
+            let mut args = vec!["ofs-delta\n".to_owned(), "no-progress\n".to_owned()];
+            if capabilities.command_supports("fetch", "filter") {
+                args.push(format!("filter {}\n", ObjectFilter::BlobNone));
+            }
+            args.extend(oids.iter().map(|oid| format!("want {}\n", oid)));
+            args.push("done\n".to_owned());
+            self.write_v2_command("fetch", &args).await?;
+            self.recv_packfile_section(&ProgressSink::default()).await?;
+            self.write_flush_packet().await?;
+            return Ok(());
+        }
+        if packet == b"version 1\n" {
+            packet = self.recv_packet().await?;
+        }
+
+        // the objects aren't necessarily advertised so the remote has to allow us to want them
+        // (`uploadpack.allowAnySHA1InWant` or similar)
+        let (_, capabilities) = self.parse_ref_discovery_and_capabilities(packet).await?;
+        ensure!(
+            capabilities.contains(&Capability::SideBand64k),
+            "require `side-band-64k` capability"
+        );
+        let mut want_capabilities =
+            vec![Capability::OfsDelta, Capability::SideBand64k, Capability::NoProgress];
+        let filter = capabilities.contains(&Capability::Filter);
+        if filter {
+            want_capabilities.push(Capability::Filter);
+        }
+        for (i, &oid) in oids.iter().enumerate() {
+            if i == 0 {
+                let capabilities =
+                    want_capabilities.iter().map(|cap| cap.to_string()).collect::<Vec<_>>();
+                let capabilities = capabilities.join(" ");
+                self.write_packet(format!("want {oid} {capabilities}\n").as_bytes()).await?;
+            } else {
+                self.want(oid).await?;
+            }
+        }
+        if filter {
+            self.write_packet(format!("filter {}\n", ObjectFilter::BlobNone).as_bytes()).await?;
+        }
+        self.write_flush_packet().await?;
+        self.done().await?;
+        let repo = self.repo().clone();
+        self.recv_pack(repo, &ProgressSink::default()).await
+    }
+
+    /// List the remote refs that the fetch refspec of `remote` may match (and `HEAD`).
+    /// Also returns what the remote `HEAD` points to unless it is detached.
+    async fn ls_refs(
+        &mut self,
+        remote: &Remote,
+    ) -> BitResult<(HashMap<SymbolicRef, Oid>, Option<SymbolicRef>)> {
+        let args = [
+            "symrefs\n".to_owned(),
+            "ref-prefix HEAD\n".to_owned(),
+            format!("ref-prefix {}\n", remote.fetch.ref_prefix()),
+        ];
+        self.write_v2_command("ls-refs", &args).await?;
+
+        let mut refs = HashMap::new();
+        let mut head_symref = None;
+        loop {
+            let packet = self.recv_packet().await?;
+            if packet.is_empty() {
+                break Ok((refs, head_symref));
+            }
+            let (oid, sym, symref_target) = parse_ls_refs_line(std::str::from_utf8(&packet)?)?;
+            if sym == SymbolicRef::HEAD {
+                head_symref = symref_target;
+            }
+            refs.insert(sym, oid);
+        }
+    }
+
+    /// Sends `fetch` commands with batches of `have`s until either the server is ready to send
+    /// the pack or we run out of commits, and then receives the pack
+    async fn negotiate_packs_v2(
         &mut self,
         remote_mapping: &HashMap<SymbolicRef, Oid>,
+        opts: &FetchOpts,
+        shallow_args: Vec<String>,
     ) -> BitResult<FetchStatus> {
-        let mut wanted = vec![];
-        let mut local_tips = vec![];
-        for (&remote, &remote_oid) in remote_mapping {
-            let local_oid = self.repo().try_fully_resolve_ref(remote)?;
-            if let Some(local_oid) = local_oid {
-                local_tips.push(local_oid)
+        let (wanted, local_tips) =
+            wanted_and_local_tips(self.repo(), remote_mapping, opts.deepens())?;
+        if wanted.is_empty() {
+            self.write_flush_packet().await?;
+            return Ok(FetchStatus::UpToDate);
+        }
+
+        // each `fetch` command stands on its own so everything we know is in common is resent
+        let mut common = vec![];
+        let mut walk = self.repo().revwalk_builder().roots_iter(local_tips)?.build()?;
+        loop {
+            let mut args = vec!["ofs-delta\n".to_owned()];
+            args.extend(wanted.iter().map(|oid| format!("want {}\n", oid)));
+            args.extend(shallow_args.iter().cloned());
+            args.extend(opts.filter.map(|filter| format!("filter {}\n", filter)));
+            args.extend(common.iter().map(|oid| format!("have {}\n", oid)));
+            let mut done = false;
+            for _ in 0..MULTI_ACK_BATCH_SIZE {
+                match walk.next()? {
+                    Some(commit) => args.push(format!("have {}\n", commit.oid())),
+                    None => {
+                        done = true;
+                        break;
+                    }
+                }
+            }
+            if done {
+                // the server sends the pack straight away without any acknowledgments
+                args.push("done\n".to_owned());
+            }
+            self.write_v2_command("fetch", &args).await?;
+            if done || self.recv_acknowledgments(&mut common).await? {
+                break;
+            }
+        }
+
+        let shallow_update = self.recv_packfile_section(&opts.progress).await?;
+        self.write_flush_packet().await?;
+        self.repo().update_shallow(&shallow_update.shallow, &shallow_update.unshallow)?;
+        Ok(FetchStatus::NotUpToDate)
+    }
+
+    /// Receive the `acknowledgments` section of a `fetch` response, recording any newly
+    /// acknowledged commits in `common`.
+    /// Returns whether the server is ready to send the pack (in which case more sections follow).
+    async fn recv_acknowledgments(&mut self, common: &mut Vec<Oid>) -> BitResult<bool> {
+        let header = self.recv_packet().await?;
+        ensure!(header == b"acknowledgments\n", "expected `acknowledgments` section");
+
+        let mut ready = false;
+        loop {
+            let line = match self.recv_v2_packet().await? {
+                Packet::Data(line) => line,
+                Packet::Delim => {
+                    ensure!(ready, "server sent more sections without being ready");
+                    break Ok(true);
+                }
+                Packet::Flush => break Ok(false),
+                Packet::ResponseEnd => bail!("unexpected response end in `acknowledgments`"),
+            };
+            let line = std::str::from_utf8(&line)?.trim_end();
+            if line == "ready" {
+                ready = true;
+            } else if let Some(oid) = line.strip_prefix("ACK ") {
+                let oid = oid.parse()?;
+                if !common.contains(&oid) {
+                    common.push(oid);
+                }
+            } else if line != "NAK" {
+                bail!("malformed `acknowledgments` line `{}`", line)
             }
-            if local_oid != Some(remote_oid) {
-                wanted.push(remote_oid);
+        }
+    }
+
+    /// Receive the `shallow-info` section (if there is one) and the `packfile` section, skipping
+    /// any other sections
+    async fn recv_packfile_section(&mut self, progress: &ProgressSink) -> BitResult<ShallowUpdate> {
+        let mut shallow_update = ShallowUpdate::default();
+        loop {
+            match self.recv_v2_packet().await? {
+                Packet::Data(header) if header == b"packfile\n" => break,
+                Packet::Data(header) if header == b"shallow-info\n" =>
+                    shallow_update = self.recv_shallow_info().await?,
+                Packet::Data(..) | Packet::Delim => continue,
+                Packet::Flush | Packet::ResponseEnd => bail!("expected `packfile` section"),
             }
         }
+        let repo = self.repo().clone();
+        self.recv_pack(repo, progress).await?;
+        Ok(shallow_update)
+    }
 
+    /// Receive the new shallow boundary (`shallow <oid>` and `unshallow <oid>` lines) up to the
+    /// next flush (or delim in protocol v2)
+    async fn recv_shallow_info(&mut self) -> BitResult<ShallowUpdate> {
+        let mut shallow_update = ShallowUpdate::default();
+        loop {
+            let line = match self.recv_v2_packet().await? {
+                Packet::Data(line) => line,
+                _ => break Ok(shallow_update),
+            };
+            let line = std::str::from_utf8(&line)?.trim_end();
+            if let Some(oid) = line.strip_prefix("shallow ") {
+                shallow_update.shallow.push(oid.parse()?);
+            } else if let Some(oid) = line.strip_prefix("unshallow ") {
+                shallow_update.unshallow.push(oid.parse()?);
+            } else {
+                bail!("malformed shallow line `{}`", line)
+            }
+        }
+    }
+
+    async fn push(
+        &mut self,
+        remote: &Remote,
+        refspecs: &[Refspec],
+        opts: PushOpts,
+    ) -> BitResult<PushSummary> {
+        if self.fill_buf().await?.is_empty() {
+            bail!("could not read from remote repository")
+        }
+
+        let packet = self.recv_packet().await?;
+        let (refs, capabilities) = self.parse_ref_discovery_and_capabilities(packet).await?;
+        let repo = self.repo().clone();
+        let mut updates = repo.plan_push(remote, &refs, refspecs, opts)?;
+        // with an atomic push, nothing is sent if we already know that something will be rejected
+        if opts.atomic && updates.iter().any(PushUpdate::is_rejected) {
+            for update in updates.iter_mut().filter(|update| update.status == PushStatus::Ok) {
+                update.status = PushStatus::AtomicPushFailed;
+            }
+        }
+
+        let commands = updates
+            .iter()
+            .filter(|update| update.status == PushStatus::Ok)
+            .map(|update| (update.old, update.new, update.dst))
+            .collect::<Vec<_>>();
+        if commands.is_empty() {
+            // nothing to do, just let the remote know it can hang up
+            self.write_flush_packet().await?;
+            return Ok(PushSummary { updates });
+        }
+
+        if commands.iter().any(|(_, new, _)| new.is_unknown()) {
+            ensure!(
+                capabilities.contains(&Capability::DeleteRefs),
+                "the remote does not support deleting refs"
+            );
+        }
+
+        let report_status = capabilities.contains(&Capability::ReportStatus);
+        // over http, nothing is sent until there is a response to read
+        ensure!(
+            report_status || !self.is_stateless_rpc(),
+            "the remote does not support `report-status` which is required to push over http"
+        );
+        let mut requested = vec![Capability::Agent(AGENT.to_owned())];
+        if report_status {
+            requested.push(Capability::ReportStatus);
+        }
+        if opts.atomic {
+            ensure!(
+                capabilities.contains(&Capability::Atomic),
+                "the remote does not support atomic pushes"
+            );
+            requested.push(Capability::Atomic);
+        }
+        let requested = requested.iter().map(|cap| cap.to_string()).collect::<Vec<_>>().join(" ");
+
+        for (i, &(old, new, dst)) in commands.iter().enumerate() {
+            let command = if i == 0 {
+                format!("{old} {new} {dst}\0{requested}\n")
+            } else {
+                format!("{old} {new} {dst}\n")
+            };
+            self.write_packet(command.as_bytes()).await?;
+        }
+        self.write_flush_packet().await?;
+
+        // a pack must be sent unless every command is a deletion
+        let include = commands
+            .iter()
+            .map(|&(_, new, _)| new)
+            .filter(|new| !new.is_unknown())
+            .collect::<Vec<_>>();
+        if !include.is_empty() {
+            // anything reachable from the remote's refs (that we know of) doesn't need to be sent
+            let mut exclude = vec![];
+            for &oid in refs.values() {
+                let is_commit = repo.obj_exists(oid)?
+                    && repo.read_obj_header(oid)?.obj_type == BitObjType::Commit;
+                if is_commit {
+                    exclude.push(oid);
+                }
+            }
+            let objects = repo.reachable_objects(&include, &exclude)?;
+            let mut builder = PackBuilder::new(repo.clone(), objects);
+            if !capabilities.contains(&Capability::OfsDelta) {
+                builder = builder.window(0);
+            }
+            self.write_pack(builder, false).await?;
+            self.flush().await?;
+        }
+
+        if report_status {
+            self.recv_report_status(&mut updates).await?;
+        }
+
+        // keep our remote tracking branches in sync with what we just pushed
+        for update in &updates {
+            if update.status != PushStatus::Ok {
+                continue;
+            }
+            let tracking = match remote.fetch.match_ref(update.dst) {
+                Some(tracking) => tracking,
+                None => continue,
+            };
+            if update.new.is_unknown() {
+                if repo.refdb()?.exists(tracking)? {
+                    repo.refdb()?.delete(tracking)?;
+                }
+            } else {
+                repo.update_ref(tracking, update.new, RefUpdateCause::Push)?;
+            }
+        }
+
+        Ok(PushSummary { updates })
+    }
+
+    /// Parse the `report-status` response of `receive-pack`, marking updates the remote rejected
+    async fn recv_report_status(&mut self, updates: &mut [PushUpdate]) -> BitResult<()> {
+        let packet = self.recv_packet().await?;
+        let line = std::str::from_utf8(&packet)?.trim_end();
+        match line.strip_prefix("unpack ") {
+            Some("ok") => {}
+            Some(err) => bail!("remote failed to unpack objects: {}", err),
+            None => bail!("malformed `report-status` line `{}`", line),
+        }
+
+        loop {
+            let packet = self.recv_packet().await?;
+            if packet.is_empty() {
+                break Ok(());
+            }
+            let line = std::str::from_utf8(&packet)?.trim_end();
+            let (dst, reason) = if let Some(dst) = line.strip_prefix("ok ") {
+                (dst, None)
+            } else if let Some(rest) = line.strip_prefix("ng ") {
+                let (dst, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+                (dst, Some(reason))
+            } else {
+                bail!("malformed `report-status` line `{}`", line)
+            };
+
+            let update = updates
+                .iter_mut()
+                .find(|update| update.dst.path().as_str() == dst)
+                .ok_or_else(|| anyhow!("remote reported status for unknown ref `{}`", dst))?;
+            if let Some(reason) = reason {
+                update.status = PushStatus::RemoteRejected(reason.to_owned());
+            }
+        }
+    }
+
+    async fn negotiate_packs(
+        &mut self,
+        remote_mapping: &HashMap<SymbolicRef, Oid>,
+        opts: &FetchOpts,
+        shallow_args: &[String],
+    ) -> BitResult<FetchStatus> {
+        let (wanted, local_tips) =
+            wanted_and_local_tips(self.repo(), remote_mapping, opts.deepens())?;
         if wanted.is_empty() {
+            // an empty want list lets the remote know it can hang up
+            self.write_flush_packet().await?;
             return Ok(FetchStatus::UpToDate);
         }
 
+        let mut capabilities =
+            vec![Capability::MultiAckDetailed, Capability::OfsDelta, Capability::SideBand64k];
+        if !shallow_args.is_empty() {
+            capabilities.push(Capability::Shallow);
+        }
+        if opts.deepen.is_some() {
+            capabilities.push(Capability::DeepenRelative);
+        }
+        if opts.filter.is_some() {
+            capabilities.push(Capability::Filter);
+        }
         for (i, &oid) in wanted.iter().enumerate() {
             if i == 0 {
                 let capabilities =
-                    [Capability::MultiAckDetailed, Capability::OfsDelta, Capability::SideBand64k]
-                        .map(|cap| cap.to_string())
-                        .join(" ");
+                    capabilities.iter().map(|cap| cap.to_string()).collect::<Vec<_>>().join(" ");
                 self.write_packet(format!("want {oid} {capabilities}\n").as_bytes()).await?;
             } else {
                 self.want(oid).await?;
             }
         }
+        for arg in shallow_args {
+            self.write_packet(arg.as_bytes()).await?;
+        }
+        if let Some(filter) = opts.filter {
+            self.write_packet(format!("filter {}\n", filter).as_bytes()).await?;
+        }
         self.write_flush_packet().await?;
 
-        let mut walk = self.repo().revwalk_builder().roots_iter(local_tips)?.build();
+        // the new shallow boundary is sent straight away if it's changing
+        let (shallow_update, repeats_shallow_info) = if opts.deepens() {
+            (self.recv_shallow_info().await?, self.is_stateless_rpc())
+        } else {
+            (ShallowUpdate::default(), false)
+        };
+
+        let mut walk = self.repo().revwalk_builder().roots_iter(local_tips)?.build()?;
         'outer: loop {
             // TODO exit early when "ready" whatever that means
             for _ in 0..MULTI_ACK_BATCH_SIZE {
@@ -101,6 +565,9 @@ pub trait ProtocolTransport: BitProtocolRead + BitProtocolWrite {
                     Some(commit) => commit,
                     None => {
                         self.write_flush_packet().await?;
+                        if repeats_shallow_info {
+                            self.recv_shallow_info().await?;
+                        }
                         self.recv_packet().await?;
                         break 'outer;
                     }
@@ -108,19 +575,27 @@ pub trait ProtocolTransport: BitProtocolRead + BitProtocolWrite {
                 self.have(next_commit.oid()).await?;
             }
             self.write_flush_packet().await?;
+            if repeats_shallow_info {
+                self.recv_shallow_info().await?;
+            }
             self.recv_packet().await?;
         }
 
         self.done().await?;
+        if repeats_shallow_info {
+            self.recv_shallow_info().await?;
+        }
         let repo = self.repo().clone();
-        self.recv_pack(repo).await?;
+        self.recv_pack(repo, &opts.progress).await?;
+        repo.update_shallow(&shallow_update.shallow, &shallow_update.unshallow)?;
         Ok(FetchStatus::NotUpToDate)
     }
 
+    /// `packet` is the first packet the remote sent
     async fn parse_ref_discovery_and_capabilities(
         &mut self,
+        packet: Vec<u8>,
     ) -> BitResult<(HashMap<SymbolicRef, Oid>, Capabilities)> {
-        let packet = self.recv_packet().await?;
         let mut mapping = HashMap::new();
 
         // if we are cloning an empty repository, `git-upload-pack` just sends a flush
@@ -139,21 +614,115 @@ pub trait ProtocolTransport: BitProtocolRead + BitProtocolWrite {
             })
             .collect::<BitResult<Capabilities>>()?;
 
-        let (oid, sym) = parse_ref_line(ref_line)?;
-        mapping.insert(sym, oid);
+        if let Some((oid, sym)) = parse_ref_line(ref_line)? {
+            mapping.insert(sym, oid);
+        }
 
         loop {
             let packet = self.recv_packet().await?;
             if packet.is_empty() {
                 break Ok((mapping, parsed_capabilities));
             }
-            let (oid, sym) = parse_ref_line(std::str::from_utf8(&packet)?)?;
-            mapping.insert(sym, oid);
+            if let Some((oid, sym)) = parse_ref_line(std::str::from_utf8(&packet)?)? {
+                mapping.insert(sym, oid);
+            }
         }
     }
 }
 
-fn parse_ref_line(s: &str) -> BitResult<(Oid, SymbolicRef)> {
-    let (oid, sym) = s.split_once(' ').ok_or_else(|| anyhow!("malformed ref line"))?;
-    Ok((oid.parse()?, sym.parse()?))
+/// Maps the remote refs to the local refs they are fetched into (according to the fetch refspec)
+fn map_remote_refs(remote: &Remote, refs: HashMap<SymbolicRef, Oid>) -> HashMap<SymbolicRef, Oid> {
+    refs.into_iter().filter_map(|(sym, oid)| Some((remote.fetch.match_ref(sym)?, oid))).collect()
 }
+
+/// Returns the remote commits we don't have yet and what we have of the remote refs already.
+/// Everything is wanted when deepening as we are after the history of commits we already have.
+fn wanted_and_local_tips(
+    repo: &BitRepo,
+    remote_mapping: &HashMap<SymbolicRef, Oid>,
+    deepen: bool,
+) -> BitResult<(Vec<Oid>, Vec<Oid>)> {
+    let mut wanted = vec![];
+    let mut local_tips = vec![];
+    for (&remote, &remote_oid) in remote_mapping {
+        let local_oid = repo.try_fully_resolve_ref(remote)?;
+        if let Some(local_oid) = local_oid {
+            local_tips.push(local_oid)
+        }
+        if (deepen || local_oid != Some(remote_oid)) && !wanted.contains(&remote_oid) {
+            wanted.push(remote_oid);
+        }
+    }
+    Ok((wanted, local_tips))
+}
+
+#[derive(Debug, Default)]
+struct ShallowUpdate {
+    /// commits that are now shallow
+    shallow: Vec<Oid>,
+    /// commits that are no longer shallow (as their parents were sent)
+    unshallow: Vec<Oid>,
+}
+
+/// The lines that follow the `want`s: a `shallow` line for each of our shallow commits, so the
+/// remote knows what history we don't have, and whatever `deepen` lines `opts` calls for
+fn shallow_args(repo: &BitRepo, opts: &FetchOpts) -> BitResult<Vec<String>> {
+    let mut shallow = repo.shallow_commits()?.iter().copied().collect::<Vec<_>>();
+    shallow.sort();
+    let mut args = shallow.into_iter().map(|oid| format!("shallow {}\n", oid)).collect::<Vec<_>>();
+
+    if let Some(depth) = opts.depth.or(opts.deepen) {
+        args.push(format!("deepen {}\n", depth));
+    }
+    if opts.unshallow {
+        args.push(format!("deepen {}\n", INFINITE_DEPTH));
+    }
+    if let Some(since) = opts.shallow_since {
+        let since = since.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        args.push(format!("deepen-since {}\n", since));
+    }
+    for exclude in &opts.shallow_exclude {
+        args.push(format!("deepen-not {}\n", exclude));
+    }
+    Ok(args)
+}
+
+fn update_remote_refs(repo: &BitRepo, remote_mapping: &HashMap<SymbolicRef, Oid>) -> BitResult<()> {
+    // TODO check the refspec for forcedness before updating: create a function
+    // `try_update_remote_ref`
+    for (&remote, &oid) in remote_mapping {
+        repo.update_ref_for_fetch(remote, BitRef::Direct(oid))?;
+    }
+    Ok(())
+}
+
+/// Parses a line of the `ls-refs` response of the form
+/// `<oid> <refname> [symref-target:<target>] [peeled:<oid>]`.
+/// Returns the oid, the name, and the symref target if there is one.
+fn parse_ls_refs_line(s: &str) -> BitResult<(Oid, SymbolicRef, Option<SymbolicRef>)> {
+    let mut parts = s.trim_end().split(' ');
+    let (oid, sym) = match (parts.next(), parts.next()) {
+        (Some(oid), Some(sym)) => (oid.parse()?, sym.parse()?),
+        _ => bail!("malformed `ls-refs` line `{}`", s.trim_end()),
+    };
+    let mut symref_target = None;
+    for attribute in parts {
+        if let Some(target) = attribute.strip_prefix("symref-target:") {
+            symref_target = Some(target.parse()?);
+        }
+    }
+    Ok((oid, sym, symref_target))
+}
+
+/// Returns `None` for lines that don't correspond to a ref
+/// (i.e. peeled tags and the placeholder line that `receive-pack` sends for an empty repository)
+fn parse_ref_line(s: &str) -> BitResult<Option<(Oid, SymbolicRef)>> {
+    let (oid, sym) = s.trim_end().split_once(' ').ok_or_else(|| anyhow!("malformed ref line"))?;
+    if sym.ends_with("^{}") {
+        return Ok(None);
+    }
+    Ok(Some((oid.parse()?, sym.parse()?)))
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,94 @@
+use super::*;
+use crate::daemon::DEFAULT_DAEMON_PORT;
+use anyhow::Context as _;
+use git_url_parse::GitUrl;
+use pin_project_lite::pin_project;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{self, AsyncBufRead, AsyncRead, AsyncWrite, BufReader, ReadBuf};
+use tokio::net::TcpStream;
+
+pin_project! {
+    /// The transport for `git://` urls which talks to a `git daemon` (or `bit-daemon`) over tcp
+    pub struct GitDaemonTransport {
+        repo: BitRepo,
+        #[pin]
+        stream: BufReader<TcpStream>,
+    }
+}
+
+impl GitDaemonTransport {
+    /// `program` is the service to request from the daemon (e.g. `git-upload-pack`)
+    pub async fn new(repo: &BitRepo, url: &GitUrl, program: &str) -> BitResult<Self> {
+        let host = url.host.as_deref().ok_or_else(|| anyhow!("git url is missing a host"))?;
+        let port = url.port.unwrap_or(DEFAULT_DAEMON_PORT);
+        let stream = TcpStream::connect((host, port))
+            .await
+            .with_context(|| format!("failed to connect to `{}:{}`", host, port))?;
+        let mut stream = BufReader::new(stream);
+
+        // the daemon runs the service as soon as it receives this request so there is no
+        // response to wait for
+        let host = match url.port {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_owned(),
+        };
+        let mut request = format!("{} {}\0host={}\0", program, url.path, host);
+        // extra parameters follow an empty one, daemons that don't understand them ignore them
+        if request_protocol_v2(repo, program) {
+            request.push_str("\0version=2\0");
+        }
+        stream.write_packet(request.as_bytes()).await?;
+        stream.flush().await?;
+
+        Ok(Self { repo: repo.clone(), stream })
+    }
+}
+
+#[async_trait]
+impl ProtocolTransport for GitDaemonTransport {
+    fn repo(&self) -> &BitRepo {
+        &self.repo
+    }
+}
+
+impl AsyncBufRead for GitDaemonTransport {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        self.project().stream.poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.project().stream.consume(amt)
+    }
+}
+
+impl AsyncRead for GitDaemonTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.project().stream.poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for GitDaemonTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().stream.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().stream.poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        self.project().stream.poll_shutdown(cx)
+    }
+}
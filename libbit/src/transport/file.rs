@@ -19,9 +19,14 @@ pin_project! {
 }
 
 impl FileTransport {
-    pub async fn new(repo: &BitRepo, url: &GitUrl) -> BitResult<Self> {
+    /// `program` is the service to run on the remote end (e.g. `git-upload-pack`)
+    pub async fn new(repo: &BitRepo, url: &GitUrl, program: &str) -> BitResult<Self> {
         let path = path::normalize(&repo.to_absolute_path(&url.path));
-        let mut child = Command::new("git-upload-pack")
+        let mut command = Command::new(program);
+        if request_protocol_v2(repo, program) {
+            command.env("GIT_PROTOCOL", "version=2");
+        }
+        let mut child = command
             .arg(&path)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
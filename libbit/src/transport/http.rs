@@ -0,0 +1,620 @@
+use super::*;
+use anyhow::Context as _;
+use futures::StreamExt;
+use git_url_parse::{GitUrl, Scheme};
+use parking_lot::Mutex;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, Url};
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{self, AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+use tokio::process::Command;
+use tokio_util::io::StreamReader;
+
+/// the maximum number of redirects followed by the initial request
+const MAX_REDIRECTS: usize = 20;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the server may go without sending anything before the request is abandoned.
+/// There is no limit on the request as a whole as a large clone can take arbitrarily long.
+const READ_TIMEOUT: Duration = Duration::from_secs(120);
+
+type PendingResponse = Pin<Box<dyn Future<Output = BitResult<Response>> + Send>>;
+type ResponseReader = Pin<Box<dyn AsyncBufRead + Send>>;
+
+/// The smart HTTP transport which speaks the stateless-RPC variant of the protocol.
+/// Everything written is buffered until the next read which sends it as the body of a single
+/// `POST` request, and the response to that request is what is then read (as it arrives).
+// https://github.com/git/git/blob/master/Documentation/technical/http-protocol.txt
+pub struct HttpTransport {
+    repo: BitRepo,
+    client: Arc<HttpClient>,
+    service: String,
+    /// the body of the next request
+    request: Vec<u8>,
+    /// the body of the last response
+    response: Option<ResponseReader>,
+    pending: Option<PendingResponse>,
+    /// whether the server speaks protocol v2 where every request stands on its own anyway
+    v2: bool,
+    /// The (protocol v1) server doesn't remember anything between requests so the `want`s of the
+    /// first request and every commit the server has acknowledged as common are resent with each
+    /// request
+    wants: Option<Vec<u8>>,
+    common: Arc<Mutex<Vec<Oid>>>,
+}
+
+impl HttpTransport {
+    /// `service` is the service to request from the server (e.g. `git-upload-pack`).
+    /// The reference discovery is done upfront as it is a separate request.
+    pub async fn new(repo: &BitRepo, url: &GitUrl, service: &str) -> BitResult<Self> {
+        let mut client = HttpClient::new(repo, url, request_protocol_v2(repo, service))?;
+        let advertisement = client.discover_refs(service).await?;
+        let client = Arc::new(client);
+
+        // smart servers prefix the v0 advertisement with the service name,
+        // but protocol v2 servers start with the capability advertisement straight away
+        let mut reader = &advertisement[..];
+        let packet = reader.recv_packet().await?;
+        let expected = format!("# service={}\n", service);
+        let response = if packet == expected.as_bytes() {
+            ensure!(reader.recv_packet().await?.is_empty(), "expected flush after service line");
+            reader.to_vec()
+        } else {
+            ensure!(
+                packet == b"version 2\n",
+                "invalid smart http advertisement from `{}`",
+                client.url
+            );
+            advertisement
+        };
+        let v2 = response.starts_with(b"000eversion 2\n");
+
+        Ok(Self {
+            repo: repo.clone(),
+            client,
+            service: service.to_owned(),
+            request: vec![],
+            response: Some(Box::pin(std::io::Cursor::new(response))),
+            pending: None,
+            v2,
+            wants: None,
+            common: Default::default(),
+        })
+    }
+
+    fn start_request(&mut self) {
+        let request = std::mem::take(&mut self.request);
+        let body = match &self.wants {
+            // a push is a single request
+            _ if self.v2 || self.service.ends_with("receive-pack") => request,
+            Some(wants) => {
+                let mut body = wants.clone();
+                for oid in self.common.lock().iter() {
+                    push_packet(&mut body, format!("have {}\n", oid).as_bytes());
+                }
+                body.extend_from_slice(&request);
+                body
+            }
+            None => {
+                self.wants = wants_len(&request).map(|len| request[..len].to_vec());
+                request
+            }
+        };
+
+        let client = Arc::clone(&self.client);
+        let service = self.service.clone();
+        self.pending = Some(Box::pin(async move { client.rpc(&service, &body).await }));
+    }
+
+    /// Reads the body of `response` as it arrives, recording the commits the (protocol v1) server
+    /// acknowledges so they can be included in later requests
+    fn read_response(&self, response: Response) -> ResponseReader {
+        let mut acks = (!self.v2).then(|| AckRecorder::new(Arc::clone(&self.common)));
+        let body = response.bytes_stream().map(move |chunk| {
+            let chunk = chunk.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            if let Some(acks) = &mut acks {
+                acks.record(&chunk);
+            }
+            Ok::<_, io::Error>(chunk)
+        });
+        Box::pin(StreamReader::new(body))
+    }
+}
+
+#[async_trait]
+impl ProtocolTransport for HttpTransport {
+    fn repo(&self) -> &BitRepo {
+        &self.repo
+    }
+
+    fn is_stateless_rpc(&self) -> bool {
+        !self.v2
+    }
+}
+
+impl AsyncBufRead for HttpTransport {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.pending.is_none() && this.response.is_none() && !this.request.is_empty() {
+            this.start_request();
+        }
+
+        if let Some(pending) = &mut this.pending {
+            let response = ready!(pending.as_mut().poll(cx));
+            this.pending = None;
+            let response = response.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            this.response = Some(this.read_response(response));
+        }
+
+        match &mut this.response {
+            Some(response) => response.as_mut().poll_fill_buf(cx),
+            None => Poll::Ready(Ok(&[])),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        if let Some(response) = &mut self.get_mut().response {
+            response.as_mut().consume(amt);
+        }
+    }
+}
+
+impl AsyncRead for HttpTransport {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let available = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let n = available.len().min(buf.remaining());
+        buf.put_slice(&available[..n]);
+        self.consume(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for HttpTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // whatever is left of the last response is stale once the next request begins
+        this.response = None;
+        this.request.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    // the request is only sent once the response is read
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Parses the `ACK`s at the start of a protocol v1 negotiation response as its chunks arrive
+struct AckRecorder {
+    /// the start of a packet that is split across chunks
+    partial: Vec<u8>,
+    /// whether the acknowledgements are over (and the rest of the response can be ignored)
+    done: bool,
+    common: Arc<Mutex<Vec<Oid>>>,
+}
+
+impl AckRecorder {
+    fn new(common: Arc<Mutex<Vec<Oid>>>) -> Self {
+        Self { partial: vec![], done: false, common }
+    }
+
+    fn record(&mut self, chunk: &[u8]) {
+        if self.done {
+            return;
+        }
+        self.partial.extend_from_slice(chunk);
+        let mut response = &self.partial[..];
+        loop {
+            let (packet, rest) = match split_packet(response) {
+                Some((Some(packet), rest)) => (packet, rest),
+                // the acknowledgements end with a flush
+                Some((None, _)) => {
+                    self.done = true;
+                    break;
+                }
+                // or with the pack itself (which isn't made up of packets without a sideband)
+                None if response.len() >= 4 && packet_len(response).is_none() => {
+                    self.done = true;
+                    break;
+                }
+                // the rest of the packet is in the next chunk
+                None => break,
+            };
+            match parse_ack(packet) {
+                Some(Some(oid)) => {
+                    let mut common = self.common.lock();
+                    if !common.contains(&oid) {
+                        common.push(oid);
+                    }
+                }
+                Some(None) => {}
+                None => {
+                    self.done = true;
+                    break;
+                }
+            }
+            response = rest;
+        }
+
+        if self.done {
+            self.partial = vec![];
+        } else {
+            let consumed = self.partial.len() - response.len();
+            self.partial.drain(..consumed);
+        }
+    }
+}
+
+/// Parses an `ACK` returning the acknowledged commit (or `None` for a `NAK`).
+/// Returns `None` if `packet` is neither.
+fn parse_ack(packet: &[u8]) -> Option<Option<Oid>> {
+    let line = std::str::from_utf8(packet).ok()?.trim_end();
+    if line == "NAK" {
+        return Some(None);
+    }
+    let oid = line.strip_prefix("ACK ")?.split(' ').next()?;
+    oid.parse().ok().map(Some)
+}
+
+#[derive(Debug)]
+enum HttpAuth {
+    Basic { username: String, password: String },
+    Bearer(String),
+}
+
+/// The client shared by every request to a single repository
+struct HttpClient {
+    client: Client,
+    /// the url of the repository (that every request is relative to)
+    url: String,
+    /// the scheme of the url (as told to the credential helper)
+    protocol: String,
+    /// `host:port` (or just `host` for the default port)
+    authority: String,
+    username: Option<String>,
+    extra_header: Option<String>,
+    credential_helper: Option<String>,
+    /// whether to ask the server to speak protocol v2 (with the `Git-Protocol` header)
+    protocol_v2: bool,
+    auth: Mutex<Option<HttpAuth>>,
+}
+
+/// What the credential helper is asked to do (`git credential-<helper> <action>`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CredentialAction {
+    Get,
+    /// the credentials worked (`approve`)
+    Store,
+    /// the credentials were rejected (`reject`)
+    Erase,
+}
+
+impl CredentialAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            CredentialAction::Get => "get",
+            CredentialAction::Store => "store",
+            CredentialAction::Erase => "erase",
+        }
+    }
+}
+
+impl HttpClient {
+    fn new(repo: &BitRepo, url: &GitUrl, protocol_v2: bool) -> BitResult<Self> {
+        ensure!(matches!(url.scheme, Scheme::Http | Scheme::Https), "`{}` is not an http url", url);
+        let host = url.host.as_deref().ok_or_else(|| anyhow!("http url is missing a host"))?;
+        let port = url.port.map(|port| format!(":{}", port)).unwrap_or_default();
+        let path = match url.path.trim_matches('/') {
+            "" => String::new(),
+            path => format!("/{}", path),
+        };
+        let base = Url::parse(&format!("{}://{}{}{}", url.scheme, host, port, path))?;
+        let auth = match (&url.user, &url.token) {
+            (Some(username), Some(password)) =>
+                Some(HttpAuth::Basic { username: username.clone(), password: password.clone() }),
+            _ => None,
+        };
+        let client = Client::builder()
+            .user_agent(AGENT)
+            // only the initial request follows redirects (see `discover_refs`)
+            .redirect(Policy::none())
+            .connect_timeout(CONNECT_TIMEOUT)
+            .read_timeout(READ_TIMEOUT)
+            .build()?;
+
+        Ok(Self {
+            client,
+            url: base.as_str().trim_end_matches('/').to_owned(),
+            protocol: url.scheme.to_string(),
+            authority: authority(&base)?,
+            username: url.user.clone(),
+            extra_header: repo.config().http_extra_header(),
+            credential_helper: repo.config().credential_helper(),
+            protocol_v2,
+            auth: Mutex::new(auth),
+        })
+    }
+
+    /// `GET $url/info/refs?service=$service`.
+    /// Like git (with the default `http.followRedirects=initial`), only this request follows
+    /// redirects and the url it is redirected to becomes the base for every later request.
+    async fn discover_refs(&mut self, service: &str) -> BitResult<Vec<u8>> {
+        let query = format!("/info/refs?service={}", service);
+        let mut redirects = 0;
+        let response = loop {
+            let response = self.send(Method::GET, &query, None, &[]).await?;
+            match redirect_location(&response) {
+                Some(location) => {
+                    ensure!(redirects < MAX_REDIRECTS, "too many redirects from `{}`", self.url);
+                    redirects += 1;
+                    self.redirect(location, &query)?;
+                }
+                None => break response,
+            }
+        };
+        // a dumb server would just send the file as is
+        let expected = format!("application/x-{}-advertisement", service);
+        ensure!(
+            content_type(&response) == Some(expected.as_str()),
+            "`{}` is not a smart http server (dumb http is not supported)",
+            self.url
+        );
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// `POST $url/$service` returning the response (whose body is yet to be read)
+    async fn rpc(&self, service: &str, body: &[u8]) -> BitResult<Response> {
+        let request_type = format!("application/x-{}-request", service);
+        let response =
+            self.send(Method::POST, &format!("/{}", service), Some(&request_type), body).await?;
+        ensure!(redirect_location(&response).is_none(), "unexpected redirect from `{}`", self.url);
+        let expected = format!("application/x-{}-result", service);
+        ensure!(
+            content_type(&response) == Some(expected.as_str()),
+            "invalid content type `{}` in response from `{}`",
+            content_type(&response).unwrap_or_default(),
+            self.url
+        );
+        Ok(response)
+    }
+
+    /// Rebase the url on the `location` a request for `query` was redirected to
+    fn redirect(&mut self, location: &str, query: &str) -> BitResult<()> {
+        let mut url = Url::parse(&self.url)?
+            .join(location)
+            .with_context(|| format!("invalid redirect to `{}`", location))?;
+        ensure!(
+            matches!(url.scheme(), "http" | "https"),
+            "redirected to unsupported `{}` url `{}`",
+            url.scheme(),
+            location
+        );
+        // ignore any credentials in the location
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+        let base = url.as_str().strip_suffix(query).ok_or_else(|| {
+            anyhow!("unable to update url base from redirection to `{}`", location)
+        })?;
+        let authority = authority(&url)?;
+
+        // the credentials we have are for the original host
+        if authority != self.authority {
+            *self.auth.get_mut() = None;
+        }
+        self.url = base.to_owned();
+        self.protocol = url.scheme().to_owned();
+        self.authority = authority;
+        Ok(())
+    }
+
+    /// Send the request, asking the credential helper for credentials if the server requires
+    /// authentication that we don't have (and telling it whether they worked)
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> BitResult<Response> {
+        let mut response = self.send_once(method.clone(), path, content_type, body).await?;
+        let authenticated = self.auth.lock().is_some();
+        if response.status() == StatusCode::UNAUTHORIZED && !authenticated {
+            if let Some(auth) = self.fill_credentials().await? {
+                *self.auth.lock() = Some(auth);
+                response = self.send_once(method, path, content_type, body).await?;
+                match response.status().as_u16() {
+                    401 => self.run_credential_helper(CredentialAction::Erase).await?,
+                    200..=299 => self.run_credential_helper(CredentialAction::Store).await?,
+                    _ => None,
+                };
+            }
+        }
+
+        match response.status().as_u16() {
+            200..=299 => Ok(response),
+            _ if redirect_location(&response).is_some() => Ok(response),
+            401 => bail!("authentication failed for `{}`", self.url),
+            404 => bail!("repository `{}` not found", self.url),
+            status => bail!("unexpected http status `{}` from `{}`", status, self.url),
+        }
+    }
+
+    async fn send_once(
+        &self,
+        method: Method,
+        path: &str,
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> BitResult<Response> {
+        let mut request =
+            self.authorize(self.client.request(method, format!("{}{}", self.url, path)));
+        if self.protocol_v2 {
+            request = request.header("Git-Protocol", "version=2");
+        }
+        if let Some((name, value)) = self.extra_header.as_deref().and_then(|h| h.split_once(':')) {
+            request = request.header(name.trim(), value.trim());
+        }
+        if let Some(content_type) = content_type {
+            request = request.header(CONTENT_TYPE, content_type).body(body.to_vec());
+        }
+        request.send().await.with_context(|| format!("failed to connect to `{}`", self.url))
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &*self.auth.lock() {
+            Some(HttpAuth::Basic { username, password }) =>
+                request.basic_auth(username, Some(password)),
+            Some(HttpAuth::Bearer(token)) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Ask the configured credential helper for credentials using git's credential protocol.
+    /// The helper may either return a username and password or a bearer token.
+    async fn fill_credentials(&self) -> BitResult<Option<HttpAuth>> {
+        let output = match self.run_credential_helper(CredentialAction::Get).await? {
+            Some(output) => output,
+            None => return Ok(None),
+        };
+        let mut attributes = HashMap::new();
+        for line in output.lines() {
+            if let Some((key, value)) = line.split_once('=') {
+                attributes.insert(key, value);
+            }
+        }
+
+        let auth = match (attributes.get("authtype"), attributes.get("credential")) {
+            (Some(authtype), Some(token)) if authtype.eq_ignore_ascii_case("bearer") =>
+                Some(HttpAuth::Bearer(token.to_string())),
+            _ => match (attributes.get("username"), attributes.get("password")) {
+                (Some(username), Some(password)) => Some(HttpAuth::Basic {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                }),
+                _ => None,
+            },
+        };
+        Ok(auth)
+    }
+
+    /// Runs the configured credential helper (if any) returning its output.
+    /// Everything we know about the credentials is given to the helper, which for `store` and
+    /// `erase` includes the credentials themselves.
+    async fn run_credential_helper(&self, action: CredentialAction) -> BitResult<Option<String>> {
+        let helper = match &self.credential_helper {
+            Some(helper) => helper,
+            None => return Ok(None),
+        };
+        // the helper is always run by the shell, just like git does
+        let command = match helper.strip_prefix('!') {
+            Some(command) => command.to_owned(),
+            None if Path::new(helper).is_absolute() => helper.to_owned(),
+            None => format!("git credential-{}", helper),
+        };
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} {}", command, action.as_str()))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let input = self.credential_input(action);
+        let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("failed to open stdin"))?;
+        stdin.write_all(input.as_bytes()).await?;
+        drop(stdin);
+
+        let output = child.wait_with_output().await?;
+        ensure!(
+            output.status.success(),
+            "credential helper `{}` failed to {} credentials",
+            helper,
+            action.as_str()
+        );
+        Ok(Some(String::from_utf8(output.stdout)?))
+    }
+
+    fn credential_input(&self, action: CredentialAction) -> String {
+        let mut input = format!("protocol={}\nhost={}\n", self.protocol, self.authority);
+        match &*self.auth.lock() {
+            Some(HttpAuth::Basic { username, password }) if action != CredentialAction::Get =>
+                input.push_str(&format!("username={}\npassword={}\n", username, password)),
+            Some(HttpAuth::Bearer(token)) if action != CredentialAction::Get =>
+                input.push_str(&format!("authtype=Bearer\ncredential={}\n", token)),
+            _ => input.extend(self.username.iter().map(|name| format!("username={}\n", name))),
+        }
+        input.push('\n');
+        input
+    }
+}
+
+/// `host:port` (or just `host` for the default port)
+fn authority(url: &Url) -> BitResult<String> {
+    let host = url.host_str().ok_or_else(|| anyhow!("url `{}` is missing a host", url))?;
+    Ok(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    })
+}
+
+fn content_type(response: &Response) -> Option<&str> {
+    response.headers().get(CONTENT_TYPE)?.to_str().ok()
+}
+
+/// where the response redirects to (if it is a redirect)
+fn redirect_location(response: &Response) -> Option<&str> {
+    match response.status().as_u16() {
+        301 | 302 | 303 | 307 | 308 => response.headers().get(LOCATION)?.to_str().ok(),
+        _ => None,
+    }
+}
+
+fn push_packet(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(format!("{:04x}", 4 + bytes.len()).as_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Splits off the pkt-line at the start of `bytes` returning its contents and the remaining bytes.
+/// The contents of a flush packet are `None`.
+fn split_packet(bytes: &[u8]) -> Option<(Option<&[u8]>, &[u8])> {
+    let len = packet_len(bytes)?;
+    match len {
+        0 => Some((None, &bytes[4..])),
+        4.. if len <= bytes.len() => Some((Some(&bytes[4..len]), &bytes[len..])),
+        _ => None,
+    }
+}
+
+/// The length of the pkt-line at the start of `bytes` (as given by its 4 hex digit prefix)
+fn packet_len(bytes: &[u8]) -> Option<usize> {
+    usize::from_str_radix(std::str::from_utf8(bytes.get(..4)?).ok()?, 16).ok()
+}
+
+/// The length of the `want` section of a request (i.e. up to and including the first flush)
+fn wants_len(request: &[u8]) -> Option<usize> {
+    let mut rest = request;
+    loop {
+        let (packet, next) = split_packet(rest)?;
+        rest = next;
+        if packet.is_none() {
+            break Some(request.len() - rest.len());
+        }
+    }
+}
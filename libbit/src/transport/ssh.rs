@@ -1,53 +1,77 @@
 use super::*;
-use crate::repo::BitRepo;
 use git_url_parse::GitUrl;
-use openssh::{RemoteChild, Session};
 use pin_project_lite::pin_project;
 use std::pin::Pin;
 use std::process::Stdio;
 use std::task::{Context, Poll};
 use tokio::io::{self, AsyncBufRead, AsyncRead, AsyncWrite, BufReader, ReadBuf};
-use tokio::process::{ChildStderr, ChildStdin, ChildStdout};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
 
 pin_project! {
-    pub struct SshTransport<'s> {
+    pub struct SshTransport {
         repo: BitRepo,
-        child: RemoteChild<'s>,
+        child: Child,
+        #[pin]
         stdin: ChildStdin,
         #[pin]
         stdout: BufReader<ChildStdout>,
-        stderr: BufReader<ChildStderr>,
     }
 }
 
-impl<'s> SshTransport<'s> {
-    pub async fn new(
-        repo: BitRepo,
-        session: &'s Session,
-        url: &GitUrl,
-    ) -> BitResult<SshTransport<'s>> {
-        let mut child = session
-            .command("git-upload-pack")
-            .arg(&url.path)
+impl SshTransport {
+    /// `program` is the service to run on the remote end (e.g. `git-upload-pack`)
+    // The ssh client is run directly (as git does) so that `GIT_PROTOCOL` can be forwarded with
+    // `SendEnv`, servers that don't accept the variable just speak v1.
+    pub async fn new(repo: &BitRepo, url: &GitUrl, program: &str) -> BitResult<Self> {
+        let host = url.host.as_deref().ok_or_else(|| anyhow!("ssh url is missing a host"))?;
+        let dst = match &url.user {
+            Some(user) => format!("{}@{}", user, host),
+            None => host.to_owned(),
+        };
+
+        let mut command = Command::new("ssh");
+        if request_protocol_v2(repo, program) {
+            command.env("GIT_PROTOCOL", "version=2").args(["-o", "SendEnv=GIT_PROTOCOL"]);
+        }
+        if let Some(port) = url.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        // the remote command is interpreted by the remote user's shell
+        let path = format!("'{}'", url.path.replace('\'', r"'\''"));
+        let mut child = command
+            .arg(dst)
+            .arg(format!("{} {}", program, path))
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
+            // errors from ssh itself (e.g. authentication failures) and from the remote program
+            // are only reported on stderr so they go straight to the user as with git
+            .stderr(Stdio::inherit())
             .spawn()?;
-        let stdin = child.stdin().take().unwrap();
-        let stdout = BufReader::new(child.stdout().take().unwrap());
-        let stderr = BufReader::new(child.stderr().take().unwrap());
-        Ok(Self { repo, child, stdin, stdout, stderr })
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+
+        Ok(Self { repo: repo.clone(), child, stdin, stdout })
+    }
+
+    /// Closes our end of the connection and waits for ssh to exit
+    pub async fn wait(self) -> BitResult<()> {
+        let Self { mut child, stdin, .. } = self;
+        drop(stdin);
+        let status = child.wait().await?;
+        ensure!(status.success(), "ssh exited unsuccessfully ({})", status);
+        Ok(())
     }
 }
 
 #[async_trait]
-impl ProtocolTransport for SshTransport<'_> {
+impl ProtocolTransport for SshTransport {
     fn repo(&self) -> &BitRepo {
         &self.repo
     }
 }
 
-impl AsyncBufRead for SshTransport<'_> {
+impl AsyncBufRead for SshTransport {
     fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
         self.project().stdout.poll_fill_buf(cx)
     }
@@ -57,33 +81,33 @@ impl AsyncBufRead for SshTransport<'_> {
     }
 }
 
-impl AsyncRead for SshTransport<'_> {
+impl AsyncRead for SshTransport {
     fn poll_read(
-        mut self: Pin<&mut Self>,
+        self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.stdout).poll_read(cx, buf)
+        self.project().stdout.poll_read(cx, buf)
     }
 }
 
-impl AsyncWrite for SshTransport<'_> {
+impl AsyncWrite for SshTransport {
     fn poll_write(
-        mut self: Pin<&mut Self>,
+        self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.stdin).poll_write(cx, buf)
+        self.project().stdin.poll_write(cx, buf)
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.stdin).poll_flush(cx)
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().stdin.poll_flush(cx)
     }
 
     fn poll_shutdown(
-        mut self: Pin<&mut Self>,
+        self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), std::io::Error>> {
-        Pin::new(&mut self.stdin).poll_shutdown(cx)
+        self.project().stdin.poll_shutdown(cx)
     }
 }
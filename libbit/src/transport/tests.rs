@@ -0,0 +1,221 @@
+use super::*;
+use crate::error::{BitError, BitErrorExt};
+use crate::protocol::{SIDEBAND_ERROR, SIDEBAND_PROGRESS};
+use crate::remote::DEFAULT_REMOTE;
+use crate::upload_pack::UploadPack;
+use std::io::Cursor;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{self, AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+
+const REMOTE_OID: &str = "1111111111111111111111111111111111111111";
+
+/// The pkt-lines that the remote is going to send (whatever we send it)
+#[derive(Default)]
+struct Script(Vec<u8>);
+
+impl Script {
+    fn packet(mut self, bytes: impl AsRef<[u8]>) -> Self {
+        let bytes = bytes.as_ref();
+        self.0.extend_from_slice(format!("{:04x}", 4 + bytes.len()).as_bytes());
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    fn sideband(self, band: u8, bytes: impl AsRef<[u8]>) -> Self {
+        self.packet([&[band][..], bytes.as_ref()].concat())
+    }
+
+    fn flush(mut self) -> Self {
+        self.0.extend_from_slice(b"0000");
+        self
+    }
+
+    /// The ref advertisement of a protocol v0 remote with only `master`
+    fn advertise_master(self) -> Self {
+        self.packet(format!("{} HEAD\0multi_ack_detailed side-band-64k ofs-delta\n", REMOTE_OID))
+            .packet(format!("{} refs/heads/master\n", REMOTE_OID))
+            .flush()
+    }
+}
+
+/// A transport that plays back a script instead of talking to an actual remote
+struct ScriptedTransport {
+    repo: BitRepo,
+    script: Cursor<Vec<u8>>,
+    sent: Vec<u8>,
+}
+
+impl ScriptedTransport {
+    fn new(repo: &BitRepo, script: Script) -> Self {
+        Self { repo: repo.clone(), script: Cursor::new(script.0), sent: vec![] }
+    }
+}
+
+#[async_trait]
+impl ProtocolTransport for ScriptedTransport {
+    fn repo(&self) -> &BitRepo {
+        &self.repo
+    }
+}
+
+impl AsyncBufRead for ScriptedTransport {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().script).poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.get_mut().script).consume(amt)
+    }
+}
+
+impl AsyncRead for ScriptedTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().script).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ScriptedTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().sent).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().sent).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().sent).poll_shutdown(cx)
+    }
+}
+
+#[tokio::main]
+async fn scripted_fetch(repo: &BitRepo, script: Script) -> BitResult<FetchSummary> {
+    let remote = repo.get_remote(DEFAULT_REMOTE)?;
+    ScriptedTransport::new(repo, script).fetch(&remote, &FetchOpts::default()).await
+}
+
+fn with_scripted_remote(f: impl FnOnce(BitRepo) -> BitResult<()>) -> BitResult<()> {
+    let dir = tempfile::tempdir()?;
+    BitRepo::init_load(dir.path(), |repo| {
+        repo.add_remote(DEFAULT_REMOTE, "https://example.com/scripted")?;
+        f(repo)
+    })
+}
+
+fn pack_dir_entries(repo: &BitRepo) -> BitResult<usize> {
+    Ok(std::fs::read_dir(repo.pack_objects_dir())?.count())
+}
+
+#[test]
+fn test_remote_err_instead_of_ref_advertisement() -> BitResult<()> {
+    with_scripted_remote(|repo| {
+        let script = Script::default().packet("ERR access denied or repository not exported\n");
+        let err = scripted_fetch(&repo, script).unwrap_err();
+        assert_eq!(
+            err.try_into_bit_error()?,
+            BitError::RemoteErr("access denied or repository not exported".to_owned())
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_remote_err_during_negotiation() -> BitResult<()> {
+    with_scripted_remote(|repo| {
+        let script = Script::default()
+            .advertise_master()
+            .packet(format!("ERR upload-pack: not our ref {}\n", REMOTE_OID));
+        let err = scripted_fetch(&repo, script).unwrap_err();
+        assert_eq!(
+            err.try_into_bit_error()?,
+            BitError::RemoteErr(format!("upload-pack: not our ref {}", REMOTE_OID))
+        );
+        // nothing was fetched so nothing should have been updated
+        assert!(repo.try_fully_resolve_ref(symbolic_ref!("refs/remotes/origin/master"))?.is_none());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_remote_sideband_error() -> BitResult<()> {
+    with_scripted_remote(|repo| {
+        let pack_dir_entries_before = pack_dir_entries(&repo)?;
+        let script = Script::default()
+            .advertise_master()
+            .packet("NAK\n")
+            .sideband(SIDEBAND_PROGRESS, "Enumerating objects: 3, done.\n")
+            .sideband(SIDEBAND_ERROR, "fatal: git upload-pack: not our ref\n");
+        let err = scripted_fetch(&repo, script).unwrap_err();
+        assert_eq!(
+            err.try_into_bit_error()?,
+            BitError::RemoteSidebandError("fatal: git upload-pack: not our ref".to_owned())
+        );
+        // the partial pack should be cleaned up
+        assert_eq!(pack_dir_entries(&repo)?, pack_dir_entries_before);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_remote_sideband_error_with_protocol_v2() -> BitResult<()> {
+    with_scripted_remote(|repo| {
+        let pack_dir_entries_before = pack_dir_entries(&repo)?;
+        let script = Script::default()
+            .packet("version 2\n")
+            .packet("ls-refs\n")
+            .packet("fetch=shallow\n")
+            .flush()
+            .packet(format!("{} HEAD symref-target:refs/heads/master\n", REMOTE_OID))
+            .packet(format!("{} refs/heads/master\n", REMOTE_OID))
+            .flush()
+            .packet("packfile\n")
+            .sideband(SIDEBAND_ERROR, "fatal: pack-objects died\n");
+        let err = scripted_fetch(&repo, script).unwrap_err();
+        assert!(err.is_remote_err());
+        assert_eq!(err.to_string(), "remote error while sending pack: fatal: pack-objects died");
+        assert_eq!(pack_dir_entries(&repo)?, pack_dir_entries_before);
+        Ok(())
+    })
+}
+
+#[tokio::main]
+async fn upload_pack_blocking(repo: BitRepo, request: Script) -> (BitResult<()>, Vec<u8>) {
+    let mut response = vec![];
+    let result = UploadPack::new(repo, Cursor::new(request.0), &mut response).serve().await;
+    (result, response)
+}
+
+#[tokio::main]
+async fn recv_ref_advertisement_and_next_packet(response: Vec<u8>) -> BitResult<Vec<u8>> {
+    let mut response = Cursor::new(response);
+    response.recv_message().await?;
+    response.recv_packet().await
+}
+
+#[test]
+fn test_upload_pack_sends_err_for_unadvertised_want() -> BitResult<()> {
+    BitRepo::with_sample_repo(|repo| {
+        let tree = repo.read_obj_commit(repo.fully_resolve_head()?)?.tree;
+        let request =
+            Script::default().packet(format!("want {} side-band-64k ofs-delta\n", tree)).flush();
+        let (result, response) = upload_pack_blocking(repo, request);
+        assert!(result.unwrap_err().to_string().contains("not our ref"));
+
+        // the client sees the reason rather than the connection just closing
+        let err = recv_ref_advertisement_and_next_packet(response).unwrap_err();
+        assert_eq!(
+            err.try_into_bit_error()?,
+            BitError::RemoteErr(format!("upload-pack: not our ref {}", tree))
+        );
+        Ok(())
+    })
+}
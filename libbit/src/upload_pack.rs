@@ -1,89 +1,189 @@
-use crate::error::BitGenericError;
-use crate::obj::Oid;
-use crate::protocol::{BitProtocolRead, BitProtocolWrite};
+use crate::obj::{BitObjType, BitObject, Oid};
+use crate::pack::PackBuilder;
+use crate::peel::Peel;
+use crate::protocol::{
+    BitProtocolRead, BitProtocolWrite, Capabilities, Capability, AGENT, SIDEBAND_ERROR,
+};
+use crate::refs::{BitRef, SymbolicRef};
 use anyhow::Result;
 use libbit::repo::BitRepo;
-use std::str::FromStr;
-use tokio::io;
+use rustc_hash::FxHashSet;
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt};
 
 // https://github.com/git/git/blob/master/Documentation/technical/protocol-common.txt
 pub struct UploadPack<R, W> {
     repo: BitRepo,
     reader: R,
     writer: W,
+    /// the capabilities the client requested (only those we recognize)
+    capabilities: Capabilities,
+    /// the client is only allowed to want objects that we advertised
+    advertised: FxHashSet<Oid>,
+    /// whether we've started on the pack (after which errors are sent over the sideband)
+    sending_pack: bool,
 }
 
-// TODO on error send an error line
-//   error-line     =  PKT-LINE("ERR" SP explanation-text)
-
-// TODO
-const CAPABILITIES: &[&str] = &[
-    // "multi_ack",
-    // "thin-pack",
-    // "side-band",
-    // "side-band-64k",
-    // "ofs-delta",
-    // "shallow",
-    // "deepen-since",
-    // "deepen-not",
-    // "deepen-relative",
-    // "no-progress",
-    // "include-tag",
-    // "multi_ack_detailed",
-    // // TODO this one needs to actually read HEAD
-    // "symref=HEAD:refs/heads/remote",
-    // "object-format=sha1",
-    // "agent=bit",
-];
-
 impl<R, W> UploadPack<R, W>
 where
     R: BitProtocolRead,
     W: BitProtocolWrite,
 {
     pub fn new(repo: BitRepo, reader: R, writer: W) -> Self {
-        Self { repo, reader, writer }
+        Self {
+            repo,
+            reader,
+            writer,
+            capabilities: Default::default(),
+            advertised: Default::default(),
+            sending_pack: false,
+        }
     }
 
     #[tokio::main]
     pub async fn run(&mut self) -> Result<()> {
+        self.serve().await
+    }
+
+    /// The same as `run` but within the caller's runtime.
+    /// Any error is also sent to the client so it isn't left guessing why we hung up.
+    pub async fn serve(&mut self) -> Result<()> {
+        let result = self.upload_pack().await;
+        if let Err(err) = &result {
+            // the client may well have hung up already, in which case there's no one to tell
+            let _ = self.send_error(&err.to_string()).await;
+        }
+        result
+    }
+
+    async fn upload_pack(&mut self) -> Result<()> {
         self.write_ref_discovery().await?;
-        let wanted = self.recv_wanted().await?;
-        if wanted.is_empty() {
+        let wants = self.recv_wants().await?;
+        // the client is either up to date or was only interested in the refs
+        if wants.is_empty() {
             return Ok(());
         }
-        let has = self.recv_has().await?;
-        dbg!(wanted);
-        dbg!(has);
-        Ok(())
+        let common = self.negotiate().await?;
+        self.send_pack(&wants, &common).await
+    }
+
+    fn capabilities(&self) -> Result<Vec<Capability>> {
+        let mut capabilities =
+            vec![Capability::MultiAckDetailed, Capability::SideBand64k, Capability::OfsDelta];
+        if let BitRef::Symbolic(sym) = self.repo.read_head()? {
+            capabilities.push(Capability::Symref(SymbolicRef::HEAD, sym));
+        }
+        capabilities.push(Capability::Agent(AGENT.to_owned()));
+        Ok(capabilities)
     }
 
-    fn parse_cmd_line<T>(&mut self, cmd: &str, packet: &[u8]) -> Result<T>
-    where
-        T: FromStr<Err = BitGenericError>,
-    {
-        let s = std::str::from_utf8(packet)?;
-        let (c, oid) = s.split_once(' ').ok_or_else(|| anyhow!("bad `{}` line", cmd))?;
-        ensure_eq!(c, cmd);
-        oid.parse()
+    // want-list = first-want *additional-want
+    // first-want = PKT-LINE("want" SP obj-id SP capability-list)
+    // additional-want = PKT-LINE("want" SP obj-id)
+    async fn recv_wants(&mut self) -> Result<Vec<Oid>> {
+        let mut wants = vec![];
+        // the client may just hang up after reading the refs
+        if self.reader.fill_buf().await?.is_empty() {
+            return Ok(wants);
+        }
+
+        loop {
+            let packet = self.reader.recv_packet().await?;
+            if packet.is_empty() {
+                break Ok(wants);
+            }
+
+            let line = std::str::from_utf8(&packet)?.trim_end();
+            let want = line
+                .strip_prefix("want ")
+                .ok_or_else(|| anyhow!("expected want, got `{}`", line))?;
+            let (oid, capabilities) = want.split_once(' ').unwrap_or((want, ""));
+            if wants.is_empty() {
+                // unknown capabilities are ignored as we never advertised them anyway
+                self.capabilities = capabilities
+                    .split_ascii_whitespace()
+                    .filter_map(|capability| capability.parse().ok())
+                    .collect();
+            }
+
+            let oid = oid.parse()?;
+            ensure!(self.advertised.contains(&oid), "upload-pack: not our ref {}", oid);
+            wants.push(oid);
+        }
+    }
+
+    /// Acknowledge the commits the client has until it sends `done`.
+    /// Returns all commits that we have in common.
+    async fn negotiate(&mut self) -> Result<Vec<Oid>> {
+        let multi_ack_detailed = self.capabilities.contains(&Capability::MultiAckDetailed);
+        let multi_ack = multi_ack_detailed || self.capabilities.contains(&Capability::MultiAck);
+
+        let mut common = vec![];
+        loop {
+            let packet = self.reader.recv_packet().await?;
+            // the client flushes after each batch of `have`s and waits for a response
+            if packet.is_empty() {
+                if multi_ack || common.is_empty() {
+                    self.write(b"NAK\n").await?;
+                }
+                self.writer.flush().await?;
+                continue;
+            }
+
+            let line = std::str::from_utf8(&packet)?.trim_end();
+            if line == "done" {
+                break;
+            }
+
+            let oid = line
+                .strip_prefix("have ")
+                .ok_or_else(|| anyhow!("expected have or done, got `{}`", line))?
+                .parse()?;
+            if !self.is_common(oid)? {
+                continue;
+            }
+
+            if multi_ack_detailed {
+                self.write(format!("ACK {} common\n", oid)).await?;
+            } else if multi_ack {
+                self.write(format!("ACK {} continue\n", oid)).await?;
+            } else if common.is_empty() {
+                // without multi_ack only the first common commit is acknowledged
+                self.write(format!("ACK {}\n", oid)).await?;
+            }
+            common.push(oid);
+        }
+
+        match common.last() {
+            Some(oid) if multi_ack => self.write(format!("ACK {}\n", oid)).await?,
+            Some(..) => {}
+            None => self.write(b"NAK\n").await?,
+        }
+        Ok(common)
     }
 
-    async fn recv_wanted(&mut self) -> Result<Vec<Oid>> {
-        self.reader
-            .recv_message()
-            .await?
-            .iter()
-            .map(|packet| self.parse_cmd_line("want", packet))
-            .collect()
+    // we only consider commits as the object walk starts from the commits anyway
+    fn is_common(&self, oid: Oid) -> Result<bool> {
+        Ok(self.repo.obj_exists(oid)?
+            && self.repo.read_obj_header(oid)?.obj_type == BitObjType::Commit)
     }
 
-    async fn recv_has(&mut self) -> Result<Vec<Oid>> {
-        self.reader
-            .recv_message()
-            .await?
-            .iter()
-            .map(|packet| self.parse_cmd_line("have", packet))
-            .collect()
+    async fn send_pack(&mut self, wants: &[Oid], common: &[Oid]) -> Result<()> {
+        self.sending_pack = true;
+        let objects = self.repo.reachable_objects(wants, common)?;
+        let mut builder = PackBuilder::new(self.repo.clone(), objects);
+        // we only ever produce `OFS_DELTA`s, so send whole objects if the client can't handle them
+        if !self.capabilities.contains(&Capability::OfsDelta) {
+            builder = builder.window(0);
+        }
+
+        let sideband = self.capabilities.contains(&Capability::SideBand64k);
+        self.writer.write_pack(builder, sideband).await?;
+        if sideband {
+            self.writer.write_flush_packet().await?;
+        } else {
+            self.writer.flush().await?;
+        }
+        Ok(())
     }
 
     // Reference Discovery
@@ -123,20 +223,42 @@ where
         // The order isn't really significant but keeping it close to git
         // The ord impl for refs is tailored for other purposes (i.e. remotes before heads in bit log)
         refs.sort_by_key(|r| r.path());
-        for (i, r) in refs.into_iter().enumerate() {
+
+        let capabilities = self.capabilities()?.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let mut capabilities = Some(capabilities.join(" "));
+        for r in refs {
             let oid = match self.repo.try_fully_resolve_ref(r)? {
                 Some(oid) => oid,
                 None => continue,
             };
-            if i == 0 {
-                self.write(format!("{} {}\0{}\n", oid, r.path(), CAPABILITIES.join(" "))).await?;
-                continue;
+            match capabilities.take() {
+                Some(capabilities) =>
+                    self.write(format!("{} {}\0{}\n", oid, r.path(), capabilities)).await?,
+                None => self.write(format!("{} {}\n", oid, r.path())).await?,
+            }
+            self.advertised.insert(oid);
+
+            if self.repo.read_obj_header(oid)?.obj_type == BitObjType::Tag {
+                let peeled = self.repo.read_obj(oid)?.try_into_tag()?.peel(&self.repo)?.oid();
+                self.write(format!("{} {}^{{}}\n", peeled, r.path())).await?;
+                self.advertised.insert(peeled);
             }
-            self.write(format!("{} {}\n", oid, r.path()).as_bytes()).await?;
         }
         Ok(self.writer.write_flush_packet().await?)
     }
 
+    // error-line     =  PKT-LINE("ERR" SP explanation-text)
+    // The client is expecting pack data once we've started on the pack so git sends the error
+    // over the error sideband instead
+    async fn send_error(&mut self, msg: &str) -> io::Result<()> {
+        if self.sending_pack && self.capabilities.contains(&Capability::SideBand64k) {
+            self.writer.write_sideband(SIDEBAND_ERROR, format!("{}\n", msg).as_bytes()).await?;
+            self.writer.flush().await
+        } else {
+            self.writer.write_error_line(msg).await
+        }
+    }
+
     #[inline]
     async fn write(&mut self, bytes: impl AsRef<[u8]>) -> io::Result<()> {
         self.writer.write_packet(bytes.as_ref()).await
@@ -1,5 +1,5 @@
 use super::*;
-use crate::diff::{Diff, Differ, WorkspaceStatus};
+use crate::diff::{Diff, Differ, RenameEntry, WorkspaceStatus};
 use crate::error::BitResult;
 use crate::index::BitIndexEntry;
 use crate::iter::BitEntry;
@@ -35,10 +35,59 @@ impl WorkspaceStatus {
         for deleted in &self.deleted {
             println!("delete mode {} {}", deleted.mode, deleted.path);
         }
+
+        for rename in &self.renamed {
+            println!("rename {} ({}%)", rename_path(rename), rename.similarity);
+        }
+
+        for copy in &self.copied {
+            println!("copy {} ({}%)", rename_path(copy), copy.similarity);
+        }
         Ok(())
     }
 }
 
+/// Formats the paths of a rename compactly by factoring out the common leading and trailing
+/// directories, e.g. `a/b/c` -> `a/d/c` is formatted as `a/{b => d}/c`
+fn rename_path(rename: &RenameEntry) -> String {
+    let old = rename.old.path.as_str();
+    let new = rename.new.path.as_str();
+
+    // only whole directories are factored out (and '/' is ascii so these are char boundaries)
+    let common_prefix = old.bytes().zip(new.bytes()).take_while(|(a, b)| a == b).count();
+    let prefix = old.as_bytes()[..common_prefix]
+        .iter()
+        .rposition(|&b| b == b'/')
+        .map(|i| i + 1)
+        .unwrap_or(0);
+
+    // the suffix must not overlap with the prefix in either path
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let common_suffix = old
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take_while(|(a, b)| a == b)
+        .count()
+        .min(max_suffix);
+    let suffix = old.as_bytes()[old.len() - common_suffix..]
+        .iter()
+        .position(|&b| b == b'/')
+        .map(|i| common_suffix - i)
+        .unwrap_or(0);
+
+    if prefix == 0 && suffix == 0 {
+        return format!("{} => {}", old, new);
+    }
+    format!(
+        "{}{{{} => {}}}{}",
+        &old[..prefix],
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+        &old[old.len() - suffix..]
+    )
+}
+
 impl<D: Diff> DiffFormatExt for D {
     fn format_diffstat_into(self, repo: &BitRepo, writer: impl Write) -> BitResult<()> {
         DiffStatFormatter::format_diffstat_into(repo, writer, self)
@@ -55,6 +104,7 @@ impl<W: Write> DiffFormatter<W> {
     }
 
     pub fn format_diff_into(repo: &BitRepo, writer: W, status: impl Diff) -> BitResult<()> {
+        status.prefetch_objects(repo)?;
         status.apply_with(&mut Self::new(repo.clone(), writer))
     }
 }
@@ -117,6 +167,53 @@ impl<W: Write> Differ for DiffFormatter<W> {
         xdiff::format_patch_into(writer, &patch)?;
         Ok(())
     }
+
+    fn on_renamed(&mut self, rename: RenameEntry) -> BitResult<()> {
+        self.format_rename("rename", rename)
+    }
+
+    fn on_copied(&mut self, copy: RenameEntry) -> BitResult<()> {
+        self.format_rename("copy", copy)
+    }
+}
+
+impl<W: Write> DiffFormatter<W> {
+    /// `kind` is either `rename` or `copy`
+    fn format_rename(&mut self, kind: &str, rename: RenameEntry) -> BitResult<()> {
+        let RenameEntry { old, new, similarity } = rename;
+        let a: BitPath = BitPath::A.join(old.path);
+        let b: BitPath = BitPath::B.join(new.path);
+
+        let writer = &mut self.writer;
+        writeln!(writer, "diff --git {a} {b}")?;
+        if old.mode != new.mode {
+            writeln!(writer, "old mode {}", old.mode)?;
+            writeln!(writer, "new mode {}", new.mode)?;
+        }
+        writeln!(writer, "similarity index {}%", similarity)?;
+        writeln!(writer, "{} from {}", kind, old.path)?;
+        writeln!(writer, "{} to {}", kind, new.path)?;
+
+        // an exact rename has no content changes to show
+        if similarity == 100 {
+            return Ok(());
+        }
+
+        let old_txt = old.read_to_bytes(&self.repo)?;
+        let new_txt = new.read_to_bytes(&self.repo)?;
+        let mut patch = xdiff::xdiff(&old_txt, &new_txt);
+        let new_oid = if new.oid.is_known() {
+            new.oid
+        } else {
+            self.repo.hash_blob_from_worktree(new.path)?
+        };
+        let writer = &mut self.writer;
+        writeln!(writer, "index {:#}..{:#}", old.oid, new_oid)?;
+        patch.set_original(Cow::Borrowed(a.as_bytes()));
+        patch.set_modified(Cow::Borrowed(b.as_bytes()));
+        xdiff::format_patch_into(writer, &patch)?;
+        Ok(())
+    }
 }
 
 pub struct DiffStatFormatter<W> {
@@ -146,6 +243,7 @@ impl<W: Write> DiffStatFormatter<W> {
     // could just do a `diffstat` method on workspace status and then the user can write as one wishes
     pub fn format_diffstat_into(repo: &BitRepo, writer: W, status: impl Diff) -> BitResult<()> {
         let mut this = Self::new(repo.clone(), writer);
+        status.prefetch_objects(repo)?;
         status.apply_with(&mut this)?;
 
         let lines = DiffStat {
@@ -271,4 +369,18 @@ impl<W: Write> Differ for DiffStatFormatter<W> {
         self.add_line(diff_stat_line);
         Ok(())
     }
+
+    fn on_renamed(&mut self, rename: RenameEntry) -> BitResult<()> {
+        let old_txt = rename.old.read_to_bytes(&self.repo)?;
+        let new_txt = rename.new.read_to_bytes(&self.repo)?;
+        let patch = xdiff::xdiff(&old_txt, &new_txt);
+        let path = BitPath::intern(rename_path(&rename));
+        let diff_stat_line = DiffStatLine::from_patch(path, &patch);
+        self.add_line(diff_stat_line);
+        Ok(())
+    }
+
+    fn on_copied(&mut self, copy: RenameEntry) -> BitResult<()> {
+        self.on_renamed(copy)
+    }
 }
@@ -103,3 +103,65 @@ fn test_diff_format_created_staged_header() -> BitResult<()> {
         Ok(())
     })
 }
+
+macro_rules! diff_staged_with_renames {
+    ($repo:ident, $format:ident) => {{
+        use $crate::xdiff::DiffFormatExt;
+        let mut diff = $repo.diff_head_index(Pathspec::MATCH_ALL)?;
+        diff.detect_renames(&$repo, $crate::diff::RenameOpts::default())?;
+        let mut output = vec![];
+        diff.$format(&$repo, &mut output)?;
+        String::from_utf8(output).unwrap()
+    }};
+}
+
+#[test]
+fn test_diff_format_exact_rename_header() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        mkdir!(repo: "dir");
+        touch!(repo: "dir/foo" < "some content\n");
+        bit_commit_all!(repo);
+
+        rm!(repo: "dir/foo");
+        touch!(repo: "dir/bar" < "some content\n");
+        bit_add_all!(repo);
+
+        let output = diff_staged_with_renames!(repo, format_diff_into);
+        let mut lines = output.lines();
+        assert_eq!(lines.next().unwrap(), "diff --git a/dir/foo b/dir/bar");
+        assert_eq!(lines.next().unwrap(), "similarity index 100%");
+        assert_eq!(lines.next().unwrap(), "rename from dir/foo");
+        assert_eq!(lines.next().unwrap(), "rename to dir/bar");
+        assert!(lines.next().is_none());
+
+        let output = diff_staged_with_renames!(repo, format_diffstat_into);
+        assert!(output.lines().next().unwrap().starts_with(" dir/{foo => bar} | 0"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_diff_format_similar_rename() -> BitResult<()> {
+    BitRepo::with_empty_repo(|repo| {
+        touch!(repo: "foo" < "a\nb\nc\nd\n");
+        bit_commit_all!(repo);
+
+        rm!(repo: "foo");
+        touch!(repo: "bar" < "a\nb\nc\ne\n");
+        bit_add_all!(repo);
+
+        let output = diff_staged_with_renames!(repo, format_diff_into);
+        let mut lines = output.lines();
+        assert_eq!(lines.next().unwrap(), "diff --git a/foo b/bar");
+        assert_eq!(lines.next().unwrap(), "similarity index 75%");
+        assert_eq!(lines.next().unwrap(), "rename from foo");
+        assert_eq!(lines.next().unwrap(), "rename to bar");
+        assert!(lines.next().unwrap().starts_with("index "));
+        assert_eq!(lines.next().unwrap(), "--- a/foo");
+        assert_eq!(lines.next().unwrap(), "+++ b/bar");
+
+        let output = diff_staged_with_renames!(repo, format_diffstat_into);
+        assert!(output.lines().next().unwrap().starts_with(" foo => bar | 2"));
+        Ok(())
+    })
+}